@@ -0,0 +1,33 @@
+//! Reads the forms of the `tests/perf*.mal` inputs. Run with `cargo bench`.
+
+#![feature(test)]
+
+extern crate test;
+
+use rust2::reader::Reader;
+use test::Bencher;
+
+fn read_all(input: &str) -> usize {
+	let mut reader = Reader::new(input);
+	let mut forms = 0;
+	while !reader.is_done().unwrap() {
+		test::black_box(reader.read_form().unwrap());
+		forms += 1;
+	}
+	forms
+}
+
+macro_rules! bench_file {
+	($name:ident, $file:literal) => {
+		#[bench]
+		fn $name(bench: &mut Bencher) {
+			let input = include_str!(concat!("../../../tests/", $file));
+			bench.bytes = input.len() as u64;
+			bench.iter(|| read_all(input));
+		}
+	};
+}
+
+bench_file!(perf1, "perf1.mal");
+bench_file!(perf2, "perf2.mal");
+bench_file!(perf3, "perf3.mal");
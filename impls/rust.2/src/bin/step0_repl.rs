@@ -12,7 +12,7 @@ fn main() -> io::Result<()> {
 		let input = match stdin.next() {
 			Some(input) => input?,
 			None => {
-				println!("");
+				println!();
 				continue;
 			},
 		};
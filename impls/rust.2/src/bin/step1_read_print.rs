@@ -1,5 +1,3 @@
-#![feature(let_else)]
-
 use std::io;
 use std::io::Write;
 
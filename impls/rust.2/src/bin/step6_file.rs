@@ -0,0 +1,39 @@
+use std::process;
+
+use eyre::Result;
+use rust2::eval::Interpreter;
+use rust2::special_forms;
+use rust2::types::ListKind;
+use rust2::types::MalType;
+use rust2::types::TracedError;
+
+fn main() -> Result<()> {
+	let mut args = std::env::args().skip(1);
+	let script = args.next();
+
+	let interpreter = Interpreter::new(rust2::core::ns());
+	interpreter.set_special_form("def!", special_forms::def);
+	interpreter.set_special_form("do", special_forms::do_);
+	interpreter.set_special_form("fn*", special_forms::fn_star);
+	interpreter.set_special_form("if", special_forms::if_);
+	interpreter.set_special_form("let*", special_forms::let_star);
+
+	let env = interpreter.env();
+	env.set("eval".to_string(), interpreter.eval_function());
+	env.set(
+		"*ARGV*".to_string(),
+		MalType::L(ListKind::List, args.map(MalType::String).collect(), None),
+	);
+	interpreter.rep("(def! not (fn* (a) (if a false true)))")?;
+	env.set("load-file".to_string(), interpreter.load_file_function());
+
+	if let Some(script) = script {
+		if let Err(err) = interpreter.load_file(&script) {
+			eprintln!("Error: {}", TracedError::describe(&err));
+			process::exit(1);
+		}
+		return Ok(());
+	}
+
+	interpreter.repl()
+}
@@ -0,0 +1,29 @@
+use std::process;
+
+use eyre::Result;
+use rust2::eval::Interpreter;
+use rust2::types::ListKind;
+use rust2::types::MalType;
+use rust2::types::TracedError;
+
+fn main() -> Result<()> {
+	let mut args = std::env::args().skip(1);
+	let script = args.next();
+
+	let interpreter = Interpreter::standard()?;
+	interpreter.env().set(
+		"*ARGV*".to_string(),
+		MalType::L(ListKind::List, args.map(MalType::String).collect(), None),
+	);
+
+	if let Some(script) = script {
+		if let Err(err) = interpreter.load_file(&script) {
+			eprintln!("Error: {}", TracedError::describe(&err));
+			process::exit(1);
+		}
+		return Ok(());
+	}
+
+	interpreter.rep(r#"(println (str "Mal [" *host-language* "]"))"#)?;
+	interpreter.repl()
+}
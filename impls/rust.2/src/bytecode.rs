@@ -0,0 +1,304 @@
+//! The instructions that `compiler` compiles forms to and `vm` runs.
+
+use std::cell::Cell;
+use std::fmt::Display;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::types::MalType;
+use crate::types::Span;
+
+/// An instruction. Operands index into the tables of the `Proto` it's part
+/// of, count values on the stack, or are the address of another instruction.
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+	/// Pushes a constant.
+	Const(usize),
+	/// Pushes a local of the running function.
+	Load(usize),
+	/// Pushes a local of the function `depth` functions out from the running
+	/// one.
+	LoadOuter {
+		depth: usize,
+		slot:  usize,
+	},
+	/// Pushes the first of a list of locals that `def!` binds that it's
+	/// bound, or else a local of the function `depth` functions out.
+	LoadDefinedOrLocal {
+		defined: usize,
+		depth:   usize,
+		slot:    usize,
+	},
+	/// Pops into a local of the running function.
+	Store(usize),
+	/// Pushes the global named by a constant symbol.
+	LoadGlobal(usize),
+	/// Like `LoadDefinedOrLocal`, but falls back to the global named by a
+	/// constant symbol.
+	LoadDefinedOrGlobal {
+		defined: usize,
+		name:    usize,
+	},
+	/// Binds the global named by a constant to the value on top of the
+	/// stack, leaving it there.
+	DefGlobal(usize),
+	/// Binds a local of the function `depth` functions out from the running
+	/// one, which a constant symbol names, to the value on top of the stack,
+	/// leaving it there.
+	DefLocal {
+		name:  usize,
+		depth: usize,
+		slot:  usize,
+	},
+	/// Like `DefGlobal`, but makes the function on top of the stack a macro
+	/// first.
+	DefMacro(usize),
+	Pop,
+	Jump(usize),
+	/// Pops a value, and jumps if it's `nil` or `false`.
+	JumpIfFalse(usize),
+	/// Jumps if any of a list of macros, which a form was expanded with, has
+	/// been redefined.
+	JumpIfRedefined {
+		macros: usize,
+		to:     usize,
+	},
+	/// If the function on top of the stack, which a deferred call was made
+	/// to, is a macro, pops it, jumps past the call, and runs the call
+	/// instead.
+	ExpandIfMacro {
+		deferred: usize,
+		to:       usize,
+	},
+	/// Compiles a deferred form and runs it.
+	Eval(usize),
+	/// Pops a function and its args, which were pushed in that order, and
+	/// pushes the result of calling it.
+	Call {
+		argc: usize,
+		site: usize,
+	},
+	/// Pops a function and its args, and calls it in place of the running
+	/// function.
+	TailCall {
+		argc: usize,
+		site: usize,
+	},
+	/// Returns the value on top of the stack from the running function.
+	Return,
+	/// Pushes a closure of a nested proto over the running function's locals.
+	Closure(usize),
+	/// Pushes a lazy seq that calls a closure of a nested proto, which takes
+	/// no args, when it's realized.
+	LazySeq(usize),
+	/// Pops items and pushes a vector of them.
+	Vector(usize),
+	/// Pops a value for each entry of a constant hashmap, in order, and
+	/// pushes a copy of the hashmap with those values.
+	HashMap(usize),
+	/// Pops items and pushes a set of them.
+	Set(usize),
+	/// Installs a handler at the address, which is removed by the next
+	/// `EndTry`. If an error is raised in between, the stack is unwound to
+	/// where it was here, the thrown value and the stack trace are pushed, and
+	/// the handler is jumped to.
+	Try(usize),
+	EndTry,
+	/// Runs a special form that the compiler doesn't know.
+	Special(usize),
+	/// Throws a constant, which a macro threw while the form was compiled.
+	Throw(usize),
+	/// Raises an error with a constant message, which was raised while the
+	/// form was compiled.
+	Fail(usize),
+}
+
+/// A compiled `fn*`, `lazy-seq` body or top-level form.
+pub struct Proto {
+	/// The params the function was defined with, including any `&`.
+	pub params:    Vec<String>,
+	/// The body the function was defined with.
+	pub body:      MalType,
+	/// How many params there are, not counting a rest param.
+	pub arity:     usize,
+	/// Whether the last param takes the rest of the args.
+	pub variadic:  bool,
+	/// The name of each local, by slot. Params take the first slots.
+	pub names:     Vec<String>,
+	pub code:      Vec<Op>,
+	pub constants: Vec<MalType>,
+	pub protos:    Vec<Rc<Proto>>,
+	pub sites:     Vec<Site>,
+	pub specials:  Vec<Special>,
+	/// The macros that each expanded form was expanded with.
+	pub macros:    Vec<Vec<Guard>>,
+	pub deferred:  Vec<Deferred>,
+	/// The depth and slot of each local that `def!` may bind a name to,
+	/// innermost first, for each lookup of a name that it may bind.
+	pub defined:   Vec<Vec<(usize, Late)>>,
+}
+
+/// The slot that `def!` binds a name to in an env, which is unset until the
+/// `def!` has been compiled. Code compiled before then shares it with the
+/// compiler, and finds the slot when it's run.
+pub type Late = Rc<Cell<Option<usize>>>;
+
+/// Where a call was made, for stack traces.
+pub struct Site {
+	/// The symbol the function was called through, if it was.
+	pub name: Option<String>,
+	pub span: Option<Span>,
+}
+
+/// A use of a special form that the compiler doesn't know, which is run with
+/// the locals in scope copied into an env.
+pub struct Special {
+	pub name:   String,
+	pub args:   Vec<MalType>,
+	/// The name, depth and slot of each local in scope, outermost first,
+	/// including those that `def!` may bind.
+	pub locals: Vec<(String, usize, Late)>,
+}
+
+/// A macro that a form was expanded with, and the global it was found in.
+pub struct Guard {
+	pub name: String,
+	pub mac:  MalType,
+}
+
+/// A form that's compiled when it's run, with the locals in scope copied into
+/// an env, as the code it was compiled to is out of date.
+pub struct Deferred {
+	pub ast:    MalType,
+	/// The name, depth and slot of each local in scope, outermost first,
+	/// including those that `def!` may bind.
+	pub locals: Vec<(String, usize, Late)>,
+	/// Whether the form is in tail position.
+	pub tail:   bool,
+}
+
+impl Proto {
+	pub fn new(params: Vec<String>, body: MalType) -> Self {
+		Self {
+			params,
+			body,
+			arity: 0,
+			variadic: false,
+			names: Vec::new(),
+			code: Vec::new(),
+			constants: Vec::new(),
+			protos: Vec::new(),
+			sites: Vec::new(),
+			specials: Vec::new(),
+			macros: Vec::new(),
+			deferred: Vec::new(),
+			defined: Vec::new(),
+		}
+	}
+
+	/// A listing of the instructions, followed by that of each nested proto,
+	/// which is headed by its path from this one and its params.
+	pub fn disassemble(&self) -> String {
+		let mut out = String::new();
+		self.write_listing(&mut out, "");
+		out
+	}
+
+	fn write_listing(&self, out: &mut String, path: &str) {
+		for (pc, &op) in self.code.iter().enumerate() {
+			match self.note(op) {
+				Some(note) => {
+					writeln!(out, "{pc:4}  {:<20} ; {note}", op.to_string())
+				},
+				None => writeln!(out, "{pc:4}  {op}"),
+			}
+			.unwrap();
+		}
+		for (i, proto) in self.protos.iter().enumerate() {
+			let path = format!("{path}#{i}");
+			writeln!(out, "\n{path} ({})", proto.params.join(" ")).unwrap();
+			proto.write_listing(out, &path);
+		}
+	}
+
+	/// What an instruction's operand refers to, where that's more telling
+	/// than its index.
+	fn note(&self, op: Op) -> Option<String> {
+		match op {
+			Op::Const(i)
+			| Op::LoadGlobal(i)
+			| Op::LoadDefinedOrGlobal { name: i, .. }
+			| Op::DefGlobal(i)
+			| Op::DefMacro(i)
+			| Op::DefLocal { name: i, .. }
+			| Op::Throw(i)
+			| Op::Fail(i) => Some(format!("{:#}", self.constants[i])),
+			Op::Load(slot) | Op::Store(slot) => Some(self.names[slot].clone()),
+			Op::Call { site, .. } | Op::TailCall { site, .. } => {
+				self.sites[site].name.clone()
+			},
+			Op::Special(i) => Some(self.specials[i].name.clone()),
+			Op::ExpandIfMacro { deferred: i, .. } | Op::Eval(i) => {
+				Some(format!("{:#}", self.deferred[i].ast))
+			},
+			Op::JumpIfRedefined { macros, .. } => Some(
+				self.macros[macros]
+					.iter()
+					.map(|guard| guard.name.as_str())
+					.collect::<Vec<_>>()
+					.join(" "),
+			),
+			_ => None,
+		}
+	}
+}
+
+impl Display for Op {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match *self {
+			Op::Const(i) => write!(f, "const {i}"),
+			Op::Load(slot) => write!(f, "load {slot}"),
+			Op::LoadOuter { depth, slot } => {
+				write!(f, "load-outer {depth} {slot}")
+			},
+			Op::LoadDefinedOrLocal {
+				defined,
+				depth,
+				slot,
+			} => write!(f, "load-defined-or-local {defined} {depth} {slot}"),
+			Op::Store(slot) => write!(f, "store {slot}"),
+			Op::LoadGlobal(i) => write!(f, "load-global {i}"),
+			Op::LoadDefinedOrGlobal { defined, name } => {
+				write!(f, "load-defined-or-global {defined} {name}")
+			},
+			Op::DefGlobal(i) => write!(f, "def-global {i}"),
+			Op::DefLocal { name, depth, slot } => {
+				write!(f, "def-local {name} {depth} {slot}")
+			},
+			Op::DefMacro(i) => write!(f, "def-macro {i}"),
+			Op::Pop => write!(f, "pop"),
+			Op::Jump(to) => write!(f, "jump {to}"),
+			Op::JumpIfFalse(to) => write!(f, "jump-if-false {to}"),
+			Op::JumpIfRedefined { macros, to } => {
+				write!(f, "jump-if-redefined {macros} {to}")
+			},
+			Op::ExpandIfMacro { deferred, to } => {
+				write!(f, "expand-if-macro {deferred} {to}")
+			},
+			Op::Eval(i) => write!(f, "eval {i}"),
+			Op::Call { argc, .. } => write!(f, "call {argc}"),
+			Op::TailCall { argc, .. } => write!(f, "tail-call {argc}"),
+			Op::Return => write!(f, "return"),
+			Op::Closure(i) => write!(f, "closure #{i}"),
+			Op::LazySeq(i) => write!(f, "lazy-seq #{i}"),
+			Op::Vector(len) => write!(f, "vector {len}"),
+			Op::HashMap(i) => write!(f, "hash-map {i}"),
+			Op::Set(len) => write!(f, "set {len}"),
+			Op::Try(to) => write!(f, "try {to}"),
+			Op::EndTry => write!(f, "end-try"),
+			Op::Special(i) => write!(f, "special {i}"),
+			Op::Throw(i) => write!(f, "throw {i}"),
+			Op::Fail(i) => write!(f, "fail {i}"),
+		}
+	}
+}
@@ -0,0 +1,811 @@
+//! Compiles forms to bytecode for the VM. Macros are expanded and special
+//! forms decoded as a form is compiled, and each symbol is resolved once: to a
+//! local's slot, and how many functions out it's bound, or else to a global,
+//! which is looked up by name in the env the form was compiled for.
+//!
+//! A form that's expanded is compiled along with a check that the macros it
+//! was expanded with haven't been redefined since, and if any has, it's
+//! compiled afresh when it's run. Likewise, a call to a global that's become a
+//! macro since it was compiled is expanded when it's run, so a function can
+//! use a macro that's defined after it.
+//!
+//! An error found while compiling a form, like a malformed special form or a
+//! macro that throws, is compiled into code that raises it, so that it's
+//! raised when and if the form would have been evaluated.
+//!
+//! Locals are kept in slots rather than an env. `def!` binds in the innermost
+//! env, as it does in the tree-walker: a global at the top level, and
+//! otherwise a local of the enclosing function, `let*` or `catch*`, which is
+//! unset until the `def!` is run. Until then, looking it up carries on to
+//! where the name is bound further out. A lookup compiled before the `def!`
+//! finds its slot through a `Late` that the `def!` sets, so an env is only
+//! compiled, and its macros expanded, once.
+//!
+//! Special forms other than the standard ones, and forms compiled afresh, are
+//! run with the locals in scope copied into an env.
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use eyre::Result;
+
+use crate::bytecode::Deferred;
+use crate::bytecode::Guard;
+use crate::bytecode::Late;
+use crate::bytecode::Op;
+use crate::bytecode::Proto;
+use crate::bytecode::Site;
+use crate::bytecode::Special;
+use crate::env::Env;
+use crate::eval;
+use crate::eval::Interpreter;
+use crate::special_forms::destructure;
+use crate::types::ListKind;
+use crate::types::MalException;
+use crate::types::MalType;
+use crate::types::Span;
+use crate::types::TracedError;
+
+/// The special forms that are compiled, rather than run through `Special`,
+/// when they're registered under these names.
+const STANDARD_FORMS: [&str; 11] = [
+	"def!",
+	"defmacro!",
+	"do",
+	"fn*",
+	"if",
+	"lazy-seq",
+	"let*",
+	"quasiquote",
+	"quasiquoteexpand",
+	"quote",
+	"try*",
+];
+
+/// Compiles `ast` to be run in `env`.
+pub fn compile(interpreter: &Interpreter, ast: MalType, env: &Env) -> Proto {
+	let mut compiler = Compiler {
+		interpreter,
+		env,
+		functions: vec![Function::new(
+			Proto::new(Vec::new(), ast.clone()),
+			false,
+		)],
+	};
+	compiler.compile(ast, true);
+	compiler.emit(Op::Return);
+	let mut proto = compiler.functions.pop().unwrap().proto;
+	settle(&mut proto);
+	proto
+}
+
+/// Drops the slots that `def!` didn't bind from each lookup of a name that it
+/// might have, now that every `def!` that could has been compiled, and makes
+/// a lookup that's left with none a plain one.
+fn settle(proto: &mut Proto) {
+	for op in &mut proto.code {
+		let (Op::LoadDefinedOrLocal { defined, .. }
+		| Op::LoadDefinedOrGlobal { defined, .. }) = *op
+		else {
+			continue;
+		};
+		let table = &mut proto.defined[defined];
+		table.retain(|(_, late)| late.get().is_some());
+		if !table.is_empty() {
+			continue;
+		}
+		*op = match *op {
+			Op::LoadDefinedOrLocal { depth: 0, slot, .. } => Op::Load(slot),
+			Op::LoadDefinedOrLocal { depth, slot, .. } => {
+				Op::LoadOuter { depth, slot }
+			},
+			Op::LoadDefinedOrGlobal { name, .. } => Op::LoadGlobal(name),
+			_ => unreachable!(),
+		};
+	}
+	for proto in &mut proto.protos {
+		// nothing else holds a proto until it's been compiled
+		settle(Rc::get_mut(proto).unwrap());
+	}
+}
+
+/// How many functions out a local is bound, and its slot there.
+type Slot = (usize, usize);
+
+struct Compiler<'a> {
+	interpreter: &'a Interpreter,
+	env:         &'a Env,
+	/// The functions being compiled, innermost last.
+	functions:   Vec<Function>,
+}
+
+struct Function {
+	proto:     Proto,
+	/// The locals in scope, innermost last.
+	locals:    Vec<Local>,
+	/// The envs that `def!` binds in, innermost last.
+	envs:      Vec<DefEnv>,
+	/// Whether `def!` outside of its envs binds in the env the function was
+	/// made in, as for a `lazy-seq`, rather than a global.
+	enclosing: bool,
+}
+
+#[derive(Clone)]
+struct Local {
+	name:    String,
+	slot:    usize,
+	/// A `let*` binding is pending until its value has been compiled, and
+	/// until then it's only visible from functions within the `let*`, which
+	/// won't have been called before it's bound.
+	pending: bool,
+}
+
+/// The body of a function, `let*` or `catch*`, which `def!` binds in.
+struct DefEnv {
+	/// The first of the function's locals that are bound in it.
+	start: usize,
+	/// The slot of each name that `def!` binds in it, or may yet.
+	defs:  Rc<RefCell<HashMap<String, Late>>>,
+}
+
+impl DefEnv {
+	fn new(start: usize) -> Self {
+		Self {
+			start,
+			defs: Rc::default(),
+		}
+	}
+
+	fn late(&self, name: &str) -> Late {
+		let mut defs = self.defs.borrow_mut();
+		defs.entry(name.to_string()).or_default().clone()
+	}
+
+	/// The slot that `def!` binds `name` to, if it's been compiled.
+	fn defined(&self, name: &str) -> Option<usize> {
+		self.defs.borrow().get(name).and_then(|late| late.get())
+	}
+}
+
+impl Function {
+	fn new(proto: Proto, enclosing: bool) -> Self {
+		Self {
+			proto,
+			locals: Vec::new(),
+			envs: Vec::new(),
+			enclosing,
+		}
+	}
+
+	/// Gives `name` a new slot.
+	fn push_local(&mut self, name: String, pending: bool) -> usize {
+		let slot = self.push_slot(name.clone());
+		self.locals.push(Local {
+			name,
+			slot,
+			pending,
+		});
+		slot
+	}
+
+	fn push_slot(&mut self, name: String) -> usize {
+		self.proto.names.push(name);
+		self.proto.names.len() - 1
+	}
+}
+
+impl Compiler<'_> {
+	fn function(&mut self) -> &mut Function {
+		self.functions.last_mut().unwrap()
+	}
+
+	fn emit(&mut self, op: Op) -> usize {
+		let code = &mut self.function().proto.code;
+		code.push(op);
+		code.len() - 1
+	}
+
+	/// Points the jump at `from` to the next instruction.
+	fn patch(&mut self, from: usize) {
+		let code = &mut self.function().proto.code;
+		let next = code.len();
+		match &mut code[from] {
+			Op::Jump(to)
+			| Op::JumpIfFalse(to)
+			| Op::Try(to)
+			| Op::JumpIfRedefined { to, .. }
+			| Op::ExpandIfMacro { to, .. } => *to = next,
+			op => unreachable!("expected a jump (got {op})"),
+		}
+	}
+
+	fn constant(&mut self, value: MalType) -> usize {
+		let constants = &mut self.function().proto.constants;
+		constants.push(value);
+		constants.len() - 1
+	}
+
+	fn push_constant(&mut self, value: MalType) {
+		let i = self.constant(value);
+		self.emit(Op::Const(i));
+	}
+
+	/// Gives `name` a new slot in the innermost function.
+	fn bind(&mut self, name: String, pending: bool) -> usize {
+		self.function().push_local(name, pending)
+	}
+
+	/// Where `name` may be bound: the slot that `def!` binds it to in each
+	/// env it's looked up in, innermost first, which may be unset, and then
+	/// the local it's bound to, if it's not a global.
+	fn resolve(&self, name: &str) -> (Vec<(usize, Late)>, Option<Slot>) {
+		let mut defined = Vec::new();
+		for (depth, function) in self.functions.iter().rev().enumerate() {
+			let visible = |local: &&Local| {
+				local.name == name && !(local.pending && depth == 0)
+			};
+			let mut end = function.locals.len();
+			for env in function.envs.iter().rev() {
+				let locals = &function.locals[env.start..end];
+				if let Some(local) = locals.iter().rev().find(visible) {
+					return (defined, Some((depth, local.slot)));
+				}
+				defined.push((depth, env.late(name)));
+				end = env.start;
+			}
+			let locals = &function.locals[..end];
+			if let Some(local) = locals.iter().rev().find(visible) {
+				return (defined, Some((depth, local.slot)));
+			}
+		}
+		(defined, None)
+	}
+
+	fn is_global(&self, name: &str) -> bool {
+		let (defined, local) = self.resolve(name);
+		local.is_none() && defined.iter().all(|(_, late)| late.get().is_none())
+	}
+
+	fn macro_call(&self, ast: &MalType) -> Option<Guard> {
+		let MalType::L(ListKind::List, list, _) = ast else {
+			return None;
+		};
+		let Some(MalType::Symbol(sym, _)) = list.first() else {
+			return None;
+		};
+		if !self.is_global(sym) {
+			return None;
+		}
+		match self.env.get(sym) {
+			Some(mac @ MalType::TCOFunction { is_macro: true, .. }) => {
+				Some(Guard {
+					name: sym.clone(),
+					mac,
+				})
+			},
+			_ => None,
+		}
+	}
+
+	/// Expands `ast` for as long as it's a call to a macro, adding the macros
+	/// it's expanded with to `guards`.
+	fn macroexpand(
+		&self,
+		mut ast: MalType,
+		guards: &mut Vec<Guard>,
+	) -> Result<MalType> {
+		while let Some(guard) = self.macro_call(&ast) {
+			let MalType::L(_, list, _) = &mut ast else {
+				unreachable!("macro calls are lists");
+			};
+			ast = guard.mac.call(&mut list[1..])?;
+			guards.push(guard);
+		}
+		Ok(ast)
+	}
+
+	/// Compiles the rest of an env whose locals start at `start`, and include
+	/// those bound so far, with `compile`.
+	fn env(&mut self, start: usize, compile: impl FnOnce(&mut Self)) {
+		self.function().envs.push(DefEnv::new(start));
+		compile(self);
+		let function = self.function();
+		function.envs.pop();
+		function.locals.truncate(start);
+	}
+
+	/// The local that `def!` binds `name` to, which it's given if it has none
+	/// yet, or `None` if `def!` binds a global.
+	fn def_local(&mut self, name: &str) -> Option<Slot> {
+		let (depth, function) =
+			self.functions.iter_mut().rev().enumerate().find(
+				|(_, function)| {
+					!function.envs.is_empty() || !function.enclosing
+				},
+			)?;
+		let env = function.envs.last()?;
+		let local = function.locals[env.start..]
+			.iter()
+			.rev()
+			.find(|local| local.name == name);
+		if let Some(local) = local {
+			return Some((depth, local.slot));
+		}
+		if let Some(slot) = env.defined(name) {
+			return Some((depth, slot));
+		}
+		let late = env.late(name);
+		let slot = function.push_slot(name.to_string());
+		late.set(Some(slot));
+		Some((depth, slot))
+	}
+
+	/// Compiles code that pushes the value of `ast`. If `tail` is set, a call
+	/// that the value comes from is made as a tail call.
+	fn compile(&mut self, ast: MalType, tail: bool) {
+		let depth = self.functions.len();
+		let function = self.function();
+		let (pc, locals, envs) = (
+			function.proto.code.len(),
+			function.locals.len(),
+			function.envs.len(),
+		);
+		if let Err(err) = self.compile_form(ast, tail) {
+			self.functions.truncate(depth);
+			let function = self.function();
+			function.proto.code.truncate(pc);
+			function.locals.truncate(locals);
+			function.envs.truncate(envs);
+			self.fail(err);
+		}
+	}
+
+	fn fail(&mut self, err: eyre::Report) {
+		let (err, _) = TracedError::split(err);
+		match err.downcast::<MalException>() {
+			Ok(exc) => {
+				let i = self.constant(exc.into_value());
+				self.emit(Op::Throw(i));
+			},
+			Err(err) => {
+				let i = self.constant(MalType::String(err.to_string()));
+				self.emit(Op::Fail(i));
+			},
+		}
+	}
+
+	/// Compiles a form that may be a macro call, which is compiled afresh when
+	/// it's run if any macro it was expanded with has been redefined since.
+	fn compile_form(&mut self, ast: MalType, tail: bool) -> Result<()> {
+		if self.macro_call(&ast).is_none() {
+			return self.compile_expanded(ast, tail);
+		}
+		let mut guards = Vec::new();
+		let expanded = self.macroexpand(ast.clone(), &mut guards)?;
+		let macros = &mut self.function().proto.macros;
+		macros.push(guards);
+		let macros = macros.len() - 1;
+		let redefined = self.emit(Op::JumpIfRedefined { macros, to: 0 });
+		self.compile_expanded(expanded, tail)?;
+		let to_end = self.emit(Op::Jump(0));
+		self.patch(redefined);
+		let i = self.deferred(ast, tail);
+		self.emit(Op::Eval(i));
+		self.patch(to_end);
+		Ok(())
+	}
+
+	fn compile_expanded(&mut self, ast: MalType, tail: bool) -> Result<()> {
+		match ast {
+			MalType::Symbol(sym, span) => self.symbol(sym, span),
+			MalType::L(ListKind::List, list, _) if !list.is_empty() => {
+				return self.list(list, tail);
+			},
+			ast if is_constant(&ast) => self.push_constant(ast),
+			MalType::L(ListKind::Vector, items, _) => {
+				let len = items.len();
+				for item in items {
+					self.compile(item, false);
+				}
+				self.emit(Op::Vector(len));
+			},
+			MalType::HashMap(map, _) => {
+				for value in map.values() {
+					self.compile(value.clone(), false);
+				}
+				let i = self.constant(MalType::HashMap(map, None));
+				self.emit(Op::HashMap(i));
+			},
+			MalType::Set(set) => {
+				let len = set.len();
+				for item in set {
+					self.compile(item, false);
+				}
+				self.emit(Op::Set(len));
+			},
+			ast => self.push_constant(ast),
+		}
+		Ok(())
+	}
+
+	/// Compiles a lookup of `sym`, which first tries each local that `def!`
+	/// binds it to in turn.
+	fn symbol(&mut self, sym: String, span: Option<Rc<Span>>) {
+		let (defined, local) = self.resolve(&sym);
+		let defined = (!defined.is_empty()).then(|| {
+			let table = &mut self.function().proto.defined;
+			table.push(defined);
+			table.len() - 1
+		});
+		let op = match (local, defined) {
+			(Some((0, slot)), None) => Op::Load(slot),
+			(Some((depth, slot)), None) => Op::LoadOuter { depth, slot },
+			(Some((depth, slot)), Some(defined)) => Op::LoadDefinedOrLocal {
+				defined,
+				depth,
+				slot,
+			},
+			(None, defined) => {
+				let name = self.constant(MalType::Symbol(sym, span));
+				match defined {
+					Some(defined) => Op::LoadDefinedOrGlobal { defined, name },
+					None => Op::LoadGlobal(name),
+				}
+			},
+		};
+		self.emit(op);
+	}
+
+	fn list(&mut self, mut list: Vec<MalType>, tail: bool) -> Result<()> {
+		if let MalType::Symbol(sym, _) = &list[0] {
+			if self.interpreter.special_form(sym).is_some() {
+				let sym = sym.clone();
+				let args = list.split_off(1);
+				return self.special_form(&sym, args, tail);
+			}
+		}
+
+		let site = match &list[0] {
+			MalType::Symbol(sym, span) => Site {
+				name: Some(sym.clone()),
+				span: span.as_deref().cloned(),
+			},
+			_ => Site {
+				name: None,
+				span: None,
+			},
+		};
+		// a global may be a macro by the time the call's run
+		let expand = match &list[0] {
+			MalType::Symbol(sym, _) if self.resolve(sym).1.is_none() => {
+				Some(MalType::L(ListKind::List, list.clone(), None))
+			},
+			_ => None,
+		};
+		let argc = list.len() - 1;
+		let mut items = list.into_iter();
+		self.compile(items.next().unwrap(), false);
+		let expand = expand.map(|ast| {
+			let deferred = self.deferred(ast, tail);
+			self.emit(Op::ExpandIfMacro { deferred, to: 0 })
+		});
+		for item in items {
+			self.compile(item, false);
+		}
+		let sites = &mut self.function().proto.sites;
+		sites.push(site);
+		let site = sites.len() - 1;
+		self.emit(match tail {
+			true => Op::TailCall { argc, site },
+			false => Op::Call { argc, site },
+		});
+		if let Some(expand) = expand {
+			self.patch(expand);
+		}
+		Ok(())
+	}
+
+	fn special_form(
+		&mut self,
+		name: &str,
+		args: Vec<MalType>,
+		tail: bool,
+	) -> Result<()> {
+		if !STANDARD_FORMS.contains(&name) {
+			self.special(name, args);
+			return Ok(());
+		}
+		match name {
+			"def!" => {
+				let [MalType::Symbol(key, _), value] =
+					destructure("def!", args)?
+				else {
+					return Err(eyre!("can't `def!` a non-variable"));
+				};
+				self.compile(value, false);
+				let local = self.def_local(&key);
+				let i = self.constant(MalType::Symbol(key, None));
+				self.emit(match local {
+					Some((depth, slot)) => Op::DefLocal {
+						name: i,
+						depth,
+						slot,
+					},
+					None => Op::DefGlobal(i),
+				});
+			},
+			"defmacro!" => {
+				let [MalType::Symbol(key, _), value] =
+					destructure("defmacro!", args)?
+				else {
+					return Err(eyre!("can't `defmacro!` a non-variable"));
+				};
+				self.compile(value, false);
+				let i = self.constant(MalType::Symbol(key, None));
+				self.emit(Op::DefMacro(i));
+			},
+			"do" => self.body(args, tail),
+			"fn*" => self.fn_star(args)?,
+			"if" => {
+				let mut args = args.into_iter();
+				let (Some(cond), Some(then), els, None) =
+					(args.next(), args.next(), args.next(), args.next())
+				else {
+					return Err(eyre!("`if` expects 2 or 3 arguments"));
+				};
+				self.compile(cond, false);
+				let to_else = self.emit(Op::JumpIfFalse(0));
+				self.compile(then, tail);
+				let to_end = self.emit(Op::Jump(0));
+				self.patch(to_else);
+				self.compile(els.unwrap_or(MalType::Nil), tail);
+				self.patch(to_end);
+			},
+			"lazy-seq" => {
+				let proto = Proto::new(Vec::new(), MalType::Nil);
+				let i = self.nested(proto, args, true);
+				self.emit(Op::LazySeq(i));
+			},
+			"let*" => self.let_star(args, tail)?,
+			"quasiquote" => {
+				let [ast] = destructure("quasiquote", args)?;
+				self.compile(eval::quasiquote(ast), tail);
+			},
+			"quasiquoteexpand" => {
+				let [ast] = destructure("quasiquoteexpand", args)?;
+				self.push_constant(eval::quasiquote(ast));
+			},
+			"quote" => {
+				let [ast] = destructure("quote", args)?;
+				self.push_constant(ast);
+			},
+			"try*" => self.try_star(args, tail)?,
+			_ => unreachable!("`{name}` isn't a standard form"),
+		}
+		Ok(())
+	}
+
+	/// Compiles `forms` in turn, keeping the value of the last.
+	fn body(&mut self, forms: Vec<MalType>, tail: bool) {
+		let mut forms = forms.into_iter();
+		let Some(last) = forms.next_back() else {
+			self.push_constant(MalType::Nil);
+			return;
+		};
+		for form in forms {
+			self.compile(form, false);
+			self.emit(Op::Pop);
+		}
+		self.compile(last, tail);
+	}
+
+	/// Compiles a nested function, which `proto` has the params of, and
+	/// returns its index. Its body is an env of its own unless `enclosing` is
+	/// set, when `def!` in it binds in the env it's made in.
+	fn nested(
+		&mut self,
+		proto: Proto,
+		body: Vec<MalType>,
+		enclosing: bool,
+	) -> usize {
+		let mut function = Function::new(proto, enclosing);
+		let params = function.proto.params.clone();
+		for param in params.into_iter().filter(|param| param != "&") {
+			function.push_local(param, false);
+		}
+		self.functions.push(function);
+		match enclosing {
+			true => self.body(body, true),
+			false => self.env(0, |compiler| compiler.body(body, true)),
+		}
+		self.emit(Op::Return);
+		let proto = self.functions.pop().unwrap().proto;
+		let protos = &mut self.function().proto.protos;
+		protos.push(Rc::new(proto));
+		protos.len() - 1
+	}
+
+	fn fn_star(&mut self, args: Vec<MalType>) -> Result<()> {
+		let [MalType::L(_, params, _), body] = destructure("fn*", args)? else {
+			return Err(eyre!("invalid `fn*` form: expected parameter list"));
+		};
+		let mut params = params
+			.into_iter()
+			.map(|param_name| match param_name {
+				MalType::Symbol(sym, _) => Ok(sym),
+				_ => Err(eyre!(
+					"invalid `fn*` form: expected symbol for parameter name"
+				)),
+			})
+			.collect::<Result<Vec<_>>>()?;
+		// like the tree-walker, anything after the rest param is ignored
+		let arity = params.iter().position(|param| param == "&");
+		if let Some(arity) = arity {
+			if params.len() == arity + 1 {
+				return Err(eyre!(
+					"invalid `fn*` form: no variable binding for varargs"
+				));
+			}
+			params.truncate(arity + 2);
+		}
+		let mut proto = Proto::new(params, body.clone());
+		proto.arity = arity.unwrap_or(proto.params.len());
+		proto.variadic = arity.is_some();
+		let i = self.nested(proto, vec![body], false);
+		self.emit(Op::Closure(i));
+		Ok(())
+	}
+
+	/// A `let*` gives each name it binds one slot, however many times it binds
+	/// it, as the tree-walker binds them all in one env.
+	fn let_star(&mut self, args: Vec<MalType>, tail: bool) -> Result<()> {
+		let [MalType::L(_, bindings, _), body] = destructure("let*", args)?
+		else {
+			return Err(eyre!("invalid `let*` form: expected bindings"));
+		};
+		let mut pairs = Vec::with_capacity(bindings.len() / 2);
+		let mut bindings = bindings.into_iter();
+		while let Some(key) = bindings.next() {
+			let MalType::Symbol(key, _) = key else {
+				return Err(eyre!(
+					"invalid `let*` form: expected symbol for binding"
+				));
+			};
+			let Some(value) = bindings.next() else {
+				return Err(eyre!(
+					"invalid `let*` form: expected value to bind to variable \
+					 `{key}`"
+				));
+			};
+			pairs.push((key, value));
+		}
+
+		let locals = self.function().locals.len();
+		let mut slots = HashMap::new();
+		for (key, _) in &pairs {
+			if !slots.contains_key(key) {
+				slots.insert(key.clone(), self.bind(key.clone(), true));
+			}
+		}
+		self.env(locals, |compiler| {
+			for (key, value) in &pairs {
+				compiler.compile(value.clone(), false);
+				let slot = slots[key];
+				compiler.emit(Op::Store(slot));
+				let function = compiler.function();
+				let local = function.locals[locals..]
+					.iter_mut()
+					.find(|local| local.slot == slot)
+					.unwrap();
+				local.pending = false;
+			}
+			compiler.compile(body.clone(), tail);
+		});
+		Ok(())
+	}
+
+	fn try_star(&mut self, mut args: Vec<MalType>, tail: bool) -> Result<()> {
+		if args.len() == 1 {
+			self.compile(args.remove(0), tail);
+			return Ok(());
+		}
+		let [body, MalType::L(ListKind::List, catch, _)] =
+			destructure("try*", args)?
+		else {
+			return Err(eyre!("invalid `try*` form: expected `catch*` clause"));
+		};
+		let Ok(
+			[MalType::Symbol(catch_sym, _), MalType::Symbol(bind, _), handler],
+		) = <[MalType; 3]>::try_from(catch)
+		else {
+			return Err(eyre!(
+				"invalid `catch*` form: expected binding and handler"
+			));
+		};
+		if catch_sym != "catch*" {
+			return Err(eyre!("invalid `try*` form: expected `catch*` clause"));
+		}
+
+		let to_handler = self.emit(Op::Try(0));
+		self.compile(body, false);
+		self.emit(Op::EndTry);
+		let to_end = self.emit(Op::Jump(0));
+		self.patch(to_handler);
+		let locals = self.function().locals.len();
+		let bind = self.bind(bind, false);
+		let trace = self.bind("*stack-trace*".to_string(), false);
+		self.emit(Op::Store(trace));
+		self.emit(Op::Store(bind));
+		self.env(locals, |compiler| compiler.compile(handler.clone(), tail));
+		self.patch(to_end);
+		Ok(())
+	}
+
+	fn special(&mut self, name: &str, args: Vec<MalType>) {
+		let locals = self.locals();
+		let specials = &mut self.function().proto.specials;
+		specials.push(Special {
+			name: name.to_string(),
+			args,
+			locals,
+		});
+		let i = specials.len() - 1;
+		self.emit(Op::Special(i));
+	}
+
+	/// Records `ast` to be compiled afresh when it's run, and returns its
+	/// index.
+	fn deferred(&mut self, ast: MalType, tail: bool) -> usize {
+		let locals = self.locals();
+		let deferred = &mut self.function().proto.deferred;
+		deferred.push(Deferred { ast, locals, tail });
+		deferred.len() - 1
+	}
+
+	/// The name, depth and slot of each local in scope, outermost first,
+	/// including those that `def!` may yet bind.
+	fn locals(&self) -> Vec<(String, usize, Late)> {
+		let innermost = self.functions.len() - 1;
+		let mut locals = Vec::new();
+		for (i, function) in self.functions.iter().enumerate() {
+			let depth = innermost - i;
+			let bound = |locals: &mut Vec<_>, range: &[Local]| {
+				let range = range
+					.iter()
+					.filter(|local| !(local.pending && depth == 0))
+					.map(|local| {
+						let slot = Rc::new(Cell::new(Some(local.slot)));
+						(local.name.clone(), depth, slot)
+					});
+				locals.extend(range);
+			};
+			let mut start = 0;
+			for env in &function.envs {
+				bound(&mut locals, &function.locals[start..env.start]);
+				start = env.start;
+				let defs = env.defs.borrow();
+				locals.extend(
+					defs.iter().map(|(name, late)| {
+						(name.clone(), depth, late.clone())
+					}),
+				);
+			}
+			bound(&mut locals, &function.locals[start..]);
+		}
+		locals
+	}
+}
+
+/// Whether `ast` evaluates to itself.
+fn is_constant(ast: &MalType) -> bool {
+	match ast {
+		MalType::Symbol(..) => false,
+		MalType::L(ListKind::List, list, _) => list.is_empty(),
+		MalType::L(ListKind::Vector, items, _) => items.iter().all(is_constant),
+		MalType::HashMap(map, _) => map.values().all(is_constant),
+		MalType::Set(set) => set.iter().all(is_constant),
+		_ => true,
+	}
+}
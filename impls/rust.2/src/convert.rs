@@ -0,0 +1,425 @@
+//! Conversions between `MalType` and plain Rust types, so that ordinary Rust
+//! functions can be exposed to mal without matching on argument slices by
+//! hand:
+//!
+//! ```
+//! use rust2::env::Env;
+//!
+//! let env = Env::new(None);
+//! env.register_fn("repeat", |s: String, n: f64| Ok(s.repeat(n as usize)));
+//! ```
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use eyre::Result;
+use num_bigint::BigInt;
+use regex::Regex;
+
+use crate::number::Number;
+use crate::types::ListKind;
+use crate::types::MalType;
+
+/// A type that can be extracted from a mal value.
+pub trait FromMal: Sized {
+	/// What the value is expected to be, for error messages (e.g. "a
+	/// number").
+	const EXPECTED: &'static str;
+
+	fn from_mal(value: MalType) -> Result<Self>;
+}
+
+/// A type that can be turned into a mal value.
+pub trait IntoMal {
+	fn into_mal(self) -> MalType;
+}
+
+/// A Rust function that can be turned into a mal builtin. This is implemented
+/// for functions of up to 6 arguments that implement `FromMal`, returning a
+/// `Result` of something that implements `IntoMal`.
+pub trait IntoFunction<Args> {
+	/// `name` is only used in error messages.
+	fn into_function(self, name: String) -> MalType;
+}
+
+fn mismatch<T: FromMal>(value: &MalType) -> eyre::Report {
+	eyre!("expected {} (got {value:#})", T::EXPECTED)
+}
+
+impl FromMal for MalType {
+	const EXPECTED: &'static str = "a value";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		Ok(value)
+	}
+}
+
+impl FromMal for bool {
+	const EXPECTED: &'static str = "a boolean";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::Bool(b) => Ok(b),
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+impl FromMal for Number {
+	const EXPECTED: &'static str = "a number";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::Number(num) => Ok(num),
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+impl FromMal for f64 {
+	const EXPECTED: &'static str = "a number";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::Number(num) => Ok(num.to_f64()),
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+macro_rules! from_mal_int {
+	($($int:ty),*) => {$(
+		impl FromMal for $int {
+			const EXPECTED: &'static str = "an integer";
+
+			fn from_mal(value: MalType) -> Result<Self> {
+				let int = match &value {
+					MalType::Number(Number::Int(int)) => (*int).try_into().ok(),
+					MalType::Number(Number::BigInt(int)) => int.try_into().ok(),
+					MalType::Number(Number::Float(num))
+						if num.fract() == 0.0
+							&& *num >= <$int>::MIN as f64
+							&& *num <= <$int>::MAX as f64 =>
+					{
+						Some(*num as $int)
+					},
+					_ => None,
+				};
+				int.ok_or_else(|| mismatch::<Self>(&value))
+			}
+		}
+	)*};
+}
+
+from_mal_int!(i32, i64, u32, u64, usize);
+
+impl FromMal for String {
+	const EXPECTED: &'static str = "a string";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::String(string) => Ok(string),
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+impl FromMal for char {
+	const EXPECTED: &'static str = "a char";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::Char(ch) => Ok(ch),
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+impl FromMal for Rc<Regex> {
+	const EXPECTED: &'static str = "a regex";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::Regex(regex) => Ok(regex),
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+impl<T: FromMal> FromMal for Option<T> {
+	const EXPECTED: &'static str = T::EXPECTED;
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::Nil => Ok(None),
+			value => T::from_mal(value).map(Some),
+		}
+	}
+}
+
+impl<T: FromMal> FromMal for Vec<T> {
+	const EXPECTED: &'static str = "a list";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::L(_, list, _) => {
+				list.into_iter().map(T::from_mal).collect()
+			},
+			value @ MalType::LazySeq(_) => {
+				value.into_items().map(|item| T::from_mal(item?)).collect()
+			},
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+impl<K: FromMal + Eq + Hash, V: FromMal> FromMal for HashMap<K, V> {
+	const EXPECTED: &'static str = "a hashmap";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::HashMap(map, _) => map
+				.into_iter()
+				.map(|(key, value)| {
+					Ok((K::from_mal(key)?, V::from_mal(value)?))
+				})
+				.collect(),
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+impl<T: FromMal + Eq + Hash> FromMal for HashSet<T> {
+	const EXPECTED: &'static str = "a set";
+
+	fn from_mal(value: MalType) -> Result<Self> {
+		match value {
+			MalType::Set(set) => set.into_iter().map(T::from_mal).collect(),
+			value => Err(mismatch::<Self>(&value)),
+		}
+	}
+}
+
+impl IntoMal for MalType {
+	fn into_mal(self) -> MalType {
+		self
+	}
+}
+
+impl IntoMal for () {
+	fn into_mal(self) -> MalType {
+		MalType::Nil
+	}
+}
+
+impl IntoMal for bool {
+	fn into_mal(self) -> MalType {
+		MalType::Bool(self)
+	}
+}
+
+impl IntoMal for Number {
+	fn into_mal(self) -> MalType {
+		MalType::Number(self)
+	}
+}
+
+impl IntoMal for f64 {
+	fn into_mal(self) -> MalType {
+		MalType::Number(Number::Float(self))
+	}
+}
+
+macro_rules! into_mal_int {
+	($($int:ty),*) => {$(
+		impl IntoMal for $int {
+			fn into_mal(self) -> MalType {
+				MalType::Number(match i64::try_from(self) {
+					Ok(int) => Number::Int(int),
+					Err(_) => BigInt::from(self).into(),
+				})
+			}
+		}
+	)*};
+}
+
+into_mal_int!(i32, i64, u32, u64, usize);
+
+impl IntoMal for String {
+	fn into_mal(self) -> MalType {
+		MalType::String(self)
+	}
+}
+
+impl IntoMal for &str {
+	fn into_mal(self) -> MalType {
+		MalType::String(self.to_string())
+	}
+}
+
+impl IntoMal for char {
+	fn into_mal(self) -> MalType {
+		MalType::Char(self)
+	}
+}
+
+impl IntoMal for Rc<Regex> {
+	fn into_mal(self) -> MalType {
+		MalType::Regex(self)
+	}
+}
+
+impl<T: IntoMal> IntoMal for Option<T> {
+	fn into_mal(self) -> MalType {
+		self.map_or(MalType::Nil, T::into_mal)
+	}
+}
+
+impl<T: IntoMal> IntoMal for Vec<T> {
+	fn into_mal(self) -> MalType {
+		MalType::L(
+			ListKind::List,
+			self.into_iter().map(T::into_mal).collect(),
+			None,
+		)
+	}
+}
+
+impl<K: IntoMal, V: IntoMal> IntoMal for HashMap<K, V> {
+	fn into_mal(self) -> MalType {
+		MalType::HashMap(
+			self.into_iter()
+				.map(|(key, value)| (key.into_mal(), value.into_mal()))
+				.collect(),
+			None,
+		)
+	}
+}
+
+impl<T: IntoMal> IntoMal for HashSet<T> {
+	fn into_mal(self) -> MalType {
+		MalType::Set(self.into_iter().map(T::into_mal).collect())
+	}
+}
+
+macro_rules! into_function {
+	($n:literal $(, $arg:ident $idx:literal)*) => {
+		impl<Fun, Ret $(, $arg)*> IntoFunction<($($arg,)*)> for Fun
+		where
+			Fun: Fn($($arg),*) -> Result<Ret> + 'static,
+			Ret: IntoMal,
+			$($arg: FromMal,)*
+		{
+			#[allow(non_snake_case)]
+			fn into_function(self, name: String) -> MalType {
+				MalType::builtin(move |args| {
+					let [$($arg),*] = args else {
+						return Err(eyre!(
+							"`{name}` expects {} args: {} were provided",
+							$n,
+							args.len(),
+						));
+					};
+					$(
+						let $arg = $arg::from_mal($arg.clone()).map_err(|err| {
+							eyre!("`{name}`, argument {}: {err}", $idx)
+						})?;
+					)*
+					self($($arg),*).map(IntoMal::into_mal)
+				})
+			}
+		}
+	};
+}
+
+into_function!(0);
+into_function!(1, A1 1);
+into_function!(2, A1 1, A2 2);
+into_function!(3, A1 1, A2 2, A3 3);
+into_function!(4, A1 1, A2 2, A3 3, A4 4);
+into_function!(5, A1 1, A2 2, A3 3, A4 4, A5 5);
+into_function!(6, A1 1, A2 2, A3 3, A4 4, A5 5, A6 6);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::env::Env;
+
+	fn call(env: &Env, name: &str, args: &mut [MalType]) -> Result<MalType> {
+		env.get(name).unwrap().call(args)
+	}
+
+	#[test]
+	fn test_register_fn() -> Result<()> {
+		let env = Env::new(None);
+		env.register_fn("split", |s: String, sep: String| {
+			Ok(s.split(&sep).map(str::to_string).collect::<Vec<_>>())
+		});
+		env.register_fn("nth-or", |v: Vec<f64>, n: usize, d: Option<f64>| {
+			Ok(v.get(n).copied().or(d))
+		});
+
+		assert_eq!(
+			call(&env, "split", &mut [
+				MalType::String("a,b".to_string()),
+				MalType::String(",".to_string())
+			])?,
+			MalType::L(
+				ListKind::List,
+				vec![
+					MalType::String("a".to_string()),
+					MalType::String("b".to_string())
+				],
+				None,
+			)
+		);
+		assert_eq!(
+			call(&env, "nth-or", &mut [
+				MalType::L(
+					ListKind::Vector,
+					vec![MalType::Number(Number::Float(1.0))],
+					None,
+				),
+				MalType::Number(Number::Int(3)),
+				MalType::Nil,
+			])?,
+			MalType::Nil
+		);
+
+		let err = call(&env, "split", &mut [MalType::Nil]).unwrap_err();
+		assert_eq!(err.to_string(), "`split` expects 2 args: 1 were provided");
+
+		let err = call(&env, "nth-or", &mut [
+			MalType::L(ListKind::List, vec![], None),
+			MalType::Number(Number::Float(1.5)),
+			MalType::Nil,
+		])
+		.unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"`nth-or`, argument 2: expected an integer (got 1.5)"
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_register_value() {
+		let env = Env::new(None);
+		env.register_value("answer", 42);
+		env.register_value("names", vec!["a", "b"]);
+		assert_eq!(env.get("answer"), Some(MalType::Number(Number::Int(42))));
+		assert_eq!(
+			env.get("names"),
+			Some(MalType::L(
+				ListKind::List,
+				vec![
+					MalType::String("a".to_string()),
+					MalType::String("b".to_string())
+				],
+				None,
+			))
+		);
+	}
+}
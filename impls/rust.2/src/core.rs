@@ -1,198 +1,1770 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::io::stdin;
 use std::io::stdout;
-use std::io::IoSlice;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Lines;
 use std::io::Write;
+use std::mem;
 use std::rc::Rc;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+use crate::convert::FromMal;
+use crate::convert::IntoMal;
+use crate::edn;
 use crate::env::Env;
+use crate::json;
+use crate::lazy::Lazy;
+use crate::map::Comparator;
+use crate::map::MalMap;
+use crate::number::Number;
+use crate::re;
+use crate::reader;
+use crate::string;
 use crate::types::ListKind;
+use crate::types::MalException;
 use crate::types::MalType;
 
 pub fn ns() -> Env {
 	let bindings = [
 		(
-			"+",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::Number(l), MalType::Number(r)] => {
-					Ok(MalType::Number(*l + *r))
+			"list",
+			MalType::builtin(|args| {
+				Ok(MalType::L(ListKind::List, args.to_vec(), None))
+			}),
+		),
+		(
+			"list?",
+			MalType::builtin(|args| match args {
+				[MalType::L(ListKind::List, ..), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"empty?",
+			MalType::builtin(|args| match args {
+				[MalType::L(_, list, _), ..] => {
+					Ok(MalType::Bool(list.is_empty()))
+				},
+				[MalType::HashMap(map, _), ..] => {
+					Ok(MalType::Bool(map.is_empty()))
+				},
+				[MalType::Set(set), ..] => Ok(MalType::Bool(set.is_empty())),
+				[MalType::LazySeq(lazy), ..] => {
+					Ok(MalType::Bool(lazy.realize()?.is_none()))
+				},
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"count",
+			MalType::builtin(|args| match args {
+				[MalType::L(_, list, _), ..] => {
+					Ok(MalType::Number(Number::Int(list.len() as _)))
+				},
+				[MalType::HashMap(map, _), ..] => {
+					Ok(MalType::Number(Number::Int(map.len() as _)))
+				},
+				[MalType::Set(set), ..] => {
+					Ok(MalType::Number(Number::Int(set.len() as _)))
+				},
+				// strings are counted in chars, as they're indexed
+				[MalType::String(string), ..] => Ok(MalType::Number(
+					Number::Int(string.chars().count() as _),
+				)),
+				[seq @ MalType::LazySeq(_), ..] => {
+					let count = seq
+						.items()
+						.try_fold(0, |count, item| item.map(|_| count + 1))?;
+					Ok(MalType::Number(Number::Int(count)))
+				},
+				_ => Ok(MalType::Number(Number::Int(0))),
+			}),
+		),
+		(
+			"=",
+			MalType::builtin(|args| match args {
+				[l, r] => Ok(MalType::Bool(l == r)),
+				_ => Err(eyre!("`=` expects 2 parameters")),
+			}),
+		),
+		(
+			"pr-str",
+			MalType::builtin(|args| {
+				args.iter().try_for_each(MalType::realize)?;
+				Ok(MalType::String(MalType::pr_list::<true>(
+					args.iter(),
+					"",
+					"",
+					" ",
+				)))
+			}),
+		),
+		(
+			"str",
+			MalType::builtin(|args| {
+				args.iter().try_for_each(MalType::realize)?;
+				Ok(MalType::String(MalType::pr_list::<false>(
+					args.iter(),
+					"",
+					"",
+					"",
+				)))
+			}),
+		),
+		(
+			"prn",
+			MalType::builtin(|args| {
+				args.iter().try_for_each(MalType::realize)?;
+				writeln!(
+					stdout(),
+					"{}",
+					MalType::pr_list::<true>(args.iter(), "", "", " ")
+				)?;
+				Ok(MalType::Nil)
+			}),
+		),
+		(
+			"println",
+			MalType::builtin(|args| {
+				args.iter().try_for_each(MalType::realize)?;
+				writeln!(
+					stdout(),
+					"{}",
+					MalType::pr_list::<false>(args.iter(), "", "", " ")
+				)?;
+				Ok(MalType::Nil)
+			}),
+		),
+		(
+			"atom",
+			MalType::builtin(|args| match args {
+				[value] => {
+					Ok(MalType::Atom(Rc::new(RefCell::new(value.clone()))))
+				},
+				_ => Err(eyre!("`atom` expects 1 parameter")),
+			}),
+		),
+		(
+			"atom?",
+			MalType::builtin(|args| match args {
+				[MalType::Atom(_), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"deref",
+			MalType::builtin(|args| match args {
+				[MalType::Atom(atom)] => Ok(atom.borrow().clone()),
+				[MalType::Reduced(value)] => Ok((**value).clone()),
+				_ => Err(eyre!("`deref` expects an atom")),
+			}),
+		),
+		(
+			"reset!",
+			MalType::builtin(|args| match args {
+				[MalType::Atom(atom), value] => {
+					atom.replace(value.clone());
+					Ok(value.clone())
+				},
+				_ => Err(eyre!("`reset!` expects an atom and a value")),
+			}),
+		),
+		(
+			"swap!",
+			MalType::builtin(|args| match args {
+				[MalType::Atom(atom), function, rest @ ..] => {
+					let mut fargs = Vec::with_capacity(rest.len() + 1);
+					fargs.push(atom.borrow().clone());
+					fargs.extend(rest.iter().cloned());
+					let value = function.call(&mut fargs)?;
+					atom.replace(value.clone());
+					Ok(value)
+				},
+				_ => Err(eyre!("`swap!` expects an atom and a function")),
+			}),
+		),
+		(
+			"cons",
+			MalType::builtin(|args| match args {
+				[head, MalType::L(_, tail, _)] => {
+					let mut list = Vec::with_capacity(tail.len() + 1);
+					list.push(head.clone());
+					list.extend(tail.iter().cloned());
+					Ok(MalType::L(ListKind::List, list, None))
+				},
+				[head, MalType::Nil] => {
+					Ok(MalType::L(ListKind::List, vec![head.clone()], None))
+				},
+				[head, tail @ MalType::LazySeq(_)] => {
+					Ok(Lazy::cons(head.clone(), tail.clone()))
+				},
+				_ => Err(eyre!("`cons` expects a value and a list")),
+			}),
+		),
+		(
+			"concat",
+			MalType::builtin(|args| {
+				let mut res = Vec::new();
+				for arg in args.iter() {
+					match arg {
+						MalType::L(_, list, _) => {
+							res.extend(list.iter().cloned())
+						},
+						MalType::LazySeq(_) => {
+							for item in arg.items() {
+								res.push(item?);
+							}
+						},
+						MalType::Nil => {},
+						_ => return Err(eyre!("`concat` expects lists")),
+					}
+				}
+				Ok(MalType::L(ListKind::List, res, None))
+			}),
+		),
+		(
+			"vec",
+			MalType::builtin(|args| match args {
+				[MalType::L(_, list, _)] => {
+					Ok(MalType::L(ListKind::Vector, list.clone(), None))
+				},
+				[MalType::Nil] => {
+					Ok(MalType::L(ListKind::Vector, Vec::new(), None))
 				},
-				[_, _] => Err(eyre!("`+` expects 2 numbers")),
-				args => Err(eyre!(
-					"`+` expects 2 args: {} were provided",
-					args.len()
+				[seq @ MalType::LazySeq(_)] => Ok(MalType::L(
+					ListKind::Vector,
+					seq.items().collect::<eyre::Result<_>>()?,
+					None,
 				)),
-			})),
+				_ => Err(eyre!("`vec` expects a list")),
+			}),
 		),
 		(
-			"-",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::Number(l), MalType::Number(r)] => {
-					Ok(MalType::Number(*l - *r))
+			"nth",
+			MalType::builtin(|args| match args {
+				[MalType::L(_, list, _), MalType::Number(Number::Int(idx))] => {
+					usize::try_from(*idx)
+						.ok()
+						.and_then(|idx| list.get(idx))
+						.cloned()
+						.ok_or_else(|| eyre!("`nth`: index out of range"))
 				},
-				[_, _] => Err(eyre!("`-` expects 2 numbers")),
-				args => Err(eyre!(
-					"`-` expects 2 args: {} were provided",
-					args.len()
+				[seq @ MalType::LazySeq(_), MalType::Number(Number::Int(idx))] => {
+					usize::try_from(*idx)
+						.ok()
+						.and_then(|idx| seq.items().nth(idx))
+						.transpose()?
+						.ok_or_else(|| eyre!("`nth`: index out of range"))
+				},
+				[MalType::String(string), MalType::Number(Number::Int(idx))] => {
+					usize::try_from(*idx)
+						.ok()
+						.and_then(|idx| string.chars().nth(idx))
+						.map(MalType::Char)
+						.ok_or_else(|| eyre!("`nth`: index out of range"))
+				},
+				_ => Err(eyre!("`nth` expects a list and an index")),
+			}),
+		),
+		(
+			"first",
+			MalType::builtin(|args| match args {
+				[MalType::L(_, list, _)] => {
+					Ok(list.first().cloned().unwrap_or(MalType::Nil))
+				},
+				[MalType::LazySeq(lazy)] => {
+					Ok(lazy.realize()?.map_or(MalType::Nil, |(first, _)| first))
+				},
+				[MalType::Nil] => Ok(MalType::Nil),
+				_ => Err(eyre!("`first` expects a list")),
+			}),
+		),
+		(
+			"rest",
+			MalType::builtin(|args| match args {
+				[MalType::L(_, list, _)] => Ok(MalType::L(
+					ListKind::List,
+					list.iter().skip(1).cloned().collect(),
+					None,
 				)),
-			})),
+				[MalType::LazySeq(lazy)] => match lazy.realize()? {
+					Some((_, MalType::Nil)) | None => {
+						Ok(MalType::L(ListKind::List, Vec::new(), None))
+					},
+					Some((_, rest)) => Ok(rest),
+				},
+				[MalType::Nil] => {
+					Ok(MalType::L(ListKind::List, Vec::new(), None))
+				},
+				_ => Err(eyre!("`rest` expects a list")),
+			}),
+		),
+		(
+			"throw",
+			MalType::builtin(|args| match args {
+				[value] => Err(MalException::new(value.clone()).into()),
+				_ => Err(eyre!("`throw` expects 1 parameter")),
+			}),
+		),
+		(
+			"apply",
+			MalType::builtin(|args| match args {
+				[function, middle @ .., MalType::L(_, last, _)] => {
+					let mut fargs = middle.to_vec();
+					fargs.extend(last.iter().cloned());
+					function.call(&mut fargs)
+				},
+				[function, middle @ .., last @ MalType::LazySeq(_)] => {
+					let mut fargs = middle.to_vec();
+					for item in last.items() {
+						fargs.push(item?);
+					}
+					function.call(&mut fargs)
+				},
+				_ => Err(eyre!("`apply` expects a function and a list")),
+			}),
 		),
+		// `map`, `filter`, `remove`, `keep`, `mapcat`, `partition`, `take` and
+		// `drop` are lazy when they're given a lazy seq, and otherwise return
+		// a list right away, so that errors are raised where the call is
+		// made. Given just a function, the first five return a transducer.
 		(
-			"*",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::Number(l), MalType::Number(r)] => {
-					Ok(MalType::Number(*l * *r))
+			"map",
+			MalType::builtin(|args| match args {
+				[function] => Ok(transducer(
+					function.clone(),
+					|function, rf, acc, item| {
+						rf.call(&mut [acc, function.call(&mut [item])?])
+					},
+				)),
+				[function, seq @ MalType::LazySeq(_)] => {
+					Ok(lazy_map(function.clone(), seq.clone()))
 				},
-				[_, _] => Err(eyre!("`*` expects 2 numbers")),
-				args => Err(eyre!(
-					"`*` expects 2 args: {} were provided",
-					args.len()
+				[function, coll] => Ok(MalType::L(
+					ListKind::List,
+					coll_items("map", coll)?
+						.map(|item| function.call(&mut [item?]))
+						.collect::<Result<Vec<_>, _>>()?,
+					None,
 				)),
-			})),
+				_ => Err(eyre!("`map` expects a function and a collection")),
+			}),
 		),
 		(
-			"/",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::Number(l), MalType::Number(r)] => {
-					Ok(MalType::Number(*l / *r))
+			"filter",
+			MalType::builtin(|args| {
+				keep_items("filter", args, |item, res| {
+					truthy(&res).then_some(item)
+				})
+			}),
+		),
+		(
+			"remove",
+			MalType::builtin(|args| {
+				keep_items("remove", args, |item, res| {
+					(!truthy(&res)).then_some(item)
+				})
+			}),
+		),
+		(
+			"keep",
+			MalType::builtin(|args| {
+				keep_items("keep", args, |_, res| match res {
+					MalType::Nil => None,
+					res => Some(res),
+				})
+			}),
+		),
+		(
+			"mapcat",
+			MalType::builtin(|args| match args {
+				[function] => Ok(transducer(
+					function.clone(),
+					|function, rf, mut acc, item| {
+						let coll = function.call(&mut [item])?;
+						for item in coll_items("mapcat", &coll)? {
+							acc = rf.call(&mut [acc, item?])?;
+							if let MalType::Reduced(_) = acc {
+								break;
+							}
+						}
+						Ok(acc)
+					},
+				)),
+				[function, seq @ MalType::LazySeq(_)] => {
+					Ok(lazy_mapcat(function.clone(), seq.clone()))
+				},
+				[function, coll] => {
+					let mut res = Vec::new();
+					for item in coll_items("mapcat", coll)? {
+						let coll = function.call(&mut [item?])?;
+						for item in coll_items("mapcat", &coll)? {
+							res.push(item?);
+						}
+					}
+					Ok(MalType::L(ListKind::List, res, None))
 				},
-				[_, _] => Err(eyre!("`/` expects 2 numbers")),
-				args => Err(eyre!(
-					"`/` expects 2 args: {} were provided",
-					args.len()
+				_ => Err(eyre!("`mapcat` expects a function and a collection")),
+			}),
+		),
+		(
+			"partition",
+			MalType::builtin(|args| {
+				let (n, step, pad, coll) = match &*args {
+					[n, coll] => (n, n, None, coll),
+					[n, step, coll] => (n, step, None, coll),
+					[n, step, pad, coll] => (n, step, Some(pad), coll),
+					_ => {
+						return Err(eyre!(
+							"`partition` expects a size, an optional step and \
+							 padding, and a collection"
+						))
+					},
+				};
+				let (n, step) = match (n, step) {
+					(
+						MalType::Number(Number::Int(n @ 1..)),
+						MalType::Number(Number::Int(step @ 1..)),
+					) => (*n as usize, *step as usize),
+					_ => {
+						return Err(eyre!(
+							"`partition` expects a positive size and step"
+						))
+					},
+				};
+				match coll {
+					MalType::LazySeq(_) => {
+						Ok(lazy_partition(n, step, pad.cloned(), coll.clone()))
+					},
+					coll => partition(n, step, pad, coll),
+				}
+			}),
+		),
+		(
+			"reduce",
+			MalType::builtin(|args| match args {
+				[function, coll] => {
+					let mut items = coll_items("reduce", coll)?;
+					match items.next() {
+						Some(init) => reduce_items(function, init?, items),
+						None => function.call(&mut []),
+					}
+				},
+				[function, init, coll] => reduce_items(
+					function,
+					init.clone(),
+					coll_items("reduce", coll)?,
+				),
+				_ => Err(eyre!(
+					"`reduce` expects a function, an optional initial value \
+					 and a collection"
 				)),
-			})),
+			}),
 		),
 		(
-			"list",
-			MalType::Function(Rc::new(|args| {
+			"reduce-kv",
+			MalType::builtin(|args| match args {
+				[function, init, coll] => {
+					let entries: Box<dyn Iterator<Item = (MalType, MalType)>> =
+						match coll {
+							MalType::HashMap(map, _) => {
+								Box::new(map.iter().map(|(key, value)| {
+									(key.clone(), value.clone())
+								}))
+							},
+							MalType::L(ListKind::Vector, list, _) => Box::new(
+								list.iter().enumerate().map(|(idx, item)| {
+									(
+										MalType::Number(Number::Int(idx as _)),
+										item.clone(),
+									)
+								}),
+							),
+							MalType::Nil => Box::new(std::iter::empty()),
+							coll => {
+								return Err(eyre!(
+									"`reduce-kv` expects a hashmap or vector \
+									 (got {coll:#})"
+								))
+							},
+						};
+					let mut acc = init.clone();
+					for (key, value) in entries {
+						acc = function.call(&mut [acc, key, value])?;
+						if let MalType::Reduced(value) = acc {
+							return Ok(*value);
+						}
+					}
+					Ok(acc)
+				},
+				_ => Err(eyre!(
+					"`reduce-kv` expects a function, an initial value and a \
+					 collection"
+				)),
+			}),
+		),
+		(
+			"reduced",
+			MalType::builtin(|args| match args {
+				[value] => Ok(MalType::Reduced(Box::new(value.clone()))),
+				_ => Err(eyre!("`reduced` expects 1 parameter")),
+			}),
+		),
+		(
+			"reduced?",
+			MalType::builtin(|args| match args {
+				[MalType::Reduced(_), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"transduce",
+			MalType::builtin(|args| match args {
+				[xform, function, coll] => {
+					let init = function.call(&mut [])?;
+					transduce(xform, function.clone(), init, coll)
+				},
+				[xform, function, init, coll] => {
+					transduce(xform, function.clone(), init.clone(), coll)
+				},
+				_ => Err(eyre!(
+					"`transduce` expects a transducer, a reducing function, \
+					 an optional initial value and a collection"
+				)),
+			}),
+		),
+		(
+			"into",
+			MalType::builtin(|args| match args {
+				[] => Ok(MalType::L(ListKind::Vector, Vec::new(), None)),
+				[to] => Ok(to.clone()),
+				[to, from] => reduce_items(
+					&MalType::builtin(conj_builtin),
+					to.clone(),
+					coll_items("into", from)?,
+				),
+				[to, xform, from] => transduce(
+					xform,
+					MalType::builtin(conj_builtin),
+					to.clone(),
+					from,
+				),
+				_ => Err(eyre!(
+					"`into` expects a collection, an optional transducer and \
+					 a collection"
+				)),
+			}),
+		),
+		(
+			"group-by",
+			MalType::builtin(|args| match args {
+				[function, coll] => {
+					let mut idxs = HashMap::<MalType, usize>::new();
+					let mut groups = Vec::<(MalType, Vec<MalType>)>::new();
+					for item in coll_items("group-by", coll)? {
+						let item = item?;
+						let key = function.call(&mut [item.clone()])?;
+						match idxs.get(&key) {
+							Some(&idx) => groups[idx].1.push(item),
+							None => {
+								idxs.insert(key.clone(), groups.len());
+								groups.push((key, vec![item]));
+							},
+						}
+					}
+					Ok(MalType::HashMap(
+						groups
+							.into_iter()
+							.map(|(key, group)| {
+								(key, MalType::L(ListKind::Vector, group, None))
+							})
+							.collect(),
+						None,
+					))
+				},
+				_ => {
+					Err(eyre!("`group-by` expects a function and a collection"))
+				},
+			}),
+		),
+		(
+			"frequencies",
+			MalType::builtin(|args| match args {
+				[coll] => {
+					let mut idxs = HashMap::<MalType, usize>::new();
+					let mut counts = Vec::<(MalType, i64)>::new();
+					for item in coll_items("frequencies", coll)? {
+						let item = item?;
+						match idxs.get(&item) {
+							Some(&idx) => counts[idx].1 += 1,
+							None => {
+								idxs.insert(item.clone(), counts.len());
+								counts.push((item, 1));
+							},
+						}
+					}
+					Ok(MalType::HashMap(
+						counts
+							.into_iter()
+							.map(|(item, count)| {
+								(item, MalType::Number(Number::Int(count)))
+							})
+							.collect(),
+						None,
+					))
+				},
+				_ => Err(eyre!("`frequencies` expects a collection")),
+			}),
+		),
+		(
+			"sort",
+			MalType::builtin(|args| {
+				let (cmp, coll) = match args {
+					[coll] => (Comparator::new(None), coll),
+					[cmp, coll] => (Comparator::new(Some(cmp.clone())), coll),
+					_ => {
+						return Err(eyre!(
+							"`sort` expects an optional comparator and a \
+							 collection"
+						))
+					},
+				};
+				let items =
+					coll_items("sort", coll)?.collect::<eyre::Result<_>>()?;
+				let sorted = merge_sort(items, &|l, r| cmp.compare(l, r))?;
+				Ok(MalType::L(ListKind::List, sorted, None))
+			}),
+		),
+		(
+			"sort-by",
+			MalType::builtin(|args| {
+				let (keyfn, cmp, coll) = match args {
+					[keyfn, coll] => (keyfn, Comparator::new(None), coll),
+					[keyfn, cmp, coll] => {
+						(keyfn, Comparator::new(Some(cmp.clone())), coll)
+					},
+					_ => {
+						return Err(eyre!(
+							"`sort-by` expects a key function, an optional \
+							 comparator and a collection"
+						))
+					},
+				};
+				// each key is only computed once
+				let mut keyed = Vec::new();
+				for item in coll_items("sort-by", coll)? {
+					let item = item?;
+					keyed.push((keyfn.call(&mut [item.clone()])?, item));
+				}
+				let sorted =
+					merge_sort(keyed, &|l, r| cmp.compare(&l.0, &r.0))?;
 				Ok(MalType::L(
 					ListKind::List,
-					args.into_iter().map(|arg| arg.clone()).collect(),
+					sorted.into_iter().map(|(_, item)| item).collect(),
+					None,
 				))
-			})),
+			}),
 		),
 		(
-			"list?",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::L(ListKind::List, ..), ..] => Ok(MalType::Bool(true)),
+			"range",
+			MalType::builtin(|args| {
+				let mut nums = args.iter().map(|arg| match arg {
+					MalType::Number(num) => Ok(num.clone()),
+					arg => Err(eyre!("`range` expects numbers (got {arg:#})")),
+				});
+				let (start, end, step) = match args.len() {
+					0 => (Number::Int(0), None, Number::Int(1)),
+					1 => (
+						Number::Int(0),
+						Some(nums.next().unwrap()?),
+						Number::Int(1),
+					),
+					2 => (
+						nums.next().unwrap()?,
+						Some(nums.next().unwrap()?),
+						Number::Int(1),
+					),
+					3 => (
+						nums.next().unwrap()?,
+						Some(nums.next().unwrap()?),
+						nums.next().unwrap()?,
+					),
+					len => {
+						return Err(eyre!(
+							"`range` expects at most 3 args: {len} were \
+							 provided"
+						))
+					},
+				};
+				let towards = match step.compare(&Number::Int(0)) {
+					Some(Ordering::Less) => Ordering::Greater,
+					_ => Ordering::Less,
+				};
+				Ok(lazy_range(start, end, step, towards))
+			}),
+		),
+		(
+			"repeat",
+			MalType::builtin(|args| match args {
+				[value] => Ok(lazy_repeat(value.clone())),
+				[MalType::Number(Number::Int(count)), value] => {
+					Ok(lazy_take(*count, lazy_repeat(value.clone())))
+				},
+				_ => {
+					Err(eyre!("`repeat` expects an optional count and a value"))
+				},
+			}),
+		),
+		(
+			"nil?",
+			MalType::builtin(|args| match args {
+				[MalType::Nil, ..] => Ok(MalType::Bool(true)),
 				_ => Ok(MalType::Bool(false)),
-			})),
+			}),
 		),
 		(
-			"empty?",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::L(_, list), ..] => Ok(MalType::Bool(list.is_empty())),
-				[MalType::HashMap(map), ..] => {
-					Ok(MalType::Bool(map.is_empty()))
+			"true?",
+			MalType::builtin(|args| match args {
+				[MalType::Bool(true), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"false?",
+			MalType::builtin(|args| match args {
+				[MalType::Bool(false), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"symbol?",
+			MalType::builtin(|args| match args {
+				[MalType::Symbol(..), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"keyword",
+			MalType::builtin(|args| match args {
+				[MalType::String(name) | MalType::Keyword(name)] => {
+					Ok(MalType::Keyword(name.clone()))
 				},
+				_ => Err(eyre!("`keyword` expects a string")),
+			}),
+		),
+		(
+			"keyword?",
+			MalType::builtin(|args| match args {
+				[MalType::Keyword(_), ..] => Ok(MalType::Bool(true)),
 				_ => Ok(MalType::Bool(false)),
-			})),
+			}),
 		),
 		(
-			"count",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::L(_, list), ..] => {
-					Ok(MalType::Number(list.len() as _))
+			"vector",
+			MalType::builtin(|args| {
+				Ok(MalType::L(ListKind::Vector, args.to_vec(), None))
+			}),
+		),
+		(
+			"vector?",
+			MalType::builtin(|args| match args {
+				[MalType::L(ListKind::Vector, ..), ..] => {
+					Ok(MalType::Bool(true))
 				},
-				[MalType::HashMap(map), ..] => {
-					Ok(MalType::Number((map.len() / 2) as _))
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"sequential?",
+			MalType::builtin(|args| match args {
+				[MalType::L(..) | MalType::LazySeq(_), ..] => {
+					Ok(MalType::Bool(true))
 				},
-				_ => Ok(MalType::Number(0 as _)),
-			})),
+				_ => Ok(MalType::Bool(false)),
+			}),
 		),
 		(
-			"=",
-			MalType::Function(Rc::new(|args| match args {
-				[l, r] => Ok(MalType::Bool(l == r)),
-				_ => Err(eyre!("`=` expects 2 parameters")),
-			})),
+			"hash-map",
+			MalType::builtin(|args| assoc(MalMap::new(), args)),
+		),
+		(
+			"sorted-map",
+			MalType::builtin(|args| assoc(MalMap::sorted(None), args)),
 		),
 		(
-			"<",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::Number(l), MalType::Number(r)] => {
-					Ok(MalType::Bool(l < r))
+			"sorted-map-by",
+			MalType::builtin(|args| match args {
+				[comparator, rest @ ..] => {
+					assoc(MalMap::sorted(Some(comparator.clone())), rest)
 				},
-				_ => Err(eyre!("`<` expects 2 numbers")),
-			})),
+				[] => Err(eyre!("`sorted-map-by` expects a comparator")),
+			}),
 		),
 		(
-			"<=",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::Number(l), MalType::Number(r)] => {
-					Ok(MalType::Bool(l <= r))
+			"map?",
+			MalType::builtin(|args| match args {
+				[MalType::HashMap(..), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"set?",
+			MalType::builtin(|args| match args {
+				[MalType::Set(_), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"assoc",
+			MalType::builtin(|args| match args {
+				[MalType::HashMap(map, _), rest @ ..] => {
+					assoc(map.clone(), rest)
 				},
-				_ => Err(eyre!("`<=` expects 2 numbers")),
-			})),
+				_ => Err(eyre!("`assoc` expects a hashmap")),
+			}),
 		),
 		(
-			">",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::Number(l), MalType::Number(r)] => {
-					Ok(MalType::Bool(l > r))
+			"dissoc",
+			MalType::builtin(|args| match args {
+				[MalType::HashMap(map, _), keys @ ..] => {
+					let mut map = map.clone();
+					for key in keys.iter() {
+						map.remove(key)?;
+					}
+					Ok(MalType::HashMap(map, None))
 				},
-				_ => Err(eyre!("`>` expects 2 numbers")),
-			})),
+				_ => Err(eyre!("`dissoc` expects a hashmap")),
+			}),
 		),
 		(
-			">=",
-			MalType::Function(Rc::new(|args| match args {
-				[MalType::Number(l), MalType::Number(r)] => {
-					Ok(MalType::Bool(l >= r))
+			"disj",
+			MalType::builtin(|args| match args {
+				[MalType::Set(set), keys @ ..] => {
+					let mut set = set.clone();
+					for key in keys.iter() {
+						set.remove(key);
+					}
+					Ok(MalType::Set(set))
 				},
-				_ => Err(eyre!("`>=` expects 2 numbers")),
-			})),
+				_ => Err(eyre!("`disj` expects a set")),
+			}),
 		),
 		(
-			"pr-str",
-			MalType::Function(Rc::new(|args| {
-				Ok(MalType::String(MalType::pr_list::<true>(
-					args.iter(),
-					"",
-					"",
-					" ",
-				)))
-			})),
+			"get",
+			MalType::builtin(|args| match args {
+				[MalType::HashMap(map, _), key] => {
+					Ok(map.get(key)?.cloned().unwrap_or(MalType::Nil))
+				},
+				[MalType::Nil, _] => Ok(MalType::Nil),
+				_ => Err(eyre!("`get` expects a hashmap and a key")),
+			}),
 		),
 		(
-			"str",
-			MalType::Function(Rc::new(|args| {
-				Ok(MalType::String(MalType::pr_list::<false>(
-					args.iter(),
-					"",
-					"",
-					"",
-				)))
-			})),
+			"contains?",
+			MalType::builtin(|args| match args {
+				[MalType::HashMap(map, _), key] => {
+					Ok(MalType::Bool(map.contains_key(key)?))
+				},
+				[MalType::Set(set), key] => {
+					Ok(MalType::Bool(set.contains(key)))
+				},
+				_ => {
+					Err(eyre!("`contains?` expects a hashmap or set and a key"))
+				},
+			}),
 		),
 		(
-			"prn",
-			MalType::Function(Rc::new(|args| {
-				stdout().write_vectored(&[
-					IoSlice::new(
-						MalType::pr_list::<true>(args.iter(), "", "", " ")
-							.as_bytes(),
-					),
-					IoSlice::new(b"\n"),
-				])?;
-				Ok(MalType::Nil)
-			})),
+			"keys",
+			MalType::builtin(|args| match args {
+				[MalType::HashMap(map, _)] => Ok(MalType::L(
+					ListKind::List,
+					map.keys().cloned().collect(),
+					None,
+				)),
+				_ => Err(eyre!("`keys` expects a hashmap")),
+			}),
 		),
 		(
-			"println",
-			MalType::Function(Rc::new(|args| {
-				stdout().write_vectored(&[
-					IoSlice::new(
-						MalType::pr_list::<false>(args.iter(), "", "", " ")
-							.as_bytes(),
+			"vals",
+			MalType::builtin(|args| match args {
+				[MalType::HashMap(map, _)] => Ok(MalType::L(
+					ListKind::List,
+					map.values().cloned().collect(),
+					None,
+				)),
+				_ => Err(eyre!("`vals` expects a hashmap")),
+			}),
+		),
+		(
+			"meta",
+			MalType::builtin(|args| match args {
+				[MalType::TCOFunction { meta, .. }] => Ok((**meta).clone()),
+				[MalType::L(_, _, meta)
+				| MalType::HashMap(_, meta)
+				| MalType::Function(_, meta)] => {
+					Ok(meta.as_deref().cloned().unwrap_or(MalType::Nil))
+				},
+				[_] => Ok(MalType::Nil),
+				_ => Err(eyre!("`meta` expects 1 parameter")),
+			}),
+		),
+		(
+			"with-meta",
+			MalType::builtin(|args| match args {
+				[MalType::TCOFunction {
+					ast,
+					params,
+					env,
+					function,
+					is_macro,
+					name,
+					#[cfg(feature = "vm")]
+					compiled,
+					..
+				}, meta] => Ok(MalType::TCOFunction {
+					ast: ast.clone(),
+					params: params.clone(),
+					env: env.clone(),
+					function: function.clone(),
+					is_macro: *is_macro,
+					meta: Box::new(meta.clone()),
+					name: name.clone(),
+					#[cfg(feature = "vm")]
+					compiled: compiled.clone(),
+				}),
+				[MalType::L(kind, list, _), meta] => Ok(MalType::L(
+					kind.clone(),
+					mem::take(list),
+					Some(Rc::new(meta.clone())),
+				)),
+				[MalType::HashMap(map, _), meta] => Ok(MalType::HashMap(
+					mem::take(map),
+					Some(Rc::new(meta.clone())),
+				)),
+				[MalType::Function(function, _), meta] => {
+					Ok(MalType::Function(
+						function.clone(),
+						Some(Rc::new(meta.clone())),
+					))
+				},
+				_ => Err(eyre!(
+					"`with-meta` expects a function or a collection and a \
+					 value"
+				)),
+			}),
+		),
+		(
+			"fn?",
+			MalType::builtin(|args| match args {
+				[MalType::Function(..), ..]
+				| [MalType::TCOFunction {
+					is_macro: false, ..
+				}, ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"string?",
+			MalType::builtin(|args| match args {
+				[MalType::String(_), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"char?",
+			MalType::builtin(|args| match args {
+				[MalType::Char(_), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"number?",
+			MalType::builtin(|args| match args {
+				[MalType::Number(_), ..] => Ok(MalType::Bool(true)),
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		(
+			"macro?",
+			MalType::builtin(|args| match args {
+				[MalType::TCOFunction { is_macro: true, .. }, ..] => {
+					Ok(MalType::Bool(true))
+				},
+				_ => Ok(MalType::Bool(false)),
+			}),
+		),
+		("conj", MalType::builtin(conj_builtin)),
+		(
+			"seq",
+			MalType::builtin(|args| match args {
+				[MalType::L(_, list, _)] if list.is_empty() => Ok(MalType::Nil),
+				[MalType::L(_, list, _)] => {
+					Ok(MalType::L(ListKind::List, list.clone(), None))
+				},
+				[MalType::String(string)] if string.is_empty() => {
+					Ok(MalType::Nil)
+				},
+				[MalType::String(string)] => Ok(MalType::L(
+					ListKind::List,
+					string
+						.chars()
+						.map(|ch| MalType::String(ch.to_string()))
+						.collect(),
+					None,
+				)),
+				[MalType::Set(set)] if set.is_empty() => Ok(MalType::Nil),
+				[MalType::Set(set)] => Ok(MalType::L(
+					ListKind::List,
+					set.iter().cloned().collect(),
+					None,
+				)),
+				[seq @ MalType::LazySeq(_)] => match seq.uncons()? {
+					Some(_) => Ok(seq.clone()),
+					None => Ok(MalType::Nil),
+				},
+				[MalType::Nil] => Ok(MalType::Nil),
+				_ => Err(eyre!("`seq` expects a list, vector, set or string")),
+			}),
+		),
+		(
+			"json-parse",
+			MalType::builtin(|args| {
+				let (json, keywordize_keys) = match args {
+					[MalType::String(json)] => (json, false),
+					[MalType::String(json), MalType::HashMap(opts, _)] => (
+						json,
+						!matches!(
+							opts.get(&MalType::Keyword(
+								"keywordize-keys".to_string()
+							))?,
+							None | Some(MalType::Nil | MalType::Bool(false))
+						),
 					),
-					IoSlice::new(b"\n"),
-				])?;
-				Ok(MalType::Nil)
-			})),
+					_ => {
+						return Err(eyre!(
+							"`json-parse` expects a string and an optional \
+							 options hashmap"
+						))
+					},
+				};
+				json::parse(json, keywordize_keys)
+					.map_err(|err| eyre!("`json-parse`: {err}"))
+			}),
 		),
 	]
 	.into_iter()
 	.map(|(sym, fun)| (sym.to_string(), fun));
 
-	Env::new_with_bindings_list(None, bindings)
+	let env = Env::new_with_bindings_list(None, bindings);
+	register_variadic(&env, "+", 0, |nums| fold(nums, 0, |l, r| Ok(l + r)));
+	register_variadic(&env, "-", 1, |nums| match nums {
+		[num] => Ok(-num.clone()),
+		nums => fold(nums, None, |l, r| Ok(l - r)),
+	});
+	register_variadic(&env, "*", 0, |nums| fold(nums, 1, |l, r| Ok(l * r)));
+	register_variadic(&env, "/", 1, |nums| match nums {
+		[num] => Number::Int(1).checked_div(num),
+		nums => fold(nums, None, |l, r| l.checked_div(&r)),
+	});
+	register_variadic(&env, "max", 1, |nums| {
+		fold(nums, None, |l, r| Ok(extremum(l, r, Ordering::Greater)))
+	});
+	register_variadic(&env, "min", 1, |nums| {
+		fold(nums, None, |l, r| Ok(extremum(l, r, Ordering::Less)))
+	});
+	register_variadic(&env, "bit-and", 2, |nums| {
+		fold(nums, None, |l, r| l.bit_and(&r))
+	});
+	register_variadic(&env, "bit-or", 2, |nums| {
+		fold(nums, None, |l, r| l.bit_or(&r))
+	});
+	register_variadic(&env, "bit-xor", 2, |nums| {
+		fold(nums, None, |l, r| l.bit_xor(&r))
+	});
+	register_variadic(&env, "<", 1, |nums| {
+		Ok(ordered(nums, |ord| ord.is_lt()))
+	});
+	register_variadic(&env, "<=", 1, |nums| {
+		Ok(ordered(nums, |ord| ord.is_le()))
+	});
+	register_variadic(&env, ">", 1, |nums| {
+		Ok(ordered(nums, |ord| ord.is_gt()))
+	});
+	register_variadic(&env, ">=", 1, |nums| {
+		Ok(ordered(nums, |ord| ord.is_ge()))
+	});
+	env.register_fn("quot", |l: Number, r: Number| {
+		l.checked_quot(&r).map_err(|err| eyre!("`quot`: {err}"))
+	});
+	env.register_fn("rem", |l: Number, r: Number| {
+		l.checked_rem(&r).map_err(|err| eyre!("`rem`: {err}"))
+	});
+	env.register_fn("mod", |l: Number, r: Number| {
+		l.checked_mod(&r).map_err(|err| eyre!("`mod`: {err}"))
+	});
+	env.register_fn("inc", |num: Number| Ok(num + Number::Int(1)));
+	env.register_fn("dec", |num: Number| Ok(num - Number::Int(1)));
+	env.register_fn("abs", |num: Number| Ok(num.abs()));
+	env.register_fn("bit-not", |num: Number| {
+		num.bit_not().map_err(|err| eyre!("`bit-not`: {err}"))
+	});
+	env.register_fn("bit-shift-left", |num: Number, bits: Number| {
+		num.shift_left(&bits)
+			.map_err(|err| eyre!("`bit-shift-left`: {err}"))
+	});
+	env.register_fn("bit-shift-right", |num: Number, bits: Number| {
+		num.shift_right(&bits)
+			.map_err(|err| eyre!("`bit-shift-right`: {err}"))
+	});
+	env.register_fn("set", |coll: MalType| match coll {
+		MalType::L(_, list, _) => Ok(MalType::Set(list.into_iter().collect())),
+		MalType::Set(set) => Ok(MalType::Set(set)),
+		MalType::Nil => Ok(MalType::Set(HashSet::new())),
+		coll => {
+			Err(eyre!("`set` expects a list, vector or set (got {coll:#})"))
+		},
+	});
+	register_set_op(&env, "union", 0, |mut l, r| {
+		l.extend(r.iter().cloned());
+		l
+	});
+	register_set_op(&env, "intersection", 1, |mut l, r| {
+		l.retain(|item| r.contains(item));
+		l
+	});
+	register_set_op(&env, "difference", 1, |mut l, r| {
+		l.retain(|item| !r.contains(item));
+		l
+	});
+	env.register_fn("compare", |l: MalType, r: MalType| {
+		let ord = l.compare(&r).map_err(|err| eyre!("`compare`: {err}"))?;
+		Ok(ord as i64)
+	});
+	env.register_fn("take", |count: i64, seq: MalType| match seq {
+		MalType::LazySeq(_) => Ok(lazy_take(count, seq)),
+		seq => Ok(seq
+			.into_items()
+			.take(count.max(0) as usize)
+			.collect::<eyre::Result<Vec<_>>>()
+			.map_err(|err| eyre!("`take`: {err}"))?
+			.into_mal()),
+	});
+	env.register_fn("drop", |count: i64, seq: MalType| match seq {
+		MalType::LazySeq(_) => Ok(lazy_drop(count, seq)),
+		seq => Ok(seq
+			.into_items()
+			.skip(count.max(0) as usize)
+			.collect::<eyre::Result<Vec<_>>>()
+			.map_err(|err| eyre!("`drop`: {err}"))?
+			.into_mal()),
+	});
+	env.register_fn("iterate", |function: MalType, value: MalType| {
+		Ok(iterate(function, value))
+	});
+	env.register_fn("cycle", |seq: MalType| match seq {
+		MalType::L(..)
+		| MalType::Set(_)
+		| MalType::LazySeq(_)
+		| MalType::Nil => Ok(lazy_cycle(seq)),
+		seq => Err(eyre!("`cycle` expects a list (got {seq:#})")),
+	});
+	// Errors from realizing the seq aren't prefixed, so that thrown values can
+	// still be caught.
+	env.register_fn("doall", |seq: MalType| {
+		seq.items().try_for_each(|item| item.map(drop))?;
+		Ok(seq)
+	});
+	// like `doall`, but without keeping hold of the head of the seq
+	env.register_fn("dorun", |seq: MalType| {
+		seq.into_items().try_for_each(|item| item.map(drop))
+	});
+	env.register_fn("line-seq", |path: String| {
+		let file = File::open(&path)
+			.map_err(|err| eyre!("`line-seq`: {path}: {err}"))?;
+		Ok(lines(Rc::new(RefCell::new(BufReader::new(file).lines()))))
+	});
+	env.register_fn("read-string", |input: String| reader::read_str(&input));
+	env.register_fn("read-all", |input: String| reader::read_all(&input));
+	env.register_fn("slurp", |path: String| Ok(fs::read_to_string(path)?));
+	env.register_fn("symbol", |name: String| Ok(MalType::Symbol(name, None)));
+	env.register_fn("time-ms", || {
+		Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64)
+	});
+	env.register_fn("readline", readline);
+	env.register_fn("json-stringify", |value: MalType| {
+		json::stringify(&value).map_err(|err| eyre!("`json-stringify`: {err}"))
+	});
+	env.register_fn("edn-read", |input: String| {
+		edn::read(&input).map_err(|err| eyre!("`edn-read`: {err}"))
+	});
+	env.register_fn("edn-write", |value: MalType| {
+		edn::write(&value).map_err(|err| eyre!("`edn-write`: {err}"))
+	});
+	string::register(&env);
+	re::register(&env);
+	env
+}
+
+/// Binds `name` to a builtin taking at least `min` numbers. Errors from
+/// `function` are prefixed with `name`.
+fn register_variadic<Ret: IntoMal>(
+	env: &Env,
+	name: &'static str,
+	min: usize,
+	function: impl Fn(&[Number]) -> eyre::Result<Ret> + 'static,
+) {
+	let builtin = MalType::builtin(move |args| {
+		if args.len() < min {
+			return Err(eyre!(
+				"`{name}` expects at least {min} args: {} were provided",
+				args.len(),
+			));
+		}
+		let nums = args
+			.iter()
+			.enumerate()
+			.map(|(idx, arg)| {
+				Number::from_mal(arg.clone()).map_err(|err| {
+					eyre!("`{name}`, argument {}: {err}", idx + 1)
+				})
+			})
+			.collect::<eyre::Result<Vec<_>>>()?;
+		function(&nums)
+			.map(IntoMal::into_mal)
+			.map_err(|err| eyre!("`{name}`: {err}"))
+	});
+	env.set(name.to_string(), builtin);
+}
+
+/// Binds `name` to a builtin folding at least `min` sets from left to right
+/// with `op`. No sets at all give the empty set.
+fn register_set_op(
+	env: &Env,
+	name: &'static str,
+	min: usize,
+	op: fn(HashSet<MalType>, &HashSet<MalType>) -> HashSet<MalType>,
+) {
+	let builtin = MalType::builtin(move |args| {
+		if args.len() < min {
+			return Err(eyre!(
+				"`{name}` expects at least {min} args: {} were provided",
+				args.len(),
+			));
+		}
+		let mut sets = args.iter().enumerate().map(|(idx, arg)| {
+			HashSet::<MalType>::from_mal(arg.clone())
+				.map_err(|err| eyre!("`{name}`, argument {}: {err}", idx + 1))
+		});
+		let init = sets.next().transpose()?.unwrap_or_default();
+		sets.try_fold(init, |acc, set| Ok(op(acc, &set?)))
+			.map(MalType::Set)
+	});
+	env.set(name.to_string(), builtin);
+}
+
+/// Combines `nums` from left to right with `op`, starting from `init` if
+/// there is one and from the first number otherwise. `nums` mustn't be empty
+/// if there's no `init`.
+fn fold(
+	nums: &[Number],
+	init: impl Into<Option<i64>>,
+	op: impl Fn(Number, Number) -> eyre::Result<Number>,
+) -> eyre::Result<Number> {
+	let (init, rest) = match init.into() {
+		Some(init) => (Number::Int(init), nums),
+		None => (nums[0].clone(), &nums[1..]),
+	};
+	rest.iter().cloned().try_fold(init, op)
+}
+
+/// Whether each number compares to the next as accepted by `accept`. This is
+/// false if any of them is NaN.
+fn ordered(nums: &[Number], accept: impl Fn(Ordering) -> bool) -> bool {
+	nums.windows(2)
+		.all(|pair| pair[0].compare(&pair[1]).is_some_and(&accept))
+}
+
+/// `l`, unless `r` compares to it as `ord`. NaN always wins.
+fn extremum(l: Number, r: Number, ord: Ordering) -> Number {
+	match r.compare(&l) {
+		Some(cmp) if cmp != ord => l,
+		Some(_) => r,
+		None if l.is_nan() => l,
+		None => r,
+	}
+}
+
+fn readline(prompt: String) -> eyre::Result<Option<String>> {
+	let mut stdout = stdout();
+	write!(stdout, "{prompt}")?;
+	stdout.flush()?;
+
+	let mut line = String::new();
+	if stdin().read_line(&mut line)? == 0 {
+		return Ok(None);
+	}
+	if line.ends_with('\n') {
+		line.pop();
+		if line.ends_with('\r') {
+			line.pop();
+		}
+	}
+	Ok(Some(line))
+}
+
+fn assoc(mut map: MalMap, args: &[MalType]) -> eyre::Result<MalType> {
+	let mut items = args.iter();
+	while let Some(key) = items.next() {
+		let Some(value) = items.next() else {
+			return Err(eyre!("no value for hashmap key"));
+		};
+		map.insert(key.clone(), value.clone())?;
+	}
+	Ok(MalType::HashMap(map, None))
+}
+
+fn truthy(value: &MalType) -> bool {
+	!matches!(value, MalType::Nil | MalType::Bool(false))
+}
+
+fn lazy_map(function: MalType, seq: MalType) -> MalType {
+	Lazy::seq(move || match seq.uncons()? {
+		Some((first, rest)) => Ok(Lazy::cons(
+			function.call(&mut [first])?,
+			lazy_map(function.clone(), rest),
+		)),
+		None => Ok(MalType::Nil),
+	})
+}
+
+/// The items of any collection, without copying it. The entries of a hashmap
+/// are `[key value]` vectors, and the items of a string are 1-char strings.
+pub fn coll_items<'a>(
+	name: &str,
+	coll: &'a MalType,
+) -> eyre::Result<Box<dyn Iterator<Item = eyre::Result<MalType>> + 'a>> {
+	Ok(match coll {
+		MalType::L(_, list, _) => Box::new(list.iter().cloned().map(Ok)),
+		MalType::Set(set) => Box::new(set.iter().cloned().map(Ok)),
+		MalType::HashMap(map, _) => Box::new(map.iter().map(|(key, value)| {
+			Ok(MalType::L(
+				ListKind::Vector,
+				vec![key.clone(), value.clone()],
+				None,
+			))
+		})),
+		MalType::String(string) => Box::new(
+			string.chars().map(|ch| Ok(MalType::String(ch.to_string()))),
+		),
+		MalType::LazySeq(_) => Box::new(coll.items()),
+		MalType::Nil => Box::new(std::iter::empty()),
+		coll => {
+			return Err(eyre!("`{name}` expects a collection (got {coll:#})"))
+		},
+	})
+}
+
+/// What `filter`, `remove` and `keep` keep of an item, given the item and
+/// what their function returned for it.
+type Pick = fn(MalType, MalType) -> Option<MalType>;
+
+fn keep_items(
+	name: &str,
+	args: &[MalType],
+	pick: Pick,
+) -> eyre::Result<MalType> {
+	match args {
+		[function] => Ok(transducer(
+			function.clone(),
+			move |function, rf, acc, item| match pick(
+				item.clone(),
+				function.call(&mut [item])?,
+			) {
+				Some(item) => rf.call(&mut [acc, item]),
+				None => Ok(acc),
+			},
+		)),
+		[function, seq @ MalType::LazySeq(_)] => {
+			Ok(lazy_keep(function.clone(), seq.clone(), pick))
+		},
+		[function, coll] => {
+			let mut res = Vec::new();
+			for item in coll_items(name, coll)? {
+				let item = item?;
+				let picked = function.call(&mut [item.clone()])?;
+				res.extend(pick(item, picked));
+			}
+			Ok(MalType::L(ListKind::List, res, None))
+		},
+		_ => Err(eyre!("`{name}` expects a function and a collection")),
+	}
+}
+
+/// A transducer: given a reducing function `rf`, it returns one that calls
+/// `step` with `function`, `rf`, the accumulated value and each item.
+fn transducer(
+	function: MalType,
+	step: impl Fn(&MalType, &MalType, MalType, MalType) -> eyre::Result<MalType>
+		+ Copy
+		+ 'static,
+) -> MalType {
+	MalType::builtin(move |args| match args {
+		[rf] => Ok(reducing_fn(function.clone(), rf.clone(), step)),
+		_ => Err(eyre!("transducers expect a reducing function")),
+	})
+}
+
+/// A reducing function, which returns an initial value when called with no
+/// args, completes the result with one, and takes the result and an item
+/// with two.
+fn reducing_fn(
+	function: MalType,
+	rf: MalType,
+	step: impl Fn(&MalType, &MalType, MalType, MalType) -> eyre::Result<MalType>
+		+ 'static,
+) -> MalType {
+	MalType::builtin(move |args| match args {
+		[] | [_] => rf.call(args),
+		[acc, item] => step(
+			&function,
+			&rf,
+			mem::replace(acc, MalType::Nil),
+			mem::replace(item, MalType::Nil),
+		),
+		_ => Err(eyre!(
+			"reducing functions expect at most 2 args: {} were provided",
+			args.len()
+		)),
+	})
+}
+
+/// Calls `function` with the accumulated value and each item in turn,
+/// stopping early if it returns a value wrapped by `reduced`.
+fn reduce_items(
+	function: &MalType,
+	init: MalType,
+	items: impl Iterator<Item = eyre::Result<MalType>>,
+) -> eyre::Result<MalType> {
+	let mut acc = init;
+	for item in items {
+		acc = function.call(&mut [acc, item?])?;
+		if let MalType::Reduced(value) = acc {
+			return Ok(*value);
+		}
+	}
+	Ok(acc)
+}
+
+/// Reduces with `(xform function)`, then calls that with the result to
+/// complete it.
+fn transduce(
+	xform: &MalType,
+	function: MalType,
+	init: MalType,
+	coll: &MalType,
+) -> eyre::Result<MalType> {
+	let rf = xform.call(&mut [function])?;
+	let acc = reduce_items(&rf, init, coll_items("transduce", coll)?)?;
+	rf.call(&mut [acc])
+}
+
+/// The items of a hashmap are `[key value]` vectors, or hashmaps to merge
+/// in. With no args, this is an empty vector, so `conj` can start a
+/// `transduce`.
+fn conj_builtin(args: &mut [MalType]) -> eyre::Result<MalType> {
+	let [coll, items @ ..] = args else {
+		return Ok(MalType::L(ListKind::Vector, Vec::new(), None));
+	};
+	// the collection is taken rather than copied, so `into` can build up a
+	// vector in place
+	match mem::replace(coll, MalType::Nil) {
+		MalType::L(ListKind::List, list, _) => Ok(MalType::L(
+			ListKind::List,
+			items.iter().rev().chain(list.iter()).cloned().collect(),
+			None,
+		)),
+		MalType::L(ListKind::Vector, mut list, _) => {
+			list.extend(items.iter().cloned());
+			Ok(MalType::L(ListKind::Vector, list, None))
+		},
+		MalType::Set(mut set) => {
+			set.extend(items.iter().cloned());
+			Ok(MalType::Set(set))
+		},
+		MalType::HashMap(mut map, _) => {
+			for item in items.iter() {
+				match item {
+					MalType::L(ListKind::Vector, entry, _)
+						if entry.len() == 2 =>
+					{
+						map.insert(entry[0].clone(), entry[1].clone())?
+					},
+					MalType::HashMap(other, _) => {
+						for (key, value) in other.iter() {
+							map.insert(key.clone(), value.clone())?;
+						}
+					},
+					item => {
+						return Err(eyre!(
+							"`conj` expects [key value] vectors to add to a \
+							 hashmap (got {item:#})"
+						))
+					},
+				}
+			}
+			Ok(MalType::HashMap(map, None))
+		},
+		_ => Err(eyre!("`conj` expects a list, vector, set or hashmap")),
+	}
+}
+
+/// Lists of `n` items, starting every `step` items. Any items left over at
+/// the end are dropped, unless there are items to `pad` them with, in which
+/// case they make one last, possibly shorter, list.
+fn partition(
+	n: usize,
+	step: usize,
+	pad: Option<&MalType>,
+	coll: &MalType,
+) -> eyre::Result<MalType> {
+	let mut parts = Vec::new();
+	let mut part = VecDeque::new();
+	let mut skip = 0;
+	for item in coll_items("partition", coll)? {
+		let item = item?;
+		if skip > 0 {
+			skip -= 1;
+			continue;
+		}
+		part.push_back(item);
+		if part.len() == n {
+			parts.push(MalType::L(
+				ListKind::List,
+				part.iter().cloned().collect(),
+				None,
+			));
+			if step < n {
+				part.drain(..step);
+			} else {
+				part.clear();
+				skip = step - n;
+			}
+		}
+	}
+	if let Some(pad) = pad {
+		if !part.is_empty() {
+			parts.push(padded(n, part.into(), pad)?);
+		}
+	}
+	Ok(MalType::L(ListKind::List, parts, None))
+}
+
+fn lazy_partition(
+	n: usize,
+	step: usize,
+	pad: Option<MalType>,
+	seq: MalType,
+) -> MalType {
+	Lazy::seq(move || {
+		let mut part = Vec::new();
+		let mut rest = seq.clone();
+		while part.len() < n {
+			let Some((first, next)) = rest.uncons()? else {
+				break;
+			};
+			part.push(first);
+			rest = next;
+		}
+		if part.len() < n {
+			return match &pad {
+				Some(pad) if !part.is_empty() => Ok(MalType::L(
+					ListKind::List,
+					vec![padded(n, part, pad)?],
+					None,
+				)),
+				_ => Ok(MalType::Nil),
+			};
+		}
+		let rest = lazy_drop(step as i64, seq.clone());
+		Ok(Lazy::cons(
+			MalType::L(ListKind::List, part, None),
+			lazy_partition(n, step, pad.clone(), rest),
+		))
+	})
+}
+
+fn padded(
+	n: usize,
+	mut part: Vec<MalType>,
+	pad: &MalType,
+) -> eyre::Result<MalType> {
+	for item in coll_items("partition", pad)?.take(n - part.len()) {
+		part.push(item?);
+	}
+	Ok(MalType::L(ListKind::List, part, None))
+}
+
+/// A stable merge sort. `slice::sort_by` can't stop at an error from the
+/// comparator, and may panic if it isn't a total order, which a mal
+/// comparator needn't be.
+fn merge_sort<T>(
+	mut items: Vec<T>,
+	cmp: &dyn Fn(&T, &T) -> eyre::Result<Ordering>,
+) -> eyre::Result<Vec<T>> {
+	if items.len() <= 1 {
+		return Ok(items);
+	}
+	let right = items.split_off(items.len() / 2);
+	let mut left = merge_sort(items, cmp)?.into_iter().peekable();
+	let mut right = merge_sort(right, cmp)?.into_iter().peekable();
+	let mut res = Vec::with_capacity(left.len() + right.len());
+	while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+		// an item from the left comes first unless it's greater
+		let next = if cmp(r, l)?.is_lt() {
+			right.next()
+		} else {
+			left.next()
+		};
+		res.extend(next);
+	}
+	res.extend(left.chain(right));
+	Ok(res)
+}
+
+fn lazy_keep(function: MalType, seq: MalType, pick: Pick) -> MalType {
+	Lazy::seq(move || {
+		let mut seq = seq.clone();
+		while let Some((first, rest)) = seq.uncons()? {
+			let res = function.call(&mut [first.clone()])?;
+			if let Some(item) = pick(first, res) {
+				return Ok(Lazy::cons(
+					item,
+					lazy_keep(function.clone(), rest, pick),
+				));
+			}
+			seq = rest;
+		}
+		Ok(MalType::Nil)
+	})
+}
+
+fn lazy_mapcat(function: MalType, seq: MalType) -> MalType {
+	Lazy::seq(move || match seq.uncons()? {
+		Some((first, rest)) => {
+			let coll = match function.call(&mut [first])? {
+				coll @ (MalType::L(..)
+				| MalType::Set(_)
+				| MalType::LazySeq(_)
+				| MalType::Nil) => coll,
+				coll => MalType::L(
+					ListKind::List,
+					coll_items("mapcat", &coll)?
+						.collect::<eyre::Result<_>>()?,
+					None,
+				),
+			};
+			Ok(lazy_concat(coll, lazy_mapcat(function.clone(), rest)))
+		},
+		None => Ok(MalType::Nil),
+	})
+}
+
+fn lazy_take(count: i64, seq: MalType) -> MalType {
+	Lazy::seq(move || {
+		if count <= 0 {
+			return Ok(MalType::Nil);
+		}
+		match seq.uncons()? {
+			Some((first, rest)) => {
+				Ok(Lazy::cons(first, lazy_take(count - 1, rest)))
+			},
+			None => Ok(MalType::Nil),
+		}
+	})
+}
+
+fn lazy_drop(count: i64, seq: MalType) -> MalType {
+	Lazy::seq(move || {
+		let mut seq = seq.clone();
+		for _ in 0..count {
+			match seq.uncons()? {
+				Some((_, rest)) => seq = rest,
+				None => return Ok(MalType::Nil),
+			}
+		}
+		Ok(seq)
+	})
+}
+
+/// `value`, then `function` applied to the previous item, forever.
+fn iterate(function: MalType, value: MalType) -> MalType {
+	let prev = value.clone();
+	Lazy::cons(
+		value,
+		Lazy::seq(move || {
+			let next = function.call(&mut [prev.clone()])?;
+			Ok(iterate(function.clone(), next))
+		}),
+	)
+}
+
+/// From `start` up to `end`, or down to it if `step` is negative, or forever
+/// if there's no `end`.
+fn lazy_range(
+	start: Number,
+	end: Option<Number>,
+	step: Number,
+	towards: Ordering,
+) -> MalType {
+	Lazy::seq(move || {
+		if let Some(end) = &end {
+			if start.compare(end) != Some(towards) {
+				return Ok(MalType::Nil);
+			}
+		}
+		let next = start.clone() + step.clone();
+		Ok(Lazy::cons(
+			MalType::Number(start.clone()),
+			lazy_range(next, end.clone(), step.clone(), towards),
+		))
+	})
+}
+
+fn lazy_repeat(value: MalType) -> MalType {
+	Lazy::seq(move || Ok(Lazy::cons(value.clone(), lazy_repeat(value.clone()))))
+}
+
+fn lazy_cycle(seq: MalType) -> MalType {
+	Lazy::seq(move || match seq.uncons()? {
+		Some(_) => Ok(lazy_concat(seq.clone(), lazy_cycle(seq.clone()))),
+		None => Ok(MalType::Nil),
+	})
+}
+
+fn lazy_concat(seq: MalType, tail: MalType) -> MalType {
+	Lazy::seq(move || match seq.uncons()? {
+		Some((first, rest)) => {
+			Ok(Lazy::cons(first, lazy_concat(rest, tail.clone())))
+		},
+		None => Ok(tail.clone()),
+	})
+}
+
+/// A lazy seq of the lines of a file, read as they're needed.
+fn lines(lines: Rc<RefCell<Lines<BufReader<File>>>>) -> MalType {
+	Lazy::seq(move || match lines.borrow_mut().next() {
+		Some(line) => Ok(Lazy::cons(
+			MalType::String(line?),
+			self::lines(lines.clone()),
+		)),
+		None => Ok(MalType::Nil),
+	})
 }
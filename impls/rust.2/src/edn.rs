@@ -0,0 +1,434 @@
+//! EDN support for the `edn-read` and `edn-write` builtins.
+//!
+//! EDN is close to mal's own syntax, but unlike `read-string` this never
+//! produces code (no quote or deref shorthands) and accepts the rest of the
+//! EDN data syntax: `#_` discards, characters, `#inst`/`#uuid` tags (read as
+//! their strings) and symbolic numbers like `##Inf`.
+
+use std::fmt::Display;
+
+use eyre::Result;
+
+use crate::map::MalMap;
+use crate::number::Number;
+use crate::reader;
+use crate::types::ListKind;
+use crate::types::MalType;
+
+/// Reads the single EDN value in `input`, or `nil` if it's empty.
+pub fn read(input: &str) -> Result<MalType> {
+	let mut parser = Parser { input, posn: 0 };
+	let value = match parser.parse_value()? {
+		Some(value) => value,
+		None if parser.peek().is_none() => MalType::Nil,
+		None => return Err(parser.unexpected("a value")),
+	};
+	parser.skip_whitespace();
+	if parser.peek().is_some() {
+		return Err(parser.unexpected("end of input"));
+	}
+	Ok(value)
+}
+
+pub fn write(value: &MalType) -> Result<String> {
+	let mut out = String::new();
+	write_value(value, &mut out)?;
+	Ok(out)
+}
+
+struct Parser<'source> {
+	input: &'source str,
+	posn:  usize,
+}
+
+fn is_delimiter(ch: char) -> bool {
+	ch.is_whitespace()
+		|| matches!(ch, ',' | '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+}
+
+impl<'source> Parser<'source> {
+	fn error_at(&self, posn: usize, msg: impl Display) -> eyre::Report {
+		let (line, col) = reader::line_col(self.input, posn);
+		eyre!("{msg} at line {line}, column {col}")
+	}
+
+	fn unexpected(&self, expected: &str) -> eyre::Report {
+		match self.peek() {
+			Some(ch) => self.error_at(
+				self.posn,
+				format!("expected {expected}, found `{ch}`"),
+			),
+			None => self.error_at(
+				self.posn,
+				format!("expected {expected}, found end of input"),
+			),
+		}
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.input[self.posn..].chars().next()
+	}
+
+	fn bump(&mut self) -> Option<char> {
+		let ch = self.peek()?;
+		self.posn += ch.len_utf8();
+		Some(ch)
+	}
+
+	fn eat(&mut self, prefix: &str) -> bool {
+		let res = self.input[self.posn..].starts_with(prefix);
+		if res {
+			self.posn += prefix.len();
+		}
+		res
+	}
+
+	fn skip_whitespace(&mut self) {
+		while let Some(ch) = self.peek() {
+			if ch == ';' {
+				while !matches!(self.bump(), None | Some('\n')) {}
+			} else if ch.is_whitespace() || ch == ',' {
+				self.posn += ch.len_utf8();
+			} else {
+				break;
+			}
+		}
+	}
+
+	/// The token starting at the current position, up to the next delimiter.
+	fn token(&mut self) -> &'source str {
+		let start = self.posn;
+		while self.peek().is_some_and(|ch| !is_delimiter(ch)) {
+			self.bump();
+		}
+		&self.input[start..self.posn]
+	}
+
+	/// Parses the next value, or returns `None` at a closing delimiter or the
+	/// end of input.
+	fn parse_value(&mut self) -> Result<Option<MalType>> {
+		self.skip_whitespace();
+		let start = self.posn;
+		Ok(Some(match self.peek() {
+			None | Some(')' | ']' | '}') => return Ok(None),
+			Some('(') => MalType::L(ListKind::List, self.parse_seq(')')?, None),
+			Some('[') => {
+				MalType::L(ListKind::Vector, self.parse_seq(']')?, None)
+			},
+			Some('{') => self.parse_map()?,
+			Some('"') => MalType::String(self.parse_string()?),
+			Some('\\') => MalType::Char(self.parse_char()?),
+			Some('#') => {
+				if self.eat("#_") {
+					self.expect_value()?;
+					return self.parse_value();
+				} else if self.eat("##") {
+					match self.token() {
+						"Inf" => MalType::Number(Number::Float(f64::INFINITY)),
+						"-Inf" => {
+							MalType::Number(Number::Float(f64::NEG_INFINITY))
+						},
+						"NaN" => MalType::Number(Number::Float(f64::NAN)),
+						_ => {
+							return Err(
+								self.error_at(start, "invalid symbolic value")
+							)
+						},
+					}
+				} else if self.input[self.posn..].starts_with("#{") {
+					self.posn += 1;
+					MalType::Set(self.parse_seq('}')?.into_iter().collect())
+				} else {
+					self.posn += 1;
+					let tag = self.token();
+					match tag {
+						"inst" | "uuid" => match self.expect_value()? {
+							value @ MalType::String(_) => value,
+							_ => {
+								return Err(self.error_at(
+									start,
+									format!("`#{tag}` expects a string"),
+								))
+							},
+						},
+						_ => {
+							return Err(self.error_at(
+								start,
+								format!("unsupported tag `#{tag}`"),
+							))
+						},
+					}
+				}
+			},
+			Some(_) => self.parse_atom(start)?,
+		}))
+	}
+
+	fn expect_value(&mut self) -> Result<MalType> {
+		self.parse_value()?
+			.ok_or_else(|| self.unexpected("a value"))
+	}
+
+	/// Parses the values up to `close`, starting at the opening delimiter.
+	fn parse_seq(&mut self, close: char) -> Result<Vec<MalType>> {
+		let start = self.posn;
+		self.bump();
+		let mut list = Vec::new();
+		while let Some(value) = self.parse_value()? {
+			list.push(value);
+		}
+		match self.bump() {
+			Some(ch) if ch == close => Ok(list),
+			Some(ch) => {
+				self.posn -= ch.len_utf8();
+				Err(self.unexpected(&format!("`{close}`")))
+			},
+			None => Err(self.error_at(start, "unclosed delimiter")),
+		}
+	}
+
+	fn parse_map(&mut self) -> Result<MalType> {
+		let start = self.posn;
+		self.bump();
+		let mut map = MalMap::new();
+		loop {
+			self.skip_whitespace();
+			let key_posn = self.posn;
+			let Some(key) = self.parse_value()? else {
+				break;
+			};
+			let Some(value) = self.parse_value()? else {
+				return Err(self.error_at(key_posn, "no value for hashmap key"));
+			};
+			map.insert(key, value)?;
+		}
+		match self.bump() {
+			Some('}') => Ok(MalType::HashMap(map, None)),
+			Some(ch) => {
+				self.posn -= ch.len_utf8();
+				Err(self.unexpected("`}`"))
+			},
+			None => Err(self.error_at(start, "unclosed delimiter")),
+		}
+	}
+
+	fn parse_string(&mut self) -> Result<String> {
+		let start = self.posn;
+		self.bump();
+		let mut string = String::new();
+		loop {
+			let posn = self.posn;
+			match self.bump() {
+				None => break Err(self.error_at(start, "unterminated string")),
+				Some('"') => break Ok(string),
+				Some('\\') => string.push(match self.bump() {
+					Some('"') => '"',
+					Some('\\') => '\\',
+					Some('b') => '\u{8}',
+					Some('f') => '\u{c}',
+					Some('n') => '\n',
+					Some('r') => '\r',
+					Some('t') => '\t',
+					Some('u') => self.parse_unicode(posn)?,
+					_ => {
+						break Err(
+							self.error_at(posn, "invalid escape sequence")
+						)
+					},
+				}),
+				Some(ch) => string.push(ch),
+			}
+		}
+	}
+
+	fn parse_char(&mut self) -> Result<char> {
+		let start = self.posn;
+		self.bump();
+		// the character itself may be a delimiter, like `\(`
+		let Some(first) = self.bump() else {
+			return Err(self.unexpected("a character"));
+		};
+		let rest = self.token();
+		Ok(match (first, rest) {
+			(ch, "") => ch,
+			('n', "ewline") => '\n',
+			('s', "pace") => ' ',
+			('t', "ab") => '\t',
+			('r', "eturn") => '\r',
+			('f', "ormfeed") => '\u{c}',
+			('b', "ackspace") => '\u{8}',
+			('u', _) => {
+				self.posn -= rest.len();
+				let ch = self.parse_unicode(start)?;
+				if !self.token().is_empty() {
+					return Err(self.error_at(start, "invalid character"));
+				}
+				ch
+			},
+			_ => return Err(self.error_at(start, "invalid character")),
+		})
+	}
+
+	/// Parses the `XXXX` of a `\uXXXX` escape starting at `start`.
+	fn parse_unicode(&mut self, start: usize) -> Result<char> {
+		self.input
+			.get(self.posn..self.posn + 4)
+			.filter(|hex| hex.chars().all(|ch| ch.is_ascii_hexdigit()))
+			.and_then(|hex| char::from_u32(u32::from_str_radix(hex, 16).ok()?))
+			.inspect(|_| self.posn += 4)
+			.ok_or_else(|| self.error_at(start, "invalid unicode escape"))
+	}
+
+	fn parse_atom(&mut self, start: usize) -> Result<MalType> {
+		let token = self.token();
+		if token.is_empty() {
+			return Err(self.unexpected("a value"));
+		}
+		let mut chars = token.chars();
+		let is_number = match chars.next() {
+			Some('0'..='9') => true,
+			Some('+' | '-') => matches!(chars.next(), Some('0'..='9')),
+			_ => false,
+		};
+		Ok(match token {
+			"nil" => MalType::Nil,
+			"true" => MalType::Bool(true),
+			"false" => MalType::Bool(false),
+			_ if is_number => {
+				let invalid = |_| self.error_at(start, "invalid number");
+				MalType::Number(match token.strip_suffix('M') {
+					// there are no decimals, so these are read as floats
+					Some(decimal) => Number::Float(
+						decimal.parse::<Number>().map_err(invalid)?.to_f64(),
+					),
+					None => token.parse().map_err(invalid)?,
+				})
+			},
+			_ if token.starts_with(':') => match &token[1..] {
+				"" => return Err(self.error_at(start, "invalid keyword")),
+				name => MalType::Keyword(name.to_string()),
+			},
+			_ => MalType::Symbol(token.to_string(), None),
+		})
+	}
+}
+
+fn write_value(value: &MalType, out: &mut String) -> Result<()> {
+	match value {
+		MalType::Nil => out.push_str("nil"),
+		MalType::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+		MalType::Number(num) => out.push_str(&num.to_string()),
+		MalType::Keyword(name) => {
+			out.push(':');
+			out.push_str(name);
+		},
+		MalType::Symbol(name, _) => out.push_str(name),
+		MalType::Char(_) => out.push_str(&value.pr_str::<true>()),
+		MalType::String(string) => {
+			out.push('"');
+			for ch in string.chars() {
+				match ch {
+					'"' => out.push_str("\\\""),
+					'\\' => out.push_str("\\\\"),
+					'\n' => out.push_str("\\n"),
+					'\r' => out.push_str("\\r"),
+					'\t' => out.push_str("\\t"),
+					ch => out.push(ch),
+				}
+			}
+			out.push('"');
+		},
+		MalType::L(ListKind::List, list, _) => {
+			write_seq("(", list.iter(), ")", out)?
+		},
+		MalType::L(ListKind::Vector, list, _) => {
+			write_seq("[", list.iter(), "]", out)?
+		},
+		MalType::Set(set) => write_seq("#{", set.iter(), "}", out)?,
+		MalType::LazySeq(_) => {
+			let items = value.items().collect::<Result<Vec<_>>>()?;
+			write_seq("(", items.iter(), ")", out)?
+		},
+		MalType::HashMap(map, _) => {
+			out.push('{');
+			for (idx, (key, value)) in map.iter().enumerate() {
+				if idx > 0 {
+					out.push_str(", ");
+				}
+				write_value(key, out)?;
+				out.push(' ');
+				write_value(value, out)?;
+			}
+			out.push('}');
+		},
+		value => return Err(eyre!("can't represent {value:#} as EDN")),
+	}
+	Ok(())
+}
+
+fn write_seq<'a>(
+	open: &str,
+	items: impl Iterator<Item = &'a MalType>,
+	close: &str,
+	out: &mut String,
+) -> Result<()> {
+	out.push_str(open);
+	for (idx, item) in items.enumerate() {
+		if idx > 0 {
+			out.push(' ');
+		}
+		write_value(item, out)?;
+	}
+	out.push_str(close);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_read() -> Result<()> {
+		assert_eq!(
+			read(
+				"{:a [1 -2.5 3N \\x \\newline] ; comment\n :b #_ skipped (sym \
+				 \"s\\n\") \"k\" #inst \"2020-01-01\"}"
+			)?,
+			reader::read_str(
+				r#"{:a [1 -2.5 3 \x \newline] :b (sym "s\n") "k" "2020-01-01"}"#
+			)?
+		);
+		assert_eq!(read("  ")?, MalType::Nil);
+		assert_eq!(read("#{1 #{[2]}}")?, reader::read_str("#{1 #{[2]}}")?);
+
+		let err = |input| read(input).unwrap_err().to_string();
+		assert_eq!(
+			err("[1 2\n  3)"),
+			"expected `]`, found `)` at line 2, column 4"
+		);
+		assert_eq!(
+			err("{:a 1\n :b}"),
+			"no value for hashmap key at line 2, column 2"
+		);
+		assert_eq!(err("(1 (2)"), "unclosed delimiter at line 1, column 1");
+		assert_eq!(
+			err("1 2"),
+			"expected end of input, found `2` at line 1, column 3"
+		);
+		assert_eq!(err("#foo 1"), "unsupported tag `#foo` at line 1, column 1");
+		Ok(())
+	}
+
+	#[test]
+	fn test_write() -> Result<()> {
+		let value =
+			reader::read_str(r#"(1 2.5 "a\"b" :kw sym nil [true \c \space])"#)?;
+		assert_eq!(
+			write(&value)?,
+			r#"(1 2.5 "a\"b" :kw sym nil [true \c \space])"#
+		);
+		assert_eq!(read(&write(&value)?)?, value);
+		assert_eq!(write(&reader::read_str("#{#{}}")?)?, "#{#{}}");
+		Ok(())
+	}
+}
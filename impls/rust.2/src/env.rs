@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::rc::Rc;
 
+use crate::convert::IntoFunction;
+use crate::convert::IntoMal;
 use crate::types::MalType;
 
 /// Note: this type is a shared reference *with interior mutability only*.
@@ -46,12 +48,29 @@ impl Env {
 		inner.data.insert(key, value);
 	}
 
+	/// Binds `name` to a Rust function, converting its arguments from and its
+	/// result to mal values. Calls with the wrong number or type of arguments
+	/// fail with an error naming the function.
+	pub fn register_fn<Args>(
+		&self,
+		name: impl Into<String>,
+		function: impl IntoFunction<Args>,
+	) {
+		let name = name.into();
+		self.set(name.clone(), function.into_function(name));
+	}
+
+	/// Binds `name` to a Rust value converted to a mal value.
+	pub fn register_value(&self, name: impl Into<String>, value: impl IntoMal) {
+		self.set(name.into(), value.into_mal());
+	}
+
 	pub fn find(&self, key: impl AsRef<str>) -> Option<Env> {
 		let inner = self.0.borrow();
-		if inner.data.get(key.as_ref()).is_some() {
+		if inner.data.contains_key(key.as_ref()) {
 			Some(self.clone())
 		} else {
-			inner.outer.as_ref().map(|outer| outer.find(key)).flatten()
+			inner.outer.as_ref().and_then(|outer| outer.find(key))
 		}
 	}
 
@@ -60,7 +79,20 @@ impl Env {
 		if let Some(res) = inner.data.get(key.as_ref()).cloned() {
 			Some(res)
 		} else {
-			inner.outer.as_ref().map(|outer| outer.get(key)).flatten()
+			inner.outer.as_ref().and_then(|outer| outer.get(key))
+		}
+	}
+
+	/// Calls `f` with what `key` is bound to, without cloning it.
+	pub fn inspect<R>(
+		&self,
+		key: impl AsRef<str>,
+		f: impl FnOnce(Option<&MalType>) -> R,
+	) -> R {
+		let inner = self.0.borrow();
+		match (inner.data.get(key.as_ref()), &inner.outer) {
+			(None, Some(outer)) => outer.inspect(key, f),
+			(value, _) => f(value),
 		}
 	}
 }
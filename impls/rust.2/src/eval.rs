@@ -0,0 +1,791 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+use eyre::Result;
+
+use crate::core;
+use crate::env::Env;
+use crate::reader;
+use crate::reader::Forms;
+use crate::special_forms;
+use crate::types::Frame;
+use crate::types::ListKind;
+use crate::types::MalType;
+use crate::types::TracedError;
+#[cfg(feature = "vm")]
+use crate::vm;
+
+/// A special form receives the unevaluated arguments of its list (i.e.
+/// without the leading symbol) and the environment it was evaluated in.
+pub type SpecialForm =
+	Rc<dyn Fn(&Interpreter, Vec<MalType>, Env) -> Result<Continuation>>;
+
+/// What a special form hands back to the evaluator.
+pub enum Continuation {
+	/// The form evaluated to this value.
+	Return(MalType),
+	/// The form's value is the result of evaluating this AST in this
+	/// environment. The evaluator loops on it instead of recursing, which is
+	/// what makes tail calls in `if`, `do`, `let*` and friends not grow the
+	/// stack.
+	Eval(MalType, Env),
+}
+
+/// Note: like `Env`, this is a shared reference with interior mutability.
+/// Closures created by `fn*` keep a clone of the interpreter that created
+/// them, so that calling them from builtins evaluates with the same special
+/// forms.
+#[derive(Clone)]
+pub struct Interpreter(Rc<RefCell<InterpreterInner>>);
+
+struct InterpreterInner {
+	env:           Env,
+	special_forms: HashMap<String, SpecialForm>,
+	/// The mal functions currently being called, innermost call last.
+	stack:         Vec<Frame>,
+}
+
+impl Interpreter {
+	/// Creates an interpreter evaluating in `env`, without any special forms.
+	pub fn new(env: Env) -> Self {
+		Self(Rc::new(RefCell::new(InterpreterInner {
+			env,
+			special_forms: HashMap::new(),
+			stack: Vec::new(),
+		})))
+	}
+
+	/// Creates an interpreter with everything a stepA mal provides: all
+	/// special forms, `core::ns()`, `eval`, an empty `*ARGV*` and the
+	/// functions defined in mal itself.
+	pub fn standard() -> Result<Self> {
+		let interpreter = Self::new(core::ns());
+		interpreter.set_special_form("def!", special_forms::def);
+		interpreter.set_special_form("defmacro!", special_forms::defmacro);
+		interpreter.set_special_form("do", special_forms::do_);
+		interpreter.set_special_form("fn*", special_forms::fn_star);
+		interpreter.set_special_form("if", special_forms::if_);
+		interpreter.set_special_form("lazy-seq", special_forms::lazy_seq);
+		interpreter.set_special_form("let*", special_forms::let_star);
+		interpreter.set_special_form("macroexpand", special_forms::macroexpand);
+		interpreter.set_special_form("quasiquote", special_forms::quasiquote);
+		interpreter.set_special_form(
+			"quasiquoteexpand",
+			special_forms::quasiquoteexpand,
+		);
+		interpreter.set_special_form("quote", special_forms::quote);
+		interpreter.set_special_form("try*", special_forms::try_star);
+
+		let env = interpreter.env();
+		env.set("eval".to_string(), interpreter.eval_function());
+		env.set("load-file".to_string(), interpreter.load_file_function());
+		env.set(
+			"*ARGV*".to_string(),
+			MalType::L(ListKind::List, Vec::new(), None),
+		);
+		#[cfg(feature = "vm")]
+		vm::register(&env);
+		interpreter.rep(r#"(def! *host-language* "rust.2")"#)?;
+		interpreter.rep("(def! not (fn* (a) (if a false true)))")?;
+		interpreter.rep(
+			r#"(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw "odd number of forms to cond")) (cons 'cond (rest (rest xs)))))))"#,
+		)?;
+
+		Ok(interpreter)
+	}
+
+	/// The top-level environment of this interpreter.
+	pub fn env(&self) -> Env {
+		self.0.borrow().env.clone()
+	}
+
+	/// Registers a special form, replacing any previous one with that name.
+	pub fn set_special_form(
+		&self,
+		name: impl Into<String>,
+		form: impl Fn(&Interpreter, Vec<MalType>, Env) -> Result<Continuation>
+			+ 'static,
+	) {
+		self.0
+			.borrow_mut()
+			.special_forms
+			.insert(name.into(), Rc::new(form));
+	}
+
+	pub fn special_form(&self, name: impl AsRef<str>) -> Option<SpecialForm> {
+		self.0.borrow().special_forms.get(name.as_ref()).cloned()
+	}
+
+	/// A builtin `eval` function, evaluating its argument in the top-level
+	/// environment.
+	pub fn eval_function(&self) -> MalType {
+		let interpreter = self.clone();
+		MalType::builtin(move |args| match args {
+			[ast] => interpreter.eval(ast.clone(), interpreter.env()),
+			_ => Err(eyre!("`eval` expects 1 parameter")),
+		})
+	}
+
+	/// Evaluates every form in the file at `path` in the top-level
+	/// environment, reading each one just before it's evaluated. The forms
+	/// remember the file they were read from, so that errors can point back
+	/// into it.
+	pub fn load_file(&self, path: &str) -> Result<MalType> {
+		let file = File::open(path)
+			.map_err(|err| eyre!("can't load `{path}`: {err}"))?;
+		for form in Forms::with_file(file, path) {
+			self.eval(form?, self.env())?;
+		}
+		Ok(MalType::Nil)
+	}
+
+	/// A builtin `load-file` function, see `load_file`.
+	pub fn load_file_function(&self) -> MalType {
+		let interpreter = self.clone();
+		MalType::builtin(move |args| match args {
+			[MalType::String(path)] => interpreter.load_file(path),
+			_ => Err(eyre!("`load-file` expects a file name")),
+		})
+	}
+
+	/// Reads, evaluates and prints `input` in the top-level environment.
+	pub fn rep(&self, input: &str) -> Result<String> {
+		let res_read = reader::read_str(input)?;
+		let res_eval = self.eval(res_read, self.env())?;
+		// realizing lazy seqs can fail, so that's done before printing
+		res_eval.realize()?;
+		Ok(format!("{res_eval:#}"))
+	}
+
+	/// Runs a read-eval-print loop over stdin until it's closed.
+	pub fn repl(&self) -> Result<()> {
+		// `readline` needs to read from stdin too, so it can't stay locked
+		let stdin = io::stdin();
+		let mut stdout = io::stdout();
+
+		loop {
+			print!("user> ");
+			stdout.flush()?;
+
+			let mut input = String::new();
+			match stdin.read_line(&mut input) {
+				Ok(0) => {
+					eprintln!("Goodbye.");
+					return Ok(());
+				},
+				Ok(_) => {},
+				Err(err) => {
+					eprintln!("Error reading from stdin: {}", err);
+					continue;
+				},
+			}
+			match self.rep(&input) {
+				Ok(result) => println!("{result}"),
+				Err(err) => println!("ERROR: {}", TracedError::describe(&err)),
+			}
+		}
+	}
+
+	/// Evaluates `ast` in `env`. Errors raised inside mal functions carry the
+	/// call stack at the point they were raised, see `TracedError`.
+	pub fn eval(&self, ast: MalType, env: Env) -> Result<MalType> {
+		self.traced(|depth| self.eval_frame(ast, env, depth))
+	}
+
+	/// Runs `f` with the depth of the call stack, attaching the stack to any
+	/// error it raises, and then pops any calls it left on the stack.
+	pub(crate) fn traced<T>(
+		&self,
+		f: impl FnOnce(usize) -> Result<T>,
+	) -> Result<T> {
+		let depth = self.stack_depth();
+		let res = f(depth).map_err(|err| self.trace_error(err));
+		self.truncate_stack(depth);
+		res
+	}
+
+	/// Attaches the call stack to `err`, unless it's empty or `err` already
+	/// has one.
+	pub(crate) fn trace_error(&self, err: eyre::Report) -> eyre::Report {
+		let inner = self.0.borrow();
+		if inner.stack.is_empty() || err.is::<TracedError>() {
+			return err;
+		}
+		let trace = inner.stack.iter().rev().cloned().collect();
+		TracedError { error: err, trace }.into()
+	}
+
+	pub(crate) fn stack_depth(&self) -> usize {
+		self.0.borrow().stack.len()
+	}
+
+	pub(crate) fn truncate_stack(&self, depth: usize) {
+		self.0.borrow_mut().stack.truncate(depth);
+	}
+
+	/// Pushes a frame for a call made by the `eval` that started at `depth`.
+	/// A frame that `eval` already pushed is replaced instead, since the call
+	/// is in tail position.
+	pub(crate) fn push_frame(&self, depth: usize, mut frame: Frame) {
+		let stack = &mut self.0.borrow_mut().stack;
+		if stack.len() > depth {
+			let top = stack.last_mut().unwrap();
+			frame.elided = top.elided + 1;
+			*top = frame;
+		} else {
+			stack.push(frame);
+		}
+	}
+
+	#[cfg(feature = "vm")]
+	fn eval_frame(
+		&self,
+		ast: MalType,
+		env: Env,
+		_depth: usize,
+	) -> Result<MalType> {
+		vm::eval(self, ast, env)
+	}
+
+	#[cfg(not(feature = "vm"))]
+	fn eval_frame(
+		&self,
+		mut ast: MalType,
+		mut env: Env,
+		depth: usize,
+	) -> Result<MalType> {
+		'eval: loop {
+			ast = self.macroexpand(ast, &env)?;
+			match ast {
+				MalType::L(ListKind::List, mut list, _) if !list.is_empty() => {
+					let form = match &list[0] {
+						MalType::Symbol(sym, _) => self.special_form(sym),
+						_ => None,
+					};
+					if let Some(form) = form {
+						list.remove(0);
+						match form(self, list, env)? {
+							Continuation::Return(value) => return Ok(value),
+							Continuation::Eval(new_ast, new_env) => {
+								ast = new_ast;
+								env = new_env;
+								continue 'eval;
+							},
+						}
+					}
+
+					let (site_name, site_span) = match &list[0] {
+						MalType::Symbol(sym, span) => {
+							(Some(sym.clone()), span.as_deref().cloned())
+						},
+						_ => (None, None),
+					};
+					let MalType::L(ListKind::List, mut list, _) = self
+						.eval_ast(
+							MalType::L(ListKind::List, list, None),
+							&env,
+						)?
+					else {
+						unreachable!("expected `eval_ast` to return a list");
+					};
+					return match list.remove(0) {
+						MalType::Function(closure, _) => {
+							self.0.borrow_mut().stack.push(Frame {
+								name:   site_name,
+								span:   site_span,
+								elided: 0,
+							});
+							let res = closure(&mut list)?;
+							self.0.borrow_mut().stack.pop();
+							Ok(res)
+						},
+						MalType::TCOFunction {
+							ast: new_ast,
+							params,
+							env: closed_env,
+							name,
+							..
+						} => {
+							self.push_frame(depth, Frame {
+								name:   name.or(site_name),
+								span:   site_span,
+								elided: 0,
+							});
+							ast = *new_ast;
+							env = gen_env_from_param_app(
+								// evaluate using closed env
+								Some(closed_env),
+								params.into_iter(),
+								list.into_iter(),
+							)?;
+							continue 'eval;
+						},
+						not => Err(eyre!("expected a function (got {not:#})")),
+					};
+				},
+				value => return self.eval_ast(value, &env),
+			}
+		}
+	}
+
+	pub fn eval_ast(&self, ast: MalType, env: &Env) -> Result<MalType> {
+		match ast {
+			MalType::Symbol(sym, span) => {
+				env.get(&sym).ok_or_else(|| {
+					match span.filter(|span| span.file.is_some()) {
+						Some(span) => eyre!("{span}: '{sym}' not found"),
+						None => eyre!("'{sym}' not found"),
+					}
+				})
+			},
+			MalType::L(kind, list, _) => Ok(MalType::L(
+				kind,
+				list.into_iter()
+					.map(|item| self.eval(item, env.clone()))
+					.collect::<Result<Vec<_>>>()?,
+				None,
+			)),
+			MalType::HashMap(map, _) => {
+				let mut evaluated = map.empty();
+				for (key, value) in map {
+					evaluated.insert(key, self.eval(value, env.clone())?)?;
+				}
+				Ok(MalType::HashMap(evaluated, None))
+			},
+			MalType::Set(set) => Ok(MalType::Set(
+				set.into_iter()
+					.map(|item| self.eval(item, env.clone()))
+					.collect::<Result<HashSet<_>>>()?,
+			)),
+			ast => Ok(ast),
+		}
+	}
+
+	/// Expands `ast` for as long as it's a call to a macro.
+	pub fn macroexpand(&self, mut ast: MalType, env: &Env) -> Result<MalType> {
+		while let Some(function) = macro_call(&ast, env) {
+			let MalType::L(_, list, _) = &mut ast else {
+				unreachable!("macro calls are lists");
+			};
+			ast = function.call(&mut list[1..])?;
+		}
+		Ok(ast)
+	}
+}
+
+fn macro_call(ast: &MalType, env: &Env) -> Option<MalType> {
+	let MalType::L(ListKind::List, list, _) = ast else {
+		return None;
+	};
+	let Some(MalType::Symbol(sym, _)) = list.first() else {
+		return None;
+	};
+	match env.get(sym) {
+		Some(function @ MalType::TCOFunction { is_macro: true, .. }) => {
+			Some(function)
+		},
+		_ => None,
+	}
+}
+
+pub fn quasiquote(ast: MalType) -> MalType {
+	fn is_form(list: &[MalType], name: &str) -> bool {
+		matches!(list, [MalType::Symbol(sym, _), _] if sym == name)
+	}
+
+	fn qq_iter(list: Vec<MalType>) -> MalType {
+		list.into_iter().rev().fold(
+			MalType::L(ListKind::List, Vec::new(), None),
+			|acc, elt| match elt {
+				MalType::L(ListKind::List, mut elt, _)
+					if is_form(&elt, "splice-unquote") =>
+				{
+					MalType::L(
+						ListKind::List,
+						vec![
+							MalType::Symbol("concat".to_string(), None),
+							elt.remove(1),
+							acc,
+						],
+						None,
+					)
+				},
+				elt => MalType::L(
+					ListKind::List,
+					vec![
+						MalType::Symbol("cons".to_string(), None),
+						quasiquote(elt),
+						acc,
+					],
+					None,
+				),
+			},
+		)
+	}
+
+	match ast {
+		MalType::L(ListKind::List, mut list, _)
+			if is_form(&list, "unquote") =>
+		{
+			list.remove(1)
+		},
+		MalType::L(ListKind::List, list, _) => qq_iter(list),
+		MalType::L(ListKind::Vector, list, _) => MalType::L(
+			ListKind::List,
+			vec![MalType::Symbol("vec".to_string(), None), qq_iter(list)],
+			None,
+		),
+		ast
+		@ (MalType::HashMap(..) | MalType::Set(_) | MalType::Symbol(..)) => {
+			MalType::L(
+				ListKind::List,
+				vec![MalType::Symbol("quote".to_string(), None), ast],
+				None,
+			)
+		},
+		ast => ast,
+	}
+}
+
+pub fn gen_env_from_param_app(
+	parent_env: Option<Env>,
+	mut param_names: impl Iterator<Item = String>,
+	mut args: impl Iterator<Item = MalType>,
+) -> Result<Env> {
+	let mut params = Vec::with_capacity(param_names.size_hint().0);
+	let mut va_bind = None;
+	while let Some(name) = param_names.next() {
+		if name == "&" {
+			let Some(va_name) = param_names.next() else {
+				return Err(eyre!(
+					"invalid `fn*` form: no variable binding for varargs"
+				));
+			};
+			va_bind = Some(va_name);
+		} else {
+			params.push(name);
+		}
+	}
+
+	let n_reg_args = params.len();
+	let reg_args = Iterator::take(&mut args, n_reg_args);
+	let res = Env::new_with_bindings(parent_env, params.into_iter(), reg_args);
+
+	if let Some(va_bind) = va_bind {
+		res.set(va_bind, MalType::L(ListKind::List, args.collect(), None));
+	}
+
+	Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::iter;
+
+	use super::*;
+	use crate::number::Number;
+
+	#[test]
+	fn test_gen_env_from_param_app() -> Result<()> {
+		let basic = gen_env_from_param_app(None, iter::empty(), iter::empty())?;
+		assert_eq!(basic, Env::new(None));
+
+		let one_arg = gen_env_from_param_app(
+			None,
+			iter::once("foo".to_string()),
+			iter::once(MalType::Nil),
+		)?;
+		assert_eq!(
+			one_arg,
+			Env::new_with_bindings_list(
+				None,
+				iter::once(("foo".to_string(), MalType::Nil))
+			)
+		);
+
+		let multi_args = gen_env_from_param_app(
+			None,
+			vec!["foo".to_string(), "bar".to_string(), "quux".to_string()]
+				.into_iter(),
+			vec![
+				MalType::Nil,
+				MalType::Number(Number::Int(1)),
+				MalType::Bool(true),
+			]
+			.into_iter(),
+		)?;
+		assert_eq!(
+			multi_args,
+			Env::new_with_bindings_list(
+				None,
+				vec![
+					("foo".to_string(), MalType::Nil),
+					("bar".to_string(), MalType::Number(Number::Int(1))),
+					("quux".to_string(), MalType::Bool(true))
+				]
+				.into_iter()
+			)
+		);
+
+		let only_varargs = gen_env_from_param_app(
+			None,
+			vec!["&".to_string(), "va".to_string()].into_iter(),
+			vec![
+				MalType::Nil,
+				MalType::Number(Number::Int(1)),
+				MalType::Bool(true),
+			]
+			.into_iter(),
+		)?;
+		assert_eq!(
+			only_varargs,
+			Env::new_with_bindings_list(
+				None,
+				vec![(
+					"va".to_string(),
+					MalType::L(
+						ListKind::List,
+						vec![
+							MalType::Nil,
+							MalType::Number(Number::Int(1)),
+							MalType::Bool(true)
+						],
+						None,
+					)
+				)]
+				.into_iter()
+			)
+		);
+
+		let with_varargs = gen_env_from_param_app(
+			None,
+			vec![
+				"foo".to_string(),
+				"bar".to_string(),
+				"&".to_string(),
+				"va".to_string(),
+			]
+			.into_iter(),
+			vec![
+				MalType::Nil,
+				MalType::Number(Number::Int(1)),
+				MalType::Bool(true),
+				MalType::Bool(false),
+				MalType::Keyword("cool".to_string()),
+				MalType::String("hello, world!".to_string()),
+			]
+			.into_iter(),
+		)?;
+		assert_eq!(
+			with_varargs,
+			Env::new_with_bindings_list(
+				None,
+				vec![
+					("foo".to_string(), MalType::Nil),
+					("bar".to_string(), MalType::Number(Number::Int(1))),
+					(
+						"va".to_string(),
+						MalType::L(
+							ListKind::List,
+							vec![
+								MalType::Bool(true),
+								MalType::Bool(false),
+								MalType::Keyword("cool".to_string()),
+								MalType::String("hello, world!".to_string()),
+							],
+							None,
+						)
+					)
+				]
+				.into_iter()
+			)
+		);
+
+		let empty_varargs = gen_env_from_param_app(
+			None,
+			vec!["foo".to_string(), "&".to_string(), "va".to_string()]
+				.into_iter(),
+			vec![MalType::Nil].into_iter(),
+		)?;
+		assert_eq!(
+			empty_varargs,
+			Env::new_with_bindings_list(
+				None,
+				vec![
+					("foo".to_string(), MalType::Nil),
+					(
+						"va".to_string(),
+						MalType::L(ListKind::List, vec![], None)
+					)
+				]
+				.into_iter()
+			)
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_rep() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		assert_eq!(interpreter.rep("(+ 1 2)")?, "3");
+		interpreter.rep(
+			"(def! sum (fn* (n acc) (if (= n 0) acc (sum (- n 1) (+ n acc)))))",
+		)?;
+		assert_eq!(interpreter.rep("(sum 10000 0)")?, "50005000");
+		assert_eq!(
+			interpreter
+				.rep(r#"(try* (throw {:a 1}) (catch* e (get e :a)))"#)?,
+			"1"
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_stack_trace() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		interpreter.rep("(def! inner (fn* () (throw :oops)))")?;
+		interpreter.rep(
+			"(def! count-down (fn* (n) (if (= n 0) (inner) (count-down (- n \
+			 1)))))",
+		)?;
+		interpreter.rep("(def! outer (fn* () (do (count-down 3) nil)))")?;
+
+		let err = interpreter.rep("(outer)").unwrap_err();
+		assert_eq!(
+			TracedError::describe(&err),
+			":oops\n  at throw (1:22)\n  at inner (1:40) [4 tail calls \
+			 elided]\n  at outer (1:2)"
+		);
+		assert_eq!(
+			interpreter.rep(
+				"(try* (outer) (catch* e (map (fn* (f) (get f :name)) \
+				 *stack-trace*)))"
+			)?,
+			r#"("throw" "inner" "outer")"#
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_custom_special_form() -> Result<()> {
+		let interpreter = Interpreter::new(Env::new(None));
+		interpreter.set_special_form("when", |interpreter, mut args, env| {
+			let cond = args.remove(0);
+			Ok(match interpreter.eval(cond, env.clone())? {
+				MalType::Bool(false) | MalType::Nil => {
+					Continuation::Return(MalType::Nil)
+				},
+				_ => Continuation::Eval(args.remove(0), env),
+			})
+		});
+		interpreter
+			.env()
+			.set("x".to_string(), MalType::Number(Number::Int(1)));
+
+		assert_eq!(interpreter.rep("(when true x)")?, "1");
+		assert_eq!(interpreter.rep("(when nil x)")?, "nil");
+		assert!(interpreter.rep("(if true x)").is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn test_def_binds_innermost_env() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		for (input, output) in [
+			("(let* (x 1) (do (def! x 2) x))", "2"),
+			("(let* (y 1) (do (def! z y) z))", "1"),
+			("((fn* () (do (def! a 3) a)))", "3"),
+			("(def! g (fn* () (do (def! b 4) b)))", "#<function>"),
+			("(g)", "4"),
+			("(try* (throw 5) (catch* e (do (def! c e) c)))", "5"),
+			("(def! d 1)", "1"),
+			("(let* (f (fn* () d)) (do (def! d 2) (f)))", "2"),
+			("(let* () (do (if false (def! d 3)) d))", "1"),
+			("d", "1"),
+		] {
+			assert_eq!(interpreter.rep(input)?, output, "{input}");
+		}
+		for name in ["x", "z", "a", "b", "c"] {
+			assert_eq!(
+				interpreter.rep(name).unwrap_err().to_string(),
+				format!("'{name}' not found")
+			);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_def_expands_once_in_nested_envs() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		interpreter.rep("(def! expansions (atom 0))")?;
+		interpreter.rep(
+			"(defmacro! counted (fn* (x) (do (swap! expansions inc) x)))",
+		)?;
+		let mut form = "(+ a0 a1 a2 a3 a4 a5 a6 a7 a8 a9)".to_string();
+		for i in (0..10).rev() {
+			form =
+				format!("(let* (x {i}) (do (def! a{i} (counted x)) {form}))");
+		}
+		assert_eq!(interpreter.rep(&form)?, "45");
+		// the same count from the tree-walker and the VM
+		assert_eq!(interpreter.rep("@expansions")?, "10");
+		Ok(())
+	}
+
+	#[test]
+	fn test_macros_defined_after_use() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		for (input, output) in [
+			("(defmacro! m (fn* () 2))", "#<function>"),
+			("(def! h (fn* () (m)))", "#<function>"),
+			("(h)", "2"),
+			("(defmacro! m (fn* () 1))", "#<function>"),
+			("(h)", "1"),
+			("(def! uses-later (fn* () (later)))", "#<function>"),
+			(
+				"(defmacro! later (fn* () '(quote expanded)))",
+				"#<function>",
+			),
+			("(uses-later)", "expanded"),
+			("(def! expands (fn* () (macroexpand (m))))", "#<function>"),
+			("(expands)", "1"),
+			("(defmacro! m (fn* () 3))", "#<function>"),
+			("(expands)", "3"),
+			(
+				"(def! count-down (fn* (n) (if (= n 0) :done (dec-later n))))",
+				"#<function>",
+			),
+			(
+				"(defmacro! dec-later (fn* (n) `(count-down (- ~n 1))))",
+				"#<function>",
+			),
+			("(count-down 10000)", ":done"),
+		] {
+			assert_eq!(interpreter.rep(input)?, output, "{input}");
+		}
+		Ok(())
+	}
+
+	#[test]
+	#[cfg(feature = "vm")]
+	fn test_disassemble() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		assert_eq!(
+			interpreter.rep("(disassemble (fn* (x) x))")?,
+			r#""   0  load 0               ; x\n   1  return\n""#
+		);
+		interpreter.rep("(def! f (fn* (n) (if (= n 0) n (f (- n 1)))))")?;
+		assert_eq!(
+			interpreter
+				.rep(r#"(re-find #"tail-call 1 +; f" (disassemble f))"#)?,
+			r#""tail-call 1          ; f""#
+		);
+		assert!(interpreter.rep("(disassemble +)").is_err());
+		Ok(())
+	}
+}
@@ -0,0 +1,388 @@
+//! JSON support for the `json-parse` and `json-stringify` builtins.
+//!
+//! Objects become hashmaps (with string keys, or keyword keys if
+//! `keywordize_keys` is set), arrays become vectors and `null` becomes `nil`.
+//! Keywords and symbols are written as strings holding their names.
+
+use std::fmt::Display;
+
+use eyre::Result;
+
+use crate::map::MalMap;
+use crate::number::Number;
+use crate::reader;
+use crate::types::ListKind;
+use crate::types::MalType;
+
+pub fn parse(input: &str, keywordize_keys: bool) -> Result<MalType> {
+	let mut parser = Parser {
+		input,
+		posn: 0,
+		keywordize_keys,
+	};
+	let value = parser.parse_value()?;
+	parser.skip_whitespace();
+	if parser.peek().is_some() {
+		return Err(parser.unexpected("end of input"));
+	}
+	Ok(value)
+}
+
+pub fn stringify(value: &MalType) -> Result<String> {
+	let mut out = String::new();
+	write_value(value, &mut out)?;
+	Ok(out)
+}
+
+struct Parser<'source> {
+	input:           &'source str,
+	posn:            usize,
+	keywordize_keys: bool,
+}
+
+impl<'source> Parser<'source> {
+	fn error_at(&self, posn: usize, msg: impl Display) -> eyre::Report {
+		let (line, col) = reader::line_col(self.input, posn);
+		eyre!("{msg} at line {line}, column {col}")
+	}
+
+	fn unexpected(&self, expected: &str) -> eyre::Report {
+		match self.peek() {
+			Some(ch) => self.error_at(
+				self.posn,
+				format!("expected {expected}, found `{ch}`"),
+			),
+			None => self.error_at(
+				self.posn,
+				format!("expected {expected}, found end of input"),
+			),
+		}
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.input[self.posn..].chars().next()
+	}
+
+	fn bump(&mut self) -> Option<char> {
+		let ch = self.peek()?;
+		self.posn += ch.len_utf8();
+		Some(ch)
+	}
+
+	fn eat(&mut self, ch: char) -> bool {
+		let res = self.peek() == Some(ch);
+		if res {
+			self.posn += ch.len_utf8();
+		}
+		res
+	}
+
+	fn skip_whitespace(&mut self) {
+		while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+			self.posn += 1;
+		}
+	}
+
+	fn parse_value(&mut self) -> Result<MalType> {
+		self.skip_whitespace();
+		match self.peek() {
+			Some('{') => self.parse_object(),
+			Some('[') => self.parse_array(),
+			Some('"') => self.parse_string().map(MalType::String),
+			Some('-' | '0'..='9') => self.parse_number(),
+			Some('t') => self.parse_literal("true", MalType::Bool(true)),
+			Some('f') => self.parse_literal("false", MalType::Bool(false)),
+			Some('n') => self.parse_literal("null", MalType::Nil),
+			_ => Err(self.unexpected("a JSON value")),
+		}
+	}
+
+	fn parse_literal(
+		&mut self,
+		literal: &str,
+		value: MalType,
+	) -> Result<MalType> {
+		if !self.input[self.posn..].starts_with(literal) {
+			return Err(self.unexpected("a JSON value"));
+		}
+		self.posn += literal.len();
+		Ok(value)
+	}
+
+	fn parse_array(&mut self) -> Result<MalType> {
+		self.eat('[');
+		let mut list = Vec::new();
+		self.skip_whitespace();
+		if self.eat(']') {
+			return Ok(MalType::L(ListKind::Vector, list, None));
+		}
+		loop {
+			list.push(self.parse_value()?);
+			self.skip_whitespace();
+			if self.eat(']') {
+				break Ok(MalType::L(ListKind::Vector, list, None));
+			} else if !self.eat(',') {
+				break Err(self.unexpected("`,` or `]`"));
+			}
+		}
+	}
+
+	fn parse_object(&mut self) -> Result<MalType> {
+		self.eat('{');
+		let mut map = MalMap::new();
+		self.skip_whitespace();
+		if self.eat('}') {
+			return Ok(MalType::HashMap(map, None));
+		}
+		loop {
+			self.skip_whitespace();
+			if self.peek() != Some('"') {
+				break Err(self.unexpected("a string key"));
+			}
+			let key = self.parse_string()?;
+			let key = if self.keywordize_keys {
+				MalType::Keyword(key)
+			} else {
+				MalType::String(key)
+			};
+			self.skip_whitespace();
+			if !self.eat(':') {
+				break Err(self.unexpected("`:`"));
+			}
+			map.insert(key, self.parse_value()?)?;
+			self.skip_whitespace();
+			if self.eat('}') {
+				break Ok(MalType::HashMap(map, None));
+			} else if !self.eat(',') {
+				break Err(self.unexpected("`,` or `}`"));
+			}
+		}
+	}
+
+	fn parse_string(&mut self) -> Result<String> {
+		let start = self.posn;
+		self.eat('"');
+		let mut string = String::new();
+		loop {
+			let posn = self.posn;
+			match self.bump() {
+				None => break Err(self.error_at(start, "unterminated string")),
+				Some('"') => break Ok(string),
+				Some('\\') => string.push(match self.bump() {
+					Some('"') => '"',
+					Some('\\') => '\\',
+					Some('/') => '/',
+					Some('b') => '\u{8}',
+					Some('f') => '\u{c}',
+					Some('n') => '\n',
+					Some('r') => '\r',
+					Some('t') => '\t',
+					Some('u') => self.parse_unicode_escape(posn)?,
+					_ => {
+						break Err(
+							self.error_at(posn, "invalid escape sequence")
+						)
+					},
+				}),
+				Some(ch) if ch < ' ' => {
+					break Err(
+						self.error_at(posn, "control character in string")
+					)
+				},
+				Some(ch) => string.push(ch),
+			}
+		}
+	}
+
+	/// Parses the `XXXX` of a `\uXXXX` escape starting at `start`, including
+	/// the second half of a surrogate pair.
+	fn parse_unicode_escape(&mut self, start: usize) -> Result<char> {
+		let high = self.parse_hex4()?;
+		let code = match high {
+			0xD800..=0xDBFF => {
+				if !self.input[self.posn..].starts_with("\\u") {
+					return Err(self.error_at(start, "unpaired surrogate"));
+				}
+				self.posn += 2;
+				let low = self.parse_hex4()?;
+				if !(0xDC00..=0xDFFF).contains(&low) {
+					return Err(self.error_at(start, "unpaired surrogate"));
+				}
+				0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+			},
+			0xDC00..=0xDFFF => {
+				return Err(self.error_at(start, "unpaired surrogate"))
+			},
+			code => code,
+		};
+		char::from_u32(code)
+			.ok_or_else(|| self.error_at(start, "invalid escape sequence"))
+	}
+
+	fn parse_hex4(&mut self) -> Result<u32> {
+		let hex = self
+			.input
+			.get(self.posn..self.posn + 4)
+			.filter(|hex| hex.chars().all(|ch| ch.is_ascii_hexdigit()))
+			.ok_or_else(|| self.error_at(self.posn, "expected 4 hex digits"))?;
+		self.posn += 4;
+		Ok(u32::from_str_radix(hex, 16)?)
+	}
+
+	fn parse_number(&mut self) -> Result<MalType> {
+		let start = self.posn;
+		self.eat('-');
+		if !self.eat('0') && self.digits() == 0 {
+			return Err(self.unexpected("a digit"));
+		}
+		if self.eat('.') && self.digits() == 0 {
+			return Err(self.unexpected("a digit"));
+		}
+		if self.eat('e') || self.eat('E') {
+			let _ = self.eat('+') || self.eat('-');
+			if self.digits() == 0 {
+				return Err(self.unexpected("a digit"));
+			}
+		}
+		Ok(MalType::Number(self.input[start..self.posn].parse()?))
+	}
+
+	fn digits(&mut self) -> usize {
+		let start = self.posn;
+		while matches!(self.peek(), Some('0'..='9')) {
+			self.posn += 1;
+		}
+		self.posn - start
+	}
+}
+
+fn write_value(value: &MalType, out: &mut String) -> Result<()> {
+	match value {
+		MalType::Nil => out.push_str("null"),
+		MalType::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+		MalType::Number(num) if num.is_finite() => match num {
+			// JSON has no ratios
+			Number::Ratio(_) => out.push_str(&num.to_f64().to_string()),
+			num => out.push_str(&num.to_string()),
+		},
+		MalType::Keyword(string)
+		| MalType::String(string)
+		| MalType::Symbol(string, _) => write_string(string, out),
+		// JSON has no chars
+		MalType::Char(ch) => write_string(&ch.to_string(), out),
+		// JSON has no sets
+		MalType::L(_, list, _) => write_array(list.iter(), out)?,
+		MalType::Set(set) => write_array(set.iter(), out)?,
+		MalType::LazySeq(_) => {
+			let items = value.items().collect::<Result<Vec<_>>>()?;
+			write_array(items.iter(), out)?
+		},
+		MalType::HashMap(map, _) => {
+			out.push('{');
+			for (idx, (key, value)) in map.iter().enumerate() {
+				if idx > 0 {
+					out.push(',');
+				}
+				let (MalType::Keyword(key) | MalType::String(key)) = key else {
+					return Err(eyre!(
+						"can't use {key:#} as a JSON object key"
+					));
+				};
+				write_string(key, out);
+				out.push(':');
+				write_value(value, out)?;
+			}
+			out.push('}');
+		},
+		value => return Err(eyre!("can't represent {value:#} as JSON")),
+	}
+	Ok(())
+}
+
+fn write_array<'a>(
+	items: impl Iterator<Item = &'a MalType>,
+	out: &mut String,
+) -> Result<()> {
+	out.push('[');
+	for (idx, item) in items.enumerate() {
+		if idx > 0 {
+			out.push(',');
+		}
+		write_value(item, out)?;
+	}
+	out.push(']');
+	Ok(())
+}
+
+fn write_string(string: &str, out: &mut String) {
+	out.push('"');
+	for ch in string.chars() {
+		match ch {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			ch if ch < ' ' => out.push_str(&format!("\\u{:04x}", ch as u32)),
+			ch => out.push(ch),
+		}
+	}
+	out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse() -> Result<()> {
+		assert_eq!(
+			parse(r#" {"a": [1, -2.5e1, "é😀\n"], "b": null} "#, true)?,
+			reader::read_str(r#"{:a [1 -25.0 "é😀\n"] :b nil}"#)?
+		);
+		assert_eq!(
+			parse(r#"{"a": {"b": true}}"#, false)?,
+			reader::read_str(r#"{"a" {"b" true}}"#)?
+		);
+
+		let err = |input| parse(input, false).unwrap_err().to_string();
+		assert_eq!(
+			err("{\"a\": 1,\n \"b\" 2}"),
+			"expected `:`, found `2` at line 2, column 6"
+		);
+		assert_eq!(
+			err("[1, 2"),
+			"expected `,` or `]`, found end of input at line 1, column 6"
+		);
+		assert_eq!(err(r#"["abc"#), "unterminated string at line 1, column 2");
+		assert_eq!(
+			err("01"),
+			"expected end of input, found `1` at line 1, column 2"
+		);
+		assert_eq!(
+			err(r#""\x""#),
+			"invalid escape sequence at line 1, column 2"
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_stringify() -> Result<()> {
+		let value = reader::read_str(r#"[1 2.5 "a\"b" :kw nil true {:k []}]"#)?;
+		assert_eq!(
+			stringify(&value)?,
+			r#"[1,2.5,"a\"b","kw",null,true,{"k":[]}]"#
+		);
+		assert_eq!(
+			parse(&stringify(&value)?, true)?,
+			reader::read_str(r#"[1 2.5 "a\"b" "kw" nil true {:k []}]"#)?
+		);
+		assert_eq!(
+			stringify(&reader::read_str("{1 2}")?)
+				.unwrap_err()
+				.to_string(),
+			"can't use 1 as a JSON object key"
+		);
+		Ok(())
+	}
+}
@@ -0,0 +1,212 @@
+//! Lazy seqs, whose items are only produced as they're needed, so they can be
+//! infinite, or stream data that wouldn't fit in memory.
+//!
+//! A lazy seq starts out as a thunk. Once it's been realized, it caches its
+//! first item and the rest of the seq, which is usually another lazy seq.
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use eyre::Result;
+
+use crate::types::ListKind;
+use crate::types::MalType;
+
+type Thunk = Rc<dyn Fn() -> Result<MalType>>;
+
+/// The first item and the rest of a seq, or `None` if it's empty.
+pub type Step = Option<(MalType, MalType)>;
+
+pub struct Lazy(RefCell<State>);
+
+enum State {
+	Pending(Thunk),
+	Realized(Step),
+}
+
+impl Lazy {
+	/// A lazy seq of whatever seq `thunk` returns, which is only called once
+	/// it's needed.
+	pub fn seq(thunk: impl Fn() -> Result<MalType> + 'static) -> MalType {
+		MalType::LazySeq(Rc::new(Self(RefCell::new(State::Pending(Rc::new(
+			thunk,
+		))))))
+	}
+
+	/// `first` followed by `rest`, which is left as it is rather than
+	/// realized.
+	pub fn cons(first: MalType, rest: MalType) -> MalType {
+		MalType::LazySeq(Rc::new(Self(RefCell::new(State::Realized(Some((
+			first, rest,
+		)))))))
+	}
+
+	fn pending(&self) -> Option<Thunk> {
+		match &*self.0.borrow() {
+			State::Pending(thunk) => Some(thunk.clone()),
+			State::Realized(_) => None,
+		}
+	}
+
+	/// Realizes the seq as far as its first item. The thunk is only called
+	/// again if it failed.
+	pub fn realize(&self) -> Result<Step> {
+		let Some(thunk) = self.pending() else {
+			return match &*self.0.borrow() {
+				State::Realized(step) => Ok(step.clone()),
+				State::Pending(_) => unreachable!(),
+			};
+		};
+		// A thunk returning another lazy seq, and so on, is common when items
+		// are skipped. The chain is followed in a loop, as recursing could
+		// overflow the stack.
+		let mut chain = Vec::new();
+		let mut value = thunk()?;
+		while let MalType::LazySeq(inner) = &value {
+			let inner = inner.clone();
+			let Some(thunk) = inner.pending() else {
+				break;
+			};
+			value = thunk()?;
+			chain.push(inner);
+		}
+		let step = match value {
+			// taking the rest of a list copies it, so it's linked up instead
+			MalType::L(_, list, _) => {
+				let mut items = list.into_iter();
+				items.next().map(|first| {
+					let rest = items.rev().fold(MalType::Nil, |rest, item| {
+						Self::cons(item, rest)
+					});
+					(first, rest)
+				})
+			},
+			value => value.uncons()?,
+		};
+		for lazy in chain.iter().map(|lazy| &**lazy).chain([self]) {
+			*lazy.0.borrow_mut() = State::Realized(step.clone());
+		}
+		Ok(step)
+	}
+
+	fn take_rest(&mut self) -> Option<MalType> {
+		match mem::replace(self.0.get_mut(), State::Realized(None)) {
+			State::Realized(Some((_, rest))) => Some(rest),
+			_ => None,
+		}
+	}
+}
+
+/// Dropping a long realized seq recursively would overflow the stack, so the
+/// parts that aren't shared are unlinked one at a time.
+impl Drop for Lazy {
+	fn drop(&mut self) {
+		let mut rest = self.take_rest();
+		while let Some(MalType::LazySeq(lazy)) = rest {
+			rest = Rc::try_unwrap(lazy)
+				.ok()
+				.and_then(|mut lazy| lazy.take_rest());
+		}
+	}
+}
+
+/// The items of a seq, realizing lazy seqs as they're reached. An error ends
+/// the iteration.
+pub struct Items {
+	buffered: std::vec::IntoIter<MalType>,
+	rest:     MalType,
+}
+
+impl Items {
+	pub fn new(seq: MalType) -> Self {
+		Self {
+			buffered: Vec::new().into_iter(),
+			rest:     seq,
+		}
+	}
+}
+
+impl Iterator for Items {
+	type Item = Result<MalType>;
+
+	fn next(&mut self) -> Option<Result<MalType>> {
+		loop {
+			if let Some(item) = self.buffered.next() {
+				return Some(Ok(item));
+			}
+			match mem::replace(&mut self.rest, MalType::Nil) {
+				MalType::L(_, list, _) => self.buffered = list.into_iter(),
+				MalType::Set(set) => {
+					self.buffered =
+						set.into_iter().collect::<Vec<_>>().into_iter()
+				},
+				MalType::Nil => return None,
+				seq => {
+					return match seq.uncons() {
+						Ok(Some((first, rest))) => {
+							self.rest = rest;
+							Some(Ok(first))
+						},
+						Ok(None) => None,
+						Err(err) => Some(Err(err)),
+					}
+				},
+			}
+		}
+	}
+}
+
+impl MalType {
+	/// Splits a seq into its first item and the rest, or `None` if it's
+	/// empty, realizing a lazy seq as far as its first item. The rest of a
+	/// list is a copy, so use `items` to walk one.
+	pub fn uncons(&self) -> Result<Step> {
+		match self {
+			Self::L(_, list, _) => {
+				Ok(list.split_first().map(|(first, rest)| {
+					(
+						first.clone(),
+						Self::L(ListKind::List, rest.to_vec(), None),
+					)
+				}))
+			},
+			Self::Set(set) => {
+				Self::L(ListKind::List, set.iter().cloned().collect(), None)
+					.uncons()
+			},
+			Self::LazySeq(lazy) => lazy.realize(),
+			Self::Nil => Ok(None),
+			value => Err(eyre!("can't make a seq of {value:#}")),
+		}
+	}
+
+	/// The items of a seq. See `into_items`.
+	pub fn items(&self) -> Items {
+		Items::new(self.clone())
+	}
+
+	/// The items of a seq, realizing a lazy seq as they're reached. Unlike
+	/// `items`, the head of a lazy seq isn't kept, so the items that have
+	/// been passed can be freed.
+	pub fn into_items(self) -> Items {
+		Items::new(self)
+	}
+
+	/// Realizes every lazy seq within this value, since printing can't fail.
+	pub fn realize(&self) -> Result<()> {
+		match self {
+			Self::Atom(atom) => atom.borrow().realize(),
+			Self::HashMap(map, _) => map.iter().try_for_each(|(key, value)| {
+				key.realize().and(value.realize())
+			}),
+			Self::L(_, list, _) => list.iter().try_for_each(Self::realize),
+			Self::Set(set) => set.iter().try_for_each(Self::realize),
+			Self::Reduced(value) => value.realize(),
+			Self::LazySeq(_) => {
+				self.items().try_for_each(|item| item?.realize())
+			},
+			_ => Ok(()),
+		}
+	}
+}
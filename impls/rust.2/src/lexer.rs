@@ -0,0 +1,488 @@
+//! Splits mal source into tokens, lazily and without backtracking. Each token
+//! borrows its text from the source and knows where it came from.
+
+use std::fmt::Display;
+use std::sync::Arc;
+
+use eyre::Result;
+
+use crate::types::Span;
+use crate::types::CHAR_NAMES;
+
+/// A token, along with where it appeared in the source.
+#[derive(Clone, Debug)]
+pub struct Token<'source> {
+	pub text: &'source str,
+	pub span: Span,
+}
+
+/// An iterator over the tokens of `input`. Whitespace, commas and comments
+/// are skipped. After an error, lexing resumes after the offending token.
+pub struct Lexer<'source> {
+	input: &'source str,
+	file:  Option<Arc<str>>,
+	posn:  usize,
+	line:  usize,
+	col:   usize,
+}
+
+impl<'source> Lexer<'source> {
+	pub fn new(input: &'source str, file: Option<Arc<str>>) -> Self {
+		Self {
+			input,
+			file,
+			posn: 0,
+			line: 1,
+			col: 1,
+		}
+	}
+
+	/// Creates a lexer over `input`, which continues a source at `line` and
+	/// `col`. Spans are still offsets into `input`.
+	pub fn resume(
+		input: &'source str,
+		file: Option<Arc<str>>,
+		line: usize,
+		col: usize,
+	) -> Self {
+		Self {
+			line,
+			col,
+			..Self::new(input, file)
+		}
+	}
+
+	/// The byte offset of the next token, or of the end of the input.
+	pub fn posn(&self) -> usize {
+		self.posn
+	}
+
+	/// Whether all of the input has been lexed.
+	pub fn at_end(&self) -> bool {
+		self.posn == self.input.len()
+	}
+
+	/// The empty span at the current position.
+	pub fn here(&self) -> Span {
+		self.span(self.posn, self.line, self.col)
+	}
+
+	fn peek(&self) -> Option<u8> {
+		self.input.as_bytes().get(self.posn).copied()
+	}
+
+	fn bump(&mut self) -> Option<u8> {
+		let byte = self.peek()?;
+		self.posn += 1;
+		if byte == b'\n' {
+			self.line += 1;
+			self.col = 1;
+		} else if byte & 0xC0 != 0x80 {
+			// not a UTF-8 continuation byte
+			self.col += 1;
+		}
+		Some(byte)
+	}
+
+	/// The length of the whitespace character at the current position, if
+	/// there's one there.
+	fn whitespace(&self) -> Option<usize> {
+		match self.peek()? {
+			b' ' | b'\t' | b'\n' | b'\r' | b'\x0B' | b'\x0C' => Some(1),
+			byte if byte >= 0xC0 => self.input[self.posn..]
+				.chars()
+				.next()
+				.filter(|ch| ch.is_whitespace())
+				.map(char::len_utf8),
+			_ => None,
+		}
+	}
+
+	fn at_delimiter(&self) -> bool {
+		match self.peek() {
+			None => true,
+			Some(
+				b'[' | b']' | b'{' | b'}' | b'(' | b')' | b'\'' | b'"' | b'`'
+				| b',' | b';',
+			) => true,
+			Some(_) => self.whitespace().is_some(),
+		}
+	}
+
+	fn skip_trivia(&mut self) {
+		loop {
+			if let Some(len) = self.whitespace() {
+				for _ in 0..len {
+					self.bump();
+				}
+			} else if self.peek() == Some(b',') {
+				self.bump();
+			} else if self.peek() == Some(b';') {
+				while !matches!(self.bump(), None | Some(b'\n')) {}
+			} else {
+				break;
+			}
+		}
+	}
+
+	fn span(&self, start: usize, line: usize, col: usize) -> Span {
+		Span {
+			file: self.file.clone(),
+			start,
+			end: self.posn,
+			line,
+			col,
+		}
+	}
+
+	/// Scans the rest of a string literal whose opening quote has been
+	/// consumed. On an invalid escape, this still scans to the end of the
+	/// literal before failing.
+	fn string(&mut self, start: Span) -> Result<()> {
+		let mut res = Ok(());
+		loop {
+			let (posn, line, col) = (self.posn, self.line, self.col);
+			match self.bump() {
+				None => {
+					let span = Span {
+						end: self.posn,
+						..start
+					};
+					return Err(error(
+						self.input,
+						&span,
+						"unbalanced string: expected `\"` before EOF",
+					));
+				},
+				Some(b'"') => return res,
+				Some(b'\\') => {
+					let valid = match self.bump() {
+						Some(b'n' | b't' | b'r' | b'0' | b'\\' | b'"') => true,
+						Some(b'u') => self.unicode_escape(),
+						// report the missing quote instead
+						None => continue,
+						Some(_) => false,
+					};
+					if !valid && res.is_ok() {
+						// include all of a multi-byte escaped character
+						while self.posn < self.input.len()
+							&& !self.input.is_char_boundary(self.posn)
+						{
+							self.bump();
+						}
+						res = Err(error(
+							self.input,
+							&self.span(posn, line, col),
+							format!(
+								"invalid escape sequence `{}`",
+								&self.input[posn..self.posn]
+							),
+						));
+					}
+				},
+				Some(_) => {},
+			}
+		}
+	}
+
+	/// Scans the rest of a regex literal whose opening `#"` has been
+	/// consumed. Escapes are left for the regex to check.
+	fn regex(&mut self, start: Span) -> Result<()> {
+		loop {
+			match self.bump() {
+				None => {
+					let span = Span {
+						end: self.posn,
+						..start
+					};
+					return Err(error(
+						self.input,
+						&span,
+						"unbalanced regex: expected `\"` before EOF",
+					));
+				},
+				Some(b'"') => return Ok(()),
+				Some(b'\\') => {
+					self.bump();
+				},
+				Some(_) => {},
+			}
+		}
+	}
+
+	/// Scans the rest of a `\uXXXX` or `\u{XXXX}` escape, returning whether
+	/// it's a valid code point.
+	fn unicode_escape(&mut self) -> bool {
+		let braced = self.peek() == Some(b'{');
+		if braced {
+			self.bump();
+		}
+		let start = self.posn;
+		while self.peek().is_some_and(|byte| byte.is_ascii_hexdigit())
+			&& (braced || self.posn - start < 4)
+		{
+			self.bump();
+		}
+		let hex = &self.input[start..self.posn];
+		let closed =
+			!braced || self.peek() == Some(b'}') && self.bump().is_some();
+		let len_ok = if braced {
+			(1..=6).contains(&hex.len())
+		} else {
+			hex.len() == 4
+		};
+		closed
+			&& len_ok && u32::from_str_radix(hex, 16)
+			.ok()
+			.and_then(char::from_u32)
+			.is_some()
+	}
+}
+
+impl<'source> Iterator for Lexer<'source> {
+	type Item = Result<Token<'source>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.skip_trivia();
+		let (start, line, col) = (self.posn, self.line, self.col);
+		let res = match self.bump()? {
+			b'~' if self.peek() == Some(b'@') => {
+				self.bump();
+				Ok(())
+			},
+			b'#' if self.peek() == Some(b'{') => {
+				self.bump();
+				Ok(())
+			},
+			b'#' if self.peek() == Some(b'"') => {
+				self.bump();
+				self.regex(self.span(start, line, col))
+			},
+			b'[' | b']' | b'{' | b'}' | b'(' | b')' | b'\'' | b'`' | b'~'
+			| b'^' | b'@' => Ok(()),
+			b'"' => self.string(self.span(start, line, col)),
+			// the char after the backslash is part of the token even if it's
+			// a delimiter, so that `\(` is a char
+			b'\\' => {
+				if self.bump().is_some() {
+					while !self.at_delimiter()
+						|| !self.input.is_char_boundary(self.posn)
+					{
+						self.bump();
+					}
+				}
+				Ok(())
+			},
+			_ => {
+				while !self.at_delimiter() {
+					self.bump();
+				}
+				Ok(())
+			},
+		};
+		Some(res.map(|()| Token {
+			text: &self.input[start..self.posn],
+			span: self.span(start, line, col),
+		}))
+	}
+}
+
+/// An error at `span` in `input`, followed by the line it's on with the span
+/// underlined.
+pub fn error(input: &str, span: &Span, msg: impl Display) -> eyre::Report {
+	let line_start = input[..span.start].rfind('\n').map_or(0, |idx| idx + 1);
+	let line_end = input[span.start..]
+		.find('\n')
+		.map_or(input.len(), |idx| span.start + idx);
+	let line = input[line_start..line_end].trim_end_matches('\r');
+	let padding = input[line_start..span.start]
+		.chars()
+		.map(|ch| if ch == '\t' { '\t' } else { ' ' })
+		.collect::<String>();
+	let width = input[span.start..span.end.clamp(span.start, line_end)]
+		.chars()
+		.count()
+		.max(1);
+	eyre!("{span}: {msg}\n{line}\n{padding}{}", "^".repeat(width))
+}
+
+/// The contents of a string literal token, with its escapes resolved. The
+/// token must have been produced by the lexer, which validates the escapes.
+pub fn unescape(literal: &str) -> String {
+	let literal = &literal[1..literal.len() - 1];
+	let mut res = String::with_capacity(literal.len());
+	let mut chars = literal.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			res.push(ch);
+			continue;
+		}
+		res.push(match chars.next() {
+			Some('n') => '\n',
+			Some('t') => '\t',
+			Some('r') => '\r',
+			Some('0') => '\0',
+			Some('u') => {
+				let hex = if chars.as_str().starts_with('{') {
+					chars
+						.by_ref()
+						.skip(1)
+						.take_while(|&ch| ch != '}')
+						.collect::<String>()
+				} else {
+					chars.by_ref().take(4).collect::<String>()
+				};
+				u32::from_str_radix(&hex, 16)
+					.ok()
+					.and_then(char::from_u32)
+					.unwrap_or(char::REPLACEMENT_CHARACTER)
+			},
+			Some(ch) => ch,
+			None => '\\',
+		});
+	}
+	res
+}
+
+/// The pattern of a regex literal token. Only `\"` is unescaped, as any other
+/// escape is meant for the regex.
+pub fn unescape_regex(literal: &str) -> String {
+	let literal = &literal[2..literal.len() - 1];
+	let mut res = String::with_capacity(literal.len());
+	let mut chars = literal.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			res.push(ch);
+			continue;
+		}
+		match chars.next() {
+			Some('"') => res.push('"'),
+			Some(escaped) => {
+				res.push('\\');
+				res.push(escaped);
+			},
+			None => res.push('\\'),
+		}
+	}
+	res
+}
+
+/// The char a char literal token like `\a`, `\newline` or `\u00e9` stands
+/// for, if it's valid.
+pub fn char_literal(literal: &str) -> Option<char> {
+	let name = &literal[1..];
+	let mut chars = name.chars();
+	if let (Some(ch), None) = (chars.next(), chars.next()) {
+		return Some(ch);
+	}
+	if let Some((_, ch)) = CHAR_NAMES.iter().find(|(named, _)| *named == name) {
+		return Some(*ch);
+	}
+	let hex = name.strip_prefix('u').filter(|hex| hex.len() == 4)?;
+	char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn texts(input: &str) -> Vec<&str> {
+		Lexer::new(input, None)
+			.map(|token| token.unwrap().text)
+			.collect()
+	}
+
+	#[test]
+	fn test_tokens() {
+		assert_eq!(
+			texts("(a ~@b, 'c) ; comment\n[\"d \\\" e\" -1.5]\u{a0}{:f ^g @h}"),
+			[
+				"(",
+				"a",
+				"~@",
+				"b",
+				"'",
+				"c",
+				")",
+				"[",
+				"\"d \\\" e\"",
+				"-1.5",
+				"]",
+				"{",
+				":f",
+				"^",
+				"g",
+				"@",
+				"h",
+				"}"
+			]
+		);
+		assert_eq!(texts("#{1 #a}"), ["#{", "1", "#a", "}"]);
+		assert_eq!(texts(";; only a comment"), Vec::<&str>::new());
+	}
+
+	#[test]
+	fn test_spans() {
+		let tokens = Lexer::new("(é\n  \"x\ny\" z)", Some("f.mal".into()))
+			.map(|token| token.unwrap().span.to_string())
+			.collect::<Vec<_>>();
+		assert_eq!(tokens, [
+			"f.mal:1:1",
+			"f.mal:1:2",
+			"f.mal:2:3",
+			"f.mal:3:4",
+			"f.mal:3:5"
+		]);
+	}
+
+	#[test]
+	fn test_strings() {
+		let mut lexer = Lexer::new(r#""a\tb\u{1F600}\q" ok "open"#, None);
+		let err = lexer.next().unwrap().unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"1:15: invalid escape sequence `\\q`\n\"a\\tb\\u{1F600}\\q\" ok \
+			 \"open\n              ^^"
+		);
+		assert_eq!(lexer.next().unwrap().unwrap().text, "ok");
+		let err = lexer.next().unwrap().unwrap_err();
+		assert!(err.to_string().starts_with("1:22: unbalanced string"));
+		assert!(lexer.next().is_none());
+
+		assert_eq!(unescape(r#""a\tb\u{1F600}\\\"""#), "a\tb😀\\\"");
+		assert_eq!(unescape(r#""\u0041\u00e9""#), "Aé");
+		assert!(Lexer::new(r#""\u004""#, None).next().unwrap().is_err());
+	}
+
+	#[test]
+	fn test_regexes() {
+		assert_eq!(texts(r#"(#"a\"b\q" #"")"#), [
+			"(",
+			r#"#"a\"b\q""#,
+			r#"#"""#,
+			")"
+		]);
+		let err = Lexer::new(r#"x #"open"#, None).nth(1).unwrap().unwrap_err();
+		assert!(err.to_string().starts_with("1:3: unbalanced regex"));
+
+		assert_eq!(unescape_regex(r#"#"a\"b\d\\""#), r#"a"b\d\\"#);
+	}
+
+	#[test]
+	fn test_chars() {
+		assert_eq!(texts(r"(\a \( \日 \newline \))"), [
+			"(",
+			r"\a",
+			r"\(",
+			r"\日",
+			r"\newline",
+			r"\)",
+			")"
+		]);
+		assert_eq!(char_literal(r"\日"), Some('日'));
+		assert_eq!(char_literal(r"\space"), Some(' '));
+		assert_eq!(char_literal(r"\u00e9"), Some('é'));
+		assert_eq!(char_literal(r"\ud800"), None);
+		assert_eq!(char_literal(r"\ab"), None);
+	}
+}
@@ -1,12 +1,29 @@
 #![feature(anonymous_lifetime_in_impl_trait)]
-#![feature(iter_intersperse)]
-#![feature(let_else)]
 
 #[macro_use]
 extern crate eyre;
 
+#[cfg(feature = "vm")]
+pub mod bytecode;
+#[cfg(feature = "vm")]
+pub mod compiler;
+pub mod convert;
 pub mod core;
+pub mod edn;
 pub mod env;
+pub mod eval;
+pub mod json;
+pub mod lazy;
+pub mod lexer;
+pub mod map;
+pub mod number;
 pub mod printer;
+pub mod re;
 pub mod reader;
+#[cfg(feature = "serde")]
+pub mod serde;
+pub mod special_forms;
+pub mod string;
 pub mod types;
+#[cfg(feature = "vm")]
+pub mod vm;
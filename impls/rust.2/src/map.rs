@@ -0,0 +1,276 @@
+//! Mal's hashmaps, which iterate in a predictable order: ordinary maps in the
+//! order their keys were first inserted, and sorted maps (from `sorted-map`
+//! and `sorted-map-by`) in key order.
+//!
+//! Sorted maps are B-trees ordered by a comparator, which may be a mal
+//! function. The tree can't propagate errors from a comparator, so the first
+//! one is parked in the comparator and reported once the operation is done.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use eyre::Result;
+
+use crate::number::Number;
+use crate::types::MalType;
+
+#[derive(Clone)]
+pub struct MalMap(Repr);
+
+#[derive(Clone)]
+enum Repr {
+	/// Each key is stamped with the order it was inserted in, and entries are
+	/// kept by stamp. Iterating never looks keys up, since a key containing
+	/// NaN isn't equal to itself.
+	Hashed {
+		stamps:  HashMap<MalType, u64>,
+		entries: BTreeMap<u64, (MalType, MalType)>,
+		next:    u64,
+	},
+	Sorted(BTreeMap<SortKey, MalType>, Rc<Comparator>),
+}
+
+/// How a sorted map, or `sort`, orders values.
+pub struct Comparator {
+	/// Orders by `MalType::compare` if there's no function.
+	function: Option<MalType>,
+	error:    RefCell<Option<eyre::Report>>,
+}
+
+#[derive(Clone)]
+struct SortKey {
+	key: MalType,
+	cmp: Rc<Comparator>,
+}
+
+impl Comparator {
+	pub fn new(function: Option<MalType>) -> Self {
+		Self {
+			function,
+			error: RefCell::new(None),
+		}
+	}
+
+	pub fn compare(&self, l: &MalType, r: &MalType) -> Result<Ordering> {
+		let Some(function) = &self.function else {
+			return l.compare(r);
+		};
+		// as in Clojure, either a number or a "less than" predicate
+		match function.call(&mut [l.clone(), r.clone()])? {
+			MalType::Number(num) => {
+				Ok(num.compare(&Number::Int(0)).unwrap_or(Ordering::Equal))
+			},
+			MalType::Bool(true) => Ok(Ordering::Less),
+			MalType::Bool(false) | MalType::Nil => {
+				match function.call(&mut [r.clone(), l.clone()])? {
+					MalType::Bool(false) | MalType::Nil => Ok(Ordering::Equal),
+					_ => Ok(Ordering::Greater),
+				}
+			},
+			res => Err(eyre!(
+				"comparators must return a number or boolean (got {res:#})"
+			)),
+		}
+	}
+
+	/// The error a comparator raised during the last operation, if any.
+	fn check(&self) -> Result<()> {
+		match self.error.take() {
+			Some(err) => Err(err),
+			None => Ok(()),
+		}
+	}
+}
+
+impl PartialEq for SortKey {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for SortKey {}
+
+impl PartialOrd for SortKey {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for SortKey {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.cmp
+			.compare(&self.key, &other.key)
+			.unwrap_or_else(|err| {
+				self.cmp.error.borrow_mut().get_or_insert(err);
+				Ordering::Equal
+			})
+	}
+}
+
+impl MalMap {
+	pub fn new() -> Self {
+		Self(Repr::Hashed {
+			stamps:  HashMap::new(),
+			entries: BTreeMap::new(),
+			next:    0,
+		})
+	}
+
+	/// An empty sorted map, ordered by the comparator `function`, or by
+	/// `MalType::compare` if there's none.
+	pub fn sorted(function: Option<MalType>) -> Self {
+		Self(Repr::Sorted(
+			BTreeMap::new(),
+			Rc::new(Comparator::new(function)),
+		))
+	}
+
+	/// An empty map of the same kind, so a sorted map keeps its comparator.
+	pub fn empty(&self) -> Self {
+		match &self.0 {
+			Repr::Hashed { .. } => Self::new(),
+			Repr::Sorted(_, cmp) => {
+				Self(Repr::Sorted(BTreeMap::new(), cmp.clone()))
+			},
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		match &self.0 {
+			Repr::Hashed { entries, .. } => entries.len(),
+			Repr::Sorted(tree, _) => tree.len(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn get(&self, key: &MalType) -> Result<Option<&MalType>> {
+		match &self.0 {
+			Repr::Hashed {
+				stamps, entries, ..
+			} => Ok(stamps.get(key).map(|stamp| &entries[stamp].1)),
+			Repr::Sorted(tree, cmp) => {
+				let value = tree.get(&sort_key(key, cmp));
+				cmp.check()?;
+				Ok(value)
+			},
+		}
+	}
+
+	pub fn contains_key(&self, key: &MalType) -> Result<bool> {
+		Ok(self.get(key)?.is_some())
+	}
+
+	/// Replacing the value of a key keeps its place in the order.
+	pub fn insert(&mut self, key: MalType, value: MalType) -> Result<()> {
+		match &mut self.0 {
+			Repr::Hashed {
+				stamps,
+				entries,
+				next,
+			} => {
+				let stamp = *stamps.entry(key.clone()).or_insert_with(|| {
+					*next += 1;
+					*next
+				});
+				entries.insert(stamp, (key, value));
+				Ok(())
+			},
+			Repr::Sorted(tree, cmp) => {
+				tree.insert(sort_key(&key, cmp), value);
+				cmp.check()
+			},
+		}
+	}
+
+	pub fn remove(&mut self, key: &MalType) -> Result<()> {
+		match &mut self.0 {
+			Repr::Hashed {
+				stamps, entries, ..
+			} => {
+				if let Some(stamp) = stamps.remove(key) {
+					entries.remove(&stamp);
+				}
+				Ok(())
+			},
+			Repr::Sorted(tree, cmp) => {
+				tree.remove(&sort_key(key, cmp));
+				cmp.check()
+			},
+		}
+	}
+
+	pub fn iter(&self) -> Box<dyn Iterator<Item = (&MalType, &MalType)> + '_> {
+		match &self.0 {
+			Repr::Hashed { entries, .. } => {
+				Box::new(entries.values().map(|(key, value)| (key, value)))
+			},
+			Repr::Sorted(tree, _) => {
+				Box::new(tree.iter().map(|(key, value)| (&key.key, value)))
+			},
+		}
+	}
+
+	pub fn keys(&self) -> impl Iterator<Item = &MalType> {
+		self.iter().map(|(key, _)| key)
+	}
+
+	pub fn values(&self) -> impl Iterator<Item = &MalType> {
+		self.iter().map(|(_, value)| value)
+	}
+}
+
+fn sort_key(key: &MalType, cmp: &Rc<Comparator>) -> SortKey {
+	SortKey {
+		key: key.clone(),
+		cmp: cmp.clone(),
+	}
+}
+
+impl Default for MalMap {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Builds an ordinary map, keeping the last value for a repeated key.
+impl FromIterator<(MalType, MalType)> for MalMap {
+	fn from_iter<I: IntoIterator<Item = (MalType, MalType)>>(iter: I) -> Self {
+		let mut map = Self::new();
+		for (key, value) in iter {
+			// can't fail, as there's no comparator
+			let _ = map.insert(key, value);
+		}
+		map
+	}
+}
+
+impl IntoIterator for MalMap {
+	type IntoIter = Box<dyn Iterator<Item = Self::Item>>;
+	type Item = (MalType, MalType);
+
+	fn into_iter(self) -> Self::IntoIter {
+		match self.0 {
+			Repr::Hashed { entries, .. } => Box::new(entries.into_values()),
+			Repr::Sorted(tree, _) => {
+				Box::new(tree.into_iter().map(|(key, value)| (key.key, value)))
+			},
+		}
+	}
+}
+
+/// Maps with the same entries are equal whatever their order, so a sorted map
+/// can equal an ordinary one.
+impl PartialEq for MalMap {
+	fn eq(&self, other: &Self) -> bool {
+		self.len() == other.len()
+			&& self.iter().all(
+				|(key, value)| matches!(other.get(key), Ok(Some(v)) if v == value),
+			)
+	}
+}
@@ -0,0 +1,564 @@
+//! Mal's numeric tower. Integers are `i64`s until they overflow, when they're
+//! promoted to arbitrary-precision integers. Dividing integers gives exact
+//! ratios, and any arithmetic involving a float gives a float.
+//!
+//! Numbers are always kept in their simplest form, so a ratio is never a whole
+//! number and a big integer never fits in an `i64`. This means exact numbers
+//! are equal exactly when their representations are.
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::ops::Add;
+use std::ops::Mul;
+use std::ops::Neg;
+use std::ops::Sub;
+use std::str::FromStr;
+
+use eyre::Result;
+use num_bigint::BigInt;
+use num_bigint::Sign;
+use num_rational::BigRational;
+use num_traits::Num;
+use num_traits::ToPrimitive;
+use num_traits::Zero;
+
+/// The most bits `bit-shift-left` will shift by.
+pub const MAX_SHIFT: usize = 1 << 24;
+
+#[derive(Clone, Debug)]
+pub enum Number {
+	Int(i64),
+	/// An integer too big for an `i64`.
+	BigInt(BigInt),
+	/// A ratio that isn't a whole number, in lowest terms.
+	Ratio(BigRational),
+	Float(f64),
+}
+
+/// Two numbers, converted to the least general representation that can hold
+/// both of them.
+enum Pair {
+	Ints(i64, i64),
+	BigInts(BigInt, BigInt),
+	Ratios(BigRational, BigRational),
+	Floats(f64, f64),
+}
+
+impl Pair {
+	fn of(l: &Number, r: &Number) -> Self {
+		match (l, r) {
+			(Number::Int(l), Number::Int(r)) => Pair::Ints(*l, *r),
+			_ => match l.rank().max(r.rank()) {
+				0 | 1 => Pair::BigInts(l.to_bigint(), r.to_bigint()),
+				2 => Pair::Ratios(l.to_ratio(), r.to_ratio()),
+				_ => Pair::Floats(l.to_f64(), r.to_f64()),
+			},
+		}
+	}
+}
+
+impl Number {
+	fn rank(&self) -> u8 {
+		match self {
+			Number::Int(_) => 0,
+			Number::BigInt(_) => 1,
+			Number::Ratio(_) => 2,
+			Number::Float(_) => 3,
+		}
+	}
+
+	fn to_bigint(&self) -> BigInt {
+		match self {
+			Number::Int(int) => BigInt::from(*int),
+			Number::BigInt(int) => int.clone(),
+			_ => unreachable!("only integers are converted to big integers"),
+		}
+	}
+
+	fn to_ratio(&self) -> BigRational {
+		match self {
+			Number::Ratio(ratio) => ratio.clone(),
+			Number::Float(_) => {
+				unreachable!("floats aren't converted to ratios")
+			},
+			int => BigRational::from_integer(int.to_bigint()),
+		}
+	}
+
+	/// The nearest float to this number.
+	pub fn to_f64(&self) -> f64 {
+		match self {
+			Number::Int(int) => *int as f64,
+			Number::BigInt(int) => int.to_f64().unwrap_or(f64::NAN),
+			Number::Ratio(ratio) => ratio.to_f64().unwrap_or(f64::NAN),
+			Number::Float(float) => *float,
+		}
+	}
+
+	pub fn is_zero(&self) -> bool {
+		match self {
+			Number::Int(int) => *int == 0,
+			Number::Float(float) => *float == 0.0,
+			// never zero, since they're normalized
+			Number::BigInt(_) | Number::Ratio(_) => false,
+		}
+	}
+
+	/// Whether this is neither infinite nor NaN.
+	pub fn is_finite(&self) -> bool {
+		match self {
+			Number::Float(float) => float.is_finite(),
+			_ => true,
+		}
+	}
+
+	pub fn is_nan(&self) -> bool {
+		matches!(self, Number::Float(float) if float.is_nan())
+	}
+
+	/// Divides by `other`. Exact numbers can't be divided by zero, but floats
+	/// follow IEEE 754.
+	pub fn checked_div(&self, other: &Number) -> Result<Number> {
+		if let Pair::Floats(l, r) = Pair::of(self, other) {
+			return Ok(Number::Float(l / r));
+		}
+		if other.is_zero() {
+			return Err(eyre!("divide by zero"));
+		}
+		if let (Number::Int(l), Number::Int(r)) = (self, other) {
+			if l.checked_rem(*r) == Some(0) {
+				if let Some(quot) = l.checked_div(*r) {
+					return Ok(Number::Int(quot));
+				}
+			}
+		}
+		Ok((self.to_ratio() / other.to_ratio()).into())
+	}
+
+	/// Divides by `other`, rounding towards zero.
+	pub fn checked_quot(&self, other: &Number) -> Result<Number> {
+		Ok(match Pair::of(self, other) {
+			Pair::Floats(l, r) => Number::Float((l / r).trunc()),
+			_ if other.is_zero() => return Err(eyre!("divide by zero")),
+			Pair::Ints(l, r) => l.checked_div(r).map_or_else(
+				|| (BigInt::from(l) / BigInt::from(r)).into(),
+				Number::Int,
+			),
+			Pair::BigInts(l, r) => (l / r).into(),
+			Pair::Ratios(l, r) => (l / r).trunc().into(),
+		})
+	}
+
+	/// The remainder of `checked_quot`, which has the sign of `self`.
+	pub fn checked_rem(&self, other: &Number) -> Result<Number> {
+		Ok(match Pair::of(self, other) {
+			Pair::Floats(l, r) => Number::Float(l % r),
+			_ if other.is_zero() => return Err(eyre!("divide by zero")),
+			// only `i64::MIN % -1` overflows
+			Pair::Ints(l, r) => Number::Int(l.checked_rem(r).unwrap_or(0)),
+			Pair::BigInts(l, r) => (l % r).into(),
+			Pair::Ratios(l, r) => (l % r).into(),
+		})
+	}
+
+	/// The remainder of dividing by `other` rounding towards negative
+	/// infinity, which has the sign of `other`.
+	pub fn checked_mod(&self, other: &Number) -> Result<Number> {
+		let rem = self.checked_rem(other)?;
+		Ok(
+			if !rem.is_zero() && rem.is_negative() != other.is_negative() {
+				rem + other.clone()
+			} else {
+				rem
+			},
+		)
+	}
+
+	pub fn abs(&self) -> Number {
+		if self.is_negative() {
+			-self.clone()
+		} else {
+			self.clone()
+		}
+	}
+
+	fn is_negative(&self) -> bool {
+		self.compare(&Number::Int(0)) == Some(Ordering::Less)
+	}
+
+	fn to_integer(&self) -> Result<BigInt> {
+		match self {
+			Number::Int(_) | Number::BigInt(_) => Ok(self.to_bigint()),
+			num => Err(eyre!("expected an integer (got {num})")),
+		}
+	}
+
+	pub fn bit_and(&self, other: &Number) -> Result<Number> {
+		Ok(match (self, other) {
+			(Number::Int(l), Number::Int(r)) => Number::Int(l & r),
+			(l, r) => (l.to_integer()? & r.to_integer()?).into(),
+		})
+	}
+
+	pub fn bit_or(&self, other: &Number) -> Result<Number> {
+		Ok(match (self, other) {
+			(Number::Int(l), Number::Int(r)) => Number::Int(l | r),
+			(l, r) => (l.to_integer()? | r.to_integer()?).into(),
+		})
+	}
+
+	pub fn bit_xor(&self, other: &Number) -> Result<Number> {
+		Ok(match (self, other) {
+			(Number::Int(l), Number::Int(r)) => Number::Int(l ^ r),
+			(l, r) => (l.to_integer()? ^ r.to_integer()?).into(),
+		})
+	}
+
+	pub fn bit_not(&self) -> Result<Number> {
+		Ok(match self {
+			Number::Int(int) => Number::Int(!int),
+			num => (!num.to_integer()?).into(),
+		})
+	}
+
+	/// Shifts left by `bits`, so that the result is `self * 2^bits`. Like the
+	/// other arithmetic, this promotes to a big integer rather than
+	/// overflowing, though shifts of more than `MAX_SHIFT` bits are rejected
+	/// rather than left to exhaust memory.
+	pub fn shift_left(&self, bits: &Number) -> Result<Number> {
+		let bits = bits.to_shift()?;
+		Ok(match self {
+			Number::Int(0) => Number::Int(0),
+			Number::Int(int)
+				if bits < 63 && int.unsigned_abs() < 1 << (63 - bits) =>
+			{
+				Number::Int(int << bits)
+			},
+			num => {
+				let int = num.to_integer()?;
+				if bits > MAX_SHIFT {
+					return Err(eyre!(
+						"can't shift by more than {MAX_SHIFT} bits"
+					));
+				}
+				(int << bits).into()
+			},
+		})
+	}
+
+	/// Shifts right by `bits`, rounding towards negative infinity.
+	pub fn shift_right(&self, bits: &Number) -> Result<Number> {
+		let bits = bits.to_shift()?;
+		Ok(match self {
+			Number::Int(int) => Number::Int(int >> bits.min(63)),
+			num => (num.to_integer()? >> bits).into(),
+		})
+	}
+
+	/// The shift amount in bits. Big integers never fit in an `i64`, so a
+	/// positive one is more than any shift can use.
+	fn to_shift(&self) -> Result<usize> {
+		match self {
+			Number::Int(bits) => usize::try_from(*bits)
+				.map_err(|_| eyre!("can't shift by a negative amount")),
+			Number::BigInt(bits) if bits.sign() == Sign::Plus => Ok(usize::MAX),
+			Number::BigInt(_) => Err(eyre!("can't shift by a negative amount")),
+			num => Err(eyre!("can't shift by {num}")),
+		}
+	}
+
+	/// Compares two numbers by value, so unlike `==`, a float can be equal to
+	/// an exact number. Comparisons with NaN give `None`.
+	pub fn compare(&self, other: &Number) -> Option<Ordering> {
+		match Pair::of(self, other) {
+			Pair::Ints(l, r) => Some(l.cmp(&r)),
+			Pair::BigInts(l, r) => Some(l.cmp(&r)),
+			Pair::Ratios(l, r) => Some(l.cmp(&r)),
+			Pair::Floats(l, r) => l.partial_cmp(&r),
+		}
+	}
+}
+
+impl From<i64> for Number {
+	fn from(int: i64) -> Self {
+		Number::Int(int)
+	}
+}
+
+impl From<f64> for Number {
+	fn from(float: f64) -> Self {
+		Number::Float(float)
+	}
+}
+
+impl From<BigInt> for Number {
+	fn from(int: BigInt) -> Self {
+		match int.to_i64() {
+			Some(int) => Number::Int(int),
+			None => Number::BigInt(int),
+		}
+	}
+}
+
+impl From<BigRational> for Number {
+	fn from(ratio: BigRational) -> Self {
+		if ratio.is_integer() {
+			ratio.to_integer().into()
+		} else {
+			Number::Ratio(ratio)
+		}
+	}
+}
+
+macro_rules! arithmetic {
+	($trait:ident, $method:ident, $checked:ident) => {
+		impl $trait for Number {
+			type Output = Number;
+
+			fn $method(self, other: Number) -> Number {
+				match Pair::of(&self, &other) {
+					Pair::Ints(l, r) => l.$checked(r).map_or_else(
+						|| BigInt::from(l).$method(BigInt::from(r)).into(),
+						Number::Int,
+					),
+					Pair::BigInts(l, r) => l.$method(r).into(),
+					Pair::Ratios(l, r) => l.$method(r).into(),
+					Pair::Floats(l, r) => Number::Float(l.$method(r)),
+				}
+			}
+		}
+	};
+}
+
+arithmetic!(Add, add, checked_add);
+arithmetic!(Sub, sub, checked_sub);
+arithmetic!(Mul, mul, checked_mul);
+
+impl Neg for Number {
+	type Output = Number;
+
+	fn neg(self) -> Number {
+		match self {
+			Number::Int(int) => int
+				.checked_neg()
+				.map_or_else(|| (-BigInt::from(int)).into(), Number::Int),
+			Number::BigInt(int) => (-int).into(),
+			Number::Ratio(ratio) => Number::Ratio(-ratio),
+			Number::Float(float) => Number::Float(-float),
+		}
+	}
+}
+
+/// Exact numbers are only equal to exact numbers, and floats to floats.
+impl PartialEq for Number {
+	fn eq(&self, other: &Self) -> bool {
+		match (self, other) {
+			(Number::Int(l), Number::Int(r)) => l == r,
+			(Number::BigInt(l), Number::BigInt(r)) => l == r,
+			(Number::Ratio(l), Number::Ratio(r)) => l == r,
+			(Number::Float(l), Number::Float(r)) => l == r,
+			_ => false,
+		}
+	}
+}
+
+/// NaN isn't equal to itself, so strictly this isn't an equivalence. A set
+/// can end up holding several NaNs, as in Clojure.
+impl Eq for Number {}
+
+impl Hash for Number {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		match self {
+			Number::Int(int) => int.hash(state),
+			Number::BigInt(int) => int.hash(state),
+			Number::Ratio(ratio) => ratio.hash(state),
+			// -0.0 == 0.0
+			Number::Float(float) => (float + 0.0).to_bits().hash(state),
+		}
+	}
+}
+
+/// Numbers print as they're read, except that a float always has a decimal
+/// point or an exponent so that it reads back as a float.
+impl Display for Number {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Number::Int(int) => write!(f, "{int}"),
+			Number::BigInt(int) => write!(f, "{int}"),
+			Number::Ratio(ratio) => write!(f, "{ratio}"),
+			Number::Float(float) if float.is_nan() => write!(f, "##NaN"),
+			Number::Float(float) if float.is_infinite() => {
+				write!(f, "{}", if *float > 0.0 { "##Inf" } else { "##-Inf" })
+			},
+			Number::Float(float) => write!(f, "{float:?}"),
+		}
+	}
+}
+
+/// Parses a number literal: an integer (`12`, `0xff`, `12N`), a ratio
+/// (`1/3`) or a float (`1.5`, `1e10`), optionally signed.
+impl FromStr for Number {
+	type Err = eyre::Report;
+
+	fn from_str(literal: &str) -> Result<Self> {
+		let invalid = || eyre!("invalid number `{literal}`");
+		let (negative, digits) = match literal.as_bytes().first() {
+			Some(b'-') => (true, &literal[1..]),
+			Some(b'+') => (false, &literal[1..]),
+			_ => (false, literal),
+		};
+		if !digits.starts_with(|ch: char| ch.is_ascii_digit()) {
+			return Err(invalid());
+		}
+
+		let hex = digits
+			.strip_prefix("0x")
+			.or_else(|| digits.strip_prefix("0X"));
+		let number = if let Some(hex) = hex {
+			parse_int(hex, 16).ok_or_else(invalid)?.into()
+		} else if let Some(int) = digits.strip_suffix('N') {
+			parse_int(int, 10).ok_or_else(invalid)?.into()
+		} else if let Some((numer, denom)) = digits.split_once('/') {
+			let numer = parse_int(numer, 10).ok_or_else(invalid)?;
+			let denom = parse_int(denom, 10)
+				.filter(|denom| !denom.is_zero())
+				.ok_or_else(invalid)?;
+			BigRational::new(numer, denom).into()
+		} else if let Some(int) = parse_int(digits, 10) {
+			int.into()
+		} else {
+			Number::Float(digits.parse().map_err(|_| invalid())?)
+		};
+		Ok(if negative { -number } else { number })
+	}
+}
+
+/// Parses unsigned digits in `radix`.
+pub fn parse_int(digits: &str, radix: u32) -> Option<BigInt> {
+	if digits.is_empty() || !digits.chars().all(|ch| ch.is_digit(radix)) {
+		return None;
+	}
+	if let Ok(int) = i64::from_str_radix(digits, radix) {
+		return Some(BigInt::from(int));
+	}
+	BigInt::from_str_radix(digits, radix).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn num(literal: &str) -> Number {
+		literal.parse().unwrap()
+	}
+
+	#[test]
+	fn test_parse_and_print() {
+		for literal in [
+			"0",
+			"-12",
+			"1.5",
+			"1.0",
+			"-0.25",
+			"1e100",
+			"1/3",
+			"-2/3",
+			"123456789012345678901234567890",
+			"##Inf",
+		] {
+			let printed = match literal {
+				"##Inf" => Number::Float(f64::INFINITY).to_string(),
+				literal => num(literal).to_string(),
+			};
+			assert_eq!(printed, literal);
+		}
+		assert_eq!(num("0xff"), Number::Int(255));
+		assert_eq!(num("12N"), Number::Int(12));
+		assert_eq!(num("+4/2"), Number::Int(2));
+		assert_eq!(num("1e10"), Number::Float(1e10));
+		assert_eq!(num("-9223372036854775808"), Number::Int(i64::MIN));
+		for invalid in ["1/0", "1x", "0x", "1.5N", "1/2/3", "-"] {
+			assert!(invalid.parse::<Number>().is_err(), "{invalid}");
+		}
+	}
+
+	#[test]
+	fn test_arithmetic() -> Result<()> {
+		let max = Number::Int(i64::MAX);
+		assert_eq!(
+			(max.clone() + Number::Int(1)).to_string(),
+			"9223372036854775808"
+		);
+		assert_eq!(max.clone() + Number::Int(1) - Number::Int(1), max);
+		assert_eq!(-Number::Int(i64::MIN), num("9223372036854775808"));
+		assert_eq!(num("1/3") + num("2/3"), Number::Int(1));
+		assert_eq!(num("1/2") * Number::Float(3.0), Number::Float(1.5));
+		assert_eq!(
+			Number::Int(6).checked_div(&Number::Int(3))?,
+			Number::Int(2)
+		);
+		assert_eq!(Number::Int(1).checked_div(&Number::Int(3))?, num("1/3"));
+		assert_eq!(
+			Number::Int(1).checked_div(&Number::Float(0.0))?,
+			Number::Float(f64::INFINITY)
+		);
+		assert_eq!(
+			Number::Int(1)
+				.checked_div(&Number::Int(0))
+				.unwrap_err()
+				.to_string(),
+			"divide by zero"
+		);
+
+		let (seven, two) = (Number::Int(-7), Number::Int(2));
+		assert_eq!(seven.checked_quot(&two)?, Number::Int(-3));
+		assert_eq!(seven.checked_rem(&two)?, Number::Int(-1));
+		assert_eq!(seven.checked_mod(&two)?, Number::Int(1));
+		assert_eq!(num("-7/2").checked_mod(&two)?, num("1/2"));
+		assert_eq!(Number::Float(-7.5).checked_mod(&two)?, Number::Float(0.5));
+		assert!(two.checked_mod(&Number::Int(0)).is_err());
+		assert_eq!(Number::Int(i64::MIN).abs(), num("9223372036854775808"));
+
+		assert_eq!(
+			Number::Int(1).shift_left(&Number::Int(64))?,
+			num("0x10000000000000000")
+		);
+		assert_eq!(
+			Number::Int(-8).shift_right(&Number::Int(100))?,
+			Number::Int(-1)
+		);
+		assert!(Number::Int(1)
+			.shift_left(&Number::Int(100_000_000_000))
+			.is_err());
+		assert!(Number::Int(1)
+			.shift_left(&num("99999999999999999999"))
+			.is_err());
+		assert_eq!(
+			Number::Int(0).shift_left(&num("99999999999999999999"))?,
+			Number::Int(0)
+		);
+		assert_eq!(
+			num("-99999999999999999999")
+				.shift_right(&num("99999999999999999999"))?,
+			Number::Int(-1)
+		);
+		assert_eq!(
+			num("0x10000000000000000")
+				.bit_and(&Number::Int(-1))?
+				.bit_not()?,
+			num("-0x10000000000000001")
+		);
+		assert!(Number::Float(1.0).bit_or(&Number::Int(1)).is_err());
+
+		assert_ne!(Number::Int(1), Number::Float(1.0));
+		assert_eq!(
+			Number::Int(1).compare(&Number::Float(1.0)),
+			Some(Ordering::Equal)
+		);
+		assert_eq!(num("1/3").compare(&num("0.3")), Some(Ordering::Greater));
+		assert_eq!(Number::Float(f64::NAN).compare(&Number::Int(1)), None);
+		Ok(())
+	}
+}
@@ -1,19 +1,49 @@
 use std::fmt::Display;
 
 use crate::types::ListKind;
-use crate::types::MalHashKey;
 use crate::types::MalType;
+use crate::types::CHAR_NAMES;
 
 impl MalType {
 	pub fn pr_str<const PRINT_READABLY: bool>(&self) -> String {
 		match self {
+			MalType::Atom(atom) => {
+				format!("(atom {})", atom.borrow().pr_str::<PRINT_READABLY>())
+			},
 			MalType::Bool(b) => format!("{b}"),
-			MalType::Function(_) | MalType::TCOFunction { .. } => {
+			MalType::Char(ch) if PRINT_READABLY => {
+				match CHAR_NAMES.iter().find(|(_, named)| named == ch) {
+					Some((name, _)) => format!("\\{name}"),
+					None => format!("\\{ch}"),
+				}
+			},
+			MalType::Char(ch) => ch.to_string(),
+			MalType::Function(..) | MalType::TCOFunction { .. } => {
 				"#<function>".to_string()
 			},
 			MalType::Keyword(kw) => format!(":{kw}"),
 			MalType::Nil => "nil".to_string(),
 			MalType::Number(num) => format!("{num}"),
+			MalType::Reduced(value) => {
+				format!("#<reduced {}>", value.pr_str::<true>())
+			},
+			// only quotes are escaped, as the reader leaves any other escape
+			// to the regex
+			MalType::Regex(regex) if PRINT_READABLY => {
+				let mut escaped = false;
+				regex
+					.as_str()
+					.chars()
+					.fold("#\"".to_string(), |mut res, ch| {
+						if ch == '"' && !escaped {
+							res.push('\\');
+						}
+						escaped = !escaped && ch == '\\';
+						res.push(ch);
+						res
+					}) + "\""
+			},
+			MalType::Regex(regex) => regex.as_str().to_string(),
 			MalType::String(string) if PRINT_READABLY => {
 				string
 					.chars()
@@ -28,39 +58,36 @@ impl MalType {
 					}) + "\""
 			},
 			MalType::String(string) => string.clone(),
-			MalType::Symbol(sym) => sym.clone(),
+			MalType::Symbol(sym, _) => sym.clone(),
 
-			MalType::HashMap(vals) => {
-				return Self::pr_list::<PRINT_READABLY>(
-					vals.iter()
-						.map(|(key, val)| {
-							[
-								match key {
-									MalHashKey::String(string) => {
-										MalType::String(string.clone())
-									},
-									MalHashKey::Keyword(kw) => {
-										MalType::Keyword(kw.clone())
-									},
-								},
-								val.clone(),
-							]
-						})
-						.flatten()
-						.collect::<Vec<_>>()
-						.as_slice()
-						.into_iter(),
-					"{",
-					"}",
-					" ",
-				)
-			},
-			MalType::L(ListKind::List, list) => {
+			MalType::HashMap(vals, _) => Self::pr_list::<PRINT_READABLY>(
+				vals.iter()
+					.flat_map(|(key, val)| [key.clone(), val.clone()])
+					.collect::<Vec<_>>()
+					.iter(),
+				"{",
+				"}",
+				" ",
+			),
+			MalType::L(ListKind::List, list, _) => {
 				Self::pr_list::<PRINT_READABLY>(list.iter(), "(", ")", " ")
 			},
-			MalType::L(ListKind::Vector, list) => {
+			MalType::L(ListKind::Vector, list, _) => {
 				Self::pr_list::<PRINT_READABLY>(list.iter(), "[", "]", " ")
 			},
+			MalType::Set(set) => {
+				Self::pr_list::<PRINT_READABLY>(set.iter(), "#{", "}", " ")
+			},
+			// anything that failed to realize is left out, see `realize`
+			MalType::LazySeq(_) => Self::pr_list::<PRINT_READABLY>(
+				self.items()
+					.filter_map(Result::ok)
+					.collect::<Vec<_>>()
+					.iter(),
+				"(",
+				")",
+				" ",
+			),
 		}
 	}
 
@@ -82,7 +109,7 @@ impl MalType {
 /// Note that the `alternate` flag (`#`) is used to enable "print-readably"
 impl Display for MalType {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		return write!(
+		write!(
 			f,
 			"{}",
 			if f.alternate() {
@@ -90,6 +117,6 @@ impl Display for MalType {
 			} else {
 				self.pr_str::<false>()
 			}
-		);
+		)
 	}
 }
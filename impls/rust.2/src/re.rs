@@ -0,0 +1,206 @@
+//! Regexes, read from `#"..."` literals or made by `re-pattern`. Compiled
+//! patterns are cached, so a literal in a loop or a function body is only
+//! compiled once.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use eyre::Result;
+use regex::Captures;
+use regex::Regex;
+
+use crate::env::Env;
+use crate::types::ListKind;
+use crate::types::MalType;
+
+/// The cache is emptied when it fills up, so that patterns built at runtime
+/// can't grow it without bound.
+const CACHE_SIZE: usize = 256;
+
+thread_local! {
+	static CACHE: RefCell<HashMap<String, Rc<Regex>>> = RefCell::new(HashMap::new());
+}
+
+/// Compiles `pattern`, or fetches it from the cache.
+pub fn compile(pattern: &str) -> Result<Rc<Regex>> {
+	CACHE.with(|cache| {
+		if let Some(regex) = cache.borrow().get(pattern) {
+			return Ok(regex.clone());
+		}
+		let regex = Rc::new(
+			Regex::new(pattern).map_err(|err| eyre!("invalid regex: {err}"))?,
+		);
+		let mut cache = cache.borrow_mut();
+		if cache.len() >= CACHE_SIZE {
+			cache.clear();
+		}
+		cache.insert(pattern.to_string(), regex.clone());
+		Ok(regex)
+	})
+}
+
+/// Binds the regex functions in `env`.
+pub fn register(env: &Env) {
+	env.register_fn("re-pattern", |pattern: MalType| match pattern {
+		MalType::Regex(_) => Ok(pattern),
+		MalType::String(pattern) => Ok(MalType::Regex(compile(&pattern)?)),
+		pattern => Err(eyre!(
+			"`re-pattern` expects a string or a regex (got {pattern:#})"
+		)),
+	});
+	env.register_fn("re-find", |regex: Rc<Regex>, string: String| {
+		Ok(regex.captures(&string).map(|caps| groups(&caps)))
+	});
+	env.register_fn("re-matches", |regex: Rc<Regex>, string: String| {
+		let whole = compile(&format!(r"\A(?:{})\z", regex.as_str()))?;
+		Ok(whole.captures(&string).map(|caps| groups(&caps)))
+	});
+	env.register_fn("re-seq", |regex: Rc<Regex>, string: String| {
+		Ok(MalType::L(
+			ListKind::List,
+			regex
+				.captures_iter(&string)
+				.map(|caps| groups(&caps))
+				.collect(),
+			None,
+		))
+	});
+	env.register_fn(
+		"re-replace",
+		|string: String, regex: Rc<Regex>, replacement: MalType| {
+			replace(&string, &regex, &replacement)
+		},
+	);
+}
+
+/// A match is the matched string if the regex has no groups, and otherwise
+/// a vector of the match followed by each group, which is nil if the group
+/// didn't take part in the match.
+fn groups(caps: &Captures) -> MalType {
+	if caps.len() == 1 {
+		return MalType::String(caps[0].to_string());
+	}
+	MalType::L(
+		ListKind::Vector,
+		caps.iter()
+			.map(|group| {
+				group.map_or(MalType::Nil, |group| {
+					MalType::String(group.as_str().to_string())
+				})
+			})
+			.collect(),
+		None,
+	)
+}
+
+/// Replaces every match of `regex` in `string`. The replacement is either a
+/// string, where `$1` or `${name}` stands for a group, or a function that's
+/// given each match as `re-find` would return it.
+fn replace(
+	string: &str,
+	regex: &Regex,
+	replacement: &MalType,
+) -> Result<String> {
+	match replacement {
+		MalType::String(replacement) => {
+			Ok(regex.replace_all(string, replacement.as_str()).into_owned())
+		},
+		MalType::Function(..) | MalType::TCOFunction { .. } => {
+			let mut replaced = String::with_capacity(string.len());
+			let mut last = 0;
+			for caps in regex.captures_iter(string) {
+				let found = caps.get(0).unwrap();
+				replaced.push_str(&string[last..found.start()]);
+				match replacement.call(&mut [groups(&caps)])? {
+					MalType::String(part) => replaced.push_str(&part),
+					part => {
+						return Err(eyre!(
+							"`re-replace` expects its function to return a \
+							 string (got {part:#})"
+						))
+					},
+				}
+				last = found.end();
+			}
+			replaced.push_str(&string[last..]);
+			Ok(replaced)
+		},
+		replacement => Err(eyre!(
+			"`re-replace` expects a string or function replacement (got \
+			 {replacement:#})"
+		)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::eval::Interpreter;
+
+	#[test]
+	fn test_regexes() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		for (input, output) in [
+			(r#"#"a\d+""#, r#"#"a\d+""#),
+			(r#"(str #"a\d+")"#, r#""a\\d+""#),
+			(r#"#"say \"hi\"""#, r#"#"say \"hi\"""#),
+			(r#"(re-pattern "x\"y")"#, r#"#"x\"y""#),
+			(r#"(re-pattern #"x")"#, r#"#"x""#),
+			(r#"(= #"a" (re-pattern "a"))"#, "true"),
+			(r#"(get {#"a" 1} #"a")"#, "1"),
+			(r#"(re-find #"\d+" "ab123cd45")"#, r#""123""#),
+			(r#"(re-find #"(\w)(\d)?" "日x")"#, r#"["日" "日" nil]"#),
+			(r#"(re-find #"z" "abc")"#, "nil"),
+			(r#"(re-matches #"\d+" "123")"#, r#""123""#),
+			(r#"(re-matches #"\d+" "123a")"#, "nil"),
+			(r#"(re-matches #"(\d)(\d)" "12")"#, r#"["12" "1" "2"]"#),
+			(r#"(re-seq #"\d" "a1b2")"#, r#"("1" "2")"#),
+			(
+				r#"(re-seq #"(\w)=(\d)" "a=1 b=2")"#,
+				r#"(["a=1" "a" "1"] ["b=2" "b" "2"])"#,
+			),
+			(r#"(re-seq #"q" "abc")"#, "()"),
+			(r#"(re-replace "a1b22" #"\d+" "<$0>")"#, r#""a<1>b<22>""#),
+			(
+				r#"(re-replace "a1b22" #"(\d+)" (fn* (m) (str "[" (nth m 1) "]")))"#,
+				r#""a[1]b[22]""#,
+			),
+		] {
+			assert_eq!(interpreter.rep(input)?, output, "{input}");
+		}
+
+		let err = |input| interpreter.rep(input).unwrap_err().to_string();
+		assert!(err(r#"(re-pattern "(")"#).starts_with("invalid regex: "));
+		assert!(err(r##"#"(""##).starts_with("1:1: invalid regex: "));
+		assert_eq!(
+			err(r#"(re-find "a" "a")"#),
+			"`re-find`, argument 1: expected a regex (got \"a\")"
+		);
+		assert_eq!(
+			err(r#"(re-replace "a" #"a" (fn* (m) 1))"#),
+			"`re-replace` expects its function to return a string (got 1)"
+		);
+		assert_eq!(
+			err("(re-pattern 1)"),
+			"`re-pattern` expects a string or a regex (got 1)"
+		);
+		assert_eq!(
+			err(r#"(re-find #"a")"#),
+			"`re-find` expects 2 args: 1 were provided"
+		);
+		assert_eq!(
+			err(r#"(re-seq #"a" 1)"#),
+			"`re-seq`, argument 2: expected a string (got 1)"
+		);
+		assert_eq!(
+			err(r#"(re-replace "a" "a" "b")"#),
+			"`re-replace`, argument 2: expected a regex (got \"a\")"
+		);
+		assert_eq!(
+			err(r#"(re-replace "a" #"a" 1)"#),
+			"`re-replace` expects a string or function replacement (got 1)"
+		);
+		Ok(())
+	}
+}
@@ -1,127 +1,219 @@
-use std::collections::HashMap;
-use std::ops::Neg;
-use std::str::FromStr;
+use std::fmt::Display;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use eyre::Result;
-use regex::Regex;
 
+use crate::lexer;
+use crate::lexer::Lexer;
+use crate::lexer::Token;
+use crate::map::MalMap;
+use crate::number::Number;
+use crate::re;
 use crate::types::ListKind;
-use crate::types::MalHashKey;
 use crate::types::MalType;
-
-#[derive(Clone)]
-pub enum Token {}
+use crate::types::Span;
 
 #[derive(Clone)]
 pub enum Ast {}
 
+/// Reads forms from a string. As an iterator, this yields each of its forms
+/// in turn.
 pub struct Reader<'source> {
-	matches: Vec<&'source str>,
-	posn:    usize,
+	input:  &'source str,
+	lexer:  Lexer<'source>,
+	peeked: Option<Token<'source>>,
+	/// The empty span at the end of the last token read, if any.
+	end:    Option<Span>,
 }
 
 impl<'source> Reader<'source> {
 	pub fn new(input: &'source str) -> Self {
+		Self::with_lexer(input, Lexer::new(input, None))
+	}
+
+	/// Creates a reader whose forms and errors refer to `file`.
+	pub fn with_file(input: &'source str, file: impl Into<Arc<str>>) -> Self {
+		Self::with_lexer(input, Lexer::new(input, Some(file.into())))
+	}
+
+	fn with_lexer(input: &'source str, lexer: Lexer<'source>) -> Self {
 		Self {
-			matches: tokenize(input),
-			posn:    0,
+			input,
+			lexer,
+			peeked: None,
+			end: None,
 		}
 	}
 
-	pub fn next(&mut self) -> Result<&'source str> {
-		let res = self.peek()?;
-		self.posn += 1;
-		while let Ok(tok) = self.peek() && tok.starts_with(';') {
-			self.posn += 1;
+	/// Whether every form has been read.
+	pub fn is_done(&mut self) -> Result<bool> {
+		Ok(self.peek_token()?.is_none())
+	}
+
+	fn peek_token(&mut self) -> Result<Option<&Token<'source>>> {
+		if self.peeked.is_none() {
+			self.peeked = self.lexer.next().transpose()?;
 		}
+		Ok(self.peeked.as_ref())
+	}
+
+	pub fn next_token(&mut self) -> Result<&'source str> {
+		let res = self.peek()?;
+		// the lexer hasn't gone past the peeked token
+		self.peeked = None;
+		self.end = Some(self.lexer.here());
 		Ok(res)
 	}
 
-	pub fn peek(&self) -> Result<&'source str> {
-		self.matches
-			.get(self.posn)
-			.cloned()
-			.ok_or_else(|| eyre!("EOF"))
+	pub fn peek(&mut self) -> Result<&'source str> {
+		if let Some(token) = self.peek_token()? {
+			return Ok(token.text);
+		}
+		match &self.end {
+			None => Err(eyre!("EOF")),
+			Some(end) => Err(self.error(end, "unexpected EOF")),
+		}
+	}
+
+	/// The span of the next token. At the end of input, this is the empty
+	/// span right after the last token.
+	pub fn span(&mut self) -> Result<Span> {
+		Ok(match self.peek_token()? {
+			Some(token) => token.span.clone(),
+			None => self.end.clone().unwrap_or_else(|| self.lexer.here()),
+		})
+	}
+
+	/// An error at `span`, followed by the line it's on with the span
+	/// underlined.
+	pub fn error(&self, span: &Span, msg: impl Display) -> eyre::Report {
+		lexer::error(self.input, span, msg)
 	}
 
 	pub fn read_form(&mut self) -> Result<MalType> {
 		match self.peek()? {
-			"(" | "{" | "[" => self.read_list(),
+			"(" | "{" | "[" | "#{" => self.read_list(),
 			"'" => self.read_quote("quote"),
 			"`" => self.read_quote("quasiquote"),
 			"~" => self.read_quote("unquote"),
 			"~@" => self.read_quote("splice-unquote"),
 			"@" => self.read_quote("deref"),
+			"^" => self.read_meta(),
 			_ => self.read_atom(),
 		}
 	}
 
 	fn read_quote(&mut self, kind: &str) -> Result<MalType> {
-		let ("'" | "`" | "~" | "~@" | "@") = self.next()? else {
-			return Err(eyre!("invalid quote"));
+		let span = self.span()?;
+		let ("'" | "`" | "~" | "~@" | "@") = self.next_token()? else {
+			return Err(self.error(&span, "invalid quote"));
 		};
 
-		Ok(MalType::L(ListKind::List, vec![
-			MalType::Symbol(kind.to_string()),
-			self.read_form()?,
-		]))
+		Ok(MalType::L(
+			ListKind::List,
+			vec![
+				MalType::Symbol(kind.to_string(), Some(Rc::new(span))),
+				self.read_form()?,
+			],
+			None,
+		))
 	}
 
-	fn read_list(&mut self) -> Result<MalType> {
-		fn closes(l: &str, r: &str) -> bool {
-			matches!((l, r), ("(", ")") | ("[", "]") | ("{", "}"))
-		}
+	fn read_meta(&mut self) -> Result<MalType> {
+		let span = self.span()?;
+		let "^" = self.next_token()? else {
+			return Err(self.error(&span, "expected metadata"));
+		};
 
-		let start @ ("(" | "[" | "{") = self.next()? else {
-			return Err(eyre!("expected list"))
+		let meta = self.read_form()?;
+		Ok(MalType::L(
+			ListKind::List,
+			vec![
+				MalType::Symbol("with-meta".to_string(), Some(Rc::new(span))),
+				self.read_form()?,
+				meta,
+			],
+			None,
+		))
+	}
+
+	fn read_list(&mut self) -> Result<MalType> {
+		let open = self.span()?;
+		let (start, close) = match self.next_token()? {
+			"(" => ("(", ")"),
+			"[" => ("[", "]"),
+			"{" => ("{", "}"),
+			"#{" => ("#{", "}"),
+			_ => return Err(self.error(&open, "expected list")),
 		};
 
 		let mut list = Vec::new();
+		let mut spans = Vec::new();
 		loop {
+			if self.is_done()? {
+				return Err(self.error(
+					&open,
+					format!(
+						"unbalanced `{start}`: expected `{close}` before EOF"
+					),
+				));
+			}
 			match self.peek()? {
-				end @ (")" | "]" | "}") if closes(start, end) => {
-					let _ = self.next(); // just guaranteed with peek
+				end if end == close => {
+					let _ = self.next_token(); // just guaranteed with peek
 					break Ok(match start {
-						"(" => MalType::L(ListKind::List, list),
-						"[" => MalType::L(ListKind::Vector, list),
-						"{" => MalType::HashMap({
-							let mut map =
-								HashMap::with_capacity(list.len() / 2);
-
-							let mut items = list.into_iter();
-							while let Some(key) = items.next() {
-								let key = match key {
-									MalType::Keyword(keyword) => {
-										MalHashKey::Keyword(keyword)
-									},
-									MalType::String(string) => {
-										MalHashKey::String(string.into())
-									},
-									_ => {
-										return Err(eyre!(
-											"invalid hashmap key"
-										))
-									},
-								};
-								let Some(value) = items.next() else {
-									return Err(eyre!("no value for hashmap key"));
-								};
-								map.insert(key, value);
-							}
-
-							map
-						}),
-						_ => unreachable!(),
+						"(" => MalType::L(ListKind::List, list, None),
+						"[" => MalType::L(ListKind::Vector, list, None),
+						"#{" => MalType::Set(list.into_iter().collect()),
+						_ => MalType::HashMap(
+							self.build_hashmap(list, spans)?,
+							None,
+						),
 					});
 				},
-				")" | "]" | "}" => return Err(eyre!("improperly closed list")),
-				_ => list.push(self.read_form()?),
+				end @ (")" | "]" | "}") => {
+					let span = self.span()?;
+					return Err(self.error(
+						&span,
+						format!(
+							"improperly closed list: expected `{close}`, \
+							 found `{end}`"
+						),
+					));
+				},
+				_ => {
+					spans.push(self.span()?);
+					list.push(self.read_form()?);
+				},
 			}
 		}
 	}
 
+	fn build_hashmap(
+		&self,
+		list: Vec<MalType>,
+		spans: Vec<Span>,
+	) -> Result<MalMap> {
+		let mut map = MalMap::new();
+
+		let mut items = list.into_iter().zip(spans);
+		while let Some((key, span)) = items.next() {
+			let Some((value, _)) = items.next() else {
+				return Err(self.error(&span, "no value for hashmap key"));
+			};
+			map.insert(key, value)?;
+		}
+
+		Ok(map)
+	}
+
 	fn read_atom(&mut self) -> Result<MalType> {
-		Ok(match self.next()? {
+		let span = self.span()?;
+		Ok(match self.next_token()? {
 			"false" => MalType::Bool(false),
 			"nil" => MalType::Nil,
 			"true" => MalType::Bool(true),
@@ -129,76 +221,255 @@ impl<'source> Reader<'source> {
 				MalType::Keyword(keyword.chars().skip(1).collect())
 			},
 			string if string.starts_with('"') => {
-				let mut chars = string.chars().skip(1);
-				let mut string = String::with_capacity(string.len());
-				let mut balanced = false;
-
-				while let Some(ch) = chars.next() {
-					if ch == '"' {
-						balanced = true;
-						break;
-					}
-					string.push(if '\\' == ch {
-						// next value escaped
-						match chars.next() {
-							None => return Err(eyre!("EOF")),
-							Some('n') => '\n',
-							Some('\\') => '\\',
-							Some('"') => '"',
-							Some(ch) => {
-								return Err(eyre!(
-									"unexpected escape sequence: \\{ch} isn't \
-									 a valid escape"
-								))
-							},
-						}
-					} else {
-						// push to resulting string
-						ch
-					})
-				}
-
-				if !balanced {
-					return Err(eyre!("unbalanced"));
-				}
-				MalType::String(string.into())
+				MalType::String(lexer::unescape(string))
+			},
+			ch if ch.starts_with('\\') => {
+				MalType::Char(lexer::char_literal(ch).ok_or_else(|| {
+					self.error(&span, format!("invalid char `{ch}`"))
+				})?)
 			},
+			regex if regex.starts_with("#\"") => MalType::Regex(
+				re::compile(&lexer::unescape_regex(regex))
+					.map_err(|err| self.error(&span, err))?,
+			),
+			"##Inf" => MalType::Number(Number::Float(f64::INFINITY)),
+			"##-Inf" => MalType::Number(Number::Float(f64::NEG_INFINITY)),
+			"##NaN" => MalType::Number(Number::Float(f64::NAN)),
 			number_or_symbol => {
 				let mut chars = number_or_symbol.chars();
-				match chars.next() {
-					Some(digit) if digit.is_numeric() => {
-						MalType::Number(f64::from_str(number_or_symbol)?)
+				let is_number = match chars.next() {
+					Some(digit) if digit.is_ascii_digit() => true,
+					Some('+' | '-') => {
+						chars.next().is_some_and(|ch| ch.is_ascii_digit())
 					},
-					Some('-')
-						if chars.next().map_or(false, char::is_numeric) =>
-					{
-						MalType::Number(
-							f64::from_str(&number_or_symbol[1..])?.neg(),
-						)
-					},
-					_ => MalType::Symbol(number_or_symbol.to_string()),
+					_ => false,
+				};
+				if !is_number {
+					return Ok(MalType::Symbol(
+						number_or_symbol.to_string(),
+						Some(Rc::new(span)),
+					));
 				}
+				MalType::Number(
+					number_or_symbol
+						.parse()
+						.map_err(|err| self.error(&span, err))?,
+				)
 			},
 		})
 	}
 }
 
+impl<'source> Iterator for Reader<'source> {
+	type Item = Result<MalType>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		match self.is_done() {
+			Ok(true) => None,
+			Ok(false) => Some(self.read_form()),
+			Err(err) => Some(Err(err)),
+		}
+	}
+}
+
+/// An iterator over the forms of a `Read` source, which is read a few lines
+/// at a time rather than all at once. Iteration stops after an error.
+pub struct Forms<R> {
+	source: BufReader<R>,
+	file:   Option<Arc<str>>,
+	/// Source text that hasn't been read as forms yet.
+	buffer: String,
+	/// The line and column in the source of the start of `buffer`.
+	line:   usize,
+	col:    usize,
+	eof:    bool,
+	failed: bool,
+}
+
+impl<R: Read> Forms<R> {
+	pub fn new(source: R) -> Self {
+		Self {
+			source: BufReader::new(source),
+			file:   None,
+			buffer: String::new(),
+			line:   1,
+			col:    1,
+			eof:    false,
+			failed: false,
+		}
+	}
+
+	/// Creates an iterator whose forms and errors refer to `file`.
+	pub fn with_file(source: R, file: impl Into<Arc<str>>) -> Self {
+		Self {
+			file: Some(file.into()),
+			..Self::new(source)
+		}
+	}
+
+	/// Reads the next form, reading more of the source whenever the buffered
+	/// text runs out in the middle of one.
+	fn read_form(&mut self) -> Result<Option<MalType>> {
+		loop {
+			let lexer = Lexer::resume(
+				&self.buffer,
+				self.file.clone(),
+				self.line,
+				self.col,
+			);
+			let mut reader = Reader::with_lexer(&self.buffer, lexer);
+			let res = reader.next();
+			let incomplete = reader.lexer.at_end() && !self.eof;
+			let here = reader.lexer.here();
+			match res {
+				Some(Ok(form)) => {
+					self.buffer.drain(..here.start);
+					(self.line, self.col) = (here.line, here.col);
+					return Ok(Some(form));
+				},
+				None if incomplete => {
+					// nothing but whitespace and comments
+					self.buffer.clear();
+					(self.line, self.col) = (here.line, here.col);
+					self.fill()?;
+				},
+				Some(Err(_)) if incomplete => self.fill()?,
+				None => return Ok(None),
+				Some(Err(err)) => return Err(err),
+			}
+		}
+	}
+
+	/// Reads whole lines from the source until the buffer has doubled in
+	/// size, so that a long form is only re-read a few times.
+	fn fill(&mut self) -> Result<()> {
+		let target = self.buffer.len() * 2;
+		loop {
+			if self.source.read_line(&mut self.buffer)? == 0 {
+				self.eof = true;
+				return Ok(());
+			}
+			if self.buffer.len() >= target {
+				return Ok(());
+			}
+		}
+	}
+}
+
+impl<R: Read> Iterator for Forms<R> {
+	type Item = Result<MalType>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+		let res = self.read_form().transpose();
+		self.failed = matches!(res, Some(Err(_)));
+		res
+	}
+}
+
+/// Reads the first form in `input`, ignoring the rest. See `read_all`.
 pub fn read_str(input: &str) -> Result<MalType> {
 	Reader::new(input).read_form()
 }
 
-pub fn tokenize(input: &str) -> Vec<&str> {
-	lazy_static::lazy_static! {
-		static ref REGEX: Regex = Regex::new(r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"#).unwrap();
+/// Reads every form in `input`.
+pub fn read_all(input: &str) -> Result<Vec<MalType>> {
+	Reader::new(input).collect()
+}
+
+/// The 1-based line and column of the byte `offset` in `input`.
+pub fn line_col(input: &str, offset: usize) -> (usize, usize) {
+	let before = &input[..offset];
+	let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+	(
+		before.matches('\n').count() + 1,
+		before[line_start..].chars().count() + 1,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_spans() -> Result<()> {
+		let mut reader =
+			Reader::with_file("; comment\n(a\n  \"b\nc\" d)", "f.mal");
+		let MalType::L(_, list, _) = reader.read_form()? else {
+			panic!("expected a list");
+		};
+		let spans = list
+			.iter()
+			.filter_map(|form| match form {
+				MalType::Symbol(_, span) => {
+					span.as_deref().map(Span::to_string)
+				},
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(spans, ["f.mal:2:2", "f.mal:4:4"]);
+		assert!(reader.is_done()?);
+		Ok(())
+	}
+
+	#[test]
+	fn test_errors() {
+		let err = Reader::with_file("(a\n\t[b c)", "f.mal")
+			.read_form()
+			.unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"f.mal:2:6: improperly closed list: expected `]`, found `)`\n\t[b \
+			 c)\n\t    ^"
+		);
+
+		let err = read_str("(a \"bc").unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"1:4: unbalanced string: expected `\"` before EOF\n(a \"bc\n   ^^^"
+		);
 	}
 
-	REGEX
-		.captures_iter(input)
-		.map(|capture| {
-			capture.get(1).map_or_else(
-				|| panic!("somehow the capture didn't work"),
-				|cap| cap.as_str(),
-			)
-		})
-		.collect()
+	#[test]
+	fn test_read_all() -> Result<()> {
+		assert_eq!(read_all("(+ 1 2) (+ 3 4) ; done\n")?, [
+			read_str("(+ 1 2)")?,
+			read_str("(+ 3 4)")?
+		]);
+		assert_eq!(read_all(" ; nothing")?, []);
+		assert!(read_all("1 (2").is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn test_forms() -> Result<()> {
+		let source = "; header\n(a\n  b) \"c\nd\"\n\n(e\n ]";
+		let mut forms = Forms::with_file(source.as_bytes(), "f.mal");
+		assert_eq!(forms.next().unwrap()?, read_str("(a b)")?);
+		assert_eq!(forms.next().unwrap()?, read_str(r#""c\nd""#)?);
+		let err = forms.next().unwrap().unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"f.mal:7:2: improperly closed list: expected `)`, found `]`\n ]\n \
+			 ^"
+		);
+		assert!(forms.next().is_none());
+
+		let forms =
+			Forms::new("x (y\n  z)".as_bytes()).collect::<Result<Vec<_>>>()?;
+		let [MalType::Symbol(_, Some(x)), MalType::L(_, list, _)] = &forms[..]
+		else {
+			panic!("expected a symbol and a list");
+		};
+		let MalType::Symbol(_, Some(z)) = &list[1] else {
+			panic!("expected a symbol");
+		};
+		assert_eq!(
+			(x.to_string(), z.to_string()),
+			("1:1".into(), "2:3".into())
+		);
+		Ok(())
+	}
 }
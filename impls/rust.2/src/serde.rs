@@ -0,0 +1,892 @@
+//! `serde` support for mal values, enabled with the `serde` feature.
+//!
+//! - [`to_mal`] turns any `Serialize` type into a `MalType`. Structs become
+//!   hashmaps keyed by keywords, sequences become vectors and unit enum
+//!   variants become keywords.
+//! - [`from_mal`] reads any `Deserialize` type out of a `MalType`, such as one
+//!   produced by `reader::read_str`. Hashmap keys may be keywords or strings.
+//! - `MalType` itself implements `Serialize` and `Deserialize`, so it can be
+//!   converted to and from any other serde format. Keywords and symbols are
+//!   serialized as their names.
+//!
+//! ```
+//! use rust2::reader;
+//! use rust2::serde::from_mal;
+//!
+//! let form = reader::read_str(r#"["mal" 8080]"#).unwrap();
+//! let (name, port): (String, u16) = from_mal(form).unwrap();
+//! assert_eq!((name.as_str(), port), ("mal", 8080));
+//! ```
+//! ```
+
+use std::fmt;
+use std::fmt::Display;
+
+use ::serde::de;
+use ::serde::de::value::MapDeserializer;
+use ::serde::de::value::SeqDeserializer;
+use ::serde::de::DeserializeOwned;
+use ::serde::de::IntoDeserializer;
+use ::serde::de::Visitor;
+use ::serde::forward_to_deserialize_any;
+use ::serde::ser;
+use ::serde::ser::Error as _;
+use ::serde::Deserialize;
+use ::serde::Deserializer;
+use ::serde::Serialize;
+use ::serde::Serializer;
+use num_bigint::BigInt;
+
+use crate::map::MalMap;
+use crate::number::Number;
+use crate::types::ListKind;
+use crate::types::MalType;
+
+/// Converts a Rust value into a mal value.
+pub fn to_mal<T: Serialize + ?Sized>(value: &T) -> Result<MalType, Error> {
+	value.serialize(MalSerializer)
+}
+
+/// Converts a mal value into a Rust value.
+pub fn from_mal<T: DeserializeOwned>(value: MalType) -> Result<T, Error> {
+	T::deserialize(value)
+}
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+	fn custom<T: Display>(msg: T) -> Self {
+		Self(msg.to_string())
+	}
+}
+
+impl de::Error for Error {
+	fn custom<T: Display>(msg: T) -> Self {
+		Self(msg.to_string())
+	}
+}
+
+/// Big integers are represented as 128-bit integers if they fit, and ratios
+/// as floats.
+fn too_big(int: &BigInt) -> String {
+	format!("{int} is too big to be represented")
+}
+
+impl Serialize for MalType {
+	fn serialize<S: Serializer>(
+		&self,
+		serializer: S,
+	) -> Result<S::Ok, S::Error> {
+		match self {
+			MalType::Atom(atom) => atom.borrow().serialize(serializer),
+			MalType::Bool(b) => serializer.serialize_bool(*b),
+			MalType::Char(ch) => serializer.serialize_char(*ch),
+			MalType::HashMap(map, _) => serializer.collect_map(map.iter()),
+			MalType::Keyword(name)
+			| MalType::String(name)
+			| MalType::Symbol(name, _) => serializer.serialize_str(name),
+			MalType::L(_, list, _) => serializer.collect_seq(list),
+			MalType::Set(set) => serializer.collect_seq(set),
+			MalType::LazySeq(_) => serializer.collect_seq(
+				self.items()
+					.collect::<eyre::Result<Vec<_>>>()
+					.map_err(S::Error::custom)?,
+			),
+			MalType::Nil => serializer.serialize_unit(),
+			MalType::Number(Number::Int(int)) => serializer.serialize_i64(*int),
+			MalType::Number(Number::BigInt(int)) => {
+				if let Ok(int) = u128::try_from(int) {
+					serializer.serialize_u128(int)
+				} else if let Ok(int) = i128::try_from(int) {
+					serializer.serialize_i128(int)
+				} else {
+					Err(S::Error::custom(too_big(int)))
+				}
+			},
+			MalType::Number(num) => serializer.serialize_f64(num.to_f64()),
+			MalType::Function(..) | MalType::TCOFunction { .. } => {
+				Err(S::Error::custom("can't serialize a function"))
+			},
+			MalType::Reduced(_) => {
+				Err(S::Error::custom("can't serialize a reduced value"))
+			},
+			MalType::Regex(_) => {
+				Err(S::Error::custom("can't serialize a regex"))
+			},
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for MalType {
+	fn deserialize<D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Self, D::Error> {
+		struct MalVisitor;
+
+		impl<'de> Visitor<'de> for MalVisitor {
+			type Value = MalType;
+
+			fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				f.write_str("a mal value")
+			}
+
+			fn visit_bool<E>(self, v: bool) -> Result<MalType, E> {
+				Ok(MalType::Bool(v))
+			}
+
+			fn visit_i64<E>(self, v: i64) -> Result<MalType, E> {
+				Ok(MalType::Number(Number::Int(v)))
+			}
+
+			fn visit_i128<E>(self, v: i128) -> Result<MalType, E> {
+				Ok(MalType::Number(BigInt::from(v).into()))
+			}
+
+			fn visit_u64<E>(self, v: u64) -> Result<MalType, E> {
+				Ok(MalType::Number(BigInt::from(v).into()))
+			}
+
+			fn visit_u128<E>(self, v: u128) -> Result<MalType, E> {
+				Ok(MalType::Number(BigInt::from(v).into()))
+			}
+
+			fn visit_f64<E>(self, v: f64) -> Result<MalType, E> {
+				Ok(MalType::Number(Number::Float(v)))
+			}
+
+			fn visit_char<E>(self, v: char) -> Result<MalType, E> {
+				Ok(MalType::Char(v))
+			}
+
+			fn visit_str<E>(self, v: &str) -> Result<MalType, E> {
+				Ok(MalType::String(v.to_string()))
+			}
+
+			fn visit_string<E>(self, v: String) -> Result<MalType, E> {
+				Ok(MalType::String(v))
+			}
+
+			fn visit_unit<E>(self) -> Result<MalType, E> {
+				Ok(MalType::Nil)
+			}
+
+			fn visit_none<E>(self) -> Result<MalType, E> {
+				Ok(MalType::Nil)
+			}
+
+			fn visit_some<D: Deserializer<'de>>(
+				self,
+				deserializer: D,
+			) -> Result<MalType, D::Error> {
+				MalType::deserialize(deserializer)
+			}
+
+			fn visit_seq<A: de::SeqAccess<'de>>(
+				self,
+				mut seq: A,
+			) -> Result<MalType, A::Error> {
+				let mut list = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+				while let Some(item) = seq.next_element()? {
+					list.push(item);
+				}
+				Ok(MalType::L(ListKind::Vector, list, None))
+			}
+
+			fn visit_map<A: de::MapAccess<'de>>(
+				self,
+				mut map: A,
+			) -> Result<MalType, A::Error> {
+				let mut res = MalMap::new();
+				while let Some((key, value)) =
+					map.next_entry::<MalType, MalType>()?
+				{
+					res.insert(key, value)
+						.map_err(<A::Error as de::Error>::custom)?;
+				}
+				Ok(MalType::HashMap(res, None))
+			}
+		}
+
+		deserializer.deserialize_any(MalVisitor)
+	}
+}
+
+impl<'de> IntoDeserializer<'de, Error> for MalType {
+	type Deserializer = Self;
+
+	fn into_deserializer(self) -> Self {
+		self
+	}
+}
+
+impl<'de> Deserializer<'de> for MalType {
+	type Error = Error;
+
+	forward_to_deserialize_any! {
+		bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+		bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+		identifier ignored_any
+	}
+
+	fn deserialize_any<V: Visitor<'de>>(
+		self,
+		visitor: V,
+	) -> Result<V::Value, Error> {
+		match self {
+			MalType::Atom(atom) => {
+				let value = atom.borrow().clone();
+				value.deserialize_any(visitor)
+			},
+			MalType::Bool(b) => visitor.visit_bool(b),
+			MalType::Char(ch) => visitor.visit_char(ch),
+			MalType::HashMap(map, _) => {
+				let mut map = MapDeserializer::new(map.into_iter());
+				let res = visitor.visit_map(&mut map)?;
+				map.end()?;
+				Ok(res)
+			},
+			MalType::Keyword(name)
+			| MalType::String(name)
+			| MalType::Symbol(name, _) => visitor.visit_string(name),
+			MalType::L(_, list, _) => {
+				let mut seq = SeqDeserializer::new(list.into_iter());
+				let res = visitor.visit_seq(&mut seq)?;
+				seq.end()?;
+				Ok(res)
+			},
+			MalType::Set(set) => {
+				let mut seq = SeqDeserializer::new(set.into_iter());
+				let res = visitor.visit_seq(&mut seq)?;
+				seq.end()?;
+				Ok(res)
+			},
+			value @ MalType::LazySeq(_) => {
+				let items = value
+					.into_items()
+					.collect::<eyre::Result<Vec<_>>>()
+					.map_err(Error::custom)?;
+				let mut seq = SeqDeserializer::new(items.into_iter());
+				let res = visitor.visit_seq(&mut seq)?;
+				seq.end()?;
+				Ok(res)
+			},
+			MalType::Nil => visitor.visit_unit(),
+			MalType::Number(Number::Int(int)) => visitor.visit_i64(int),
+			MalType::Number(Number::BigInt(int)) => {
+				if let Ok(int) = u128::try_from(&int) {
+					visitor.visit_u128(int)
+				} else if let Ok(int) = i128::try_from(&int) {
+					visitor.visit_i128(int)
+				} else {
+					Err(Error::custom(too_big(&int)))
+				}
+			},
+			MalType::Number(num) => visitor.visit_f64(num.to_f64()),
+			MalType::Function(..) | MalType::TCOFunction { .. } => {
+				Err(Error::custom("can't deserialize a function"))
+			},
+			MalType::Reduced(_) => {
+				Err(Error::custom("can't deserialize a reduced value"))
+			},
+			MalType::Regex(_) => {
+				Err(Error::custom("can't deserialize a regex"))
+			},
+		}
+	}
+
+	fn deserialize_option<V: Visitor<'de>>(
+		self,
+		visitor: V,
+	) -> Result<V::Value, Error> {
+		match self {
+			MalType::Nil => visitor.visit_none(),
+			value => visitor.visit_some(value),
+		}
+	}
+
+	fn deserialize_newtype_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		visitor: V,
+	) -> Result<V::Value, Error> {
+		visitor.visit_newtype_struct(self)
+	}
+
+	fn deserialize_enum<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		_variants: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, Error> {
+		let (variant, value) = match self {
+			MalType::Keyword(variant) | MalType::String(variant) => {
+				(variant, None)
+			},
+			MalType::HashMap(map, _) if map.len() == 1 => {
+				match map.into_iter().next().unwrap() {
+					(
+						MalType::Keyword(variant) | MalType::String(variant),
+						value,
+					) => (variant, Some(value)),
+					(key, _) => {
+						return Err(Error(format!(
+							"expected a keyword or string for an enum variant \
+							 (got {key:#})"
+						)))
+					},
+				}
+			},
+			value => {
+				return Err(Error(format!(
+					"expected a keyword or a hashmap with a single key for an \
+					 enum (got {value:#})"
+				)))
+			},
+		};
+		visitor.visit_enum(EnumDeserializer { variant, value })
+	}
+}
+
+struct EnumDeserializer {
+	variant: String,
+	value:   Option<MalType>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+	type Error = Error;
+	type Variant = VariantDeserializer;
+
+	fn variant_seed<V: de::DeserializeSeed<'de>>(
+		self,
+		seed: V,
+	) -> Result<(V::Value, VariantDeserializer), Error> {
+		let variant = seed.deserialize(MalType::String(self.variant))?;
+		Ok((variant, VariantDeserializer(self.value)))
+	}
+}
+
+struct VariantDeserializer(Option<MalType>);
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+	type Error = Error;
+
+	fn unit_variant(self) -> Result<(), Error> {
+		match self.0 {
+			None | Some(MalType::Nil) => Ok(()),
+			Some(value) => {
+				Err(Error(format!("expected a unit variant (got {value:#})")))
+			},
+		}
+	}
+
+	fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+		self,
+		seed: T,
+	) -> Result<T::Value, Error> {
+		seed.deserialize(self.0.unwrap_or(MalType::Nil))
+	}
+
+	fn tuple_variant<V: Visitor<'de>>(
+		self,
+		_len: usize,
+		visitor: V,
+	) -> Result<V::Value, Error> {
+		self.0.unwrap_or(MalType::Nil).deserialize_seq(visitor)
+	}
+
+	fn struct_variant<V: Visitor<'de>>(
+		self,
+		_fields: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, Error> {
+		self.0.unwrap_or(MalType::Nil).deserialize_map(visitor)
+	}
+}
+
+/// Serializes Rust values into `MalType`s.
+pub struct MalSerializer;
+
+/// A variant wrapping a value is represented as `{:variant value}`.
+fn wrap_variant(variant: Option<&'static str>, value: MalType) -> MalType {
+	match variant {
+		Some(variant) => MalType::HashMap(
+			[(MalType::Keyword(variant.to_string()), value)]
+				.into_iter()
+				.collect(),
+			None,
+		),
+		None => value,
+	}
+}
+
+impl Serializer for MalSerializer {
+	type Error = Error;
+	type Ok = MalType;
+	type SerializeMap = SerializeMap;
+	type SerializeSeq = SerializeSeq;
+	type SerializeStruct = SerializeMap;
+	type SerializeStructVariant = SerializeMap;
+	type SerializeTuple = SerializeSeq;
+	type SerializeTupleStruct = SerializeSeq;
+	type SerializeTupleVariant = SerializeSeq;
+
+	fn serialize_bool(self, v: bool) -> Result<MalType, Error> {
+		Ok(MalType::Bool(v))
+	}
+
+	fn serialize_i8(self, v: i8) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Int(v.into())))
+	}
+
+	fn serialize_i16(self, v: i16) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Int(v.into())))
+	}
+
+	fn serialize_i32(self, v: i32) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Int(v.into())))
+	}
+
+	fn serialize_i64(self, v: i64) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Int(v)))
+	}
+
+	fn serialize_i128(self, v: i128) -> Result<MalType, Error> {
+		Ok(MalType::Number(BigInt::from(v).into()))
+	}
+
+	fn serialize_u8(self, v: u8) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Int(v.into())))
+	}
+
+	fn serialize_u16(self, v: u16) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Int(v.into())))
+	}
+
+	fn serialize_u32(self, v: u32) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Int(v.into())))
+	}
+
+	fn serialize_u64(self, v: u64) -> Result<MalType, Error> {
+		Ok(MalType::Number(BigInt::from(v).into()))
+	}
+
+	fn serialize_u128(self, v: u128) -> Result<MalType, Error> {
+		Ok(MalType::Number(BigInt::from(v).into()))
+	}
+
+	fn serialize_f32(self, v: f32) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Float(v.into())))
+	}
+
+	fn serialize_f64(self, v: f64) -> Result<MalType, Error> {
+		Ok(MalType::Number(Number::Float(v)))
+	}
+
+	fn serialize_char(self, v: char) -> Result<MalType, Error> {
+		Ok(MalType::Char(v))
+	}
+
+	fn serialize_str(self, v: &str) -> Result<MalType, Error> {
+		Ok(MalType::String(v.to_string()))
+	}
+
+	fn serialize_bytes(self, v: &[u8]) -> Result<MalType, Error> {
+		Ok(MalType::L(
+			ListKind::Vector,
+			v.iter()
+				.map(|byte| MalType::Number(Number::Int(*byte as _)))
+				.collect(),
+			None,
+		))
+	}
+
+	fn serialize_none(self) -> Result<MalType, Error> {
+		Ok(MalType::Nil)
+	}
+
+	fn serialize_some<T: Serialize + ?Sized>(
+		self,
+		value: &T,
+	) -> Result<MalType, Error> {
+		value.serialize(self)
+	}
+
+	fn serialize_unit(self) -> Result<MalType, Error> {
+		Ok(MalType::Nil)
+	}
+
+	fn serialize_unit_struct(
+		self,
+		_name: &'static str,
+	) -> Result<MalType, Error> {
+		Ok(MalType::Nil)
+	}
+
+	fn serialize_unit_variant(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+	) -> Result<MalType, Error> {
+		Ok(MalType::Keyword(variant.to_string()))
+	}
+
+	fn serialize_newtype_struct<T: Serialize + ?Sized>(
+		self,
+		_name: &'static str,
+		value: &T,
+	) -> Result<MalType, Error> {
+		value.serialize(self)
+	}
+
+	fn serialize_newtype_variant<T: Serialize + ?Sized>(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+		value: &T,
+	) -> Result<MalType, Error> {
+		Ok(wrap_variant(Some(variant), value.serialize(self)?))
+	}
+
+	fn serialize_seq(self, len: Option<usize>) -> Result<SerializeSeq, Error> {
+		Ok(SerializeSeq {
+			list:    Vec::with_capacity(len.unwrap_or(0)),
+			variant: None,
+		})
+	}
+
+	fn serialize_tuple(self, len: usize) -> Result<SerializeSeq, Error> {
+		self.serialize_seq(Some(len))
+	}
+
+	fn serialize_tuple_struct(
+		self,
+		_name: &'static str,
+		len: usize,
+	) -> Result<SerializeSeq, Error> {
+		self.serialize_seq(Some(len))
+	}
+
+	fn serialize_tuple_variant(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+		len: usize,
+	) -> Result<SerializeSeq, Error> {
+		Ok(SerializeSeq {
+			list:    Vec::with_capacity(len),
+			variant: Some(variant),
+		})
+	}
+
+	fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, Error> {
+		Ok(SerializeMap {
+			map:     MalMap::new(),
+			key:     None,
+			variant: None,
+		})
+	}
+
+	fn serialize_struct(
+		self,
+		_name: &'static str,
+		len: usize,
+	) -> Result<SerializeMap, Error> {
+		self.serialize_map(Some(len))
+	}
+
+	fn serialize_struct_variant(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+		_len: usize,
+	) -> Result<SerializeMap, Error> {
+		Ok(SerializeMap {
+			map:     MalMap::new(),
+			key:     None,
+			variant: Some(variant),
+		})
+	}
+}
+
+pub struct SerializeSeq {
+	list:    Vec<MalType>,
+	variant: Option<&'static str>,
+}
+
+impl SerializeSeq {
+	fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+		self.list.push(value.serialize(MalSerializer)?);
+		Ok(())
+	}
+
+	fn finish(self) -> Result<MalType, Error> {
+		Ok(wrap_variant(
+			self.variant,
+			MalType::L(ListKind::Vector, self.list, None),
+		))
+	}
+}
+
+impl ser::SerializeSeq for SerializeSeq {
+	type Error = Error;
+	type Ok = MalType;
+
+	fn serialize_element<T: Serialize + ?Sized>(
+		&mut self,
+		value: &T,
+	) -> Result<(), Error> {
+		self.push(value)
+	}
+
+	fn end(self) -> Result<MalType, Error> {
+		self.finish()
+	}
+}
+
+impl ser::SerializeTuple for SerializeSeq {
+	type Error = Error;
+	type Ok = MalType;
+
+	fn serialize_element<T: Serialize + ?Sized>(
+		&mut self,
+		value: &T,
+	) -> Result<(), Error> {
+		self.push(value)
+	}
+
+	fn end(self) -> Result<MalType, Error> {
+		self.finish()
+	}
+}
+
+impl ser::SerializeTupleStruct for SerializeSeq {
+	type Error = Error;
+	type Ok = MalType;
+
+	fn serialize_field<T: Serialize + ?Sized>(
+		&mut self,
+		value: &T,
+	) -> Result<(), Error> {
+		self.push(value)
+	}
+
+	fn end(self) -> Result<MalType, Error> {
+		self.finish()
+	}
+}
+
+impl ser::SerializeTupleVariant for SerializeSeq {
+	type Error = Error;
+	type Ok = MalType;
+
+	fn serialize_field<T: Serialize + ?Sized>(
+		&mut self,
+		value: &T,
+	) -> Result<(), Error> {
+		self.push(value)
+	}
+
+	fn end(self) -> Result<MalType, Error> {
+		self.finish()
+	}
+}
+
+pub struct SerializeMap {
+	map:     MalMap,
+	key:     Option<MalType>,
+	variant: Option<&'static str>,
+}
+
+impl SerializeMap {
+	fn field<T: Serialize + ?Sized>(
+		&mut self,
+		key: &'static str,
+		value: &T,
+	) -> Result<(), Error> {
+		self.map
+			.insert(
+				MalType::Keyword(key.to_string()),
+				value.serialize(MalSerializer)?,
+			)
+			.map_err(Error::custom)?;
+		Ok(())
+	}
+
+	fn finish(self) -> Result<MalType, Error> {
+		Ok(wrap_variant(self.variant, MalType::HashMap(self.map, None)))
+	}
+}
+
+impl ser::SerializeMap for SerializeMap {
+	type Error = Error;
+	type Ok = MalType;
+
+	fn serialize_key<T: Serialize + ?Sized>(
+		&mut self,
+		key: &T,
+	) -> Result<(), Error> {
+		self.key = Some(key.serialize(MalSerializer)?);
+		Ok(())
+	}
+
+	fn serialize_value<T: Serialize + ?Sized>(
+		&mut self,
+		value: &T,
+	) -> Result<(), Error> {
+		let key = self
+			.key
+			.take()
+			.ok_or_else(|| Error::custom("hashmap value without a key"))?;
+		self.map
+			.insert(key, value.serialize(MalSerializer)?)
+			.map_err(Error::custom)?;
+		Ok(())
+	}
+
+	fn end(self) -> Result<MalType, Error> {
+		self.finish()
+	}
+}
+
+impl ser::SerializeStruct for SerializeMap {
+	type Error = Error;
+	type Ok = MalType;
+
+	fn serialize_field<T: Serialize + ?Sized>(
+		&mut self,
+		key: &'static str,
+		value: &T,
+	) -> Result<(), Error> {
+		self.field(key, value)
+	}
+
+	fn end(self) -> Result<MalType, Error> {
+		self.finish()
+	}
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+	type Error = Error;
+	type Ok = MalType;
+
+	fn serialize_field<T: Serialize + ?Sized>(
+		&mut self,
+		key: &'static str,
+		value: &T,
+	) -> Result<(), Error> {
+		self.field(key, value)
+	}
+
+	fn end(self) -> Result<MalType, Error> {
+		self.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use ::serde::Deserialize;
+	use ::serde::Serialize;
+
+	use super::*;
+	use crate::reader;
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize)]
+	enum Shape {
+		Point,
+		Circle(f64),
+		Rect { w: f64, h: f64 },
+	}
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize)]
+	struct Config {
+		name:    String,
+		retries: u32,
+		ratio:   f64,
+		tags:    Vec<String>,
+		parent:  Option<Box<Config>>,
+		shapes:  Vec<Shape>,
+	}
+
+	#[test]
+	fn test_from_mal() -> eyre::Result<()> {
+		let form = reader::read_str(
+			r#"{:name "a" :retries 3 :ratio 0.5 :tags ["x" "y"]
+			    :parent {"name" "b" "retries" 0 "ratio" 1 "tags" () "shapes" []}
+			    :shapes [:Point {:Circle 2} {:Rect {:w 1 :h 2}}]}"#,
+		)?;
+		let config: Config = from_mal(form)?;
+		assert_eq!(config, Config {
+			name:    "a".to_string(),
+			retries: 3,
+			ratio:   0.5,
+			tags:    vec!["x".to_string(), "y".to_string()],
+			parent:  Some(Box::new(Config {
+				name:    "b".to_string(),
+				retries: 0,
+				ratio:   1.0,
+				tags:    vec![],
+				parent:  None,
+				shapes:  vec![],
+			})),
+			shapes:  vec![Shape::Point, Shape::Circle(2.0), Shape::Rect {
+				w: 1.0,
+				h: 2.0,
+			}],
+		});
+
+		let err =
+			from_mal::<Config>(reader::read_str(r#"{:name 1}"#)?).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"invalid type: integer `1`, expected a string"
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_to_mal() -> eyre::Result<()> {
+		let config = Config {
+			name:    "a".to_string(),
+			retries: 3,
+			ratio:   0.5,
+			tags:    vec!["x".to_string()],
+			parent:  None,
+			shapes:  vec![Shape::Point, Shape::Rect { w: 1.0, h: 2.0 }],
+		};
+		let value = to_mal(&config)?;
+		assert_eq!(
+			value,
+			reader::read_str(
+				r#"{:name "a" :retries 3 :ratio 0.5 :tags ["x"] :parent nil
+				    :shapes [:Point {:Rect {:w 1.0 :h 2.0}}]}"#
+			)?
+		);
+		assert_eq!(from_mal::<Config>(value)?, config);
+		Ok(())
+	}
+
+	#[test]
+	fn test_mal_type_round_trip() -> eyre::Result<()> {
+		let form = reader::read_str(r#"[1 2.5 "s" nil true {"k" ["v"]}]"#)?;
+		assert_eq!(MalType::deserialize(form.clone())?, form);
+		assert_eq!(to_mal(&form)?, form);
+
+		// keywords only survive as hashmap keys of structs
+		let form = reader::read_str("[:kw {:k 1}]")?;
+		assert_eq!(to_mal(&form)?, reader::read_str(r#"["kw" {"k" 1}]"#)?);
+		Ok(())
+	}
+}
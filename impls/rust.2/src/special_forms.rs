@@ -0,0 +1,328 @@
+//! The special forms understood by `Interpreter::standard()`. Each of these
+//! can be registered on an `Interpreter` on its own, under any name.
+
+use std::rc::Rc;
+
+use eyre::Result;
+
+use crate::env::Env;
+use crate::eval;
+use crate::eval::gen_env_from_param_app;
+use crate::eval::Continuation;
+use crate::eval::Interpreter;
+use crate::lazy::Lazy;
+use crate::number::Number;
+use crate::types::Frame;
+use crate::types::ListKind;
+use crate::types::MalException;
+use crate::types::MalType;
+use crate::types::TracedError;
+
+/// `(def! key value)`
+pub fn def(
+	interpreter: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let [MalType::Symbol(key, _), value] = destructure("def!", args)? else {
+		return Err(eyre!("can't `def!` a non-variable"));
+	};
+	let mut value = interpreter.eval(value, env.clone())?;
+	if let MalType::TCOFunction {
+		name: name @ None, ..
+	} = &mut value
+	{
+		*name = Some(key.clone());
+	}
+	env.set(key, value.clone());
+	Ok(Continuation::Return(value))
+}
+
+/// `(defmacro! key function)`
+pub fn defmacro(
+	interpreter: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let [MalType::Symbol(key, _), value] = destructure("defmacro!", args)?
+	else {
+		return Err(eyre!("can't `defmacro!` a non-variable"));
+	};
+	let MalType::TCOFunction {
+		ast,
+		params,
+		env: closed,
+		function,
+		meta,
+		name,
+		#[cfg(feature = "vm")]
+		compiled,
+		..
+	} = interpreter.eval(value, env.clone())?
+	else {
+		return Err(eyre!("can't `defmacro!` a non-function"));
+	};
+	let value = MalType::TCOFunction {
+		ast,
+		params,
+		env: closed,
+		function,
+		is_macro: true,
+		meta,
+		name: name.or_else(|| Some(key.clone())),
+		#[cfg(feature = "vm")]
+		compiled,
+	};
+	env.set(key, value.clone());
+	Ok(Continuation::Return(value))
+}
+
+/// `(do forms...)`
+pub fn do_(
+	interpreter: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let mut forms = args.into_iter();
+	let Some(last) = forms.next_back() else {
+		return Ok(Continuation::Return(MalType::Nil));
+	};
+	for form in forms {
+		interpreter.eval(form, env.clone())?;
+	}
+	Ok(Continuation::Eval(last, env))
+}
+
+/// `(fn* (params...) body)`
+pub fn fn_star(
+	interpreter: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let [MalType::L(_, params, _), body] = destructure("fn*", args)? else {
+		return Err(eyre!("invalid `fn*` form: expected parameter list"));
+	};
+	let params = params
+		.into_iter()
+		.map(|param_name| match param_name {
+			MalType::Symbol(sym, _) => Ok(sym),
+			_ => Err(eyre!(
+				"invalid `fn*` form: expected symbol for parameter name"
+			)),
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	let interpreter = interpreter.clone();
+	let closed = env.clone();
+	Ok(Continuation::Return(MalType::TCOFunction {
+		ast: Box::new(body.clone()),
+		params: params.clone(),
+		env,
+		is_macro: false,
+		meta: Box::new(MalType::Nil),
+		name: None,
+		#[cfg(feature = "vm")]
+		compiled: None,
+		function: Rc::new(move |args| {
+			let closed_env = gen_env_from_param_app(
+				Some(closed.clone()),
+				params.clone().into_iter(),
+				args.iter().cloned(),
+			)?;
+			interpreter.eval(body.clone(), closed_env)
+		}),
+	}))
+}
+
+/// `(if cond then else?)`
+pub fn if_(
+	interpreter: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let mut args = args.into_iter();
+	let (Some(cond), Some(then), els, None) =
+		(args.next(), args.next(), args.next(), args.next())
+	else {
+		return Err(eyre!("`if` expects 2 or 3 arguments"));
+	};
+	Ok(match interpreter.eval(cond, env.clone())? {
+		MalType::Bool(false) | MalType::Nil => {
+			Continuation::Eval(els.unwrap_or(MalType::Nil), env)
+		},
+		_ => Continuation::Eval(then, env),
+	})
+}
+
+/// `(lazy-seq body...)`, a lazy seq of whatever seq the body evaluates to.
+pub fn lazy_seq(
+	interpreter: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let interpreter = interpreter.clone();
+	Ok(Continuation::Return(Lazy::seq(move || {
+		let mut res = MalType::Nil;
+		for form in &args {
+			res = interpreter.eval(form.clone(), env.clone())?;
+		}
+		Ok(res)
+	})))
+}
+
+/// `(let* (bindings...) body)`
+pub fn let_star(
+	interpreter: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let [MalType::L(_, bindings, _), body] = destructure("let*", args)? else {
+		return Err(eyre!("invalid `let*` form: expected bindings"));
+	};
+	let inner_env = Env::new(Some(env));
+	let mut bindings = bindings.into_iter();
+	while let Some(key) = bindings.next() {
+		let MalType::Symbol(key, _) = key else {
+			return Err(eyre!(
+				"invalid `let*` form: expected symbol for binding"
+			));
+		};
+		let Some(value) = bindings.next() else {
+			return Err(eyre!(
+				"invalid `let*` form: expected value to bind to variable \
+				 `{key}`"
+			));
+		};
+		let value = interpreter.eval(value, inner_env.clone())?;
+		inner_env.set(key, value)
+	}
+	Ok(Continuation::Eval(body, inner_env))
+}
+
+/// `(macroexpand form)`
+pub fn macroexpand(
+	interpreter: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let [ast] = destructure("macroexpand", args)?;
+	Ok(Continuation::Return(interpreter.macroexpand(ast, &env)?))
+}
+
+/// `(quasiquote form)`
+pub fn quasiquote(
+	_: &Interpreter,
+	args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	let [ast] = destructure("quasiquote", args)?;
+	Ok(Continuation::Eval(eval::quasiquote(ast), env))
+}
+
+/// `(quasiquoteexpand form)`
+pub fn quasiquoteexpand(
+	_: &Interpreter,
+	args: Vec<MalType>,
+	_: Env,
+) -> Result<Continuation> {
+	let [ast] = destructure("quasiquoteexpand", args)?;
+	Ok(Continuation::Return(eval::quasiquote(ast)))
+}
+
+/// `(quote form)`
+pub fn quote(
+	_: &Interpreter,
+	args: Vec<MalType>,
+	_: Env,
+) -> Result<Continuation> {
+	let [ast] = destructure("quote", args)?;
+	Ok(Continuation::Return(ast))
+}
+
+/// `(try* body (catch* binding handler)?)`
+///
+/// Besides the binding, the handler sees `*stack-trace*`: the mal call stack
+/// where the error was raised, innermost call first, as a vector of hashmaps
+/// with `:name`, `:file`, `:line`, `:column` and `:elided` keys (where known).
+/// The trace is bound rather than attached to the caught value as metadata,
+/// as most thrown values, like the strings builtins fail with, can't carry
+/// metadata.
+pub fn try_star(
+	interpreter: &Interpreter,
+	mut args: Vec<MalType>,
+	env: Env,
+) -> Result<Continuation> {
+	if args.len() == 1 {
+		return Ok(Continuation::Eval(args.remove(0), env));
+	}
+	let [body, MalType::L(ListKind::List, catch, _)] =
+		destructure("try*", args)?
+	else {
+		return Err(eyre!("invalid `try*` form: expected `catch*` clause"));
+	};
+	let Ok([MalType::Symbol(catch_sym, _), MalType::Symbol(bind, _), handler]) =
+		<[MalType; 3]>::try_from(catch)
+	else {
+		return Err(eyre!(
+			"invalid `catch*` form: expected binding and handler"
+		));
+	};
+	if catch_sym != "catch*" {
+		return Err(eyre!("invalid `try*` form: expected `catch*` clause"));
+	}
+
+	match interpreter.eval(body, env.clone()) {
+		Ok(value) => Ok(Continuation::Return(value)),
+		Err(err) => {
+			let (err, trace) = TracedError::split(err);
+			let exc = match err.downcast::<MalException>() {
+				Ok(exc) => exc.into_value(),
+				Err(err) => MalType::String(err.to_string()),
+			};
+			let trace = trace.into_iter().map(frame_to_mal).collect();
+			Ok(Continuation::Eval(
+				handler,
+				Env::new_with_bindings_list(
+					Some(env),
+					[
+						(bind, exc),
+						(
+							"*stack-trace*".to_string(),
+							MalType::L(ListKind::Vector, trace, None),
+						),
+					]
+					.into_iter(),
+				),
+			))
+		},
+	}
+}
+
+pub(crate) fn frame_to_mal(frame: Frame) -> MalType {
+	let mut entries = Vec::new();
+	let mut insert = |key: &str, value| {
+		entries.push((MalType::Keyword(key.to_string()), value));
+	};
+	if let Some(name) = frame.name {
+		insert("name", MalType::String(name));
+	}
+	if let Some(span) = frame.span {
+		if let Some(file) = span.file {
+			insert("file", MalType::String(file.to_string()));
+		}
+		insert("line", MalType::Number(Number::Int(span.line as _)));
+		insert("column", MalType::Number(Number::Int(span.col as _)));
+	}
+	insert("elided", MalType::Number(Number::Int(frame.elided as _)));
+	MalType::HashMap(entries.into_iter().collect(), None)
+}
+
+pub(crate) fn destructure<const N: usize>(
+	form: &str,
+	args: Vec<MalType>,
+) -> Result<[MalType; N]> {
+	let len = args.len();
+	<[MalType; N]>::try_from(args).map_err(|_| {
+		eyre!("`{form}` expects {N} arguments: {len} were provided")
+	})
+}
@@ -0,0 +1,579 @@
+//! String and char functions. Strings are indexed by char rather than by
+//! byte, so no index can land in the middle of a multi-byte character.
+
+use std::iter;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use eyre::Result;
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
+
+use crate::convert::FromMal;
+use crate::core::coll_items;
+use crate::env::Env;
+use crate::number::Number;
+use crate::types::ListKind;
+use crate::types::MalType;
+
+/// Binds the string functions in `env`.
+pub fn register(env: &Env) {
+	register_optional(env, "subs", 2, 3, |args| {
+		let string: String = arg("subs", args, 0)?;
+		let start = offset(&string, arg("subs", args, 1)?);
+		let end = match optional::<usize>("subs", args, 2)? {
+			Some(end) => offset(&string, end),
+			None => Some(string.len()),
+		};
+		match (start, end) {
+			(Some(start), Some(end)) if start <= end => {
+				Ok(MalType::String(string[start..end].to_string()))
+			},
+			_ => Err(eyre!("`subs`: index out of range")),
+		}
+	});
+	register_optional(env, "split", 2, 3, |args| {
+		let string: String = arg("split", args, 0)?;
+		let sep: String = arg("split", args, 1)?;
+		let limit = match optional::<usize>("split", args, 2)? {
+			Some(0) => return Err(eyre!("`split` expects a positive limit")),
+			Some(limit) => limit,
+			None => usize::MAX,
+		};
+		Ok(MalType::L(
+			ListKind::Vector,
+			split(&string, &sep, limit)
+				.into_iter()
+				.map(|part| MalType::String(part.to_string()))
+				.collect(),
+			None,
+		))
+	});
+	register_optional(env, "join", 1, 2, |args| {
+		let (sep, coll) = match args {
+			[coll] => (String::new(), coll),
+			[_, coll] => (arg("join", args, 0)?, coll),
+			_ => unreachable!(),
+		};
+		let mut joined = String::new();
+		for (idx, item) in coll_items("join", coll)?.enumerate() {
+			let item = item?;
+			item.realize()?;
+			if idx > 0 {
+				joined.push_str(&sep);
+			}
+			joined.push_str(&item.pr_str::<false>());
+		}
+		Ok(MalType::String(joined))
+	});
+	env.register_fn("replace", |string: String, from: String, to: String| {
+		if from.is_empty() {
+			return Err(eyre!("`replace` can't replace an empty string"));
+		}
+		Ok(string.replace(&from, &to))
+	});
+	env.register_fn("upper-case", |string: String| Ok(string.to_uppercase()));
+	env.register_fn("lower-case", |string: String| Ok(string.to_lowercase()));
+	env.register_fn("trim", |string: String| Ok(string.trim().to_string()));
+	env.register_fn("starts-with?", |string: String, prefix: String| {
+		Ok(string.starts_with(&prefix))
+	});
+	env.register_fn("ends-with?", |string: String, suffix: String| {
+		Ok(string.ends_with(&suffix))
+	});
+	register_optional(env, "index-of", 2, 3, |args| {
+		let string: String = arg("index-of", args, 0)?;
+		let value: String = arg("index-of", args, 1)?;
+		let from = match optional::<usize>("index-of", args, 2)? {
+			Some(from) => offset(&string, from).unwrap_or(string.len()),
+			None => 0,
+		};
+		Ok(match string[from..].find(&value) {
+			Some(found) => MalType::Number(Number::Int(
+				string[..from + found].chars().count() as i64,
+			)),
+			None => MalType::Nil,
+		})
+	});
+	env.register_fn("char-at", |string: String, idx: usize| {
+		match string.chars().nth(idx) {
+			Some(ch) => Ok(ch),
+			None => Err(eyre!("`char-at`: index out of range")),
+		}
+	});
+	env.register_fn("char", |value: MalType| match value {
+		MalType::Char(_) => Ok(value),
+		MalType::Number(Number::Int(int)) => u32::try_from(int)
+			.ok()
+			.and_then(char::from_u32)
+			.map(MalType::Char)
+			.ok_or_else(|| eyre!("`char`: {int} isn't a valid code point")),
+		value => {
+			Err(eyre!("`char` expects a char or an integer (got {value:#})"))
+		},
+	});
+	env.register_fn("int", |value: MalType| match value {
+		MalType::Char(ch) => Ok(MalType::Number(Number::Int(ch as i64))),
+		MalType::Number(Number::Int(_) | Number::BigInt(_)) => Ok(value),
+		MalType::Number(Number::Ratio(ratio)) => {
+			Ok(MalType::Number(ratio.to_integer().into()))
+		},
+		MalType::Number(Number::Float(float)) => {
+			BigInt::from_f64(float.trunc())
+				.map(|int| MalType::Number(int.into()))
+				.ok_or_else(|| eyre!("`int` can't truncate {float}"))
+		},
+		value => Err(eyre!("`int` expects a char or a number (got {value:#})")),
+	});
+	register_optional(env, "format", 1, usize::MAX, |args| {
+		let template: String = arg("format", args, 0)?;
+		format(&template, &args[1..]).map(MalType::String)
+	});
+	register_optional(env, "str->int", 1, 2, |args| {
+		let string: String = arg("str->int", args, 0)?;
+		let radix = match optional::<u32>("str->int", args, 1)? {
+			Some(radix @ 2..=36) => radix,
+			Some(_) => {
+				return Err(eyre!("`str->int` expects a radix from 2 to 36"))
+			},
+			None => 10,
+		};
+		Ok(match str_to_int(&string, radix) {
+			Some(int) => MalType::Number(int.into()),
+			None => MalType::Nil,
+		})
+	});
+	env.register_fn("parse-number", |string: String| {
+		Ok(string.parse::<Number>().ok())
+	});
+}
+
+/// Binds `name` to a builtin taking from `min` to `max` args, which it
+/// converts itself with `arg` and `optional`.
+fn register_optional(
+	env: &Env,
+	name: &'static str,
+	min: usize,
+	max: usize,
+	function: fn(&[MalType]) -> Result<MalType>,
+) {
+	let builtin = MalType::builtin(move |args| {
+		if args.len() < min || args.len() > max {
+			return Err(if max == usize::MAX {
+				eyre!(
+					"`{name}` expects at least {min} args: {} were provided",
+					args.len(),
+				)
+			} else {
+				eyre!(
+					"`{name}` expects {min} to {max} args: {} were provided",
+					args.len(),
+				)
+			});
+		}
+		function(args)
+	});
+	env.set(name.to_string(), builtin);
+}
+
+/// Converts argument `idx` of a call to `name`, which must be there.
+fn arg<T: FromMal>(name: &str, args: &[MalType], idx: usize) -> Result<T> {
+	T::from_mal(args[idx].clone())
+		.map_err(|err| eyre!("`{name}`, argument {}: {err}", idx + 1))
+}
+
+/// Converts argument `idx` of a call to `name`, if it was passed.
+fn optional<T: FromMal>(
+	name: &str,
+	args: &[MalType],
+	idx: usize,
+) -> Result<Option<T>> {
+	if idx < args.len() {
+		arg(name, args, idx).map(Some)
+	} else {
+		Ok(None)
+	}
+}
+
+/// The byte offset of the character at `idx`, which may be one past the
+/// last.
+fn offset(string: &str, idx: usize) -> Option<usize> {
+	string
+		.char_indices()
+		.map(|(offset, _)| offset)
+		.chain(iter::once(string.len()))
+		.nth(idx)
+}
+
+/// At most `limit` parts of `string`. An empty `sep` splits it into its
+/// characters.
+fn split<'a>(string: &'a str, sep: &str, limit: usize) -> Vec<&'a str> {
+	if !sep.is_empty() {
+		return string.splitn(limit, sep).collect();
+	}
+	let mut parts = vec![];
+	let mut rest = string;
+	while let Some(ch) = rest.chars().next() {
+		if parts.len() + 1 == limit {
+			break;
+		}
+		let (part, tail) = rest.split_at(ch.len_utf8());
+		parts.push(part);
+		rest = tail;
+	}
+	if !rest.is_empty() {
+		parts.push(rest);
+	}
+	parts
+}
+
+/// Parses an optionally signed integer in `radix`.
+fn str_to_int(string: &str, radix: u32) -> Option<BigInt> {
+	let (negative, digits) = match string.as_bytes().first() {
+		Some(b'-') => (true, &string[1..]),
+		Some(b'+') => (false, &string[1..]),
+		_ => (false, string),
+	};
+	let int = crate::number::parse_int(digits, radix)?;
+	Some(if negative { -int } else { int })
+}
+
+/// A conversion in a `format` template: `%[flags][width][.precision]conv`.
+#[derive(Default)]
+struct Spec {
+	/// Left-align, from the `-` flag.
+	left:      bool,
+	/// Pad numbers with zeros, from the `0` flag.
+	zero:      bool,
+	/// Sign positive numbers, from the `+` flag.
+	plus:      bool,
+	width:     usize,
+	precision: Option<usize>,
+	conv:      char,
+}
+
+impl Spec {
+	fn parse(chars: &mut Peekable<Chars>) -> Result<Self> {
+		let mut spec = Spec::default();
+		while let Some(&flag) = chars.peek() {
+			match flag {
+				'-' => spec.left = true,
+				'0' => spec.zero = true,
+				'+' => spec.plus = true,
+				_ => break,
+			}
+			chars.next();
+		}
+		spec.width = digits(chars)?;
+		if chars.next_if_eq(&'.').is_some() {
+			spec.precision = Some(digits(chars)?);
+		}
+		spec.conv = chars.next().ok_or_else(|| {
+			eyre!("`format`: incomplete conversion at the end of the template")
+		})?;
+		Ok(spec)
+	}
+
+	fn integer(&self, arg: &MalType) -> Result<BigInt> {
+		match arg {
+			MalType::Number(Number::Int(int)) => Ok(BigInt::from(*int)),
+			MalType::Number(Number::BigInt(int)) => Ok(int.clone()),
+			arg => Err(eyre!(
+				"`format`: %{} expects an integer (got {arg:#})",
+				self.conv
+			)),
+		}
+	}
+
+	fn float(&self, arg: &MalType) -> Result<f64> {
+		match arg {
+			MalType::Number(num) => Ok(num.to_f64()),
+			arg => Err(eyre!(
+				"`format`: %{} expects a number (got {arg:#})",
+				self.conv
+			)),
+		}
+	}
+
+	/// Pads `body` out to the width, signing it first if it's a number.
+	fn pad(&self, body: String, numeric: bool) -> String {
+		let body = if numeric && self.plus && !body.starts_with('-') {
+			format!("+{body}")
+		} else {
+			body
+		};
+		let fill = self.width.saturating_sub(body.chars().count());
+		if self.left {
+			body + &" ".repeat(fill)
+		} else if self.zero && numeric {
+			// the zeros go after the sign
+			let sign = if body.starts_with(['-', '+']) { 1 } else { 0 };
+			format!("{}{}{}", &body[..sign], "0".repeat(fill), &body[sign..])
+		} else {
+			" ".repeat(fill) + &body
+		}
+	}
+}
+
+/// The most a width or precision can be. Rust's formatting takes no larger
+/// precision, and there's no call for a larger width.
+const MAX_WIDTH: usize = u16::MAX as usize;
+
+/// Parses a width or precision.
+fn digits(chars: &mut Peekable<Chars>) -> Result<usize> {
+	let mut n: usize = 0;
+	while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+		n = n
+			.checked_mul(10)
+			.and_then(|n| n.checked_add(digit as usize))
+			.filter(|&n| n <= MAX_WIDTH)
+			.ok_or_else(|| {
+				eyre!("`format`: width or precision over {MAX_WIDTH}")
+			})?;
+		chars.next();
+	}
+	Ok(n)
+}
+
+/// Like C's `%e`: the exponent is signed and has at least two digits.
+fn exponent(float: f64, precision: usize) -> String {
+	let printed = format!("{float:.precision$e}");
+	match printed.split_once('e') {
+		Some((mantissa, exp)) => {
+			let exp: i32 = exp.parse().unwrap_or(0);
+			let sign = if exp < 0 { '-' } else { '+' };
+			format!("{mantissa}e{sign}{:02}", exp.abs())
+		},
+		None => printed,
+	}
+}
+
+/// printf-style formatting. The flags are `-` to left-align, `0` to pad
+/// numbers with zeros and `+` to always sign them, and the conversions are
+/// `%s` (as by `str`), `%d`, `%x`, `%X` and `%o` for integers, `%f` and `%e`
+/// for numbers, and `%%` for a percent sign.
+fn format(template: &str, args: &[MalType]) -> Result<String> {
+	let mut args = args.iter();
+	let mut formatted = String::new();
+	let mut chars = template.chars().peekable();
+	while let Some(ch) = chars.next() {
+		if ch != '%' {
+			formatted.push(ch);
+			continue;
+		}
+		let spec = Spec::parse(&mut chars)?;
+		if spec.conv == '%' {
+			formatted.push('%');
+			continue;
+		}
+		let arg = args.next().ok_or_else(|| {
+			eyre!("`format`: not enough args for the template")
+		})?;
+		let (body, numeric) = match spec.conv {
+			's' => {
+				arg.realize()?;
+				let printed = arg.pr_str::<false>();
+				match spec.precision {
+					Some(precision) => {
+						(printed.chars().take(precision).collect(), false)
+					},
+					None => (printed, false),
+				}
+			},
+			'd' => (spec.integer(arg)?.to_string(), true),
+			'x' => (spec.integer(arg)?.to_str_radix(16), true),
+			'X' => (spec.integer(arg)?.to_str_radix(16).to_uppercase(), true),
+			'o' => (spec.integer(arg)?.to_str_radix(8), true),
+			'f' => {
+				let precision = spec.precision.unwrap_or(6);
+				(format!("{:.precision$}", spec.float(arg)?), true)
+			},
+			'e' => {
+				let float = spec.float(arg)?;
+				(exponent(float, spec.precision.unwrap_or(6)), true)
+			},
+			conv => return Err(eyre!("`format`: unknown conversion %{conv}")),
+		};
+		formatted.push_str(&spec.pad(body, numeric));
+	}
+	Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::eval::Interpreter;
+
+	#[test]
+	fn test_strings() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		for (input, output) in [
+			("(subs \"héllo\" 1 3)", "\"él\""),
+			("(subs \"héllo\" 2)", "\"llo\""),
+			("(split \"a,b,,c\" \",\")", "[\"a\" \"b\" \"\" \"c\"]"),
+			("(split \"a,b,c\" \",\" 2)", "[\"a\" \"b,c\"]"),
+			("(split \"日本語\" \"\")", "[\"日\" \"本\" \"語\"]"),
+			("(join \", \" [1 \"a\" :k])", "\"1, a, :k\""),
+			("(join (map inc [1 2]))", "\"23\""),
+			("(replace \"aXbX\" \"X\" \"日\")", "\"a日b日\""),
+			("(upper-case \"straße\")", "\"STRASSE\""),
+			("(lower-case \"ÀB\")", "\"àb\""),
+			("(trim \"  hi \\n\")", "\"hi\""),
+			("(starts-with? \"日本\" \"日\")", "true"),
+			("(ends-with? \"abc\" \"b\")", "false"),
+			("(index-of \"日本語本\" \"本\")", "1"),
+			("(index-of \"日本語本\" \"本\" 2)", "3"),
+			("(index-of \"abc\" \"z\")", "nil"),
+			("(char-at \"日本語\" 2)", "\\語"),
+			("(str->int \"-ff\" 16)", "-255"),
+			(
+				"(str->int \"99999999999999999999\")",
+				"99999999999999999999",
+			),
+			("(str->int \"1x\")", "nil"),
+			("(parse-number \"1/3\")", "1/3"),
+			("(parse-number \"-1.5e3\")", "-1500.0"),
+			("(parse-number \"x\")", "nil"),
+		] {
+			assert_eq!(interpreter.rep(input)?, output, "{input}");
+		}
+
+		let err = |input| interpreter.rep(input).unwrap_err().to_string();
+		assert_eq!(err("(subs \"abc\" 2 5)"), "`subs`: index out of range");
+		assert_eq!(
+			err("(subs \"abc\")"),
+			"`subs` expects 2 to 3 args: 1 were provided"
+		);
+		assert_eq!(
+			err("(char-at \"a\" -1)"),
+			"`char-at`, argument 2: expected an integer (got -1)"
+		);
+		assert_eq!(err("(char-at \"a\" 5)"), "`char-at`: index out of range");
+		assert_eq!(
+			err("(split 1 \",\")"),
+			"`split`, argument 1: expected a string (got 1)"
+		);
+		assert_eq!(
+			err("(join)"),
+			"`join` expects 1 to 2 args: 0 were provided"
+		);
+		assert_eq!(
+			err("(replace \"a\" 1 \"b\")"),
+			"`replace`, argument 2: expected a string (got 1)"
+		);
+		assert_eq!(
+			err("(upper-case 1)"),
+			"`upper-case`, argument 1: expected a string (got 1)"
+		);
+		assert_eq!(err("(trim)"), "`trim` expects 1 args: 0 were provided");
+		assert_eq!(
+			err("(index-of \"a\" :a)"),
+			"`index-of`, argument 2: expected a string (got :a)"
+		);
+		assert_eq!(
+			err("(str->int \"1\" 99)"),
+			"`str->int` expects a radix from 2 to 36"
+		);
+		assert_eq!(
+			err("(parse-number 1)"),
+			"`parse-number`, argument 1: expected a string (got 1)"
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_format() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		for (input, output) in [
+			(
+				"(format \"%5d|%-5d|%05d|%+d|%x|%X|%o\" 42 42 -42 7 255 255 8)",
+				"\"   42|42   |-0042|+7|ff|FF|10\"",
+			),
+			(
+				"(format \"%.2f|%e|%s|%.2s|%%|%8.3f\" 3.14159 12345.678 [1 \
+				 \"a\"] \"日本語\" -1.5)",
+				"\"3.14|1.234568e+04|[1 a]|日本|%|  -1.500\"",
+			),
+			("(count (format \"%.65535f|%65535s\" 1.5 1))", "131073"),
+		] {
+			assert_eq!(interpreter.rep(input)?, output, "{input}");
+		}
+
+		let err = |input| interpreter.rep(input).unwrap_err().to_string();
+		assert_eq!(
+			err("(format 1)"),
+			"`format`, argument 1: expected a string (got 1)"
+		);
+		assert_eq!(
+			err("(format \"%d\")"),
+			"`format`: not enough args for the template"
+		);
+		assert_eq!(
+			err("(format \"%d\" \"x\")"),
+			"`format`: %d expects an integer (got \"x\")"
+		);
+		assert_eq!(
+			err("(format \"%f\" \"x\")"),
+			"`format`: %f expects a number (got \"x\")"
+		);
+		assert_eq!(err("(format \"%q\" 1)"), "`format`: unknown conversion %q");
+		assert_eq!(
+			err("(format \"%\" 1)"),
+			"`format`: incomplete conversion at the end of the template"
+		);
+		for input in [
+			"(format \"%.65536f\" 1.5)",
+			"(format \"%65536d\" 1)",
+			"(format \"%.99999999999999999999e\" 1.5)",
+			"(format \"%99999999999s\" 1.5)",
+		] {
+			assert_eq!(err(input), "`format`: width or precision over 65535");
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_chars() -> Result<()> {
+		let interpreter = Interpreter::standard()?;
+		for (input, output) in [
+			(r"\a", r"\a"),
+			(r"\newline", r"\newline"),
+			(r"\u00e9", r"\é"),
+			(r#"[\( \) \\ \"]"#, r#"[\( \) \\ \"]"#),
+			(r"(str \a \space \b)", r#""a b""#),
+			(r"(char? \a)", "true"),
+			(r#"(char? "a")"#, "false"),
+			(r#"(= \a "a")"#, "false"),
+			(r"(= \a (char 97))", "true"),
+			(r"(int \é)", "233"),
+			("(int 7/2)", "3"),
+			("(int -2.5)", "-2"),
+			(r#"(count "日本語")"#, "3"),
+			(r#"(nth "日本語" 1)"#, r"\本"),
+			(r"(get {\a 1} \a)", "1"),
+			(r"(sort [\c \a \b])", r"(\a \b \c)"),
+		] {
+			assert_eq!(interpreter.rep(input)?, output, "{input}");
+		}
+
+		let err = |input| interpreter.rep(input).unwrap_err().to_string();
+		assert!(err(r"\ab").starts_with("1:1: invalid char `\\ab`"));
+		assert!(err(r"\u12").starts_with("1:1: invalid char `\\u12`"));
+		assert_eq!(err("(char -1)"), "`char`: -1 isn't a valid code point");
+		assert_eq!(
+			err("(char 1114112)"),
+			"`char`: 1114112 isn't a valid code point"
+		);
+		assert_eq!(
+			err(r#"(char "a")"#),
+			"`char` expects a char or an integer (got \"a\")"
+		);
+		assert_eq!(err("(char)"), "`char` expects 1 args: 0 were provided");
+		assert_eq!(
+			err(r#"(int "1")"#),
+			"`int` expects a char or a number (got \"1\")"
+		);
+		assert_eq!(err("(int)"), "`int` expects 1 args: 0 were provided");
+		Ok(())
+	}
+}
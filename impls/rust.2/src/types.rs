@@ -1,44 +1,274 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::Debug;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::rc::Rc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 use eyre::Result;
 
 use crate::env::Env;
+use crate::lazy::Lazy;
+use crate::map::MalMap;
+use crate::number::Number;
 
 type Function = Rc<dyn Fn(&mut [MalType]) -> Result<MalType>>;
 
+/// The metadata of a collection or a builtin, from `with-meta`. It takes no
+/// part in comparing or hashing the value.
+pub type Meta = Option<Rc<MalType>>;
+
 #[derive(Clone)]
 pub enum MalType {
+	Atom(Rc<RefCell<MalType>>),
 	Bool(bool),
-	HashMap(HashMap<MalHashKey, MalType>),
+	Char(char),
+	HashMap(MalMap, Meta),
 	Keyword(String),
-	L(ListKind, Vec<MalType>),
+	L(ListKind, Vec<MalType>, Meta),
+	LazySeq(Rc<Lazy>),
 	Nil,
-	Number(f64),
+	Number(Number),
+	/// A value wrapped by `reduced`, which ends a `reduce` early.
+	Reduced(Box<MalType>),
+	/// A compiled regex, from a `#"..."` literal or `re-pattern`.
+	Regex(Rc<regex::Regex>),
+	Set(HashSet<MalType>),
 	String(String),
-	Symbol(String),
-	Function(Function),
+	/// A symbol, along with where it was read from, if anywhere.
+	Symbol(String, Option<Rc<Span>>),
+	Function(Function, Meta),
 	TCOFunction {
 		ast:      Box<MalType>,
 		params:   Vec<String>,
 		env:      Env,
 		function: Function,
+		is_macro: bool,
+		meta:     Box<MalType>,
+		/// The name the function was first bound to with `def!`.
+		name:     Option<String>,
+		/// The bytecode the function was compiled to, if it was.
+		#[cfg(feature = "vm")]
+		compiled: Option<Rc<crate::vm::Closure>>,
 	},
 }
 
+/// A region of source code a form was read from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+	/// The file the source was read from, if any.
+	pub file:  Option<Arc<str>>,
+	/// Byte offsets of the start and end of the form in its source.
+	pub start: usize,
+	pub end:   usize,
+	/// 1-based line and column of the start of the form.
+	pub line:  usize,
+	pub col:   usize,
+}
+
+/// The chars that are written by name, like `\newline`, rather than as
+/// themselves.
+pub const CHAR_NAMES: [(&str, char); 6] = [
+	("newline", '\n'),
+	("space", ' '),
+	("tab", '\t'),
+	("return", '\r'),
+	("backspace", '\u{8}'),
+	("formfeed", '\u{c}'),
+];
+
 #[derive(Clone)]
 pub enum ListKind {
 	List,
 	Vector,
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
-pub enum MalHashKey {
-	String(String),
-	Keyword(String),
+thread_local! {
+	/// Values in flight as exceptions. `eyre::Report` requires its errors to
+	/// be `Send + Sync`, which `MalType` isn't, so the thrown value is parked
+	/// here and the error only carries its id.
+	static THROWN: RefCell<HashMap<usize, MalType>> = RefCell::new(HashMap::new());
+}
+
+static NEXT_THROWN_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// An error carrying a mal value, as raised by `throw`. Errors that don't
+/// carry a value are caught by `catch*` as their message string.
+#[derive(Debug)]
+pub struct MalException {
+	id:      usize,
+	printed: String,
+}
+
+/// A call on the mal call stack.
+#[derive(Clone, Debug)]
+pub struct Frame {
+	/// The name the function was defined with, or else the symbol it was
+	/// called through.
+	pub name:   Option<String>,
+	/// Where the function was called.
+	pub span:   Option<Span>,
+	/// How many calls were made from this frame in tail position, each
+	/// replacing it.
+	pub elided: usize,
+}
+
+/// An error raised inside mal functions, along with the call stack at the
+/// point it was raised, innermost call first. This displays as the underlying
+/// error, so it's transparent to anything only looking at messages.
+#[derive(Debug)]
+pub struct TracedError {
+	pub error: eyre::Report,
+	pub trace: Vec<Frame>,
+}
+
+impl MalType {
+	/// A builtin, without metadata.
+	pub fn builtin(
+		function: impl Fn(&mut [MalType]) -> Result<MalType> + 'static,
+	) -> Self {
+		MalType::Function(Rc::new(function), None)
+	}
+
+	/// Applies this value as a function. Used by builtins such as `apply` and
+	/// `swap!` that need to call back into user code.
+	pub fn call(&self, args: &mut [MalType]) -> Result<MalType> {
+		match self {
+			MalType::Function(function, _)
+			| MalType::TCOFunction { function, .. } => function(args),
+			not => Err(eyre!("expected a function (got {not:#})")),
+		}
+	}
+
+	/// The natural order of values, as used by `compare` and `sorted-map`. Nil
+	/// sorts first, numbers compare by value (NaN is equal to everything), and
+	/// lists and vectors compare by length and then item by item. Anything
+	/// else only compares with its own kind.
+	pub fn compare(&self, other: &MalType) -> Result<cmp::Ordering> {
+		Ok(match (self, other) {
+			(MalType::Nil, MalType::Nil) => cmp::Ordering::Equal,
+			(MalType::Nil, _) => cmp::Ordering::Less,
+			(_, MalType::Nil) => cmp::Ordering::Greater,
+			(MalType::Bool(l), MalType::Bool(r)) => l.cmp(r),
+			(MalType::Char(l), MalType::Char(r)) => l.cmp(r),
+			(MalType::Number(l), MalType::Number(r)) => {
+				l.compare(r).unwrap_or(cmp::Ordering::Equal)
+			},
+			(MalType::String(l), MalType::String(r))
+			| (MalType::Keyword(l), MalType::Keyword(r))
+			| (MalType::Symbol(l, _), MalType::Symbol(r, _)) => l.cmp(r),
+			(MalType::L(_, l, _), MalType::L(_, r, _)) => {
+				match l.len().cmp(&r.len()) {
+					cmp::Ordering::Equal => {},
+					ord => return Ok(ord),
+				}
+				for (l, r) in l.iter().zip(r) {
+					match l.compare(r)? {
+						cmp::Ordering::Equal => {},
+						ord => return Ok(ord),
+					}
+				}
+				cmp::Ordering::Equal
+			},
+			(l, r) => return Err(eyre!("can't compare {l:#} with {r:#}")),
+		})
+	}
+}
+
+impl MalException {
+	pub fn new(value: MalType) -> Self {
+		let id = NEXT_THROWN_ID.fetch_add(1, Ordering::Relaxed);
+		let printed = value.pr_str::<true>();
+		THROWN.with(|thrown| thrown.borrow_mut().insert(id, value));
+		Self { id, printed }
+	}
+
+	/// Retrieves the thrown value. If the error somehow crossed threads, the
+	/// printed representation is all that's left of it.
+	pub fn into_value(self) -> MalType {
+		THROWN
+			.with(|thrown| thrown.borrow_mut().remove(&self.id))
+			.unwrap_or_else(|| MalType::String(self.printed.clone()))
+	}
+}
+
+/// Formats as `file:line:col`, omitting the file if there's none.
+impl Display for Span {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if let Some(file) = &self.file {
+			write!(f, "{file}:")?;
+		}
+		write!(f, "{}:{}", self.line, self.col)
+	}
+}
+
+impl Display for Frame {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "at {}", self.name.as_deref().unwrap_or("<anonymous>"))?;
+		if let Some(span) = &self.span {
+			write!(f, " ({span})")?;
+		}
+		match self.elided {
+			0 => Ok(()),
+			1 => write!(f, " [1 tail call elided]"),
+			elided => write!(f, " [{elided} tail calls elided]"),
+		}
+	}
+}
+
+impl TracedError {
+	/// Splits `err` into the underlying error and its trace, which is empty if
+	/// it wasn't raised inside a function.
+	pub fn split(err: eyre::Report) -> (eyre::Report, Vec<Frame>) {
+		match err.downcast::<TracedError>() {
+			Ok(traced) => (traced.error, traced.trace),
+			Err(err) => (err, Vec::new()),
+		}
+	}
+
+	/// The trace of `err`, if it has one.
+	pub fn trace(err: &eyre::Report) -> &[Frame] {
+		err.downcast_ref::<TracedError>()
+			.map_or(&[], |traced| &traced.trace)
+	}
+
+	/// The message of `err`, followed by its trace with a frame per line.
+	pub fn describe(err: &eyre::Report) -> String {
+		Self::trace(err)
+			.iter()
+			.fold(err.to_string(), |res, frame| format!("{res}\n  {frame}"))
+	}
 }
 
+impl Display for TracedError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.error)
+	}
+}
+
+impl std::error::Error for TracedError {}
+
+impl Display for MalException {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.printed)
+	}
+}
+
+impl Drop for MalException {
+	fn drop(&mut self) {
+		let _ = THROWN.try_with(|thrown| thrown.borrow_mut().remove(&self.id));
+	}
+}
+
+impl std::error::Error for MalException {}
+
 impl Debug for MalType {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(f, "{}", self.pr_str::<true>())
@@ -48,17 +278,32 @@ impl Debug for MalType {
 impl PartialEq for MalType {
 	fn eq(&self, other: &Self) -> bool {
 		match (self, other) {
+			(Self::Atom(l), Self::Atom(r)) => Rc::ptr_eq(l, r),
 			(Self::Bool(l), Self::Bool(r)) => l == r,
-			(Self::HashMap(l), Self::HashMap(r)) => l == r,
+			(Self::Char(l), Self::Char(r)) => l == r,
+			(Self::HashMap(l, _), Self::HashMap(r, _)) => l == r,
 			(Self::Keyword(l), Self::Keyword(r)) => l == r,
-			(Self::L(_, l), Self::L(_, r)) => l == r,
+			(Self::L(_, l, _), Self::L(_, r, _)) => l == r,
+			(Self::LazySeq(l), Self::LazySeq(r)) if Rc::ptr_eq(l, r) => true,
+			(Self::L(..) | Self::LazySeq(_), Self::LazySeq(_))
+			| (Self::LazySeq(_), Self::L(..)) => {
+				let (mut l, mut r) = (self.items(), other.items());
+				loop {
+					match (l.next(), r.next()) {
+						(None, None) => return true,
+						(Some(Ok(l)), Some(Ok(r))) if l == r => {},
+						_ => return false,
+					}
+				}
+			},
 			(Self::Nil, Self::Nil) => true,
 			(Self::Number(l), Self::Number(r)) => l == r,
+			(Self::Reduced(l), Self::Reduced(r)) => l == r,
+			(Self::Regex(l), Self::Regex(r)) => l.as_str() == r.as_str(),
+			(Self::Set(l), Self::Set(r)) => l == r,
 			(Self::String(l), Self::String(r)) => l == r,
-			(Self::Symbol(l), Self::Symbol(r)) => l == r,
-			(Self::Function(l), Self::Function(r)) => {
-				Rc::as_ptr(l) == Rc::as_ptr(r)
-			},
+			(Self::Symbol(l, _), Self::Symbol(r, _)) => l == r,
+			(Self::Function(l, _), Self::Function(r, _)) => Rc::ptr_eq(l, r),
 			(
 				Self::TCOFunction { ast: l, .. },
 				Self::TCOFunction { ast: r, .. },
@@ -67,3 +312,49 @@ impl PartialEq for MalType {
 		}
 	}
 }
+
+impl Eq for MalType {}
+
+/// Consistent with `==`, so lists and vectors with the same items hash the
+/// same, and hashmaps and sets hash the same whatever order they iterate in.
+impl Hash for MalType {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		match self {
+			Self::Atom(atom) => Rc::as_ptr(atom).hash(state),
+			Self::Bool(b) => b.hash(state),
+			Self::Char(ch) => ch.hash(state),
+			Self::HashMap(map, _) => hash_unordered(map.iter(), state),
+			Self::Keyword(keyword) => keyword.hash(state),
+			Self::L(_, list, _) => list.hash(state),
+			// as a list, ignoring anything that fails to realize
+			Self::LazySeq(_) => self
+				.items()
+				.filter_map(Result::ok)
+				.collect::<Vec<_>>()
+				.hash(state),
+			Self::Nil => {},
+			Self::Number(num) => num.hash(state),
+			Self::Reduced(value) => value.hash(state),
+			Self::Regex(regex) => regex.as_str().hash(state),
+			Self::Set(set) => hash_unordered(set.iter(), state),
+			Self::String(string) => string.hash(state),
+			Self::Symbol(sym, _) => sym.hash(state),
+			Self::Function(function, _) => {
+				Rc::as_ptr(function).cast::<()>().hash(state)
+			},
+			Self::TCOFunction { ast, .. } => ast.hash(state),
+		}
+	}
+}
+
+fn hash_unordered(
+	items: impl Iterator<Item = impl Hash>,
+	state: &mut impl Hasher,
+) {
+	let sum = items.fold(0u64, |sum, item| {
+		let mut hasher = DefaultHasher::new();
+		item.hash(&mut hasher);
+		sum.wrapping_add(hasher.finish())
+	});
+	sum.hash(state);
+}
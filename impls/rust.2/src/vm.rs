@@ -0,0 +1,641 @@
+//! Runs bytecode from `compiler` on a stack machine. This is what
+//! `Interpreter::eval` does instead of walking forms when the `vm` feature is
+//! enabled.
+//!
+//! Calls between compiled functions don't recurse on the Rust stack: each
+//! call pushes a frame onto the VM's own call stack, and a tail call replaces
+//! the running frame, so tail calls run in constant space. Calls to builtins
+//! and calls made by builtins, like those from `map`, recurse as they do in
+//! the tree-walker.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use eyre::Result;
+
+use crate::bytecode::Late;
+use crate::bytecode::Op;
+use crate::bytecode::Proto;
+use crate::compiler;
+use crate::env::Env;
+use crate::eval::Continuation;
+use crate::eval::Interpreter;
+use crate::lazy::Lazy;
+use crate::special_forms::frame_to_mal;
+use crate::types::Frame;
+use crate::types::ListKind;
+use crate::types::MalException;
+use crate::types::MalType;
+use crate::types::TracedError;
+
+/// How deep compiled functions can call each other before it's taken to be
+/// runaway recursion.
+const MAX_FRAMES: usize = 1 << 16;
+
+/// A compiled function, along with the locals of the function it was made
+/// in and the env its globals are looked up in.
+pub struct Closure {
+	pub proto: Rc<Proto>,
+	outer:     Option<Rc<Locals>>,
+	env:       Env,
+}
+
+/// The locals of a call to a compiled function. A slot is unset until it's
+/// bound, which only matters for the locals that `def!` binds.
+struct Locals {
+	slots: RefCell<Vec<Option<MalType>>>,
+	outer: Option<Rc<Locals>>,
+}
+
+impl Locals {
+	/// The locals of the function `depth` functions out from these.
+	fn outer(self: &Rc<Self>, depth: usize) -> &Rc<Self> {
+		let mut locals = self;
+		for _ in 0..depth {
+			locals = locals.outer.as_ref().unwrap();
+		}
+		locals
+	}
+
+	fn get(&self, slot: usize) -> MalType {
+		self.slots.borrow()[slot].clone().unwrap_or(MalType::Nil)
+	}
+}
+
+struct CallFrame {
+	proto:       Rc<Proto>,
+	locals:      Rc<Locals>,
+	env:         Env,
+	pc:          usize,
+	/// The height of the stack when the call was made.
+	base:        usize,
+	/// The depth of the interpreter's stack trace when the call was made,
+	/// which it's truncated to on return.
+	trace_depth: usize,
+}
+
+/// A handler installed by `Op::Try`, along with what to unwind to.
+struct Handler {
+	frames:      usize,
+	stack:       usize,
+	trace_depth: usize,
+	pc:          usize,
+}
+
+struct Vm<'a> {
+	interpreter: &'a Interpreter,
+	stack:       Vec<MalType>,
+	frames:      Vec<CallFrame>,
+	handlers:    Vec<Handler>,
+}
+
+/// Compiles `ast` and runs it in `env`. A top-level `do` compiles each of its
+/// forms just before running it, so that a macro can be defined and then
+/// used.
+pub fn eval(
+	interpreter: &Interpreter,
+	ast: MalType,
+	env: Env,
+) -> Result<MalType> {
+	if let MalType::L(ListKind::List, list, _) = &ast {
+		if matches!(list.first(), Some(MalType::Symbol(sym, _)) if sym == "do")
+			&& interpreter.special_form("do").is_some()
+		{
+			let mut res = MalType::Nil;
+			for form in &list[1..] {
+				res = interpreter.eval(form.clone(), env.clone())?;
+			}
+			return Ok(res);
+		}
+	}
+	let proto = compiler::compile(interpreter, ast, &env);
+	let closure = Closure {
+		proto: Rc::new(proto),
+		outer: None,
+		env,
+	};
+	run(interpreter, &closure, Vec::new())
+}
+
+/// Binds `disassemble`, which lists the bytecode of a compiled function.
+pub fn register(env: &Env) {
+	env.register_fn("disassemble", |function: MalType| match function {
+		MalType::TCOFunction {
+			compiled: Some(closure),
+			..
+		} => Ok(closure.proto.disassemble()),
+		function => Err(eyre!(
+			"`disassemble` expects a compiled function (got {function:#})"
+		)),
+	});
+}
+
+/// Calls `closure` with `args` on a new VM.
+fn run(
+	interpreter: &Interpreter,
+	closure: &Closure,
+	args: Vec<MalType>,
+) -> Result<MalType> {
+	let mut vm = Vm {
+		interpreter,
+		stack: Vec::new(),
+		frames: Vec::new(),
+		handlers: Vec::new(),
+	};
+	let trace_depth = interpreter.stack_depth();
+	vm.enter(closure, args, trace_depth)?;
+	loop {
+		match vm.execute() {
+			Ok(value) => return Ok(value),
+			Err(err) => vm.catch(err)?,
+		}
+	}
+}
+
+/// A closure of `proto` as a mal function.
+fn function(
+	interpreter: &Interpreter,
+	proto: &Rc<Proto>,
+	outer: &Rc<Locals>,
+	env: &Env,
+) -> MalType {
+	let closure = Rc::new(Closure {
+		proto: proto.clone(),
+		outer: Some(outer.clone()),
+		env:   env.clone(),
+	});
+	let interpreter = interpreter.clone();
+	MalType::TCOFunction {
+		ast:      Box::new(proto.body.clone()),
+		params:   proto.params.clone(),
+		env:      env.clone(),
+		function: {
+			let closure = closure.clone();
+			Rc::new(move |args| {
+				interpreter
+					.traced(|_| run(&interpreter, &closure, args.to_vec()))
+			})
+		},
+		is_macro: false,
+		meta:     Box::new(MalType::Nil),
+		name:     None,
+		compiled: Some(closure),
+	}
+}
+
+impl Vm<'_> {
+	/// Pushes a frame calling `closure` with `args`.
+	fn enter(
+		&mut self,
+		closure: &Closure,
+		mut args: Vec<MalType>,
+		trace_depth: usize,
+	) -> Result<()> {
+		if self.frames.len() >= MAX_FRAMES {
+			return Err(eyre!("stack overflow"));
+		}
+		let proto = &closure.proto;
+		// like the tree-walker, missing args are nil and extra ones ignored
+		if proto.variadic {
+			let rest = args.split_off(proto.arity.min(args.len()));
+			args.resize(proto.arity, MalType::Nil);
+			args.push(MalType::L(ListKind::List, rest, None));
+		} else {
+			args.resize(proto.arity, MalType::Nil);
+		}
+		let mut slots = args.into_iter().map(Some).collect::<Vec<_>>();
+		slots.resize(proto.names.len(), None);
+		self.frames.push(CallFrame {
+			proto: proto.clone(),
+			locals: Rc::new(Locals {
+				slots: RefCell::new(slots),
+				outer: closure.outer.clone(),
+			}),
+			env: closure.env.clone(),
+			pc: 0,
+			base: self.stack.len(),
+			trace_depth,
+		});
+		Ok(())
+	}
+
+	fn frame(&self) -> &CallFrame {
+		self.frames.last().unwrap()
+	}
+
+	fn pop(&mut self) -> MalType {
+		self.stack.pop().unwrap()
+	}
+
+	fn pop_n(&mut self, n: usize) -> Vec<MalType> {
+		self.stack.split_off(self.stack.len() - n)
+	}
+
+	/// Runs until the bottom frame returns.
+	fn execute(&mut self) -> Result<MalType> {
+		loop {
+			let frame = self.frames.last_mut().unwrap();
+			let op = frame.proto.code[frame.pc];
+			frame.pc += 1;
+			match op {
+				Op::Const(i) => {
+					let value = self.frame().proto.constants[i].clone();
+					self.stack.push(value);
+				},
+				Op::Load(slot) => {
+					let value = self.frame().locals.get(slot);
+					self.stack.push(value);
+				},
+				Op::LoadOuter { depth, slot } => {
+					let value = self.frame().locals.outer(depth).get(slot);
+					self.stack.push(value);
+				},
+				Op::LoadDefinedOrLocal {
+					defined,
+					depth,
+					slot,
+				} => {
+					let value = match self.defined(defined) {
+						Some(value) => value,
+						None => self.frame().locals.outer(depth).get(slot),
+					};
+					self.stack.push(value);
+				},
+				Op::Store(slot) => {
+					let value = self.pop();
+					self.frame().locals.slots.borrow_mut()[slot] = Some(value);
+				},
+				Op::LoadGlobal(i) => {
+					let value = self.global(i)?;
+					self.stack.push(value);
+				},
+				Op::LoadDefinedOrGlobal { defined, name } => {
+					let value = match self.defined(defined) {
+						Some(value) => value,
+						None => self.global(name)?,
+					};
+					self.stack.push(value);
+				},
+				Op::DefGlobal(i) => {
+					let key = self.global_name(i);
+					let value = self.named(&key);
+					self.frame().env.set(key, value);
+				},
+				Op::DefLocal { name, depth, slot } => {
+					let value = self.named(&self.global_name(name));
+					let locals = self.frame().locals.outer(depth);
+					locals.slots.borrow_mut()[slot] = Some(value);
+				},
+				Op::DefMacro(i) => {
+					let key = self.global_name(i);
+					let MalType::TCOFunction {
+						ast,
+						params,
+						env,
+						function,
+						meta,
+						name,
+						compiled,
+						..
+					} = self.pop()
+					else {
+						return Err(eyre!("can't `defmacro!` a non-function"));
+					};
+					let value = MalType::TCOFunction {
+						ast,
+						params,
+						env,
+						function,
+						is_macro: true,
+						meta,
+						name: name.or_else(|| Some(key.clone())),
+						compiled,
+					};
+					self.frame().env.set(key, value.clone());
+					self.stack.push(value);
+				},
+				Op::Pop => {
+					self.pop();
+				},
+				Op::Jump(to) => self.frames.last_mut().unwrap().pc = to,
+				Op::JumpIfFalse(to) => {
+					if let MalType::Bool(false) | MalType::Nil = self.pop() {
+						self.frames.last_mut().unwrap().pc = to;
+					}
+				},
+				Op::JumpIfRedefined { macros, to } => {
+					if self.redefined(macros) {
+						self.frames.last_mut().unwrap().pc = to;
+					}
+				},
+				Op::ExpandIfMacro { deferred, to } => {
+					if let Some(MalType::TCOFunction {
+						is_macro: true, ..
+					}) = self.stack.last()
+					{
+						self.pop();
+						self.frames.last_mut().unwrap().pc = to;
+						self.eval(deferred)?;
+					}
+				},
+				Op::Eval(i) => self.eval(i)?,
+				Op::Call { argc, site } => {
+					let args = self.pop_n(argc);
+					let function = self.pop();
+					if let Some(value) =
+						self.call(function, args, site, false)?
+					{
+						self.stack.push(value);
+					}
+				},
+				Op::TailCall { argc, site } => {
+					let args = self.pop_n(argc);
+					let function = self.pop();
+					if let Some(value) =
+						self.call(function, args, site, true)?
+					{
+						if let Some(value) = self.ret(value) {
+							return Ok(value);
+						}
+					}
+				},
+				Op::Return => {
+					let value = self.pop();
+					if let Some(value) = self.ret(value) {
+						return Ok(value);
+					}
+				},
+				Op::Closure(i) => {
+					let frame = self.frame();
+					let value = function(
+						self.interpreter,
+						&frame.proto.protos[i],
+						&frame.locals,
+						&frame.env,
+					);
+					self.stack.push(value);
+				},
+				Op::LazySeq(i) => {
+					let frame = self.frame();
+					let closure = Closure {
+						proto: frame.proto.protos[i].clone(),
+						outer: Some(frame.locals.clone()),
+						env:   frame.env.clone(),
+					};
+					let interpreter = self.interpreter.clone();
+					self.stack.push(Lazy::seq(move || {
+						interpreter
+							.traced(|_| run(&interpreter, &closure, Vec::new()))
+					}));
+				},
+				Op::Vector(len) => {
+					let items = self.pop_n(len);
+					self.stack.push(MalType::L(ListKind::Vector, items, None));
+				},
+				Op::HashMap(i) => {
+					let MalType::HashMap(map, _) =
+						&self.frame().proto.constants[i]
+					else {
+						unreachable!("expected a hashmap constant");
+					};
+					let (keys, mut evaluated) = (map.keys(), map.empty());
+					let keys = keys.cloned().collect::<Vec<_>>();
+					let values = self.pop_n(keys.len());
+					for (key, value) in keys.into_iter().zip(values) {
+						evaluated.insert(key, value)?;
+					}
+					self.stack.push(MalType::HashMap(evaluated, None));
+				},
+				Op::Set(len) => {
+					let items = self.pop_n(len);
+					self.stack.push(MalType::Set(items.into_iter().collect()));
+				},
+				Op::Try(pc) => self.handlers.push(Handler {
+					frames: self.frames.len(),
+					stack: self.stack.len(),
+					trace_depth: self.interpreter.stack_depth(),
+					pc,
+				}),
+				Op::EndTry => {
+					self.handlers.pop();
+				},
+				Op::Special(i) => {
+					let value = self.special(i)?;
+					self.stack.push(value);
+				},
+				Op::Throw(i) => {
+					let value = self.frame().proto.constants[i].clone();
+					return Err(MalException::new(value).into());
+				},
+				Op::Fail(i) => {
+					let MalType::String(message) =
+						&self.frame().proto.constants[i]
+					else {
+						unreachable!("expected a message constant");
+					};
+					return Err(eyre!("{message}"));
+				},
+			}
+		}
+	}
+
+	fn global_name(&self, i: usize) -> String {
+		match &self.frame().proto.constants[i] {
+			MalType::Symbol(sym, _) => sym.clone(),
+			_ => unreachable!("globals are named by symbols"),
+		}
+	}
+
+	/// The value on top of the stack, which `def!` is binding to `key`,
+	/// naming it after `key` if it's an unnamed function.
+	fn named(&mut self, key: &str) -> MalType {
+		let value = self.stack.last_mut().unwrap();
+		if let MalType::TCOFunction {
+			name: name @ None, ..
+		} = value
+		{
+			*name = Some(key.to_string());
+		}
+		value.clone()
+	}
+
+	/// Whether any of a list of macros that a form was expanded with has been
+	/// redefined.
+	fn redefined(&self, i: usize) -> bool {
+		let frame = self.frame();
+		frame.proto.macros[i].iter().any(|guard| {
+			let MalType::TCOFunction { function: mac, .. } = &guard.mac else {
+				unreachable!("macros are functions");
+			};
+			!frame.env.inspect(&guard.name, |value| {
+				matches!(
+					value,
+					Some(MalType::TCOFunction { is_macro: true, function, .. })
+						if Rc::ptr_eq(function, mac)
+				)
+			})
+		})
+	}
+
+	/// The first of a list of locals that `def!` binds that it's bound.
+	fn defined(&self, i: usize) -> Option<MalType> {
+		let frame = self.frame();
+		frame.proto.defined[i].iter().find_map(|(depth, late)| {
+			let locals = frame.locals.outer(*depth);
+			let value = locals.slots.borrow()[late.get()?].clone();
+			value
+		})
+	}
+
+	/// The global named by a constant symbol.
+	fn global(&self, i: usize) -> Result<MalType> {
+		let frame = self.frame();
+		let MalType::Symbol(sym, span) = &frame.proto.constants[i] else {
+			unreachable!("globals are named by symbols");
+		};
+		frame.env.get(sym).ok_or_else(|| {
+			match span.as_deref().filter(|span| span.file.is_some()) {
+				Some(span) => eyre!("{span}: '{sym}' not found"),
+				None => eyre!("'{sym}' not found"),
+			}
+		})
+	}
+
+	/// Compiles a deferred form, in an env holding the locals in scope, and
+	/// enters it, in place of the running frame if it's in tail position.
+	fn eval(&mut self, i: usize) -> Result<()> {
+		let frame = self.frame();
+		let deferred = &frame.proto.deferred[i];
+		let env = self.locals_env(&deferred.locals);
+		let (ast, tail) = (deferred.ast.clone(), deferred.tail);
+		let proto = compiler::compile(self.interpreter, ast, &env);
+		let closure = Closure {
+			proto: Rc::new(proto),
+			outer: None,
+			env,
+		};
+		let trace_depth = match tail {
+			true => {
+				let frame = self.frames.pop().unwrap();
+				self.stack.truncate(frame.base);
+				frame.trace_depth
+			},
+			false => self.interpreter.stack_depth(),
+		};
+		self.enter(&closure, Vec::new(), trace_depth)
+	}
+
+	/// An env holding those of `locals` that are bound, over the running
+	/// function's env.
+	fn locals_env(&self, locals: &[(String, usize, Late)]) -> Env {
+		let frame = self.frame();
+		let locals = locals.iter().filter_map(|(name, depth, slot)| {
+			let value =
+				frame.locals.outer(*depth).slots.borrow()[slot.get()?].clone();
+			Some((name.clone(), value?))
+		});
+		Env::new_with_bindings_list(Some(frame.env.clone()), locals)
+	}
+
+	/// Calls `function`, and returns its value unless it's a compiled
+	/// function, which is entered instead. A tail call replaces the running
+	/// frame.
+	fn call(
+		&mut self,
+		function: MalType,
+		mut args: Vec<MalType>,
+		site: usize,
+		tail: bool,
+	) -> Result<Option<MalType>> {
+		let site = &self.frame().proto.sites[site];
+		let (site_name, site_span) = (site.name.clone(), site.span.clone());
+		match function {
+			MalType::TCOFunction {
+				compiled: Some(closure),
+				name,
+				..
+			} => {
+				let trace_depth = match tail {
+					true => self.frame().trace_depth,
+					false => self.interpreter.stack_depth(),
+				};
+				self.interpreter.push_frame(trace_depth, Frame {
+					name:   name.or(site_name),
+					span:   site_span,
+					elided: 0,
+				});
+				if tail {
+					let frame = self.frames.pop().unwrap();
+					self.stack.truncate(frame.base);
+				}
+				self.enter(&closure, args, trace_depth)?;
+				Ok(None)
+			},
+			MalType::Function(function, _)
+			| MalType::TCOFunction { function, .. } => {
+				let trace_depth = self.interpreter.stack_depth();
+				self.interpreter.push_frame(trace_depth, Frame {
+					name:   site_name,
+					span:   site_span,
+					elided: 0,
+				});
+				let value = function(&mut args)?;
+				self.interpreter.truncate_stack(trace_depth);
+				Ok(Some(value))
+			},
+			not => Err(eyre!("expected a function (got {not:#})")),
+		}
+	}
+
+	/// Returns `value` from the running frame, or from the VM if that's the
+	/// bottom frame.
+	fn ret(&mut self, value: MalType) -> Option<MalType> {
+		let frame = self.frames.pop().unwrap();
+		self.stack.truncate(frame.base);
+		self.interpreter.truncate_stack(frame.trace_depth);
+		if self.frames.is_empty() {
+			return Some(value);
+		}
+		self.stack.push(value);
+		None
+	}
+
+	/// Runs a special form that the compiler doesn't know, in an env holding
+	/// the locals in scope.
+	fn special(&mut self, i: usize) -> Result<MalType> {
+		let special = &self.frame().proto.specials[i];
+		let env = self.locals_env(&special.locals);
+		let form =
+			self.interpreter
+				.special_form(&special.name)
+				.ok_or_else(|| {
+					eyre!("`{}` is no longer a special form", special.name)
+				})?;
+		match form(self.interpreter, special.args.clone(), env)? {
+			Continuation::Return(value) => Ok(value),
+			Continuation::Eval(ast, env) => self.interpreter.eval(ast, env),
+		}
+	}
+
+	/// Unwinds to the innermost handler and jumps to it with the error, or
+	/// gives back the error if there's none.
+	fn catch(&mut self, err: eyre::Report) -> Result<()> {
+		let Some(handler) = self.handlers.pop() else {
+			return Err(err);
+		};
+		let (err, trace) =
+			TracedError::split(self.interpreter.trace_error(err));
+		let exc = match err.downcast::<MalException>() {
+			Ok(exc) => exc.into_value(),
+			Err(err) => MalType::String(err.to_string()),
+		};
+		let trace = trace.into_iter().map(frame_to_mal).collect();
+		self.frames.truncate(handler.frames);
+		self.stack.truncate(handler.stack);
+		self.interpreter.truncate_stack(handler.trace_depth);
+		self.frames.last_mut().unwrap().pc = handler.pc;
+		self.stack.push(exc);
+		self.stack.push(MalType::L(ListKind::Vector, trace, None));
+		Ok(())
+	}
+}
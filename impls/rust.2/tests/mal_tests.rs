@@ -1,16 +1,16 @@
-#![feature(let_else)]
-/// This test is literally just so I can run the MAL test suite within cargo.
+//! This test is literally just so I can run the MAL test suite within cargo.
 
-macro_rules! step {
-	($step:literal) => {
-		paste::paste! {
-			use eyre::ContextCompat;
+use std::env;
+use std::fs;
+use std::process::Command;
 
-			use std::env;
-			use std::fs;
-			use std::process::Command;
+use eyre::ContextCompat;
 
+macro_rules! step {
+	($step:tt) => {
+		paste::paste! {
 			#[test]
+			#[allow(non_snake_case)]
 			fn [<step $step>]() -> eyre::Result<()> {
 				let pwd = env::current_dir()?;
 				for entry in fs::read_dir(&pwd)? {
@@ -21,7 +21,7 @@ macro_rules! step {
 					let Ok(file_name) = entry.file_name().into_string() else {
 						continue;
 					};
-					if file_name.starts_with(stringify!($name)) {
+					if file_name.starts_with(concat!("step", stringify!($step))) {
 						fs::remove_file(file_name)?;
 					}
 				}
@@ -32,10 +32,13 @@ macro_rules! step {
 					.parent()
 					.context("no mal/ folder")?;
 
-				let output = Command::new("make")
-					.arg(concat!("test^rust.2^step", stringify!($step)))
-					.current_dir(mal_dir)
-					.output()?;
+				let mut make = Command::new("make");
+				make.arg(concat!("test^rust.2^step", stringify!($step)))
+					.current_dir(mal_dir);
+				if cfg!(feature = "vm") {
+					make.env("FEATURES", "vm");
+				}
+				let output = make.output()?;
 
 				let stderr = std::str::from_utf8(output.stderr.as_slice())?;
 				let stdout = std::str::from_utf8(output.stdout.as_slice())?;
@@ -53,4 +56,14 @@ macro_rules! step {
 	};
 }
 
+step!(0);
+step!(1);
+step!(2);
+step!(3);
+step!(4);
 step!(5);
+step!(6);
+step!(7);
+step!(8);
+step!(9);
+step!(A);
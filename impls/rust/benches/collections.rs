@@ -0,0 +1,66 @@
+// Times collection-heavy mal programs, in the style of tests/perf3.mal, by
+// running them through the stepA interpreter. Run with `cargo bench`.
+//
+// Set MAL_BENCH_BASELINE to the path of another stepA_mal, such as one built
+// from before lists, vectors and maps were persistent, to time it alongside.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const PRELUDE: &str = "
+(def! build-list (fn* (n acc) (if (= n 0) acc (build-list (- n 1) (cons n acc)))))
+(def! walk (fn* (xs n) (if (empty? xs) n (walk (rest xs) (+ n (first xs))))))
+";
+
+const WORKLOADS: &[(&str, &str)] = &[
+    ("cons", "(count (build-list 20000 ()))"),
+    ("rest", "(walk (build-list 20000 ()) 0)"),
+    (
+        "conj",
+        "(def! f (fn* (n acc) (if (= n 0) acc (f (- n 1) (conj acc n)))))
+         (count (f 20000 []))",
+    ),
+    (
+        "assoc",
+        "(def! f (fn* (n acc) (if (= n 0) acc (f (- n 1) (assoc acc (str n) n)))))
+         (count (keys (f 20000 {})))",
+    ),
+    (
+        "rotate",
+        "(def! f (fn* (n xs) (if (= n 0) xs (f (- n 1) (concat (rest xs) (list (first xs)))))))
+         (first (f 2000 (build-list 2000 ())))",
+    ),
+];
+
+// The best of three runs of `path` through the interpreter at `bin`.
+fn time(bin: &str, path: &str) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            let status = Command::new(bin).arg(path).status().unwrap();
+            assert!(status.success());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let dir = env::temp_dir();
+    let baseline = env::var("MAL_BENCH_BASELINE").ok();
+    if baseline.is_some() {
+        println!("{:>8}  {:>10}  {:>10}", "", "baseline", "this build");
+    }
+    for (name, workload) in WORKLOADS {
+        let path = dir.join(format!("mal-bench-{}.mal", name));
+        fs::write(&path, format!("{}{}", PRELUDE, workload)).unwrap();
+        let path = path.to_str().unwrap();
+        let this = time(env!("CARGO_BIN_EXE_stepA_mal"), path);
+        match &baseline {
+            Some(bin) => println!("{:>8}: {:>10.1?}  {:>10.1?}", name, time(bin, path), this),
+            None => println!("{:>8}: {:>8.1?}", name, this),
+        }
+    }
+}
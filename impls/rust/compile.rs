@@ -0,0 +1,1042 @@
+// Compiles forms to closures for stepA, so that running a form doesn't walk
+// it again. Macros are expanded and special forms decoded when a form is
+// compiled, and each symbol is resolved once: a local becomes how many frames
+// out it's bound and its slot in that frame, and a global becomes the cell
+// it's stored in, which everything that refers to it shares.
+//
+// A call in tail position hands the function and its args back to whoever
+// is making the enclosing call, rather than making the call itself, and
+// `call` loops until it gets a value, so tail calls don't grow the stack.
+//
+// Calls to mal functions and builtins are kept on a call stack, which is
+// attached to an error raised inside one as it unwinds, for `catch*` to bind
+// as `*stack-trace*` and the REPL to print. A tail call replaces the frame of
+// the call it's made from, and counts how many it has replaced.
+//
+// A form is compiled along with the macros it was expanded with, and if any
+// of them has been redefined by the time it's run, it's expanded and compiled
+// again then. Likewise a call to a global that wasn't a macro when it was
+// compiled, but is one by the time it's made, is expanded then, so a function
+// can use a macro that's defined after it.
+//
+// `def!` binds in the innermost env, as an interpreter would: the global env
+// at the top level, and otherwise a local of the enclosing `fn*`, `let*` or
+// `catch*`. Such a local is unset until the `def!` is run, and until then
+// looking it up carries on outwards. Code compiled before the `def!` refers
+// to it through a late slot, which the `def!` fills in when it's compiled, so
+// an env is compiled, and its macros expanded, only once.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use fnv::FnvHashMap;
+use itertools::Itertools;
+
+use crate::lazy::Lazy;
+use crate::symbol;
+use crate::symbol::Symbol;
+use crate::types::Arity::{AtLeast, Between, Exactly};
+use crate::types::MalVal::{Bool, Compiled, Func, Hash, Int, List, Nil, Set, Str, Sym, Vector};
+use crate::types::{
+    check_special_form, error, hash_map, keyword, Arity, Callable, MalArgs, MalErr, MalList,
+    MalRet, MalSet, MalVal, TraceFrame,
+};
+
+// The number of args each special form takes.
+const SPECIAL_FORMS: &[(Symbol, Arity)] = &[
+    (symbol::DEF, Exactly(2)),
+    (symbol::LET, Exactly(2)),
+    (symbol::QUOTE, Exactly(1)),
+    (symbol::QUASIQUOTEEXPAND, Exactly(1)),
+    (symbol::QUASIQUOTE, Exactly(1)),
+    (symbol::DEFMACRO, Exactly(2)),
+    (symbol::MACROEXPAND, Exactly(1)),
+    (symbol::TRY, Between(1, 2)),
+    (symbol::DO, AtLeast(0)),
+    (symbol::IF, Between(2, 3)),
+    (symbol::FN, Exactly(2)),
+    (symbol::EVAL, Exactly(1)),
+    (symbol::LAZY_SEQ, AtLeast(0)),
+];
+
+// What running compiled code gives: a value, or a call in tail position
+// that's left to the caller to make, along with the symbol it's made through.
+enum Ret {
+    Value(MalVal),
+    TailCall(MalVal, MalArgs, Option<Symbol>),
+}
+
+type Code = Rc<dyn Fn(&Rc<Frame>) -> Result<Ret, MalErr>>;
+
+// The locals of one call to a function, or of a top-level form: its params,
+// followed by everything its body binds with `let*`, `catch*` or `def!`. A
+// slot is empty until its local is bound.
+struct Frame {
+    slots: RefCell<Vec<Option<MalVal>>>,
+    outer: Option<Rc<Frame>>,
+}
+
+impl Frame {
+    fn new(slots: Vec<Option<MalVal>>, outer: Option<Rc<Frame>>) -> Rc<Frame> {
+        Rc::new(Frame {
+            slots: RefCell::new(slots),
+            outer,
+        })
+    }
+
+    fn outer(&self, depth: usize) -> &Frame {
+        let mut frame = self;
+        for _ in 0..depth {
+            frame = frame.outer.as_ref().unwrap();
+        }
+        frame
+    }
+
+    fn lookup(&self, depth: usize, slot: usize) -> Option<MalVal> {
+        let value = self.outer(depth).slots.borrow()[slot].clone();
+        value
+    }
+
+    // A `let*` binding that's looked up before it's bound is nil.
+    fn get(&self, depth: usize, slot: usize) -> MalVal {
+        self.lookup(depth, slot).unwrap_or(Nil)
+    }
+
+    fn set(&self, depth: usize, slot: usize, value: MalVal) {
+        self.outer(depth).slots.borrow_mut()[slot] = Some(value);
+    }
+}
+
+// A global's cell is empty until it's defined.
+type Cell = Rc<RefCell<Option<MalVal>>>;
+
+// The global env. Code that refers to a global holds on to its cell, so
+// looking it up doesn't involve the map.
+#[derive(Default)]
+pub struct Globals {
+    cells: RefCell<FnvHashMap<Symbol, Cell>>,
+}
+
+impl Globals {
+    fn cell(&self, sym: Symbol) -> Cell {
+        self.cells.borrow_mut().entry(sym).or_default().clone()
+    }
+
+    fn get(&self, sym: Symbol) -> Option<MalVal> {
+        let cells = self.cells.borrow();
+        let value = cells.get(&sym).and_then(|cell| cell.borrow().clone());
+        value
+    }
+
+    pub fn set(&self, name: &str, value: MalVal) {
+        *self.cell(Symbol::new(name)).borrow_mut() = Some(value);
+    }
+}
+
+// A compiled `fn*`, which every closure made from it shares.
+struct Lambda {
+    arity: Arity,
+    nslots: usize,
+    body: Code,
+    params: MalVal,
+    ast: MalVal,
+}
+
+// A `fn*` along with the frame it was made in, and the name it was first
+// bound to by `def!` or `defmacro!`.
+struct Closure {
+    lambda: Rc<Lambda>,
+    frame: Rc<Frame>,
+    name: Option<Symbol>,
+}
+
+impl Closure {
+    // Binds `args` in a new frame and runs the body in it. `name` is what
+    // the call is traced under, for reporting the wrong number of args.
+    fn enter(&self, name: Option<&'static str>, mut args: MalArgs) -> Result<Ret, MalErr> {
+        let lambda = &self.lambda;
+        lambda.arity.check(name.unwrap_or("fn"), args.len())?;
+        if let AtLeast(n) = lambda.arity {
+            let rest = args.split_off(n);
+            args.push(list!(rest));
+        }
+        let mut slots: Vec<_> = args.into_iter().map(Some).collect();
+        slots.resize(lambda.nslots, None);
+        (lambda.body)(&Frame::new(slots, Some(self.frame.clone())))
+    }
+}
+
+impl Callable for Closure {
+    fn apply(&self, args: MalArgs) -> MalRet {
+        traced(|depth| {
+            let name = self.name.map(Symbol::as_str);
+            push_frame(depth, name);
+            match self.enter(name, args)? {
+                Ret::Value(value) => Ok(value),
+                Ret::TailCall(f, args, site) => run(depth, f, args, site),
+            }
+        })
+    }
+
+    fn source(&self) -> MalVal {
+        list![
+            Sym(symbol::FN),
+            self.lambda.params.clone(),
+            self.lambda.ast.clone()
+        ]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source().pr_str(true))
+    }
+}
+
+thread_local! {
+    // The calls being made, innermost call last.
+    static STACK: RefCell<Vec<TraceFrame>> = const { RefCell::new(vec![]) };
+}
+
+// Runs `f` with the depth of the call stack, attaching the stack to any
+// error it raises, and then pops any calls it left on the stack.
+fn traced<T>(f: impl FnOnce(usize) -> Result<T, MalErr>) -> Result<T, MalErr> {
+    STACK.with(|stack| {
+        let depth = stack.borrow().len();
+        let res = f(depth).map_err(|e| {
+            let stack = stack.borrow();
+            match e {
+                MalErr::Traced(..) => e,
+                _ if stack.is_empty() => e,
+                _ => MalErr::Traced(Box::new(e), Rc::new(stack.iter().rev().cloned().collect())),
+            }
+        });
+        stack.borrow_mut().truncate(depth);
+        res
+    })
+}
+
+// Pushes a frame for a call made at `depth`, unless a call has already been
+// made there, in which case this is a tail call from it and replaces it.
+fn push_frame(depth: usize, name: Option<&'static str>) {
+    STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        if stack.len() > depth {
+            let top = stack.last_mut().unwrap();
+            top.name = name;
+            top.elided += 1;
+        } else {
+            stack.push(TraceFrame { name, elided: 0 });
+        }
+    })
+}
+
+// The name to trace a call to `f` through `site` under.
+fn frame_name(f: &MalVal, site: Option<Symbol>) -> Option<&'static str> {
+    let name = match f {
+        Func(builtin, _) => return Some(builtin.name),
+        Compiled { f, .. } => match f.as_any().downcast_ref::<Closure>() {
+            Some(closure) => closure.name.or(site),
+            None => site,
+        },
+        _ => site,
+    };
+    name.map(Symbol::as_str)
+}
+
+// Calls `f` through `site`, and then whatever it tail calls, until there's a
+// value.
+fn call(f: MalVal, args: MalArgs, site: Option<Symbol>) -> MalRet {
+    traced(|depth| run(depth, f, args, site))
+}
+
+// Makes the calls for `call`, tracing them at `depth`.
+fn run(depth: usize, mut f: MalVal, mut args: MalArgs, mut site: Option<Symbol>) -> MalRet {
+    loop {
+        let name = frame_name(&f, site);
+        let ret = match f {
+            Compiled {
+                f: ref callable, ..
+            } => match callable.as_any().downcast_ref::<Closure>() {
+                Some(closure) => {
+                    push_frame(depth, name);
+                    closure.enter(name, args)?
+                }
+                None => return callable.apply(args),
+            },
+            // a builtin is traced as a call from the function that tail
+            // called it, rather than replacing it
+            Func(..) => {
+                STACK.with(|stack| stack.borrow_mut().push(TraceFrame { name, elided: 0 }));
+                return f.apply(args);
+            }
+            _ => return f.apply(args),
+        };
+        match ret {
+            Ret::Value(value) => return Ok(value),
+            Ret::TailCall(g, g_args, g_site) => {
+                f = g;
+                args = g_args;
+                site = g_site;
+            }
+        }
+    }
+}
+
+fn finish(ret: Ret) -> MalRet {
+    match ret {
+        Ret::Value(value) => Ok(value),
+        Ret::TailCall(f, args, site) => call(f, args, site),
+    }
+}
+
+// Names `value` after the symbol it's being bound to, if it's a closure that
+// hasn't been named yet.
+fn named(value: MalVal, name: Symbol) -> MalVal {
+    if let Compiled {
+        ref f,
+        is_macro,
+        ref meta,
+    } = value
+    {
+        if let Some(closure) = f.as_any().downcast_ref::<Closure>() {
+            if closure.name.is_none() {
+                return Compiled {
+                    f: Rc::new(Closure {
+                        lambda: closure.lambda.clone(),
+                        frame: closure.frame.clone(),
+                        name: Some(name),
+                    }),
+                    is_macro,
+                    meta: meta.clone(),
+                };
+            }
+        }
+    }
+    value
+}
+
+// A trace as a vector of hash-maps with `:name` and `:elided` keys.
+fn trace_to_mal(trace: Vec<TraceFrame>) -> MalRet {
+    let frames = trace
+        .into_iter()
+        .map(|frame| {
+            let name = frame.name.map_or(Nil, |name| Str(name.to_string()));
+            hash_map(vec![
+                keyword("name"),
+                name,
+                keyword("elided"),
+                Int(frame.elided as i64),
+            ])
+        })
+        .collect::<Result<MalArgs, MalErr>>()?;
+    Ok(vector!(frames))
+}
+
+// Runs `code` to a value, making any tail call it leaves.
+fn value(code: &Code, frame: &Rc<Frame>) -> MalRet {
+    finish(code(frame)?)
+}
+
+fn values(codes: &[Code], frame: &Rc<Frame>) -> Result<MalArgs, MalErr> {
+    codes.iter().map(|code| value(code, frame)).collect()
+}
+
+fn constant(value: MalVal) -> Code {
+    Rc::new(move |_| Ok(Ret::Value(value.clone())))
+}
+
+// Whether `form` evaluates to itself.
+fn is_constant(form: &MalVal) -> bool {
+    match form {
+        Sym(_) => false,
+        List(l, _) => l.is_empty(),
+        Vector(v, _) => v.iter().all(is_constant),
+        Hash(hm, _) => hm.iter().all(|(_, v)| is_constant(v)),
+        Set(s, _) => s.iter().all(is_constant),
+        _ => true,
+    }
+}
+
+fn qq_iter(elts: &MalArgs) -> MalVal {
+    let mut acc = list![];
+    for elt in elts.iter().rev() {
+        if let List(v, _) = elt {
+            if v.len() == 2 {
+                if let Some(Sym(symbol::SPLICE_UNQUOTE)) = v.first() {
+                    acc = list![Sym(symbol::CONCAT), v.last().unwrap().clone(), acc];
+                    continue;
+                }
+            }
+        }
+        acc = list![Sym(symbol::CONS), quasiquote(elt), acc];
+    }
+    acc
+}
+
+fn quasiquote(ast: &MalVal) -> MalVal {
+    match ast {
+        List(v, _) => {
+            if v.len() == 2 {
+                if let Some(Sym(symbol::UNQUOTE)) = v.first() {
+                    return v.last().unwrap().clone();
+                }
+            }
+            qq_iter(&v.iter().cloned().collect())
+        }
+        Vector(v, _) => {
+            let v: MalArgs = v.iter().cloned().collect();
+            list![Sym(symbol::VEC), qq_iter(&v)]
+        }
+        Hash(_, _) | Set(_, _) | Sym(_) => list![Sym(symbol::QUOTE), ast.clone()],
+        _ => ast.clone(),
+    }
+}
+
+#[derive(Clone)]
+struct Local {
+    name: Symbol,
+    slot: usize,
+    // A `let*` binding is pending until its value has been compiled, and
+    // until then it's only visible from a `fn*` within the `let*`, which
+    // won't have been called before it's bound. A `let*` gives each name one
+    // slot, however many times it binds it.
+    pending: bool,
+}
+
+// The slot that `def!` binds a name to in an env, which is set once the
+// `def!` has been compiled. Code compiled before then holds on to it, and
+// looks in the slot when it's run.
+type Late = Rc<std::cell::Cell<Option<usize>>>;
+
+// An env that `def!` binds in: the body of a `fn*`, `let*` or `catch*`, or a
+// form that's expanded when it's run.
+#[derive(Clone)]
+struct Env {
+    // the first of the scope's locals that are bound in it
+    start: usize,
+    defs: Rc<RefCell<FnvHashMap<Symbol, Late>>>,
+}
+
+impl Env {
+    fn new(start: usize) -> Env {
+        Env {
+            start,
+            defs: Rc::default(),
+        }
+    }
+
+    fn late(&self, name: Symbol) -> Late {
+        self.defs.borrow_mut().entry(name).or_default().clone()
+    }
+
+    // The slot that `def!` has bound `name` to, if it has.
+    fn defined(&self, name: Symbol) -> Option<usize> {
+        self.defs.borrow().get(&name).and_then(|late| late.get())
+    }
+}
+
+// The locals of a `fn*` or a top-level form that's being compiled, or of a
+// form that's being expanded when it's run.
+#[derive(Clone, Default)]
+struct Scope {
+    // innermost last
+    locals: Vec<Local>,
+    nslots: usize,
+    // innermost last, and none at the top level, where `def!` binds globals
+    envs: Vec<Env>,
+    // Its code is run as soon as the enclosing scope's would have been,
+    // rather than being a `fn*` that's called later.
+    inline: bool,
+}
+
+// A macro that a form was expanded with, and the global it was found in.
+struct Guard {
+    cell: Cell,
+    mac: Rc<dyn Callable>,
+}
+
+impl Guard {
+    // Whether the global still holds the macro.
+    fn holds(&self) -> bool {
+        match *self.cell.borrow() {
+            Some(Compiled {
+                ref f,
+                is_macro: true,
+                ..
+            }) => Rc::ptr_eq(f, &self.mac),
+            _ => false,
+        }
+    }
+}
+
+// A form to be expanded and compiled when it's run, along with the scopes it
+// was compiled in.
+struct Expansion {
+    form: MalVal,
+    tail: bool,
+    globals: Rc<Globals>,
+    scopes: Rc<Vec<Scope>>,
+    // the last compiled expansion, the macros it was expanded with, and how
+    // many slots it needs
+    compiled: RefCell<Option<(Vec<Guard>, Code, usize)>>,
+}
+
+impl Expansion {
+    // Runs the form, expanding and compiling it again if the macros it was
+    // last expanded with have been redefined. It's run in a frame of its own
+    // within `frame`, for whatever it binds.
+    fn run(&self, frame: &Rc<Frame>) -> Result<Ret, MalErr> {
+        let compiled = match *self.compiled.borrow() {
+            Some((ref guards, ref code, nslots)) if guards.iter().all(Guard::holds) => {
+                Some((code.clone(), nslots))
+            }
+            _ => None,
+        };
+        let (code, nslots) = match compiled {
+            Some(compiled) => compiled,
+            None => {
+                let (guards, code, nslots) = self.compile()?;
+                *self.compiled.borrow_mut() = Some((guards, code.clone(), nslots));
+                (code, nslots)
+            }
+        };
+        code(&Frame::new(vec![None; nslots], Some(frame.clone())))
+    }
+
+    fn compile(&self) -> Result<(Vec<Guard>, Code, usize), MalErr> {
+        let mut compiler = Compiler {
+            globals: &self.globals,
+            scopes: self.scopes.clone(),
+        };
+        compiler.scopes_mut().push(Scope {
+            envs: vec![Env::new(0)],
+            inline: true,
+            ..Scope::default()
+        });
+        let mut guards = vec![];
+        let form = compiler.macroexpand(self.form.clone(), &mut guards)?;
+        let code = compiler.compile_expanded(&form, self.tail)?;
+        Ok((guards, code, compiler.scope().nslots))
+    }
+}
+
+// How many frames out a local is bound, and its slot in that frame.
+type Slot = (usize, usize);
+
+struct Compiler<'a> {
+    globals: &'a Rc<Globals>,
+    // Innermost last. These are shared with the expansions compiled so far,
+    // and only copied when they change.
+    scopes: Rc<Vec<Scope>>,
+}
+
+impl<'a> Compiler<'a> {
+    // Where `sym` may be bound, innermost first: the slots that `def!` may
+    // bind it to in the envs it's looked up in, and then how many frames out
+    // and at which slot it's bound, if it's not a global.
+    fn resolve(&self, sym: Symbol) -> (Vec<(usize, Late)>, Option<Slot>) {
+        let mut defined = vec![];
+        // whether the code being compiled runs before any pending local has
+        // been bound
+        let mut inline = true;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            let hidden = inline;
+            let visible = |local: &&Local| local.name == sym && !(local.pending && hidden);
+            let mut end = scope.locals.len();
+            for env in scope.envs.iter().rev() {
+                if let Some(local) = scope.locals[env.start..end].iter().rev().find(visible) {
+                    return (defined, Some((depth, local.slot)));
+                }
+                defined.push((depth, env.late(sym)));
+                end = env.start;
+            }
+            if let Some(local) = scope.locals[..end].iter().rev().find(visible) {
+                return (defined, Some((depth, local.slot)));
+            }
+            inline = inline && scope.inline;
+        }
+        (defined, None)
+    }
+
+    fn is_global(&self, sym: Symbol) -> bool {
+        let (defined, local) = self.resolve(sym);
+        local.is_none() && defined.iter().all(|(_, late)| late.get().is_none())
+    }
+
+    fn bind(&mut self, name: Symbol, pending: bool) -> usize {
+        let scope = self.scope();
+        let slot = scope.nslots;
+        scope.nslots += 1;
+        scope.locals.push(Local {
+            name,
+            slot,
+            pending,
+        });
+        slot
+    }
+
+    fn scopes_mut(&mut self) -> &mut Vec<Scope> {
+        Rc::make_mut(&mut self.scopes)
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes_mut().last_mut().unwrap()
+    }
+
+    // Compiles the body of an env whose locals start at `start`, and include
+    // those bound so far.
+    fn env<T>(&mut self, start: usize, compile: impl FnOnce(&mut Self) -> T) -> T {
+        self.scope().envs.push(Env::new(start));
+        let res = compile(self);
+        let scope = self.scope();
+        scope.envs.pop();
+        scope.locals.truncate(start);
+        res
+    }
+
+    // The local that `def!` binds `name` to: how many frames out and at
+    // which slot it's bound in the innermost env, where it's bound if it
+    // isn't yet. None if that's the global env.
+    fn def_local(&mut self, name: Symbol) -> Option<Slot> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            let env = scope.envs.last()?;
+            let local = scope.locals[env.start..]
+                .iter()
+                .rev()
+                .find(|local| local.name == name);
+            if let Some(local) = local {
+                return Some((depth, local.slot));
+            }
+            if let Some(slot) = env.defined(name) {
+                return Some((depth, slot));
+            }
+            // a form expanded when it's run binds in the enclosing env if
+            // that already has `name`
+            if !scope.inline || scope.envs.len() > 1 {
+                break;
+            }
+        }
+        let scope = self.scope();
+        let slot = scope.nslots;
+        scope.nslots += 1;
+        scope.envs.last().unwrap().late(name).set(Some(slot));
+        Some((0, slot))
+    }
+
+    // The macro that `form` calls, and the args to call it with, if it's a
+    // macro call.
+    fn macro_call(&self, form: &MalVal) -> Option<(Guard, MalArgs)> {
+        let l = match form {
+            List(l, _) => l,
+            _ => return None,
+        };
+        let name = match l.first() {
+            Some(Sym(name)) if self.is_global(*name) => *name,
+            _ => return None,
+        };
+        let mac = match self.globals.get(name) {
+            Some(Compiled {
+                f, is_macro: true, ..
+            }) => f,
+            _ => return None,
+        };
+        let cell = self.globals.cell(name);
+        Some((Guard { cell, mac }, l.iter().skip(1).cloned().collect()))
+    }
+
+    // Expands `form`, adding the macros it's expanded with to `guards`.
+    fn macroexpand(&self, mut form: MalVal, guards: &mut Vec<Guard>) -> MalRet {
+        while let Some((guard, args)) = self.macro_call(&form) {
+            form = guard.mac.apply(args)?;
+            guards.push(guard);
+        }
+        Ok(form)
+    }
+
+    // What's needed to expand and compile `form` again when it's run.
+    fn expansion(&self, form: MalVal, tail: bool) -> Rc<Expansion> {
+        Rc::new(Expansion {
+            form,
+            tail,
+            globals: self.globals.clone(),
+            scopes: self.scopes.clone(),
+            compiled: RefCell::new(None),
+        })
+    }
+
+    // Compiles `form`. An error is left to be raised when the code is run,
+    // as it would be by an interpreter, so that one in a branch that isn't
+    // taken or within a `try*` behaves the same.
+    fn compile(&mut self, form: &MalVal, tail: bool) -> Code {
+        let scope = self.scopes.last().unwrap();
+        let (nscopes, nlocals, nenvs) = (self.scopes.len(), scope.locals.len(), scope.envs.len());
+        match self.try_compile(form, tail) {
+            Ok(code) => code,
+            Err(e) => {
+                self.scopes_mut().truncate(nscopes);
+                let scope = self.scope();
+                scope.locals.truncate(nlocals);
+                scope.envs.truncate(nenvs);
+                Rc::new(move |_| Err(e.clone()))
+            }
+        }
+    }
+
+    fn compile_all<'b>(&mut self, forms: impl Iterator<Item = &'b MalVal>) -> Vec<Code> {
+        forms.map(|form| self.compile(form, false)).collect()
+    }
+
+    // Expands and compiles `form`, which is expanded and compiled again when
+    // it's run if any of the macros it was expanded with have been redefined.
+    fn try_compile(&mut self, form: &MalVal, tail: bool) -> Result<Code, MalErr> {
+        let mut guards = vec![];
+        let expanded = self.macroexpand(form.clone(), &mut guards)?;
+        let code = self.compile_expanded(&expanded, tail)?;
+        if guards.is_empty() {
+            return Ok(code);
+        }
+        let expansion = self.expansion(form.clone(), tail);
+        Ok(Rc::new(move |frame| {
+            if guards.iter().all(Guard::holds) {
+                code(frame)
+            } else {
+                expansion.run(frame)
+            }
+        }))
+    }
+
+    fn compile_expanded(&mut self, form: &MalVal, tail: bool) -> Result<Code, MalErr> {
+        if is_constant(form) {
+            return Ok(constant(form.clone()));
+        }
+        Ok(match *form {
+            Sym(sym) => self.symbol(sym),
+            List(ref l, _) => self.list(form, l, tail)?,
+            Vector(ref v, _) => {
+                let items = self.compile_all(v.iter());
+                Rc::new(move |frame| Ok(Ret::Value(vector!(values(&items, frame)?))))
+            }
+            Hash(ref hm, _) => {
+                let empty = hm.empty();
+                let entries: Vec<(MalVal, Code)> = hm
+                    .iter()
+                    .map(|(k, v)| (k.clone(), self.compile(v, false)))
+                    .collect();
+                Rc::new(move |frame| {
+                    let mut new_hm = empty.clone();
+                    for (k, v) in &entries {
+                        new_hm.insert(k.clone(), value(v, frame)?)?;
+                    }
+                    Ok(Ret::Value(Hash(new_hm, Rc::new(Nil))))
+                })
+            }
+            Set(ref s, _) => {
+                let items = self.compile_all(s.iter());
+                Rc::new(move |frame| {
+                    let s: MalSet = values(&items, frame)?.into_iter().collect();
+                    Ok(Ret::Value(Set(s, Rc::new(Nil))))
+                })
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn symbol(&self, sym: Symbol) -> Code {
+        match self.resolve(sym) {
+            (ref defined, Some((0, slot))) if defined.is_empty() => {
+                Rc::new(move |frame| Ok(Ret::Value(frame.get(0, slot))))
+            }
+            (ref defined, Some((depth, slot))) if defined.is_empty() => {
+                Rc::new(move |frame| Ok(Ret::Value(frame.get(depth, slot))))
+            }
+            (defined, local) => {
+                let cell = self.globals.cell(sym);
+                Rc::new(move |frame| {
+                    for (depth, late) in &defined {
+                        let value = late.get().and_then(|slot| frame.lookup(*depth, slot));
+                        if let Some(value) = value {
+                            return Ok(Ret::Value(value));
+                        }
+                    }
+                    match (local, &*cell.borrow()) {
+                        (Some((depth, slot)), _) => Ok(Ret::Value(frame.get(depth, slot))),
+                        (None, Some(value)) => Ok(Ret::Value(value.clone())),
+                        (None, None) => Err(MalErr::String(format!("'{}' not found", sym))),
+                    }
+                })
+            }
+        }
+    }
+
+    fn name(&self, name: &MalVal, form: &str) -> Result<Symbol, MalErr> {
+        match name {
+            Sym(sym) => Ok(*sym),
+            _ => Err(MalErr::String(format!("{} with non-Sym name", form))),
+        }
+    }
+
+    fn list(&mut self, form: &MalVal, l: &MalList, tail: bool) -> Result<Code, MalErr> {
+        check_special_form(l, SPECIAL_FORMS)?;
+        let l: MalArgs = l.iter().cloned().collect();
+        Ok(match l[0] {
+            Sym(symbol::DEF) => {
+                let name = self.name(&l[1], "def!")?;
+                match self.def_local(name) {
+                    Some((depth, slot)) => {
+                        let init = self.compile(&l[2], false);
+                        Rc::new(move |frame| {
+                            let value = named(value(&init, frame)?, name);
+                            frame.set(depth, slot, value.clone());
+                            Ok(Ret::Value(value))
+                        })
+                    }
+                    None => {
+                        let cell = self.globals.cell(name);
+                        let init = self.compile(&l[2], false);
+                        Rc::new(move |frame| {
+                            let value = named(value(&init, frame)?, name);
+                            *cell.borrow_mut() = Some(value.clone());
+                            Ok(Ret::Value(value))
+                        })
+                    }
+                }
+            }
+            Sym(symbol::LET) => {
+                let binds: MalArgs = match l[1].seq_iter() {
+                    Some(binds) => binds.cloned().collect(),
+                    None => return error("let* with non-List bindings").map(constant),
+                };
+                let nlocals = self.scope().locals.len();
+                let mut names = vec![];
+                for b in binds.iter().step_by(2) {
+                    match b {
+                        Sym(name) if names.contains(name) => {}
+                        Sym(name) => {
+                            self.bind(*name, true);
+                            names.push(*name);
+                        }
+                        _ => {
+                            self.scope().locals.truncate(nlocals);
+                            return error("let* with non-Sym binding").map(constant);
+                        }
+                    }
+                }
+                let (inits, body) = self.env(nlocals, |c| {
+                    let mut inits = vec![];
+                    for (b, e) in binds.iter().tuples() {
+                        let i = nlocals + names.iter().position(|name| b == &Sym(*name)).unwrap();
+                        inits.push((c.scope().locals[i].slot, c.compile(e, false)));
+                        c.scope().locals[i].pending = false;
+                    }
+                    (inits, c.compile(&l[2], tail))
+                });
+                Rc::new(move |frame| {
+                    for (slot, init) in &inits {
+                        let value = value(init, frame)?;
+                        frame.set(0, *slot, value);
+                    }
+                    body(frame)
+                })
+            }
+            Sym(symbol::QUOTE) => constant(l[1].clone()),
+            Sym(symbol::QUASIQUOTEEXPAND) => constant(quasiquote(&l[1])),
+            Sym(symbol::QUASIQUOTE) => self.try_compile(&quasiquote(&l[1]), tail)?,
+            Sym(symbol::DEFMACRO) => {
+                let name = self.name(&l[1], "defmacro!")?;
+                let cell = self.globals.cell(name);
+                let init = self.compile(&l[2], false);
+                Rc::new(move |frame| match named(value(&init, frame)?, name) {
+                    Compiled { f, .. } => {
+                        let mac = Compiled {
+                            f,
+                            is_macro: true,
+                            meta: Rc::new(Nil),
+                        };
+                        *cell.borrow_mut() = Some(mac.clone());
+                        Ok(Ret::Value(mac))
+                    }
+                    _ => Err(MalErr::String("set_macro on non-function".to_string())),
+                })
+            }
+            Sym(symbol::MACROEXPAND) => {
+                let (form, globals, scopes) =
+                    (l[1].clone(), self.globals.clone(), self.scopes.clone());
+                Rc::new(move |_| {
+                    let compiler = Compiler {
+                        globals: &globals,
+                        scopes: scopes.clone(),
+                    };
+                    Ok(Ret::Value(compiler.macroexpand(form.clone(), &mut vec![])?))
+                })
+            }
+            Sym(symbol::TRY) if l.len() < 3 => self.try_compile(&l[1], tail)?,
+            Sym(symbol::TRY) => {
+                let body = self.compile(&l[1], false);
+                let nlocals = self.scope().locals.len();
+                let catch = match l[2] {
+                    List(ref c, _) if c.len() == 3 => {
+                        let c: MalArgs = c.iter().cloned().collect();
+                        let slot = match c[1] {
+                            Sym(name) => self.bind(name, false),
+                            _ => return error("catch* with non-Sym binding").map(constant),
+                        };
+                        let trace_slot = self.bind(Symbol::new("*stack-trace*"), false);
+                        let handler = self.env(nlocals, |c_| c_.compile(&c[2], tail));
+                        Some((slot, trace_slot, handler))
+                    }
+                    _ => None,
+                };
+                Rc::new(move |frame| match value(&body, frame) {
+                    Ok(value) => Ok(Ret::Value(value)),
+                    Err(e) => {
+                        let (exc, trace) = e.caught();
+                        match catch {
+                            Some((slot, trace_slot, ref handler)) => {
+                                frame.set(0, slot, exc);
+                                frame.set(0, trace_slot, trace_to_mal(trace)?);
+                                handler(frame)
+                            }
+                            None => Err(MalErr::String("invalid catch block".to_string())),
+                        }
+                    }
+                })
+            }
+            Sym(symbol::DO) => match l[1..].split_last() {
+                Some((last, body)) => {
+                    let body = self.compile_all(body.iter());
+                    let last = self.compile(last, tail);
+                    Rc::new(move |frame| {
+                        for code in &body {
+                            value(code, frame)?;
+                        }
+                        last(frame)
+                    })
+                }
+                None => constant(Nil),
+            },
+            Sym(symbol::IF) => {
+                let cond = self.compile(&l[1], false);
+                let then = self.compile(&l[2], tail);
+                let otherwise = l.get(3).map(|form| self.compile(form, tail));
+                Rc::new(move |frame| match value(&cond, frame)? {
+                    Bool(false) | Nil => match otherwise {
+                        Some(ref code) => code(frame),
+                        None => Ok(Ret::Value(Nil)),
+                    },
+                    _ => then(frame),
+                })
+            }
+            Sym(symbol::FN) => {
+                let (params, ast) = (l[1].clone(), l[2].clone());
+                let arity = Arity::of_params(&params)?;
+                let mut scope = Scope::default();
+                for param in params.seq_iter().unwrap() {
+                    match param {
+                        Sym(symbol::AMP) => {}
+                        Sym(name) => {
+                            scope.locals.push(Local {
+                                name: *name,
+                                slot: scope.nslots,
+                                pending: false,
+                            });
+                            scope.nslots += 1;
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                self.scopes_mut().push(scope);
+                let body = self.env(0, |c| c.compile(&ast, true));
+                let nslots = self.scopes_mut().pop().unwrap().nslots;
+                let lambda = Rc::new(Lambda {
+                    arity,
+                    nslots,
+                    body,
+                    params,
+                    ast,
+                });
+                Rc::new(move |frame| {
+                    Ok(Ret::Value(Compiled {
+                        f: Rc::new(Closure {
+                            lambda: lambda.clone(),
+                            frame: frame.clone(),
+                            name: None,
+                        }),
+                        is_macro: false,
+                        meta: Rc::new(Nil),
+                    }))
+                })
+            }
+            Sym(symbol::EVAL) => {
+                let form = self.compile(&l[1], false);
+                let globals = self.globals.clone();
+                Rc::new(move |frame| Ok(Ret::Value(eval(value(&form, frame)?, &globals)?)))
+            }
+            Sym(symbol::LAZY_SEQ) => {
+                let body = Rc::new(self.compile_all(l[1..].iter()));
+                Rc::new(move |frame| {
+                    let (body, frame) = (body.clone(), frame.clone());
+                    Ok(Ret::Value(Lazy::seq(move || {
+                        let mut res = Nil;
+                        for code in body.iter() {
+                            res = value(code, &frame)?;
+                        }
+                        Ok(res)
+                    })))
+                })
+            }
+            _ => {
+                let site = match l[0] {
+                    Sym(sym) => Some(sym),
+                    _ => None,
+                };
+                // a global may be defined as a macro by the time it's called
+                let expansion = match site {
+                    Some(sym) if self.is_global(sym) => Some(self.expansion(form.clone(), tail)),
+                    _ => None,
+                };
+                let f = self.compile(&l[0], false);
+                let args = self.compile_all(l[1..].iter());
+                Rc::new(move |frame| {
+                    let f = value(&f, frame)?;
+                    if let (Compiled { is_macro: true, .. }, Some(expansion)) = (&f, &expansion) {
+                        return expansion.run(frame);
+                    }
+                    let args = values(&args, frame)?;
+                    if tail {
+                        Ok(Ret::TailCall(f, args, site))
+                    } else {
+                        Ok(Ret::Value(call(f, args, site)?))
+                    }
+                })
+            }
+        })
+    }
+}
+
+// Evaluates `ast` in the global env.
+pub fn eval(ast: MalVal, globals: &Rc<Globals>) -> MalRet {
+    let mut compiler = Compiler {
+        globals,
+        scopes: Rc::new(vec![Scope::default()]),
+    };
+    let ast = compiler.macroexpand(ast, &mut vec![])?;
+    if let List(ref l, _) = ast {
+        if let Some(Sym(symbol::DO)) = l.first() {
+            let mut res = Nil;
+            for form in l.iter().skip(1) {
+                res = eval(form.clone(), globals)?;
+            }
+            return Ok(res);
+        }
+    }
+    let code = compiler.compile_expanded(&ast, false)?;
+    let frame = Frame::new(vec![None; compiler.scope().nslots], None);
+    value(&code, &frame)
+}
@@ -1,5 +1,8 @@
+use std::cell::RefCell;
+use std::cmp::Ordering::{Equal, Greater, Less};
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Lines, Read};
 use std::rc::Rc;
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -8,17 +11,34 @@ extern crate rustyline;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
+use crate::lazy::Lazy;
+use crate::map::Comparator;
+use crate::number;
 use crate::printer::pr_seq;
-use crate::reader::read_str;
-use crate::types::MalErr::ErrMalVal;
-use crate::types::MalVal::{Atom, Bool, Func, Hash, Int, List, MalFunc, Nil, Str, Sym, Vector};
-use crate::types::{MalArgs, MalRet, MalVal, _assoc, _dissoc, atom, error, func, hash_map};
-
-macro_rules! fn_t_int_int {
-    ($ret:ident, $fn:expr) => {{
-        |a: MalArgs| match (a[0].clone(), a[1].clone()) {
-            (Int(a0), Int(a1)) => Ok($ret($fn(a0, a1))),
-            _ => error("expecting (int,int) args"),
+use crate::re;
+use crate::reader::{read_all, read_str, Forms};
+use crate::string;
+use crate::types;
+use crate::types::Arity::{AtLeast, Between, Exactly};
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Char, Compiled, Float, Func, Hash, Int, Keyword, LazySeq, List, MalFunc,
+    Nil, Ratio, Reduced, Set, Str, Sym, Vector,
+};
+use crate::types::{
+    Arity, BuiltinFn, MalArgs, MalErr, MalHash, MalList, MalRet, MalSet, MalVal, _assoc, _dissoc,
+    atom, compare, error, func, hash_map, hash_set, keyword, partial, split_ns,
+};
+
+// Whether each numeric arg compares to the next as accepted by `$fn`.
+macro_rules! fn_cmp {
+    ($fn:expr) => {{
+        |a: MalArgs| {
+            for w in a.windows(2) {
+                if !$fn(number::compare(&w[0], &w[1])?) {
+                    return Ok(Bool(false));
+                }
+            }
+            Ok(Bool(true))
         }
     }};
 }
@@ -44,13 +64,74 @@ macro_rules! fn_str {
     }};
 }
 
+// Folds `op` over the numeric args from the left, starting from `init`, or
+// from the first arg if there's no `init`.
+fn fold(name: &str, a: MalArgs, init: Option<i64>, op: fn(&MalVal, &MalVal) -> MalRet) -> MalRet {
+    if !a.iter().all(number::is_number) {
+        return error(&format!("{}: expecting number args", name));
+    }
+    let mut args = a.into_iter();
+    let mut acc = match init {
+        Some(i) => Int(i),
+        None => args.next().unwrap(),
+    };
+    for arg in args {
+        acc = op(&acc, &arg)?;
+    }
+    Ok(acc)
+}
+
+fn max(a: &MalVal, b: &MalVal) -> MalRet {
+    Ok(if number::compare(b, a)? == Some(Greater) {
+        b
+    } else {
+        a
+    }
+    .clone())
+}
+
+fn min(a: &MalVal, b: &MalVal) -> MalRet {
+    Ok(if number::compare(b, a)? == Some(Less) {
+        b
+    } else {
+        a
+    }
+    .clone())
+}
+
 fn symbol(a: MalArgs) -> MalRet {
     match a[0] {
-        Str(ref s) => Ok(Sym(s.to_string())),
+        Str(ref s) => Ok(types::symbol(s)),
         _ => error("illegal symbol call"),
     }
 }
 
+// `(keyword name)`, or `(keyword ns name)` for `:ns/name`.
+fn make_keyword(a: MalArgs) -> MalRet {
+    match (&a[0], a.get(1)) {
+        (_, None) => a[0].keyword(),
+        (Str(ns), Some(Str(name))) => Ok(keyword(&format!("{}/{}", ns, name))),
+        (Nil, Some(Str(name))) => Ok(keyword(name)),
+        _ => error("keyword: expecting strings"),
+    }
+}
+
+fn name(a: MalArgs) -> MalRet {
+    match &a[0] {
+        Str(s) => Ok(Str(s.clone())),
+        Sym(s) | Keyword(s) => Ok(Str(split_ns(s.as_str()).1.to_string())),
+        _ => error("name: expecting a string, symbol or keyword"),
+    }
+}
+
+fn namespace(a: MalArgs) -> MalRet {
+    let ns = match &a[0] {
+        Sym(s) | Keyword(s) => split_ns(s.as_str()).0,
+        _ => return error("namespace: expecting a symbol or keyword"),
+    };
+    Ok(ns.map_or(Nil, |ns| Str(ns.to_string())))
+}
+
 fn readline(a: MalArgs) -> MalRet {
     lazy_static! {
         static ref RL: Mutex<Editor<()>> = Mutex::new(Editor::<()>::new());
@@ -87,6 +168,38 @@ fn slurp(f: String) -> MalRet {
     }
 }
 
+// A lazy seq of the lines of a file, read as they're needed.
+fn line_seq(f: String) -> MalRet {
+    match File::open(&f) {
+        Ok(file) => Ok(lines(Rc::new(RefCell::new(BufReader::new(file).lines())))),
+        Err(e) => error(&format!("line-seq: {}: {}", f, e)),
+    }
+}
+
+fn lines(lines: Rc<RefCell<Lines<BufReader<File>>>>) -> MalVal {
+    Lazy::seq(move || match lines.borrow_mut().next() {
+        Some(Ok(line)) => Ok(Lazy::cons(Str(line), self::lines(lines.clone()))),
+        Some(Err(e)) => error(&e.to_string()),
+        None => Ok(Nil),
+    })
+}
+
+// A lazy seq of the forms in a file, read as they're needed.
+fn form_seq(f: String) -> MalRet {
+    match File::open(&f) {
+        Ok(file) => Ok(forms(Rc::new(RefCell::new(Forms::new(file))))),
+        Err(e) => error(&format!("form-seq: {}: {}", f, e)),
+    }
+}
+
+fn forms(forms: Rc<RefCell<Forms<File>>>) -> MalVal {
+    Lazy::seq(move || match forms.borrow_mut().next() {
+        Some(Ok(form)) => Ok(Lazy::cons(form, self::forms(forms.clone()))),
+        Some(Err(e)) => Err(e),
+        None => Ok(Nil),
+    })
+}
+
 fn time_ms(_a: MalArgs) -> MalRet {
     let ms_e = match SystemTime::now().duration_since(UNIX_EPOCH) {
         Ok(d) => d,
@@ -100,7 +213,7 @@ fn time_ms(_a: MalArgs) -> MalRet {
 fn get(a: MalArgs) -> MalRet {
     match (a[0].clone(), a[1].clone()) {
         (Nil, _) => Ok(Nil),
-        (Hash(ref hm, _), Str(ref s)) => match hm.get(s) {
+        (Hash(ref hm, _), ref k) => match hm.get(k)? {
             Some(mv) => Ok(mv.clone()),
             None => Ok(Nil),
         },
@@ -110,242 +223,1034 @@ fn get(a: MalArgs) -> MalRet {
 
 fn assoc(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => _assoc((**hm).clone(), a[1..].to_vec()),
+        Hash(ref hm, _) => _assoc(hm.clone(), a[1..].to_vec()),
         _ => error("assoc on non-Hash Map"),
     }
 }
 
 fn dissoc(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => _dissoc((**hm).clone(), a[1..].to_vec()),
+        Hash(ref hm, _) => _dissoc(hm.clone(), a[1..].to_vec()),
         _ => error("dissoc on non-Hash Map"),
     }
 }
 
 fn contains_q(a: MalArgs) -> MalRet {
     match (a[0].clone(), a[1].clone()) {
-        (Hash(ref hm, _), Str(ref s)) => Ok(Bool(hm.contains_key(s))),
+        (Hash(ref hm, _), ref k) => Ok(Bool(hm.contains_key(k)?)),
+        (Set(ref s, _), ref k) => Ok(Bool(s.contains(k))),
         _ => error("illegal get args"),
     }
 }
 
 fn keys(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => Ok(list!(hm.keys().map(|k| { Str(k.to_string()) }).collect())),
+        Hash(ref hm, _) => Ok(list!(hm.keys().cloned())),
         _ => error("keys requires Hash Map"),
     }
 }
 
 fn vals(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => Ok(list!(hm.values().map(|v| { v.clone() }).collect())),
+        Hash(ref hm, _) => Ok(list!(hm.values().map(|v| { v.clone() }))),
         _ => error("keys requires Hash Map"),
     }
 }
 
+// The items of a list, vector or lazy seq.
+fn seq_vec(name: &str, seq: &MalVal) -> Result<Vec<MalVal>, MalErr> {
+    match seq {
+        LazySeq(_) => seq.items().collect(),
+        _ => match seq.seq_iter() {
+            Some(items) => Ok(items.cloned().collect()),
+            None => Err(MalErr::String(format!("non-seq passed to {}", name))),
+        },
+    }
+}
+
 fn vec(a: MalArgs) -> MalRet {
     match a[0] {
-        List(ref v, _) | Vector(ref v, _) => Ok(vector!(v.to_vec())),
+        List(ref l, _) => Ok(vector!(l.iter().cloned())),
+        Vector(ref v, _) => Ok(Vector(v.clone(), Rc::new(Nil))),
+        LazySeq(_) => Ok(vector!(seq_vec("vec", &a[0])?)),
         _ => error("non-seq passed to vec"),
     }
 }
 
 fn cons(a: MalArgs) -> MalRet {
-    match a[1].clone() {
-        List(v, _) | Vector(v, _) => {
-            let mut new_v = vec![a[0].clone()];
-            new_v.extend_from_slice(&v);
-            Ok(list!(new_v.to_vec()))
-        }
-        _ => error("cons expects seq as second arg"),
+    if let LazySeq(_) = a[1] {
+        return Ok(Lazy::cons(a[0].clone(), a[1].clone()));
+    }
+    match a[1].to_list() {
+        Some(l) => Ok(List(l.push_front(a[0].clone()), Rc::new(Nil))),
+        None => error("cons expects seq as second arg"),
     }
 }
 
 fn concat(a: MalArgs) -> MalRet {
-    let mut new_v = vec![];
-    for seq in a.iter() {
-        match seq {
-            List(v, _) | Vector(v, _) => new_v.extend_from_slice(v),
-            _ => return error("non-seq passed to concat"),
+    // the last seq is shared rather than copied
+    let mut res = match a.last() {
+        Some(LazySeq(_)) => seq_vec("concat", &a[a.len() - 1])?.into_iter().collect(),
+        Some(last) => match last.to_list() {
+            Some(l) => l,
+            None => return error("non-seq passed to concat"),
+        },
+        None => MalList::new(),
+    };
+    for seq in a.iter().rev().skip(1) {
+        for item in seq_vec("concat", seq)?.into_iter().rev() {
+            res.push_front_mut(item);
         }
     }
-    Ok(list!(new_v.to_vec()))
+    Ok(List(res, Rc::new(Nil)))
 }
 
 fn nth(a: MalArgs) -> MalRet {
-    match (a[0].clone(), a[1].clone()) {
-        (List(seq, _), Int(idx)) | (Vector(seq, _), Int(idx)) => {
-            if seq.len() <= idx as usize {
-                return error("nth: index out of range");
-            }
-            Ok(seq[idx as usize].clone())
+    let item = match (&a[0], &a[1]) {
+        (List(l, _), Int(idx)) if *idx >= 0 => l.iter().nth(*idx as usize).cloned(),
+        (Vector(v, _), Int(idx)) if *idx >= 0 => v.get(*idx as usize).cloned(),
+        (LazySeq(_), Int(idx)) if *idx >= 0 => a[0].items().nth(*idx as usize).transpose()?,
+        (Str(s), Int(idx)) if *idx >= 0 => s.chars().nth(*idx as usize).map(Char),
+        (List(_, _), Int(_)) | (Vector(_, _), Int(_)) | (LazySeq(_), Int(_)) | (Str(_), Int(_)) => {
+            None
         }
-        _ => error("invalid args to nth"),
+        _ => return error("invalid args to nth"),
+    };
+    match item {
+        Some(item) => Ok(item),
+        None => error("nth: index out of range"),
     }
 }
 
 fn first(a: MalArgs) -> MalRet {
-    match a[0].clone() {
-        List(ref seq, _) | Vector(ref seq, _) if seq.len() == 0 => Ok(Nil),
-        List(ref seq, _) | Vector(ref seq, _) => Ok(seq[0].clone()),
+    match a[0] {
+        List(ref l, _) => Ok(l.first().cloned().unwrap_or(Nil)),
+        Vector(ref v, _) => Ok(v.front().cloned().unwrap_or(Nil)),
+        LazySeq(ref lazy) => Ok(lazy.realize()?.map_or(Nil, |(first, _)| first)),
         Nil => Ok(Nil),
         _ => error("invalid args to first"),
     }
 }
 
 fn rest(a: MalArgs) -> MalRet {
-    match a[0].clone() {
-        List(ref seq, _) | Vector(ref seq, _) => {
-            if seq.len() > 1 {
-                Ok(list!(seq[1..].to_vec()))
-            } else {
-                Ok(list![])
-            }
-        }
+    match a[0] {
+        List(ref l, _) => Ok(List(l.drop_first().unwrap_or_default(), Rc::new(Nil))),
+        Vector(ref v, _) => Ok(list!(v.iter().skip(1).cloned())),
+        LazySeq(ref lazy) => match lazy.realize()? {
+            // the rest of a vector is a vector, see `uncons`
+            Some((_, Vector(v, _))) => Ok(list!(v.iter().cloned())),
+            Some((_, Nil)) => Ok(list![]),
+            Some((_, rest)) => Ok(rest),
+            None => Ok(list![]),
+        },
         Nil => Ok(list![]),
         _ => error("invalid args to first"),
     }
 }
 
 fn apply(a: MalArgs) -> MalRet {
-    match a[a.len() - 1] {
-        List(ref v, _) | Vector(ref v, _) => {
-            let f = &a[0];
-            let mut fargs = a[1..a.len() - 1].to_vec();
-            fargs.extend_from_slice(&v);
-            f.apply(fargs)
-        }
-        _ => error("apply called with non-seq"),
+    let mut fargs = a[1..a.len() - 1].to_vec();
+    fargs.extend(seq_vec("apply", &a[a.len() - 1])?);
+    a[0].apply(fargs)
+}
+
+fn truthy(v: &MalVal) -> bool {
+    match v {
+        Nil | Bool(false) => false,
+        _ => true,
     }
 }
 
+// The items of any collection, without copying it. The entries of a map are
+// [key value] vectors, and the items of a string are 1-char strings.
+pub fn coll_items<'a>(
+    name: &str,
+    coll: &'a MalVal,
+) -> Result<Box<dyn Iterator<Item = MalRet> + 'a>, MalErr> {
+    Ok(match coll {
+        List(l, _) => Box::new(l.iter().cloned().map(Ok)),
+        Vector(v, _) => Box::new(v.iter().cloned().map(Ok)),
+        Set(s, _) => Box::new(s.iter().cloned().map(Ok)),
+        Hash(hm, _) => Box::new(hm.iter().map(|(k, v)| Ok(vector![k.clone(), v.clone()]))),
+        Str(s) => Box::new(s.chars().map(|c| Ok(Str(c.to_string())))),
+        LazySeq(_) => Box::new(coll.items()),
+        Nil => Box::new(std::iter::empty()),
+        _ => {
+            return Err(MalErr::String(format!(
+                "{}: called with non-collection",
+                name
+            )))
+        }
+    })
+}
+
+// `map`, `filter`, `remove`, `keep`, `mapcat`, `partition`, `take` and
+// `drop` are lazy when they're given a lazy seq, and otherwise return a list
+// right away, so that errors are raised where the call is made. Given just a
+// function, the first five return a transducer instead.
 fn map(a: MalArgs) -> MalRet {
+    if a.len() == 1 {
+        return Ok(transducer("map", a[0].clone(), map_step));
+    }
+    if let LazySeq(_) = a[1] {
+        return Ok(lazy_map(a[0].clone(), a[1].clone()));
+    }
+    let mut res = vec![];
+    for item in coll_items("map", &a[1])? {
+        res.push(a[0].apply(vec![item?])?)
+    }
+    Ok(list!(res))
+}
+
+fn lazy_map(f: MalVal, seq: MalVal) -> MalVal {
+    Lazy::seq(move || match seq.uncons()? {
+        Some((first, rest)) => Ok(Lazy::cons(f.apply(vec![first])?, lazy_map(f.clone(), rest))),
+        None => Ok(Nil),
+    })
+}
+
+// What `filter`, `remove` and `keep` keep of an item, given the item and the
+// result of calling their function on it.
+type Pick = fn(MalVal, MalVal) -> Option<MalVal>;
+
+fn pick_filter(item: MalVal, res: MalVal) -> Option<MalVal> {
+    if truthy(&res) {
+        Some(item)
+    } else {
+        None
+    }
+}
+
+fn pick_remove(item: MalVal, res: MalVal) -> Option<MalVal> {
+    pick_filter(item, Bool(!truthy(&res)))
+}
+
+fn pick_keep(_item: MalVal, res: MalVal) -> Option<MalVal> {
+    match res {
+        Nil => None,
+        res => Some(res),
+    }
+}
+
+fn keep_items(name: &'static str, a: MalArgs, pick: Pick, step: fn(MalArgs) -> MalRet) -> MalRet {
+    if a.len() == 1 {
+        return Ok(transducer(name, a[0].clone(), step));
+    }
+    if let LazySeq(_) = a[1] {
+        return Ok(lazy_keep(a[0].clone(), a[1].clone(), pick));
+    }
+    let mut res = vec![];
+    for item in coll_items(name, &a[1])? {
+        let item = item?;
+        let f_res = a[0].apply(vec![item.clone()])?;
+        res.extend(pick(item, f_res));
+    }
+    Ok(list!(res))
+}
+
+fn lazy_keep(f: MalVal, seq: MalVal, pick: Pick) -> MalVal {
+    Lazy::seq(move || {
+        let mut seq = seq.clone();
+        while let Some((first, rest)) = seq.uncons()? {
+            let res = f.apply(vec![first.clone()])?;
+            if let Some(item) = pick(first, res) {
+                return Ok(Lazy::cons(item, lazy_keep(f.clone(), rest, pick)));
+            }
+            seq = rest;
+        }
+        Ok(Nil)
+    })
+}
+
+fn mapcat(a: MalArgs) -> MalRet {
+    if a.len() == 1 {
+        return Ok(transducer("mapcat", a[0].clone(), mapcat_step));
+    }
+    if let LazySeq(_) = a[1] {
+        return Ok(lazy_mapcat(a[0].clone(), a[1].clone()));
+    }
+    let mut res = vec![];
+    for item in coll_items("mapcat", &a[1])? {
+        for item in coll_items("mapcat", &a[0].apply(vec![item?])?)? {
+            res.push(item?);
+        }
+    }
+    Ok(list!(res))
+}
+
+fn lazy_mapcat(f: MalVal, seq: MalVal) -> MalVal {
+    Lazy::seq(move || match seq.uncons()? {
+        Some((first, rest)) => {
+            let coll = f.apply(vec![first])?;
+            let items = match coll {
+                List(_, _) | Vector(_, _) | Set(_, _) | LazySeq(_) | Nil => coll,
+                _ => list!(coll_items("mapcat", &coll)?.collect::<Result<Vec<_>, _>>()?),
+            };
+            Ok(lazy_concat(items, lazy_mapcat(f.clone(), rest)))
+        }
+        None => Ok(Nil),
+    })
+}
+
+// A transducer, which given a reducing function `rf` returns one that calls
+// `step` with `f`, `rf`, the accumulated value and each item in turn. See
+// `transduce`.
+fn transducer(name: &'static str, f: MalVal, step: fn(MalArgs) -> MalRet) -> MalVal {
+    let step = func(name, Exactly(4), step);
+    partial(name, Exactly(1), reducing_fn, vec![f, step])
+}
+
+// Called with `f` and `step` bound, followed by `rf`.
+fn reducing_fn(a: MalArgs) -> MalRet {
+    let name = match a[1] {
+        Func(ref b, _) => b.name,
+        _ => unreachable!(),
+    };
+    Ok(partial(name, Between(0, 2), reducing_step, a))
+}
+
+// Called with `f`, `step` and `rf` bound, followed by no args to get an
+// initial value, the result to complete it, or the result and an item.
+fn reducing_step(mut a: MalArgs) -> MalRet {
+    match a.len() {
+        3 | 4 => a[2].apply(a[3..].to_vec()),
+        _ => {
+            let item = a.pop().unwrap();
+            let acc = a.pop().unwrap();
+            let rf = a.pop().unwrap();
+            a[1].apply(vec![a[0].clone(), rf, acc, item])
+        }
+    }
+}
+
+fn map_step(a: MalArgs) -> MalRet {
+    a[1].apply(vec![a[2].clone(), a[0].apply(vec![a[3].clone()])?])
+}
+
+fn filter_step(a: MalArgs) -> MalRet {
+    if truthy(&a[0].apply(vec![a[3].clone()])?) {
+        a[1].apply(vec![a[2].clone(), a[3].clone()])
+    } else {
+        Ok(a[2].clone())
+    }
+}
+
+fn remove_step(a: MalArgs) -> MalRet {
+    if truthy(&a[0].apply(vec![a[3].clone()])?) {
+        Ok(a[2].clone())
+    } else {
+        a[1].apply(vec![a[2].clone(), a[3].clone()])
+    }
+}
+
+fn keep_step(a: MalArgs) -> MalRet {
+    match a[0].apply(vec![a[3].clone()])? {
+        Nil => Ok(a[2].clone()),
+        res => a[1].apply(vec![a[2].clone(), res]),
+    }
+}
+
+fn mapcat_step(a: MalArgs) -> MalRet {
+    let mut acc = a[2].clone();
+    for item in coll_items("mapcat", &a[0].apply(vec![a[3].clone()])?)? {
+        acc = a[1].apply(vec![acc, item?])?;
+        if let Reduced(_) = acc {
+            break;
+        }
+    }
+    Ok(acc)
+}
+
+// Calls `f` with the accumulated value and each item in turn, stopping early
+// if it returns a value wrapped by `reduced`.
+fn reduce_items(f: &MalVal, init: MalVal, items: impl Iterator<Item = MalRet>) -> MalRet {
+    let mut acc = init;
+    for item in items {
+        acc = f.apply(vec![acc, item?])?;
+        if let Reduced(ref v) = acc {
+            return Ok((**v).clone());
+        }
+    }
+    Ok(acc)
+}
+
+// Without an initial value, the first item is used, or `(f)` if there are
+// no items.
+fn reduce(a: MalArgs) -> MalRet {
+    let mut items = coll_items("reduce", &a[a.len() - 1])?;
+    let init = match a.len() {
+        2 => match items.next() {
+            Some(item) => item?,
+            None => return a[0].apply(vec![]),
+        },
+        _ => a[1].clone(),
+    };
+    reduce_items(&a[0], init, items)
+}
+
+// Calls `f` with the accumulated value and each key and value of a map, or
+// each index and item of a vector.
+fn reduce_kv(a: MalArgs) -> MalRet {
+    let entries: Box<dyn Iterator<Item = (MalVal, MalVal)>> = match a[2] {
+        Hash(ref hm, _) => Box::new(hm.iter().map(|(k, v)| (k.clone(), v.clone()))),
+        Vector(ref v, _) => Box::new(
+            v.iter()
+                .enumerate()
+                .map(|(i, v)| (Int(i as i64), v.clone())),
+        ),
+        Nil => Box::new(std::iter::empty()),
+        _ => return error("reduce-kv: called with non-map"),
+    };
+    let mut acc = a[1].clone();
+    for (k, v) in entries {
+        acc = a[0].apply(vec![acc, k, v])?;
+        if let Reduced(ref v) = acc {
+            return Ok((**v).clone());
+        }
+    }
+    Ok(acc)
+}
+
+// Reduces with `(xform f)`, then calls it with the result to complete it.
+// Without an initial value, `(f)` is used.
+fn transduce(a: MalArgs) -> MalRet {
+    let rf = a[0].apply(vec![a[1].clone()])?;
+    let init = match a.len() {
+        3 => a[1].apply(vec![])?,
+        _ => a[2].clone(),
+    };
+    let acc = reduce_items(&rf, init, coll_items("transduce", &a[a.len() - 1])?)?;
+    rf.apply(vec![acc])
+}
+
+// `conj`s each item onto `to`, through a transducer if there's one.
+fn into(a: MalArgs) -> MalRet {
+    let conj = func("conj", AtLeast(0), conj);
+    match a.len() {
+        0 => Ok(vector![]),
+        1 => Ok(a[0].clone()),
+        2 => reduce_items(&conj, a[0].clone(), coll_items("into", &a[1])?),
+        _ => transduce(vec![a[1].clone(), conj, a[0].clone(), a[2].clone()]),
+    }
+}
+
+// Lists of `n` items, starting every `step` items. Any items left over at
+// the end are dropped, unless there are items to `pad` them with, in which
+// case they make one last, possibly shorter, list.
+fn partition(a: MalArgs) -> MalRet {
+    let (n, step, pad) = match a.len() {
+        2 => (&a[0], &a[0], None),
+        3 => (&a[0], &a[1], None),
+        _ => (&a[0], &a[1], Some(a[2].clone())),
+    };
+    let (n, step) = match (n, step) {
+        (Int(n), Int(step)) if *n > 0 && *step > 0 => (*n as usize, *step as usize),
+        _ => return error("partition: n and step must be positive integers"),
+    };
+    let coll = &a[a.len() - 1];
+    if let LazySeq(_) = coll {
+        return Ok(lazy_partition(n, step, pad, coll.clone()));
+    }
+    let mut parts = vec![];
+    let mut part = VecDeque::new();
+    let mut skip = 0;
+    for item in coll_items("partition", coll)? {
+        let item = item?;
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        part.push_back(item);
+        if part.len() == n {
+            parts.push(list!(part.iter().cloned()));
+            if step < n {
+                part.drain(..step);
+            } else {
+                part.clear();
+                skip = step - n;
+            }
+        }
+    }
+    if let Some(pad) = pad {
+        if !part.is_empty() {
+            parts.push(padded(n, part.into_iter().collect(), &pad)?);
+        }
+    }
+    Ok(list!(parts))
+}
+
+fn lazy_partition(n: usize, step: usize, pad: Option<MalVal>, seq: MalVal) -> MalVal {
+    Lazy::seq(move || {
+        let mut part = vec![];
+        let mut rest = seq.clone();
+        while part.len() < n {
+            match rest.uncons()? {
+                Some((first, next)) => {
+                    part.push(first);
+                    rest = next;
+                }
+                None => break,
+            }
+        }
+        if part.len() < n {
+            return match pad {
+                Some(ref pad) if !part.is_empty() => Ok(list!(vec![padded(n, part, pad)?])),
+                _ => Ok(Nil),
+            };
+        }
+        let rest = lazy_drop(step as i64, seq.clone());
+        Ok(Lazy::cons(
+            list!(part),
+            lazy_partition(n, step, pad.clone(), rest),
+        ))
+    })
+}
+
+fn padded(n: usize, mut part: MalArgs, pad: &MalVal) -> MalRet {
+    for item in coll_items("partition", pad)?.take(n - part.len()) {
+        part.push(item?);
+    }
+    Ok(list!(part))
+}
+
+// A map from the result of `f` on each item to a vector of the items with
+// that result, in the order the results first appear.
+fn group_by(a: MalArgs) -> MalRet {
+    let mut groups = MalHash::new();
+    for item in coll_items("group-by", &a[1])? {
+        let item = item?;
+        let k = a[0].apply(vec![item.clone()])?;
+        let group = match groups.get(&k)? {
+            Some(Vector(v, _)) => {
+                let mut v = v.clone();
+                v.push_back(item);
+                Vector(v, Rc::new(Nil))
+            }
+            _ => vector!(vec![item]),
+        };
+        groups.insert(k, group)?;
+    }
+    Ok(Hash(groups, Rc::new(Nil)))
+}
+
+// A map from each distinct item to the number of times it appears.
+fn frequencies(a: MalArgs) -> MalRet {
+    let mut counts = MalHash::new();
+    for item in coll_items("frequencies", &a[0])? {
+        let item = item?;
+        let n = match counts.get(&item)? {
+            Some(Int(n)) => n + 1,
+            _ => 1,
+        };
+        counts.insert(item, Int(n))?;
+    }
+    Ok(Hash(counts, Rc::new(Nil)))
+}
+
+// `(sort coll)` or `(sort comp coll)`, where `comp` is as for
+// `sorted-map-by`. The sort is stable.
+fn sort(a: MalArgs) -> MalRet {
+    let cmp = Comparator::new(a.get(1).map(|_| a[0].clone()));
+    let items = coll_items("sort", &a[a.len() - 1])?.collect::<Result<Vec<_>, _>>()?;
+    Ok(list!(merge_sort(items, &|a, b| cmp.compare(a, b))?))
+}
+
+// `(sort-by keyfn coll)` or `(sort-by keyfn comp coll)`: sorts by the
+// result of `keyfn` on each item, which is only called once per item.
+fn sort_by(a: MalArgs) -> MalRet {
+    let cmp = Comparator::new(a.get(2).map(|_| a[1].clone()));
+    let mut keyed = vec![];
+    for item in coll_items("sort-by", &a[a.len() - 1])? {
+        let item = item?;
+        keyed.push((a[0].apply(vec![item.clone()])?, item));
+    }
+    let sorted = merge_sort(keyed, &|a, b| cmp.compare(&a.0, &b.0))?;
+    Ok(list!(sorted.into_iter().map(|(_, item)| item)))
+}
+
+// A stable merge sort. `slice::sort_by` can't stop at an error from the
+// comparator, and may panic if it isn't a total order, which a user's
+// comparator needn't be.
+fn merge_sort<T>(
+    mut items: Vec<T>,
+    cmp: &dyn Fn(&T, &T) -> Result<std::cmp::Ordering, MalErr>,
+) -> Result<Vec<T>, MalErr> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, cmp)?.into_iter().peekable();
+    let mut right = merge_sort(right, cmp)?.into_iter().peekable();
+    let mut res = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // an item from the left comes first unless it's greater
+        let next = if cmp(r, l)? == Less {
+            right.next()
+        } else {
+            left.next()
+        };
+        res.extend(next);
+    }
+    res.extend(left.chain(right));
+    Ok(res)
+}
+
+// Checks the count given to `take`, `drop` or `repeat`, and that `seq` is one.
+fn count_and_seq(name: &str, n: &MalVal, seq: &MalVal) -> Result<i64, MalErr> {
+    match (n, seq) {
+        (Int(n), List(_, _)) | (Int(n), Vector(_, _)) | (Int(n), LazySeq(_)) | (Int(n), Nil) => {
+            Ok(*n)
+        }
+        (Int(_), _) => Err(MalErr::String(format!("{}: called with non-seq", name))),
+        _ => Err(MalErr::String(format!(
+            "{}: count must be an integer",
+            name
+        ))),
+    }
+}
+
+fn take(a: MalArgs) -> MalRet {
+    let n = count_and_seq("take", &a[0], &a[1])?;
     match a[1] {
-        List(ref v, _) | Vector(ref v, _) => {
-            let mut res = vec![];
-            for mv in v.iter() {
-                res.push(a[0].apply(vec![mv.clone()])?)
+        LazySeq(_) => Ok(lazy_take(n, a[1].clone())),
+        _ => Ok(list!(a[1]
+            .items()
+            .take(n.max(0) as usize)
+            .collect::<Result<Vec<_>, _>>()?)),
+    }
+}
+
+fn lazy_take(n: i64, seq: MalVal) -> MalVal {
+    Lazy::seq(move || {
+        if n <= 0 {
+            return Ok(Nil);
+        }
+        match seq.uncons()? {
+            Some((first, rest)) => Ok(Lazy::cons(first, lazy_take(n - 1, rest))),
+            None => Ok(Nil),
+        }
+    })
+}
+
+fn drop(a: MalArgs) -> MalRet {
+    let n = count_and_seq("drop", &a[0], &a[1])?;
+    match a[1] {
+        LazySeq(_) => Ok(lazy_drop(n, a[1].clone())),
+        _ => Ok(list!(a[1]
+            .items()
+            .skip(n.max(0) as usize)
+            .collect::<Result<Vec<_>, _>>()?)),
+    }
+}
+
+fn lazy_drop(n: i64, seq: MalVal) -> MalVal {
+    Lazy::seq(move || {
+        let mut seq = seq.clone();
+        for _ in 0..n {
+            match seq.uncons()? {
+                Some((_, rest)) => seq = rest,
+                None => return Ok(Nil),
+            }
+        }
+        Ok(seq)
+    })
+}
+
+// `x`, then `f` applied to the previous item, forever.
+fn iterate(f: MalVal, x: MalVal) -> MalVal {
+    let next = x.clone();
+    Lazy::cons(
+        x,
+        Lazy::seq(move || Ok(iterate(f.clone(), f.apply(vec![next.clone()])?))),
+    )
+}
+
+// From `start` up to `end`, or down to it if `step` is negative, or forever
+// if there's no `end`.
+fn range(a: MalArgs) -> MalRet {
+    if !a.iter().all(number::is_number) {
+        return error("range: expecting number args");
+    }
+    let (start, end, step) = match a.len() {
+        0 => (Int(0), None, Int(1)),
+        1 => (Int(0), Some(a[0].clone()), Int(1)),
+        2 => (a[0].clone(), Some(a[1].clone()), Int(1)),
+        _ => (a[0].clone(), Some(a[1].clone()), a[2].clone()),
+    };
+    let towards = match number::compare(&step, &Int(0))? {
+        Some(Less) => Greater,
+        _ => Less,
+    };
+    Ok(lazy_range(start, end, step, towards))
+}
+
+fn lazy_range(
+    start: MalVal,
+    end: Option<MalVal>,
+    step: MalVal,
+    towards: std::cmp::Ordering,
+) -> MalVal {
+    Lazy::seq(move || {
+        if let Some(ref end) = end {
+            if number::compare(&start, end)? != Some(towards) {
+                return Ok(Nil);
             }
-            Ok(list!(res))
         }
-        _ => error("map called with non-seq"),
+        let next = number::add(&start, &step)?;
+        Ok(Lazy::cons(
+            start.clone(),
+            lazy_range(next, end.clone(), step.clone(), towards),
+        ))
+    })
+}
+
+fn repeat(a: MalArgs) -> MalRet {
+    match a.len() {
+        1 => Ok(lazy_repeat(a[0].clone())),
+        _ => {
+            let n = count_and_seq("repeat", &a[0], &Nil)?;
+            Ok(lazy_take(n, lazy_repeat(a[1].clone())))
+        }
     }
 }
 
+fn lazy_repeat(x: MalVal) -> MalVal {
+    Lazy::seq(move || Ok(Lazy::cons(x.clone(), lazy_repeat(x.clone()))))
+}
+
+fn cycle(a: MalArgs) -> MalRet {
+    match a[0] {
+        List(_, _) | Vector(_, _) | Set(_, _) | LazySeq(_) | Nil => Ok(lazy_cycle(a[0].clone())),
+        _ => error("cycle: called with non-seq"),
+    }
+}
+
+fn lazy_cycle(seq: MalVal) -> MalVal {
+    Lazy::seq(move || match seq.uncons()? {
+        Some(_) => Ok(lazy_concat(seq.clone(), lazy_cycle(seq.clone()))),
+        None => Ok(Nil),
+    })
+}
+
+fn lazy_concat(seq: MalVal, tail: MalVal) -> MalVal {
+    Lazy::seq(move || match seq.uncons()? {
+        Some((first, rest)) => Ok(Lazy::cons(first, lazy_concat(rest, tail.clone()))),
+        None => Ok(tail.clone()),
+    })
+}
+
+// Walks a lazy seq, realizing it, without keeping hold of its head.
+fn dorun(mut a: MalArgs) -> MalRet {
+    for item in a.remove(0).into_items() {
+        item?;
+    }
+    Ok(Nil)
+}
+
+// With no args, an empty vector, so `conj` can start a `transduce`.
 fn conj(a: MalArgs) -> MalRet {
+    if a.is_empty() {
+        return Ok(vector![]);
+    }
     match a[0] {
-        List(ref v, _) => {
-            let sl = a[1..]
-                .iter()
-                .rev()
-                .map(|a| a.clone())
-                .collect::<Vec<MalVal>>();
-            Ok(list!([&sl[..], v].concat()))
+        List(ref l, _) => {
+            let mut l = l.clone();
+            for item in a[1..].iter() {
+                l.push_front_mut(item.clone());
+            }
+            Ok(List(l, Rc::new(Nil)))
+        }
+        Vector(ref v, _) => {
+            let mut v = v.clone();
+            v.extend(a[1..].iter().cloned());
+            Ok(Vector(v, Rc::new(Nil)))
+        }
+        Set(ref s, _) => {
+            let mut s = s.clone();
+            s.extend(a[1..].iter().cloned());
+            Ok(Set(s, Rc::new(Nil)))
+        }
+        // each item is a [key value] vector, or a map to merge in
+        Hash(ref hm, _) => {
+            let mut hm = hm.clone();
+            for item in a[1..].iter() {
+                match item {
+                    Vector(kv, _) if kv.len() == 2 => hm.insert(kv[0].clone(), kv[1].clone())?,
+                    Hash(other, _) => {
+                        for (k, v) in other.iter() {
+                            hm.insert(k.clone(), v.clone())?;
+                        }
+                    }
+                    _ => return error("conj: map entries must be [key value] vectors"),
+                }
+            }
+            Ok(Hash(hm, Rc::new(Nil)))
         }
-        Vector(ref v, _) => Ok(vector!([v, &a[1..]].concat())),
         _ => error("conj: called with non-seq"),
     }
 }
 
 fn seq(a: MalArgs) -> MalRet {
     match a[0] {
-        List(ref v, _) | Vector(ref v, _) if v.len() == 0 => Ok(Nil),
-        List(ref v, _) | Vector(ref v, _) => Ok(list!(v.to_vec())),
+        List(ref l, _) if l.is_empty() => Ok(Nil),
+        List(ref l, _) => Ok(List(l.clone(), Rc::new(Nil))),
+        Vector(ref v, _) if v.is_empty() => Ok(Nil),
+        Vector(ref v, _) => Ok(list!(v.iter().cloned())),
+        Set(ref s, _) if s.is_empty() => Ok(Nil),
+        Set(ref s, _) => Ok(list!(s.iter().cloned())),
+        LazySeq(ref lazy) => match lazy.realize()? {
+            Some(_) => Ok(a[0].clone()),
+            None => Ok(Nil),
+        },
         Str(ref s) if s.len() == 0 => Ok(Nil),
-        Str(ref s) if !a[0].keyword_q() => {
-            Ok(list!(s.chars().map(|c| { Str(c.to_string()) }).collect()))
-        }
+        Str(ref s) => Ok(list!(s.chars().map(|c| { Str(c.to_string()) }))),
         Nil => Ok(Nil),
         _ => error("seq: called with non-seq"),
     }
 }
 
+fn set(a: MalArgs) -> MalRet {
+    match a[0] {
+        Set(ref s, _) => Ok(Set(s.clone(), Rc::new(Nil))),
+        Nil => Ok(hash_set(vec![])),
+        _ => match a[0].seq_iter() {
+            Some(items) => Ok(hash_set(items.cloned())),
+            None => error("set: called with non-seq"),
+        },
+    }
+}
+
+fn disj(a: MalArgs) -> MalRet {
+    match a[0] {
+        Set(ref s, _) => {
+            let mut s = s.clone();
+            for k in a[1..].iter() {
+                s.remove(k);
+            }
+            Ok(Set(s, Rc::new(Nil)))
+        }
+        Nil => Ok(Nil),
+        _ => error("disj: called with non-set"),
+    }
+}
+
+// Combines the sets in `a` with `op`, from the left.
+fn set_op(name: &str, a: MalArgs, op: fn(MalSet, MalSet) -> MalSet) -> MalRet {
+    let mut res: Option<MalSet> = None;
+    for arg in a {
+        match arg {
+            Set(s, _) => {
+                res = Some(match res {
+                    Some(res) => op(res, s),
+                    None => s,
+                })
+            }
+            _ => return error(&format!("{}: expecting set args", name)),
+        }
+    }
+    Ok(Set(res.unwrap_or_default(), Rc::new(Nil)))
+}
+
 pub fn ns() -> Vec<(&'static str, MalVal)> {
-    vec![
-        ("=", func(|a| Ok(Bool(a[0] == a[1])))),
-        ("throw", func(|a| Err(ErrMalVal(a[0].clone())))),
-        ("nil?", func(fn_is_type!(Nil))),
-        ("true?", func(fn_is_type!(Bool(true)))),
-        ("false?", func(fn_is_type!(Bool(false)))),
-        ("symbol", func(symbol)),
-        ("symbol?", func(fn_is_type!(Sym(_)))),
-        (
-            "string?",
-            func(fn_is_type!(Str(ref s) if !s.starts_with("\u{29e}"))),
-        ),
-        ("keyword", func(|a| a[0].keyword())),
-        (
-            "keyword?",
-            func(fn_is_type!(Str(ref s) if s.starts_with("\u{29e}"))),
-        ),
-        ("number?", func(fn_is_type!(Int(_)))),
+    let builtins: Vec<(&'static str, Arity, BuiltinFn)> = vec![
+        ("=", Exactly(2), |a| Ok(Bool(a[0] == a[1]))),
+        ("throw", Exactly(1), |a| Err(MalErr::MalVal(a[0].clone()))),
+        ("nil?", Exactly(1), fn_is_type!(Nil)),
+        ("true?", Exactly(1), fn_is_type!(Bool(true))),
+        ("false?", Exactly(1), fn_is_type!(Bool(false))),
+        ("symbol", Exactly(1), symbol),
+        ("symbol?", Exactly(1), fn_is_type!(Sym(_))),
+        ("string?", Exactly(1), fn_is_type!(Str(_))),
+        ("char?", Exactly(1), fn_is_type!(Char(_))),
+        ("keyword", Between(1, 2), make_keyword),
+        ("keyword?", Exactly(1), fn_is_type!(Keyword(_))),
+        ("name", Exactly(1), name),
+        ("namespace", Exactly(1), namespace),
         (
-            "fn?",
-            func(fn_is_type!(MalFunc{is_macro,..} if !is_macro,Func(_,_))),
+            "number?",
+            Exactly(1),
+            fn_is_type!(Int(_), Float(_), BigInt(_), Ratio(_)),
         ),
+        ("fn?", Exactly(1), |a| {
+            Ok(Bool(match a[0] {
+                MalFunc { is_macro, .. } | Compiled { is_macro, .. } => !is_macro,
+                Func(_, _) => true,
+                _ => false,
+            }))
+        }),
+        ("macro?", Exactly(1), |a| {
+            Ok(Bool(match a[0] {
+                MalFunc { is_macro, .. } | Compiled { is_macro, .. } => is_macro,
+                _ => false,
+            }))
+        }),
+        ("pr-str", AtLeast(0), |a| {
+            a.iter().try_for_each(MalVal::realize)?;
+            Ok(Str(pr_seq(&a, true, "", "", " ")))
+        }),
+        ("str", AtLeast(0), |a| {
+            a.iter().try_for_each(MalVal::realize)?;
+            Ok(Str(pr_seq(&a, false, "", "", "")))
+        }),
+        ("prn", AtLeast(0), |a| {
+            a.iter().try_for_each(MalVal::realize)?;
+            println!("{}", pr_seq(&a, true, "", "", " "));
+            Ok(Nil)
+        }),
+        ("println", AtLeast(0), |a| {
+            a.iter().try_for_each(MalVal::realize)?;
+            println!("{}", pr_seq(&a, false, "", "", " "));
+            Ok(Nil)
+        }),
+        ("read-string", Exactly(1), fn_str!(|s| { read_str(s) })),
+        ("read-all", Exactly(1), fn_str!(|s| { read_all(s) })),
+        ("readline", Exactly(1), readline),
+        ("slurp", Exactly(1), fn_str!(|f| { slurp(f) })),
+        ("line-seq", Exactly(1), fn_str!(|f| { line_seq(f) })),
+        ("form-seq", Exactly(1), fn_str!(|f| { form_seq(f) })),
+        ("<", AtLeast(1), fn_cmp!(|o| o == Some(Less))),
         (
-            "macro?",
-            func(fn_is_type!(MalFunc{is_macro,..} if is_macro)),
+            "<=",
+            AtLeast(1),
+            fn_cmp!(|o| o == Some(Less) || o == Some(Equal)),
         ),
-        ("pr-str", func(|a| Ok(Str(pr_seq(&a, true, "", "", " "))))),
-        ("str", func(|a| Ok(Str(pr_seq(&a, false, "", "", ""))))),
+        (">", AtLeast(1), fn_cmp!(|o| o == Some(Greater))),
         (
-            "prn",
-            func(|a| {
-                println!("{}", pr_seq(&a, true, "", "", " "));
-                Ok(Nil)
-            }),
+            ">=",
+            AtLeast(1),
+            fn_cmp!(|o| o == Some(Greater) || o == Some(Equal)),
         ),
+        ("+", AtLeast(0), |a| fold("+", a, Some(0), number::add)),
+        ("-", AtLeast(1), |a| match a.len() {
+            1 => number::negate(&a[0]),
+            _ => fold("-", a, None, number::sub),
+        }),
+        ("*", AtLeast(0), |a| fold("*", a, Some(1), number::mul)),
+        ("/", AtLeast(1), |a| match a.len() {
+            1 => number::div(&Int(1), &a[0]),
+            _ => fold("/", a, None, number::div),
+        }),
+        ("max", AtLeast(1), |a| fold("max", a, None, max)),
+        ("min", AtLeast(1), |a| fold("min", a, None, min)),
+        ("quot", Exactly(2), |a| number::quot(&a[0], &a[1])),
+        ("rem", Exactly(2), |a| number::rem(&a[0], &a[1])),
+        ("mod", Exactly(2), |a| number::modulo(&a[0], &a[1])),
+        ("inc", Exactly(1), |a| number::add(&a[0], &Int(1))),
+        ("dec", Exactly(1), |a| number::sub(&a[0], &Int(1))),
+        ("abs", Exactly(1), |a| number::abs(&a[0])),
+        ("bit-and", AtLeast(2), |a| {
+            fold("bit-and", a, None, number::bit_and)
+        }),
+        ("bit-or", AtLeast(2), |a| {
+            fold("bit-or", a, None, number::bit_or)
+        }),
+        ("bit-xor", AtLeast(2), |a| {
+            fold("bit-xor", a, None, number::bit_xor)
+        }),
+        ("bit-not", Exactly(1), |a| number::bit_not(&a[0])),
+        ("bit-shift-left", Exactly(2), |a| {
+            number::shift_left(&a[0], &a[1])
+        }),
+        ("bit-shift-right", Exactly(2), |a| {
+            number::shift_right(&a[0], &a[1])
+        }),
+        ("compare", Exactly(2), |a| {
+            Ok(Int(compare(&a[0], &a[1])? as i64))
+        }),
+        ("time-ms", Exactly(0), time_ms),
         (
-            "println",
-            func(|a| {
-                println!("{}", pr_seq(&a, false, "", "", " "));
-                Ok(Nil)
-            }),
+            "sequential?",
+            Exactly(1),
+            fn_is_type!(List(_, _), Vector(_, _), LazySeq(_)),
         ),
-        ("read-string", func(fn_str!(|s| { read_str(s) }))),
-        ("readline", func(readline)),
-        ("slurp", func(fn_str!(|f| { slurp(f) }))),
-        ("<", func(fn_t_int_int!(Bool, |i, j| { i < j }))),
-        ("<=", func(fn_t_int_int!(Bool, |i, j| { i <= j }))),
-        (">", func(fn_t_int_int!(Bool, |i, j| { i > j }))),
-        (">=", func(fn_t_int_int!(Bool, |i, j| { i >= j }))),
-        ("+", func(fn_t_int_int!(Int, |i, j| { i + j }))),
-        ("-", func(fn_t_int_int!(Int, |i, j| { i - j }))),
-        ("*", func(fn_t_int_int!(Int, |i, j| { i * j }))),
-        ("/", func(fn_t_int_int!(Int, |i, j| { i / j }))),
-        ("time-ms", func(time_ms)),
-        ("sequential?", func(fn_is_type!(List(_, _), Vector(_, _)))),
-        ("list", func(|a| Ok(list!(a)))),
-        ("list?", func(fn_is_type!(List(_, _)))),
-        ("vector", func(|a| Ok(vector!(a)))),
-        ("vector?", func(fn_is_type!(Vector(_, _)))),
-        ("hash-map", func(|a| hash_map(a))),
-        ("map?", func(fn_is_type!(Hash(_, _)))),
-        ("assoc", func(assoc)),
-        ("dissoc", func(dissoc)),
-        ("get", func(get)),
-        ("contains?", func(contains_q)),
-        ("keys", func(keys)),
-        ("vals", func(vals)),
-        ("vec", func(vec)),
-        ("cons", func(cons)),
-        ("concat", func(concat)),
-        ("empty?", func(|a| a[0].empty_q())),
-        ("nth", func(nth)),
-        ("first", func(first)),
-        ("rest", func(rest)),
-        ("count", func(|a| a[0].count())),
-        ("apply", func(apply)),
-        ("map", func(map)),
-        ("conj", func(conj)),
-        ("seq", func(seq)),
-        ("meta", func(|a| a[0].get_meta())),
-        ("with-meta", func(|a| a[0].clone().with_meta(&a[1]))),
-        ("atom", func(|a| Ok(atom(&a[0])))),
-        ("atom?", func(fn_is_type!(Atom(_)))),
-        ("deref", func(|a| a[0].deref())),
-        ("reset!", func(|a| a[0].reset_bang(&a[1]))),
-        ("swap!", func(|a| a[0].swap_bang(&a[1..].to_vec()))),
-    ]
+        ("list", AtLeast(0), |a| Ok(list!(a))),
+        ("list?", Exactly(1), fn_is_type!(List(_, _))),
+        ("vector", AtLeast(0), |a| Ok(vector!(a))),
+        ("vector?", Exactly(1), fn_is_type!(Vector(_, _))),
+        ("hash-map", AtLeast(0), |a| hash_map(a)),
+        ("sorted-map", AtLeast(0), |a| {
+            _assoc(MalHash::sorted(None), a)
+        }),
+        ("sorted-map-by", AtLeast(1), |a| {
+            _assoc(MalHash::sorted(Some(a[0].clone())), a[1..].to_vec())
+        }),
+        ("map?", Exactly(1), fn_is_type!(Hash(_, _))),
+        ("set", Exactly(1), set),
+        ("set?", Exactly(1), fn_is_type!(Set(_, _))),
+        ("assoc", AtLeast(1), assoc),
+        ("dissoc", AtLeast(1), dissoc),
+        ("get", Exactly(2), get),
+        ("contains?", Exactly(2), contains_q),
+        ("keys", Exactly(1), keys),
+        ("vals", Exactly(1), vals),
+        ("vec", Exactly(1), vec),
+        ("cons", Exactly(2), cons),
+        ("concat", AtLeast(0), concat),
+        ("empty?", Exactly(1), |a| a[0].empty_q()),
+        ("nth", Exactly(2), nth),
+        ("first", Exactly(1), first),
+        ("rest", Exactly(1), rest),
+        ("count", Exactly(1), |a| a[0].count()),
+        ("apply", AtLeast(2), apply),
+        ("map", Between(1, 2), map),
+        ("filter", Between(1, 2), |a| {
+            keep_items("filter", a, pick_filter, filter_step)
+        }),
+        ("remove", Between(1, 2), |a| {
+            keep_items("remove", a, pick_remove, remove_step)
+        }),
+        ("keep", Between(1, 2), |a| {
+            keep_items("keep", a, pick_keep, keep_step)
+        }),
+        ("mapcat", Between(1, 2), mapcat),
+        ("partition", Between(2, 4), partition),
+        ("reduce", Between(2, 3), reduce),
+        ("reduce-kv", Exactly(3), reduce_kv),
+        ("reduced", Exactly(1), |a| {
+            Ok(Reduced(Rc::new(a[0].clone())))
+        }),
+        ("reduced?", Exactly(1), fn_is_type!(Reduced(_))),
+        ("transduce", Between(3, 4), transduce),
+        ("into", Between(0, 3), into),
+        ("group-by", Exactly(2), group_by),
+        ("frequencies", Exactly(1), frequencies),
+        ("sort", Between(1, 2), sort),
+        ("sort-by", Between(2, 3), sort_by),
+        ("take", Exactly(2), take),
+        ("drop", Exactly(2), drop),
+        ("iterate", Exactly(2), |a| {
+            Ok(iterate(a[0].clone(), a[1].clone()))
+        }),
+        ("range", Between(0, 3), range),
+        ("repeat", Between(1, 2), repeat),
+        ("cycle", Exactly(1), cycle),
+        ("doall", Exactly(1), |a| {
+            a[0].items().try_for_each(|item| item.map(|_| ()))?;
+            Ok(a[0].clone())
+        }),
+        ("dorun", Exactly(1), dorun),
+        ("conj", AtLeast(0), conj),
+        ("disj", AtLeast(1), disj),
+        ("union", AtLeast(0), |a| set_op("union", a, MalSet::union)),
+        ("intersection", AtLeast(1), |a| {
+            set_op("intersection", a, MalSet::intersection)
+        }),
+        ("difference", AtLeast(1), |a| {
+            set_op("difference", a, MalSet::relative_complement)
+        }),
+        ("seq", Exactly(1), seq),
+        ("meta", Exactly(1), |a| a[0].get_meta()),
+        ("with-meta", Exactly(2), |a| a[0].clone().with_meta(&a[1])),
+        ("atom", Exactly(1), |a| Ok(atom(&a[0]))),
+        ("atom?", Exactly(1), fn_is_type!(Atom(_))),
+        ("deref", Exactly(1), |a| a[0].deref()),
+        ("reset!", Exactly(2), |a| a[0].reset_bang(&a[1])),
+        ("swap!", AtLeast(2), |a| a[0].swap_bang(&a[1..].to_vec())),
+    ];
+    builtins
+        .into_iter()
+        .map(|(name, arity, f)| (name, func(name, arity, f)))
+        .chain(string::ns())
+        .chain(re::ns())
+        .collect()
 }
@@ -3,13 +3,14 @@ use std::rc::Rc;
 //use std::collections::HashMap;
 use fnv::FnvHashMap;
 
-use crate::types::MalErr::ErrString;
-use crate::types::MalVal::{List, Nil, Sym, Vector};
-use crate::types::{error, MalErr, MalRet, MalVal};
+use crate::symbol;
+use crate::symbol::Symbol;
+use crate::types::MalVal::{List, Nil, Sym};
+use crate::types::{error, Arity, MalErr, MalRet, MalVal};
 
 #[derive(Debug)]
 pub struct EnvStruct {
-    data: RefCell<FnvHashMap<String, MalVal>>,
+    data: RefCell<FnvHashMap<Symbol, MalVal>>,
     pub outer: Option<Env>,
 }
 
@@ -27,28 +28,28 @@ pub fn env_new(outer: Option<Env>) -> Env {
 
 // TODO: mbinds and exprs as & types
 pub fn env_bind(outer: Option<Env>, mbinds: MalVal, exprs: Vec<MalVal>) -> Result<Env, MalErr> {
+    Arity::of_params(&mbinds)?.check("fn", exprs.len())?;
     let env = env_new(outer);
-    match mbinds {
-        List(binds, _) | Vector(binds, _) => {
-            for (i, b) in binds.iter().enumerate() {
-                match b {
-                    Sym(s) if s == "&" => {
-                        env_set(&env, binds[i + 1].clone(), list!(exprs[i..].to_vec()))?;
-                        break;
-                    }
-                    _ => {
-                        env_set(&env, b.clone(), exprs[i].clone())?;
-                    }
-                }
+    let binds: Vec<&MalVal> = match mbinds.seq_iter() {
+        Some(binds) => binds.collect(),
+        None => return Err(MalErr::String("env_bind binds not List/Vector".to_string())),
+    };
+    for (i, b) in binds.iter().enumerate() {
+        match b {
+            Sym(symbol::AMP) => {
+                env_set(&env, binds[i + 1].clone(), list!(exprs[i..].to_vec()))?;
+                break;
+            }
+            _ => {
+                env_set(&env, (*b).clone(), exprs[i].clone())?;
             }
-            Ok(env)
         }
-        _ => Err(ErrString("env_bind binds not List/Vector".to_string())),
     }
+    Ok(env)
 }
 
-pub fn env_find(env: &Env, key: &str) -> Option<Env> {
-    match (env.data.borrow().contains_key(key), env.outer.clone()) {
+pub fn env_find(env: &Env, key: Symbol) -> Option<Env> {
+    match (env.data.borrow().contains_key(&key), env.outer.clone()) {
         (true, _) => Some(env.clone()),
         (false, Some(o)) => env_find(&o, key),
         _ => None,
@@ -57,12 +58,12 @@ pub fn env_find(env: &Env, key: &str) -> Option<Env> {
 
 pub fn env_get(env: &Env, key: &MalVal) -> MalRet {
     match key {
-        Sym(ref s) => match env_find(env, s) {
+        Sym(s) => match env_find(env, *s) {
             Some(e) => Ok(e
                 .data
                 .borrow()
                 .get(s)
-                .ok_or(ErrString(format!("'{}' not found", s)))?
+                .ok_or(MalErr::String(format!("'{}' not found", s)))?
                 .clone()),
             _ => error(&format!("'{}' not found", s)),
         },
@@ -72,8 +73,8 @@ pub fn env_get(env: &Env, key: &MalVal) -> MalRet {
 
 pub fn env_set(env: &Env, key: MalVal, val: MalVal) -> MalRet {
     match key {
-        Sym(ref s) => {
-            env.data.borrow_mut().insert(s.to_string(), val.clone());
+        Sym(s) => {
+            env.data.borrow_mut().insert(s, val.clone());
             Ok(val)
         }
         _ => error("Env.set called with non-Str"),
@@ -81,5 +82,5 @@ pub fn env_set(env: &Env, key: MalVal, val: MalVal) -> MalRet {
 }
 
 pub fn env_sets(env: &Env, key: &str, val: MalVal) {
-    env.data.borrow_mut().insert(key.to_string(), val);
+    env.data.borrow_mut().insert(Symbol::new(key), val);
 }
@@ -0,0 +1,113 @@
+// Lazy seqs, whose items are only produced as they're needed, so they can be
+// infinite, or stream data that wouldn't fit in memory. A lazy seq starts out
+// as a thunk, and once it's been realized it caches its first item and the
+// rest of the seq, which is usually another lazy seq.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use crate::types::MalVal::LazySeq;
+use crate::types::{MalErr, MalRet, MalVal};
+
+pub struct Lazy {
+    state: RefCell<State>,
+}
+
+type Step = Option<(MalVal, MalVal)>;
+
+enum State {
+    // Produces the seq: anything `MalVal::uncons` accepts.
+    Pending(Rc<dyn Fn() -> MalRet>),
+    Realized(Step),
+}
+
+impl Lazy {
+    pub fn seq(f: impl Fn() -> MalRet + 'static) -> MalVal {
+        LazySeq(Rc::new(Lazy {
+            state: RefCell::new(State::Pending(Rc::new(f))),
+        }))
+    }
+
+    // `first` followed by `rest`, which is left as it is rather than realized.
+    pub fn cons(first: MalVal, rest: MalVal) -> MalVal {
+        LazySeq(Rc::new(Lazy {
+            state: RefCell::new(State::Realized(Some((first, rest)))),
+        }))
+    }
+
+    fn pending(&self) -> Option<Rc<dyn Fn() -> MalRet>> {
+        match *self.state.borrow() {
+            State::Pending(ref f) => Some(f.clone()),
+            State::Realized(_) => None,
+        }
+    }
+
+    // The first item and the rest of the seq, or None if it's empty. The
+    // thunk is only called again if it failed.
+    pub fn realize(&self) -> Result<Step, MalErr> {
+        let f = match self.pending() {
+            Some(f) => f,
+            None => return self.step(),
+        };
+        // A thunk returning another lazy seq, and so on, is common when items
+        // are skipped, so the chain is followed in a loop rather than
+        // recursively, which could overflow the stack.
+        let mut chain = vec![];
+        let mut value = f()?;
+        loop {
+            let inner = match value {
+                LazySeq(ref inner) => inner.clone(),
+                _ => break,
+            };
+            match inner.pending() {
+                Some(f) => value = f()?,
+                None => break,
+            }
+            chain.push(inner);
+        }
+        let step = value.uncons()?;
+        for lazy in chain.iter().map(|rc| &**rc).chain(Some(self)) {
+            *lazy.state.borrow_mut() = State::Realized(step.clone());
+        }
+        Ok(step)
+    }
+
+    fn step(&self) -> Result<Step, MalErr> {
+        match *self.state.borrow() {
+            State::Realized(ref step) => Ok(step.clone()),
+            State::Pending(_) => unreachable!("lazy seq isn't realized"),
+        }
+    }
+
+    fn take_rest(&mut self) -> Option<MalVal> {
+        match mem::replace(self.state.get_mut(), State::Realized(None)) {
+            State::Realized(Some((_, rest))) => Some(rest),
+            _ => None,
+        }
+    }
+}
+
+// As with lists, dropping a long realized seq recursively would overflow the
+// stack, so the parts that aren't shared are unlinked one at a time.
+impl Drop for Lazy {
+    fn drop(&mut self) {
+        let mut rest = self.take_rest();
+        while let Some(LazySeq(rc)) = rest {
+            rest = match Rc::try_unwrap(rc) {
+                Ok(mut unshared) => unshared.take_rest(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl fmt::Debug for Lazy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.state.borrow() {
+            State::Pending(_) => f.write_str("#<lazy-seq>"),
+            State::Realized(ref step) => f.debug_tuple("LazySeq").field(step).finish(),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// A persistent singly linked list. Cloning one, `push_front` and `drop_first`
+// are all O(1), since lists share their tails rather than copying them.
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::rc::Rc;
+
+struct Node<T> {
+    head: T,
+    tail: Option<Rc<Node<T>>>,
+}
+
+pub struct List<T> {
+    node: Option<Rc<Node<T>>>,
+    len: usize,
+}
+
+impl<T> List<T> {
+    pub fn new() -> List<T> {
+        List { node: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.node.as_ref().map(|node| &node.head)
+    }
+
+    // O(n), as the list has to be walked.
+    pub fn last(&self) -> Option<&T> {
+        self.iter().last()
+    }
+
+    pub fn push_front(&self, head: T) -> List<T> {
+        let mut list = self.clone();
+        list.push_front_mut(head);
+        list
+    }
+
+    pub fn push_front_mut(&mut self, head: T) {
+        let tail = self.node.take();
+        self.node = Some(Rc::new(Node { head, tail }));
+        self.len += 1;
+    }
+
+    // The list without its first item, or None if it's empty.
+    pub fn drop_first(&self) -> Option<List<T>> {
+        self.node.as_ref().map(|node| List {
+            node: node.tail.clone(),
+            len: self.len - 1,
+        })
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            node: self.node.as_deref(),
+            len: self.len,
+        }
+    }
+}
+
+impl<T> Clone for List<T> {
+    fn clone(&self) -> List<T> {
+        List {
+            node: self.node.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> List<T> {
+        List::new()
+    }
+}
+
+// Dropping a long list recursively would overflow the stack, so the nodes
+// that aren't shared with another list are unlinked one at a time.
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        let mut node = self.node.take();
+        while let Some(rc) = node {
+            node = match Rc::try_unwrap(rc) {
+                Ok(mut unshared) => unshared.tail.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl<T> FromIterator<T> for List<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> List<T> {
+        let items: Vec<T> = iter.into_iter().collect();
+        let mut list = List::new();
+        for item in items.into_iter().rev() {
+            list.push_front_mut(item);
+        }
+        list
+    }
+}
+
+impl<T: PartialEq> PartialEq for List<T> {
+    fn eq(&self, other: &List<T>) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, T: 'a> {
+    node: Option<&'a Node<T>>,
+    len: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.node.map(|node| {
+            self.node = node.tail.as_deref();
+            self.len -= 1;
+            &node.head
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+impl<'a, T> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
@@ -0,0 +1,258 @@
+// Maps that iterate in a predictable order: hash maps in the order their keys
+// were first inserted, and sorted maps in key order, by `compare` or by a
+// comparator function. Both are persistent, like the other collections.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+use fnv::FnvBuildHasher;
+
+use crate::types::MalVal::{Bool, Nil};
+use crate::types::{compare, MalErr, MalVal};
+
+#[derive(Clone)]
+pub struct MalMap {
+    repr: Repr,
+}
+
+#[derive(Clone)]
+enum Repr {
+    // Each key is stamped with the order it was inserted in, and the entries
+    // are kept by stamp. Iterating never looks keys up, as a key containing
+    // NaN isn't equal to itself.
+    Hashed {
+        stamps: im_rc::HashMap<MalVal, u64, FnvBuildHasher>,
+        entries: im_rc::OrdMap<u64, (MalVal, MalVal)>,
+        next: u64,
+    },
+    Sorted(im_rc::OrdMap<SortKey, MalVal>, Rc<Comparator>),
+}
+
+// How a sorted map, or `sort`, orders values. The tree can't propagate errors
+// from a comparator, so the first one is parked here until the operation is
+// done.
+pub struct Comparator {
+    f: Option<MalVal>,
+    error: RefCell<Option<MalErr>>,
+}
+
+#[derive(Clone)]
+struct SortKey {
+    key: MalVal,
+    cmp: Rc<Comparator>,
+}
+
+impl Comparator {
+    // Orders by the comparator function `f`, or by `compare` if there's none.
+    pub fn new(f: Option<MalVal>) -> Comparator {
+        Comparator {
+            f,
+            error: RefCell::new(None),
+        }
+    }
+
+    pub fn compare(&self, a: &MalVal, b: &MalVal) -> Result<Ordering, MalErr> {
+        let f = match self.f {
+            Some(ref f) => f,
+            None => return compare(a, b),
+        };
+        // like Clojure, accept either a number or a "less than" predicate
+        match f.apply(vec![a.clone(), b.clone()])? {
+            Bool(true) => Ok(Ordering::Less),
+            Bool(false) | Nil => match f.apply(vec![b.clone(), a.clone()])? {
+                Bool(false) | Nil => Ok(Ordering::Equal),
+                _ => Ok(Ordering::Greater),
+            },
+            n => compare(&n, &MalVal::Int(0)).map_err(|_| {
+                MalErr::String("comparator must return a number or boolean".to_string())
+            }),
+        }
+    }
+
+    // The error from a comparator during the last operation, if there was one.
+    fn check(&self) -> Result<(), MalErr> {
+        match self.error.borrow_mut().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &SortKey) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortKey {}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &SortKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &SortKey) -> Ordering {
+        match self.cmp.compare(&self.key, &other.key) {
+            Ok(ord) => ord,
+            Err(e) => {
+                self.cmp.error.borrow_mut().get_or_insert(e);
+                Ordering::Equal
+            }
+        }
+    }
+}
+
+impl MalMap {
+    pub fn new() -> MalMap {
+        MalMap {
+            repr: Repr::Hashed {
+                stamps: im_rc::HashMap::default(),
+                entries: im_rc::OrdMap::new(),
+                next: 0,
+            },
+        }
+    }
+
+    // An empty sorted map, ordered by the comparator `f`, or by `compare` if
+    // there's none.
+    pub fn sorted(f: Option<MalVal>) -> MalMap {
+        MalMap {
+            repr: Repr::Sorted(im_rc::OrdMap::new(), Rc::new(Comparator::new(f))),
+        }
+    }
+
+    // An empty map of the same kind, so a sorted map keeps its comparator.
+    pub fn empty(&self) -> MalMap {
+        match self.repr {
+            Repr::Hashed { .. } => MalMap::new(),
+            Repr::Sorted(_, ref cmp) => MalMap {
+                repr: Repr::Sorted(im_rc::OrdMap::new(), cmp.clone()),
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.repr {
+            Repr::Hashed { ref entries, .. } => entries.len(),
+            Repr::Sorted(ref tree, _) => tree.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, key: &MalVal) -> Result<Option<&MalVal>, MalErr> {
+        match self.repr {
+            Repr::Hashed {
+                ref stamps,
+                ref entries,
+                ..
+            } => Ok(stamps.get(key).map(|stamp| &entries[stamp].1)),
+            Repr::Sorted(ref tree, ref cmp) => {
+                let value = tree.get(&sort_key(key, cmp));
+                cmp.check()?;
+                Ok(value)
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &MalVal) -> Result<bool, MalErr> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    // Replacing the value of a key keeps its place in the order.
+    pub fn insert(&mut self, key: MalVal, value: MalVal) -> Result<(), MalErr> {
+        match self.repr {
+            Repr::Hashed {
+                ref mut stamps,
+                ref mut entries,
+                ref mut next,
+            } => {
+                let stamp = match stamps.get(&key) {
+                    Some(&stamp) => stamp,
+                    None => {
+                        *next += 1;
+                        stamps.insert(key.clone(), *next);
+                        *next
+                    }
+                };
+                entries.insert(stamp, (key, value));
+                Ok(())
+            }
+            Repr::Sorted(ref mut tree, ref cmp) => {
+                tree.insert(sort_key(&key, cmp), value);
+                cmp.check()
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MalVal) -> Result<(), MalErr> {
+        match self.repr {
+            Repr::Hashed {
+                ref mut stamps,
+                ref mut entries,
+                ..
+            } => {
+                if let Some(stamp) = stamps.remove(key) {
+                    entries.remove(&stamp);
+                }
+                Ok(())
+            }
+            Repr::Sorted(ref mut tree, ref cmp) => {
+                tree.remove(&sort_key(key, cmp));
+                cmp.check()
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&MalVal, &MalVal)> + '_> {
+        match self.repr {
+            Repr::Hashed { ref entries, .. } => Box::new(entries.values().map(|(k, v)| (k, v))),
+            Repr::Sorted(ref tree, _) => Box::new(tree.iter().map(|(k, v)| (&k.key, v))),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &MalVal> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &MalVal> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+fn sort_key(key: &MalVal, cmp: &Rc<Comparator>) -> SortKey {
+    SortKey {
+        key: key.clone(),
+        cmp: cmp.clone(),
+    }
+}
+
+impl Default for MalMap {
+    fn default() -> MalMap {
+        MalMap::new()
+    }
+}
+
+// Maps with the same entries are equal whatever their order, so a sorted
+// map can equal a hash map.
+impl PartialEq for MalMap {
+    fn eq(&self, other: &MalMap) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(k, v)| match other.get(k) {
+                Ok(Some(ov)) => v == ov,
+                _ => false,
+            })
+    }
+}
+
+impl fmt::Debug for MalMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,332 @@
+// The numeric tower: `Int` is promoted to `BigInt` on overflow, dividing
+// integers gives an exact `Ratio`, and anything involving a `Float` gives a
+// `Float`. Results are always normalized, so a `BigInt` never fits in an i64
+// and a `Ratio` is never a whole number.
+
+use num_bigint::{BigInt, Sign};
+use num_rational::BigRational;
+use num_traits::{Num, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::types::MalVal::{BigInt as Big, Float, Int, Ratio};
+use crate::types::{error, MalErr, MalRet, MalVal};
+
+// Two numbers converted to the least general representation holding both.
+enum Pair {
+    Ints(i64, i64),
+    BigInts(BigInt, BigInt),
+    Ratios(BigRational, BigRational),
+    Floats(f64, f64),
+}
+
+fn rank(n: &MalVal) -> Option<u8> {
+    match n {
+        Int(_) => Some(0),
+        Big(_) => Some(1),
+        Ratio(_) => Some(2),
+        Float(_) => Some(3),
+        _ => None,
+    }
+}
+
+pub fn is_number(n: &MalVal) -> bool {
+    rank(n).is_some()
+}
+
+fn to_bigint(n: &MalVal) -> BigInt {
+    match n {
+        Int(i) => BigInt::from(*i),
+        Big(i) => (**i).clone(),
+        _ => unreachable!(),
+    }
+}
+
+fn to_ratio(n: &MalVal) -> BigRational {
+    match n {
+        Ratio(r) => (**r).clone(),
+        _ => BigRational::from_integer(to_bigint(n)),
+    }
+}
+
+pub fn to_f64(n: &MalVal) -> f64 {
+    match n {
+        Int(i) => *i as f64,
+        Big(i) => i.to_f64().unwrap_or(f64::NAN),
+        Ratio(r) => r.to_f64().unwrap_or(f64::NAN),
+        Float(f) => *f,
+        _ => f64::NAN,
+    }
+}
+
+fn pair(a: &MalVal, b: &MalVal) -> Result<Pair, MalErr> {
+    if let (Int(a), Int(b)) = (a, b) {
+        return Ok(Pair::Ints(*a, *b));
+    }
+    Ok(match (rank(a), rank(b)) {
+        (Some(ra), Some(rb)) => match ra.max(rb) {
+            0 | 1 => Pair::BigInts(to_bigint(a), to_bigint(b)),
+            2 => Pair::Ratios(to_ratio(a), to_ratio(b)),
+            _ => Pair::Floats(to_f64(a), to_f64(b)),
+        },
+        _ => {
+            return Err(MalErr::String(
+                "expecting (number, number) args".to_string(),
+            ))
+        }
+    })
+}
+
+pub fn from_bigint(i: BigInt) -> MalVal {
+    match i.to_i64() {
+        Some(i) => Int(i),
+        None => Big(Rc::new(i)),
+    }
+}
+
+pub fn from_ratio(r: BigRational) -> MalVal {
+    if r.is_integer() {
+        from_bigint(r.to_integer())
+    } else {
+        Ratio(Rc::new(r))
+    }
+}
+
+macro_rules! arithmetic {
+    ($name:ident, $op:tt, $checked:ident) => {
+        pub fn $name(a: &MalVal, b: &MalVal) -> MalRet {
+            Ok(match pair(a, b)? {
+                Pair::Ints(a, b) => match a.$checked(b) {
+                    Some(i) => Int(i),
+                    None => from_bigint(BigInt::from(a) $op BigInt::from(b)),
+                },
+                Pair::BigInts(a, b) => from_bigint(a $op b),
+                Pair::Ratios(a, b) => from_ratio(a $op b),
+                Pair::Floats(a, b) => Float(a $op b),
+            })
+        }
+    };
+}
+
+arithmetic!(add, +, checked_add);
+arithmetic!(sub, -, checked_sub);
+arithmetic!(mul, *, checked_mul);
+
+// Whether `n` is an exact zero. Big integers and ratios are normalized, so
+// only an `Int` can be, even when the other operand is big.
+fn is_zero(n: &MalVal) -> bool {
+    matches!(n, Int(0))
+}
+
+pub fn div(a: &MalVal, b: &MalVal) -> MalRet {
+    match pair(a, b)? {
+        Pair::Floats(a, b) => Ok(Float(a / b)),
+        _ if is_zero(b) => error("divide by zero"),
+        Pair::Ints(a, b) if a.checked_rem(b) == Some(0) && a.checked_div(b).is_some() => {
+            Ok(Int(a / b))
+        }
+        _ => Ok(from_ratio(to_ratio(a) / to_ratio(b))),
+    }
+}
+
+pub fn negate(a: &MalVal) -> MalRet {
+    match a {
+        Int(i) => Ok(match i.checked_neg() {
+            Some(i) => Int(i),
+            None => from_bigint(-BigInt::from(*i)),
+        }),
+        Big(i) => Ok(from_bigint(-(**i).clone())),
+        Ratio(r) => Ok(Ratio(Rc::new(-(**r).clone()))),
+        Float(f) => Ok(Float(-f)),
+        _ => error("expecting (number) arg"),
+    }
+}
+
+// Divides, rounding towards zero.
+pub fn quot(a: &MalVal, b: &MalVal) -> MalRet {
+    match pair(a, b)? {
+        Pair::Floats(a, b) => Ok(Float((a / b).trunc())),
+        _ if is_zero(b) => error("divide by zero"),
+        Pair::Ints(a, b) => Ok(match a.checked_div(b) {
+            Some(i) => Int(i),
+            None => from_bigint(BigInt::from(a) / BigInt::from(b)),
+        }),
+        Pair::BigInts(a, b) => Ok(from_bigint(a / b)),
+        Pair::Ratios(a, b) => Ok(from_ratio((a / b).trunc())),
+    }
+}
+
+// The remainder of `quot`, which has the sign of the dividend.
+pub fn rem(a: &MalVal, b: &MalVal) -> MalRet {
+    match pair(a, b)? {
+        Pair::Floats(a, b) => Ok(Float(a % b)),
+        _ if is_zero(b) => error("divide by zero"),
+        // only `i64::MIN % -1` overflows
+        Pair::Ints(a, b) => Ok(Int(a.checked_rem(b).unwrap_or(0))),
+        Pair::BigInts(a, b) => Ok(from_bigint(a % b)),
+        Pair::Ratios(a, b) => Ok(from_ratio(a % b)),
+    }
+}
+
+// The remainder of dividing rounding towards negative infinity, which has the
+// sign of the divisor.
+pub fn modulo(a: &MalVal, b: &MalVal) -> MalRet {
+    let r = rem(a, b)?;
+    let zero = Int(0);
+    if compare(&r, &zero)? != Some(Ordering::Equal)
+        && (compare(&r, &zero)? == Some(Ordering::Less))
+            != (compare(b, &zero)? == Some(Ordering::Less))
+    {
+        add(&r, b)
+    } else {
+        Ok(r)
+    }
+}
+
+pub fn abs(a: &MalVal) -> MalRet {
+    if compare(a, &Int(0))? == Some(Ordering::Less) {
+        negate(a)
+    } else {
+        Ok(a.clone())
+    }
+}
+
+fn integer(n: &MalVal) -> Result<BigInt, MalErr> {
+    match n {
+        Int(_) | Big(_) => Ok(to_bigint(n)),
+        _ => Err(MalErr::String("expecting integer args".to_string())),
+    }
+}
+
+macro_rules! bitwise {
+    ($name:ident, $op:tt) => {
+        pub fn $name(a: &MalVal, b: &MalVal) -> MalRet {
+            Ok(match (a, b) {
+                (Int(a), Int(b)) => Int(a $op b),
+                _ => from_bigint(integer(a)? $op integer(b)?),
+            })
+        }
+    };
+}
+
+bitwise!(bit_and, &);
+bitwise!(bit_or, |);
+bitwise!(bit_xor, ^);
+
+pub fn bit_not(a: &MalVal) -> MalRet {
+    Ok(match a {
+        Int(i) => Int(!i),
+        _ => from_bigint(!integer(a)?),
+    })
+}
+
+// Left shifts by more than this many bits are rejected, rather than left to
+// exhaust memory building the result.
+const MAX_SHIFT: usize = 1 << 24;
+
+// The shift amount in bits. Big integers never fit in an i64, so a positive
+// one is more than any shift can use.
+fn shift(bits: &MalVal) -> Result<usize, MalErr> {
+    match bits {
+        Int(b) if *b >= 0 => Ok(*b as usize),
+        Big(b) if b.sign() == Sign::Plus => Ok(usize::MAX),
+        Int(_) | Big(_) => Err(MalErr::String("expecting a non-negative shift".to_string())),
+        _ => Err(MalErr::String("expecting an integer shift".to_string())),
+    }
+}
+
+// Shifts promote to a BigInt rather than overflowing, like multiplication.
+pub fn shift_left(a: &MalVal, bits: &MalVal) -> MalRet {
+    let bits = shift(bits)?;
+    Ok(match a {
+        Int(0) => Int(0),
+        Int(i) if bits < 63 && i.unsigned_abs() < 1 << (63 - bits) => Int(i << bits),
+        _ => {
+            let a = integer(a)?;
+            if bits > MAX_SHIFT {
+                return error("shift too large");
+            }
+            from_bigint(a << bits)
+        }
+    })
+}
+
+pub fn shift_right(a: &MalVal, bits: &MalVal) -> MalRet {
+    let bits = shift(bits)?;
+    Ok(match a {
+        Int(i) => Int(i >> bits.min(63)),
+        _ => from_bigint(integer(a)? >> bits),
+    })
+}
+
+// Compares by value, so unlike `=`, a float can equal an exact number.
+// Comparisons with NaN give None.
+pub fn compare(a: &MalVal, b: &MalVal) -> Result<Option<Ordering>, MalErr> {
+    Ok(match pair(a, b)? {
+        Pair::Ints(a, b) => Some(a.cmp(&b)),
+        Pair::BigInts(a, b) => Some(a.cmp(&b)),
+        Pair::Ratios(a, b) => Some(a.cmp(&b)),
+        Pair::Floats(a, b) => a.partial_cmp(&b),
+    })
+}
+
+// Floats always print with a decimal point or exponent, so they read back
+// as floats.
+pub fn format_float(f: f64) -> String {
+    if f.is_nan() {
+        "##NaN".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 { "##Inf" } else { "##-Inf" }.to_string()
+    } else {
+        format!("{:?}", f)
+    }
+}
+
+// Parses unsigned digits in `radix`.
+pub fn parse_int(digits: &str, radix: u32) -> Option<BigInt> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    BigInt::from_str_radix(digits, radix).ok()
+}
+
+// Parses `12`, `0xff`, `12N`, `1/3`, `1.5` or `1e10`, optionally signed.
+pub fn parse(token: &str) -> Option<MalVal> {
+    match token {
+        "##Inf" => return Some(Float(f64::INFINITY)),
+        "##-Inf" => return Some(Float(f64::NEG_INFINITY)),
+        "##NaN" => return Some(Float(f64::NAN)),
+        _ => {}
+    }
+    let (negative, digits) = match token.chars().next()? {
+        '-' => (true, &token[1..]),
+        '+' => (false, &token[1..]),
+        _ => (false, token),
+    };
+    if !digits.chars().next()?.is_ascii_digit() {
+        return None;
+    }
+    let n = if digits.starts_with("0x") || digits.starts_with("0X") {
+        from_bigint(parse_int(&digits[2..], 16)?)
+    } else if let Some(digits) = digits.strip_suffix('N') {
+        from_bigint(parse_int(digits, 10)?)
+    } else if let Some(idx) = digits.find('/') {
+        let numer = parse_int(&digits[..idx], 10)?;
+        let denom = parse_int(&digits[idx + 1..], 10)?;
+        if denom.is_zero() {
+            return None;
+        }
+        from_ratio(BigRational::new(numer, denom))
+    } else if let Ok(i) = digits.parse::<i64>() {
+        Int(i)
+    } else if let Some(i) = parse_int(digits, 10) {
+        from_bigint(i)
+    } else {
+        Float(digits.parse().ok()?)
+    };
+    if negative {
+        negate(&n).ok()
+    } else {
+        Some(n)
+    }
+}
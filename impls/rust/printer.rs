@@ -1,5 +1,10 @@
+use crate::number::format_float;
 use crate::types::MalVal;
-use crate::types::MalVal::{Atom, Bool, Func, Hash, Int, List, MalFunc, Nil, Str, Sym, Vector};
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Char, Compiled, Float, Func, Hash, Int, Keyword, LazySeq, List, MalFunc,
+    Nil, Ratio, Reduced, Regex, Set, Str, Sym, Vector,
+};
+use crate::types::CHAR_NAMES;
 
 fn escape_str(s: &str) -> String {
     s.chars()
@@ -13,6 +18,21 @@ fn escape_str(s: &str) -> String {
         .join("")
 }
 
+// A regex literal only needs its quotes escaped, as the reader leaves any
+// other escapes to the regex.
+fn escape_regex(s: &str) -> String {
+    let mut res = String::new();
+    let mut escaped = false;
+    for c in s.chars() {
+        if c == '"' && !escaped {
+            res.push('\\');
+        }
+        escaped = !escaped && c == '\\';
+        res.push(c);
+    }
+    res
+}
+
 impl MalVal {
     pub fn pr_str(&self, print_readably: bool) -> String {
         match self {
@@ -20,42 +40,50 @@ impl MalVal {
             Bool(true) => String::from("true"),
             Bool(false) => String::from("false"),
             Int(i) => format!("{}", i),
-            //Float(f)    => format!("{}", f),
-            Str(s) => {
-                if s.starts_with("\u{29e}") {
-                    format!(":{}", &s[2..])
-                } else if print_readably {
-                    format!("\"{}\"", escape_str(s))
-                } else {
-                    s.clone()
-                }
-            }
-            Sym(s) => s.clone(),
-            List(l, _) => pr_seq(&**l, print_readably, "(", ")", " "),
-            Vector(l, _) => pr_seq(&**l, print_readably, "[", "]", " "),
+            Float(f) => format_float(*f),
+            BigInt(i) => format!("{}", i),
+            Ratio(r) => format!("{}", r),
+            Str(s) if print_readably => format!("\"{}\"", escape_str(s)),
+            Str(s) => s.clone(),
+            Char(c) if print_readably => match CHAR_NAMES.iter().find(|(_, named)| named == c) {
+                Some((name, _)) => format!("\\{}", name),
+                None => format!("\\{}", c),
+            },
+            Char(c) => c.to_string(),
+            Sym(s) => s.to_string(),
+            Keyword(k) => format!(":{}", k),
+            Regex(re) if print_readably => format!("#\"{}\"", escape_regex(re.as_str())),
+            Regex(re) => re.as_str().to_string(),
+            List(l, _) => pr_seq(l, print_readably, "(", ")", " "),
+            Vector(l, _) => pr_seq(l, print_readably, "[", "]", " "),
             Hash(hm, _) => {
-                let l: Vec<MalVal> = hm
-                    .iter()
-                    .flat_map(|(k, v)| vec![Str(k.to_string()), v.clone()])
-                    .collect();
-                pr_seq(&l, print_readably, "{", "}", " ")
+                let l: Vec<&MalVal> = hm.iter().flat_map(|(k, v)| vec![k, v]).collect();
+                pr_seq(l, print_readably, "{", "}", " ")
+            }
+            Set(s, _) => pr_seq(s, print_readably, "#{", "}", " "),
+            // anything that failed to realize is left out, see `realize`
+            LazySeq(_) => {
+                let items: Vec<MalVal> = self.items().filter_map(Result::ok).collect();
+                pr_seq(&items, print_readably, "(", ")", " ")
             }
-            Func(f, _) => format!("#<fn {:?}>", f),
+            Func(b, _) => format!("#<fn {}>", b.name),
             MalFunc {
                 ast: a, params: p, ..
             } => format!("(fn* {} {})", p.pr_str(true), a.pr_str(true)),
+            Compiled { f, .. } => f.source().pr_str(true),
             Atom(a) => format!("(atom {})", a.borrow().pr_str(true)),
+            Reduced(v) => format!("#<reduced {}>", v.pr_str(true)),
         }
     }
 }
 
-pub fn pr_seq(
-    seq: &Vec<MalVal>,
+pub fn pr_seq<'a>(
+    seq: impl IntoIterator<Item = &'a MalVal>,
     print_readably: bool,
     start: &str,
     end: &str,
     join: &str,
 ) -> String {
-    let strs: Vec<String> = seq.iter().map(|x| x.pr_str(print_readably)).collect();
+    let strs: Vec<String> = seq.into_iter().map(|x| x.pr_str(print_readably)).collect();
     format!("{}{}{}", start, strs.join(join), end)
 }
@@ -0,0 +1,130 @@
+// The `re-*` builtins. A `Regex` value holds an `Rc` of a compiled
+// `regex::Regex`, which `compile` shares between the reader's `#"..."`
+// literals and `re-pattern` calls with the same source.
+
+use regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::types::Arity::Exactly;
+use crate::types::MalVal::{Compiled, Func, List, MalFunc, Nil, Regex, Str, Vector};
+use crate::types::{func, Arity, BuiltinFn, MalArgs, MalErr, MalRet, MalVal};
+
+// How many patterns `compile` keeps. It drops them all once it's full, so a
+// program that builds patterns in a loop can't grow the cache forever.
+const CACHE_SIZE: usize = 256;
+
+thread_local! {
+    static CACHE: RefCell<HashMap<String, Rc<regex::Regex>>> = RefCell::new(HashMap::new());
+}
+
+pub fn compile(pattern: &str) -> Result<Rc<regex::Regex>, MalErr> {
+    CACHE.with(|cache| {
+        if let Some(re) = cache.borrow().get(pattern) {
+            return Ok(re.clone());
+        }
+        let re = match regex::Regex::new(pattern) {
+            Ok(re) => Rc::new(re),
+            Err(e) => return Err(MalErr::String(format!("invalid regex: {}", e))),
+        };
+        let mut cache = cache.borrow_mut();
+        if cache.len() >= CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(pattern.to_string(), re.clone());
+        Ok(re)
+    })
+}
+
+fn regex<'a>(name: &str, v: &'a MalVal) -> Result<&'a Rc<regex::Regex>, MalErr> {
+    match v {
+        Regex(re) => Ok(re),
+        _ => Err(MalErr::String(format!("{}: expecting a regex", name))),
+    }
+}
+
+fn string<'a>(name: &str, v: &'a MalVal) -> Result<&'a str, MalErr> {
+    match v {
+        Str(s) => Ok(s),
+        _ => Err(MalErr::String(format!("{}: expecting a string", name))),
+    }
+}
+
+// A `Str` of the match when the regex has no groups. Otherwise a `Vector` of
+// the match and then each group, with `Nil` for a group that didn't match.
+fn groups(caps: &regex::Captures) -> MalVal {
+    if caps.len() == 1 {
+        return Str(caps[0].to_string());
+    }
+    vector!(caps
+        .iter()
+        .map(|group| group.map_or(Nil, |m| Str(m.as_str().to_string())))
+        .collect::<Vec<MalVal>>())
+}
+
+fn re_find(a: MalArgs) -> MalRet {
+    let re = regex("re-find", &a[0])?;
+    let s = string("re-find", &a[1])?;
+    Ok(re.captures(s).map_or(Nil, |caps| groups(&caps)))
+}
+
+// `re-find` anchored at both ends, by wrapping the pattern in `\A(?:...)\z`.
+fn re_matches(a: MalArgs) -> MalRet {
+    let re = regex("re-matches", &a[0])?;
+    let s = string("re-matches", &a[1])?;
+    let whole = compile(&format!(r"\A(?:{})\z", re.as_str()))?;
+    Ok(whole.captures(s).map_or(Nil, |caps| groups(&caps)))
+}
+
+fn re_seq(a: MalArgs) -> MalRet {
+    let re = regex("re-seq", &a[0])?;
+    let s = string("re-seq", &a[1])?;
+    Ok(list!(re
+        .captures_iter(s)
+        .map(|caps| groups(&caps))
+        .collect::<Vec<MalVal>>()))
+}
+
+// `(re-replace s re replacement)`. A `Str` replacement can refer to groups as
+// `$1` or `${name}`. A function is called with each match in the form that
+// `groups` gives it, and has to return a `Str`.
+fn re_replace(a: MalArgs) -> MalRet {
+    let s = string("re-replace", &a[0])?;
+    let re = regex("re-replace", &a[1])?;
+    match &a[2] {
+        Str(rep) => Ok(Str(re.replace_all(s, rep.as_str()).to_string())),
+        f @ Func(_, _) | f @ MalFunc { .. } | f @ Compiled { .. } => {
+            let mut res = String::new();
+            let mut last = 0;
+            for caps in re.captures_iter(s) {
+                let m = caps.get(0).unwrap();
+                res.push_str(&s[last..m.start()]);
+                res.push_str(string("re-replace", &f.apply(vec![groups(&caps)])?)?);
+                last = m.end();
+            }
+            res.push_str(&s[last..]);
+            Ok(Str(res))
+        }
+        _ => Err(MalErr::String(
+            "re-replace: expecting a string or function replacement".to_string(),
+        )),
+    }
+}
+
+pub fn ns() -> Vec<(&'static str, MalVal)> {
+    let builtins: Vec<(&'static str, Arity, BuiltinFn)> = vec![
+        ("re-pattern", Exactly(1), |a| match &a[0] {
+            Regex(_) => Ok(a[0].clone()),
+            v => Ok(Regex(compile(string("re-pattern", v)?)?)),
+        }),
+        ("re-find", Exactly(2), re_find),
+        ("re-matches", Exactly(2), re_matches),
+        ("re-seq", Exactly(2), re_seq),
+        ("re-replace", Exactly(3), re_replace),
+    ];
+    builtins
+        .into_iter()
+        .map(|(name, arity, f)| (name, func(name, arity, f)))
+        .collect()
+}
@@ -1,156 +1,483 @@
-use regex::{Captures, Regex};
+// The reader's lexer scans the input by hand, a token at a time as the
+// reader asks for them. A token borrows its text from the input, and knows
+// where it starts, so that errors can say where they are.
+
+use std::io::{BufRead, BufReader, Read};
 use std::rc::Rc;
 
-use crate::types::MalErr::ErrString;
-use crate::types::MalVal::{Bool, Int, List, Nil, Str, Sym, Vector};
-use crate::types::{error, hash_map, MalErr, MalRet, MalVal};
+use crate::number::parse as parse_number;
+use crate::re;
+use crate::symbol;
+use crate::types::MalVal::{Bool, Char, List, Nil, Str, Sym, Vector};
+use crate::types::{
+    error, hash_map, hash_set, keyword, symbol, MalArgs, MalErr, MalRet, MalVal, CHAR_NAMES,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> Token<'a> {
+    // An error at this token.
+    fn error(&self, msg: &str) -> MalErr {
+        MalErr::String(format!("{}:{}: {}", self.line, self.col, msg))
+    }
+}
 
 #[derive(Debug, Clone)]
-struct Reader {
-    tokens: Vec<String>,
+struct Reader<'a> {
+    input: &'a str,
     pos: usize,
+    // The line and column of `pos`.
+    line: usize,
+    col: usize,
+    peeked: Option<Token<'a>>,
+    // Whether the lexer has run into the end of the input, either between
+    // tokens or in the middle of a string.
+    exhausted: bool,
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "[]{}()'\"`,;".contains(c)
 }
 
-impl Reader {
-    fn next(&mut self) -> Result<String, MalErr> {
-        self.pos = self.pos + 1;
-        Ok(self
-            .tokens
-            .get(self.pos - 1)
-            .ok_or(ErrString("underflow".to_string()))?
-            .to_string())
+impl<'a> Reader<'a> {
+    fn new(input: &'a str) -> Reader<'a> {
+        Reader::resume(input, 1, 1)
+    }
+
+    // A reader of `input`, which starts at `line` and `col` of its source.
+    fn resume(input: &'a str, line: usize, col: usize) -> Reader<'a> {
+        Reader {
+            input,
+            pos: 0,
+            line,
+            col,
+            peeked: None,
+            exhausted: false,
+        }
     }
-    fn peek(&self) -> Result<String, MalErr> {
-        Ok(self
-            .tokens
-            .get(self.pos)
-            .ok_or(ErrString("underflow".to_string()))?
-            .to_string())
+
+    // The next token, or None at the end of the input.
+    fn next(&mut self) -> Result<Option<Token<'a>>, MalErr> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => self.lex(),
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<Token<'a>>, MalErr> {
+        if self.peeked.is_none() {
+            self.peeked = self.lex()?;
+        }
+        Ok(self.peeked)
+    }
+
+    // Moves `len` bytes on, keeping track of the line and column.
+    fn advance(&mut self, len: usize) {
+        for c in self.input[self.pos..self.pos + len].chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        self.pos += len;
+    }
+
+    // Scans the next token, skipping whitespace, commas and comments.
+    fn lex(&mut self) -> Result<Option<Token<'a>>, MalErr> {
+        let rest = &self.input[self.pos..];
+        let mut chars = rest.char_indices().peekable();
+        let start = loop {
+            match chars.next() {
+                None => {
+                    self.advance(rest.len());
+                    self.exhausted = true;
+                    return Ok(None);
+                }
+                Some((_, ',')) => {}
+                Some((_, ';')) => {
+                    while chars.peek().is_some_and(|&(_, c)| c != '\n') {
+                        chars.next();
+                    }
+                }
+                Some((_, c)) if c.is_whitespace() => {}
+                Some((start, _)) => break start,
+            }
+        };
+        self.advance(start);
+        let rest = &self.input[self.pos..];
+        let mut token = Token {
+            text: rest,
+            line: self.line,
+            col: self.col,
+        };
+        let len = match rest.chars().next().unwrap() {
+            '~' if rest[1..].starts_with('@') => 2,
+            '#' if rest[1..].starts_with('{') => 2,
+            '[' | ']' | '{' | '}' | '(' | ')' | '\'' | '`' | '~' | '^' | '@' => 1,
+            '#' if rest[1..].starts_with('"') => match string_len(&token, &rest[1..], false)? {
+                Some(len) => 1 + len,
+                None => {
+                    self.exhausted = true;
+                    return Err(token.error("expected '\"', got EOF"));
+                }
+            },
+            '"' => match string_len(&token, rest, true)? {
+                Some(len) => len,
+                None => {
+                    self.exhausted = true;
+                    return Err(token.error("expected '\"', got EOF"));
+                }
+            },
+            // the char after the backslash is part of the token even if it's
+            // a delimiter, so that `\(` is a char
+            '\\' => match rest[1..].chars().next() {
+                Some(c) => {
+                    let after = 1 + c.len_utf8();
+                    after
+                        + rest[after..]
+                            .find(is_delimiter)
+                            .unwrap_or(rest.len() - after)
+                }
+                None => 1,
+            },
+            _ => rest.find(is_delimiter).unwrap_or(rest.len()),
+        };
+        token.text = &rest[..len];
+        self.advance(len);
+        Ok(Some(token))
     }
 }
 
-fn tokenize(str: &str) -> Vec<String> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(
-            r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###
-        )
-        .unwrap();
+// The length of the string literal at the start of `s`, up to and including
+// its closing quote, or None if it isn't closed. Escapes are checked if
+// `escapes` is set, and otherwise left for whatever the literal is for, like
+// a regex, to check.
+fn string_len(token: &Token, s: &str, escapes: bool) -> Result<Option<usize>, MalErr> {
+    let mut chars = s.char_indices().skip(1);
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Ok(Some(idx + 1)),
+            '\\' => match chars.next() {
+                Some((_, 'n')) | Some((_, 't')) | Some((_, 'r')) | Some((_, '0'))
+                | Some((_, '\\')) | Some((_, '"')) => {}
+                Some((_, 'u')) if escapes => {
+                    let escape = &s[idx + 1..];
+                    let len = match unicode_escape(escape) {
+                        Some((_, len)) => len,
+                        None => {
+                            let escape: String =
+                                escape.chars().take_while(|&c| c != '"').take(6).collect();
+                            let msg = format!("invalid escape sequence: \\{}", escape);
+                            return Err(token.error(&msg));
+                        }
+                    };
+                    for _ in 1..len {
+                        chars.next();
+                    }
+                }
+                Some((_, c)) if escapes => {
+                    let msg = format!("invalid escape sequence: \\{}", c);
+                    return Err(token.error(&msg));
+                }
+                Some(_) => {}
+                None => break,
+            },
+            _ => {}
+        }
     }
+    Ok(None)
+}
 
-    let mut res = vec![];
-    for cap in RE.captures_iter(str) {
-        if cap[1].starts_with(";") {
-            continue;
+// The char that the `u` escape at the start of `s` stands for, and how long
+// it is. It's either `u` and 4 hex digits, or `u{...}` with 1 to 6 of them.
+fn unicode_escape(s: &str) -> Option<(char, usize)> {
+    let (hex, len) = match s[1..].strip_prefix('{') {
+        Some(braced) => {
+            let hex = &braced[..braced.find('}')?];
+            (hex, hex.len() + 3)
         }
-        res.push(String::from(&cap[1]));
+        None => (s.get(1..5)?, 5),
+    };
+    if hex.is_empty() || hex.len() > 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
     }
-    res
+    let c = std::char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+    Some((c, len))
 }
 
+// The contents of a string literal, which the lexer has checked the escapes
+// of.
 fn unescape_str(s: &str) -> String {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r#"\\(.)"#).unwrap();
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(idx) = rest.find('\\') {
+        res.push_str(&rest[..idx]);
+        let escape = &rest[idx + 1..];
+        let (c, len) = match escape.chars().next() {
+            Some('n') => ('\n', 1),
+            Some('t') => ('\t', 1),
+            Some('r') => ('\r', 1),
+            Some('0') => ('\0', 1),
+            Some('u') => unicode_escape(escape).unwrap_or(('\u{fffd}', 1)),
+            Some(c) => (c, c.len_utf8()),
+            None => ('\\', 0),
+        };
+        res.push(c);
+        rest = &escape[len..];
+    }
+    res.push_str(rest);
+    res
+}
+
+// Only `\"` is unescaped in a regex literal. Any other escape is left for the
+// regex to interpret.
+fn unescape_regex(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('"') => res.push('"'),
+                Some(c) => {
+                    res.push('\\');
+                    res.push(c);
+                }
+                None => res.push('\\'),
+            },
+            _ => res.push(c),
+        }
     }
-    RE.replace_all(&s, |caps: &Captures| {
-        format!("{}", if &caps[1] == "n" { "\n" } else { &caps[1] })
-    })
-    .to_string()
+    res
 }
 
-fn read_atom(rdr: &mut Reader) -> MalRet {
-    lazy_static! {
-        static ref INT_RE: Regex = Regex::new(r"^-?[0-9]+$").unwrap();
-        static ref STR_RE: Regex = Regex::new(r#""(?:\\.|[^\\"])*""#).unwrap();
+// `\a`, `\newline` or `\u00e9`.
+fn read_char(token: &Token) -> MalRet {
+    let name = &token.text[1..];
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(Char(c));
+    }
+    if let Some(&(_, c)) = CHAR_NAMES.iter().find(|(named, _)| *named == name) {
+        return Ok(Char(c));
     }
-    let token = rdr.next()?;
-    match &token[..] {
+    let code = match name.strip_prefix('u') {
+        Some(hex) if hex.len() == 4 => u32::from_str_radix(hex, 16).ok(),
+        _ => None,
+    };
+    match code.and_then(std::char::from_u32) {
+        Some(c) => Ok(Char(c)),
+        None => Err(token.error(&format!("invalid char: {}", token.text))),
+    }
+}
+
+fn read_atom(token: &Token) -> MalRet {
+    let text = token.text;
+    match text {
         "nil" => Ok(Nil),
         "false" => Ok(Bool(false)),
         "true" => Ok(Bool(true)),
         _ => {
-            if INT_RE.is_match(&token) {
-                Ok(Int(token.parse().unwrap()))
-            } else if STR_RE.is_match(&token) {
-                Ok(Str(unescape_str(&token[1..token.len() - 1])))
-            } else if token.starts_with("\"") {
-                error("expected '\"', got EOF")
-            } else if token.starts_with(":") {
-                Ok(Str(format!("\u{29e}{}", &token[1..])))
+            let mut chars = text.chars();
+            let is_number = match chars.next() {
+                Some('0'..='9') => true,
+                Some('+') | Some('-') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+                _ => text.starts_with("##"),
+            };
+            if is_number {
+                match parse_number(text) {
+                    Some(n) => Ok(n),
+                    None => Err(token.error(&format!("invalid number: {}", text))),
+                }
+            } else if text.starts_with("#\"") {
+                let pattern = unescape_regex(&text[2..text.len() - 1]);
+                Ok(MalVal::Regex(re::compile(&pattern)?))
+            } else if text.starts_with('"') {
+                Ok(Str(unescape_str(&text[1..text.len() - 1])))
+            } else if text.starts_with('\\') {
+                read_char(token)
+            } else if let Some(name) = text.strip_prefix(':') {
+                Ok(keyword(name))
             } else {
-                Ok(Sym(token.to_string()))
+                Ok(symbol(text))
             }
         }
     }
 }
 
-fn read_seq(rdr: &mut Reader, end: &str) -> MalRet {
+fn read_seq(rdr: &mut Reader, end: &str) -> Result<MalArgs, MalErr> {
     let mut seq: Vec<MalVal> = vec![];
-    rdr.next()?;
+    let open = rdr.next()?.unwrap();
     loop {
-        let token = match rdr.peek() {
-            Ok(t) => t,
-            Err(_) => return error(&format!("expected '{}', got EOF", end)),
-        };
-        if token == end {
-            break;
+        match rdr.peek()? {
+            None => return Err(open.error(&format!("expected '{}', got EOF", end))),
+            Some(token) if token.text == end => break,
+            Some(_) => seq.push(read_form(rdr)?),
         }
-        seq.push(read_form(rdr)?)
-    }
-    let _ = rdr.next();
-    match end {
-        ")" => Ok(list!(seq)),
-        "]" => Ok(vector!(seq)),
-        "}" => hash_map(seq),
-        _ => error("read_seq unknown end value"),
     }
+    rdr.next()?;
+    Ok(seq)
 }
 
 fn read_form(rdr: &mut Reader) -> MalRet {
-    let token = rdr.peek()?;
-    match &token[..] {
+    let token = match rdr.peek()? {
+        Some(token) => token,
+        None => return error("expected a form, got EOF"),
+    };
+    match token.text {
         "'" => {
-            let _ = rdr.next();
-            Ok(list![Sym("quote".to_string()), read_form(rdr)?])
+            rdr.next()?;
+            Ok(list![Sym(symbol::QUOTE), read_form(rdr)?])
         }
         "`" => {
-            let _ = rdr.next();
-            Ok(list![Sym("quasiquote".to_string()), read_form(rdr)?])
+            rdr.next()?;
+            Ok(list![Sym(symbol::QUASIQUOTE), read_form(rdr)?])
         }
         "~" => {
-            let _ = rdr.next();
-            Ok(list![Sym("unquote".to_string()), read_form(rdr)?])
+            rdr.next()?;
+            Ok(list![Sym(symbol::UNQUOTE), read_form(rdr)?])
         }
         "~@" => {
-            let _ = rdr.next();
-            Ok(list![Sym("splice-unquote".to_string()), read_form(rdr)?])
+            rdr.next()?;
+            Ok(list![Sym(symbol::SPLICE_UNQUOTE), read_form(rdr)?])
         }
         "^" => {
-            let _ = rdr.next();
+            rdr.next()?;
             let meta = read_form(rdr)?;
-            Ok(list![Sym("with-meta".to_string()), read_form(rdr)?, meta])
+            Ok(list![Sym(symbol::WITH_META), read_form(rdr)?, meta])
         }
         "@" => {
-            let _ = rdr.next();
-            Ok(list![Sym("deref".to_string()), read_form(rdr)?])
+            rdr.next()?;
+            Ok(list![Sym(symbol::DEREF), read_form(rdr)?])
+        }
+        ")" | "]" | "}" => Err(token.error(&format!("unexpected '{}'", token.text))),
+        "(" => Ok(list!(read_seq(rdr, ")")?)),
+        "[" => Ok(vector!(read_seq(rdr, "]")?)),
+        "{" => hash_map(read_seq(rdr, "}")?),
+        "#{" => Ok(hash_set(read_seq(rdr, "}")?)),
+        _ => {
+            rdr.next()?;
+            read_atom(&token)
         }
-        ")" => error("unexpected ')'"),
-        "(" => read_seq(rdr, ")"),
-        "]" => error("unexpected ']'"),
-        "[" => read_seq(rdr, "]"),
-        "}" => error("unexpected '}'"),
-        "{" => read_seq(rdr, "}"),
-        _ => read_atom(rdr),
     }
 }
 
 pub fn read_str(str: String) -> MalRet {
-    let tokens = tokenize(&str);
-    //println!("tokens: {:?}", tokens);
-    if tokens.len() == 0 {
+    let mut rdr = Reader::new(&str);
+    if rdr.peek()?.is_none() {
         return error("no input");
     }
-    read_form(&mut Reader {
-        pos: 0,
-        tokens: tokens,
-    })
+    read_form(&mut rdr)
+}
+
+// only used by core, which the early steps don't include
+#[allow(dead_code)]
+pub fn read_all(str: String) -> MalRet {
+    let mut rdr = Reader::new(&str);
+    let mut forms = vec![];
+    while rdr.peek()?.is_some() {
+        forms.push(read_form(&mut rdr)?);
+    }
+    Ok(list!(forms))
+}
+
+// Reads forms one at a time from `source`, reading more of it only when the
+// text that's been read so far runs out in the middle of a form. Reading
+// stops at the first error.
+#[allow(dead_code)]
+pub struct Forms<R> {
+    source: BufReader<R>,
+    // Source text that hasn't been read as forms yet.
+    buffer: String,
+    // The line and column in the source of the start of `buffer`.
+    line: usize,
+    col: usize,
+    eof: bool,
+    failed: bool,
+}
+
+// only used by core, which the early steps don't include
+#[allow(dead_code)]
+impl<R: Read> Forms<R> {
+    pub fn new(source: R) -> Forms<R> {
+        Forms {
+            source: BufReader::new(source),
+            buffer: String::new(),
+            line: 1,
+            col: 1,
+            eof: false,
+            failed: false,
+        }
+    }
+
+    fn read_form(&mut self) -> Result<Option<MalVal>, MalErr> {
+        loop {
+            let mut rdr = Reader::resume(&self.buffer, self.line, self.col);
+            let res = match rdr.peek() {
+                Ok(Some(_)) => read_form(&mut rdr).map(Some),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            };
+            let incomplete = rdr.exhausted && !self.eof;
+            let (pos, line, col) = (rdr.pos, rdr.line, rdr.col);
+            match res {
+                Ok(Some(form)) => {
+                    self.buffer.drain(..pos);
+                    self.line = line;
+                    self.col = col;
+                    return Ok(Some(form));
+                }
+                Ok(None) if incomplete => {
+                    // nothing but whitespace and comments
+                    self.buffer.clear();
+                    self.line = line;
+                    self.col = col;
+                    self.fill()?;
+                }
+                Err(_) if incomplete => self.fill()?,
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Reads whole lines from the source until the buffer has doubled in
+    // size, so that a long form is only read again a few times.
+    fn fill(&mut self) -> Result<(), MalErr> {
+        let target = self.buffer.len() * 2;
+        loop {
+            match self.source.read_line(&mut self.buffer) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(());
+                }
+                Ok(_) if self.buffer.len() >= target => return Ok(()),
+                Ok(_) => {}
+                Err(e) => return Err(MalErr::String(e.to_string())),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Forms<R> {
+    type Item = MalRet;
+
+    fn next(&mut self) -> Option<MalRet> {
+        if self.failed {
+            return None;
+        }
+        let res = self.read_form();
+        self.failed = res.is_err();
+        match res {
+            Ok(Some(form)) => Some(Ok(form)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
 }
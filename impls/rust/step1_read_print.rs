@@ -1,7 +1,9 @@
-#[macro_use]
 extern crate lazy_static;
 extern crate fnv;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
@@ -12,8 +14,19 @@ use rustyline::Editor;
 #[allow(dead_code)]
 mod types;
 use crate::types::format_error;
+#[allow(dead_code)]
+mod lazy;
+#[allow(dead_code)]
+mod list;
+#[allow(dead_code)]
+mod map;
+#[allow(dead_code)]
+mod number;
 mod printer;
+#[allow(dead_code)]
+mod re;
 mod reader;
+mod symbol;
 // TODO: figure out a way to avoid including env
 #[allow(dead_code)]
 mod env;
@@ -2,10 +2,12 @@ use std::rc::Rc;
 //use std::collections::HashMap;
 use fnv::FnvHashMap;
 
-#[macro_use]
 extern crate lazy_static;
 extern crate fnv;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
@@ -15,16 +17,28 @@ use rustyline::Editor;
 #[macro_use]
 #[allow(dead_code)]
 mod types;
-use crate::types::MalErr::ErrString;
-use crate::types::MalVal::{Hash, Int, List, Nil, Sym, Vector};
-use crate::types::{error, format_error, func, MalArgs, MalErr, MalRet, MalVal};
+use crate::types::Arity::Exactly;
+use crate::types::MalVal::{Hash, List, Nil, Set, Sym, Vector};
+use crate::types::{error, format_error, func, MalArgs, MalErr, MalRet, MalSet, MalVal};
+#[allow(dead_code)]
+mod lazy;
+#[allow(dead_code)]
+mod list;
+#[allow(dead_code)]
+mod map;
+#[allow(dead_code)]
+mod number;
 mod printer;
+#[allow(dead_code)]
+mod re;
 mod reader;
+mod symbol;
+use crate::symbol::Symbol;
 // TODO: figure out a way to avoid including env
 #[allow(dead_code)]
 mod env;
 
-pub type Env = FnvHashMap<String, MalVal>;
+pub type Env = FnvHashMap<Symbol, MalVal>;
 
 // read
 fn read(str: &str) -> MalRet {
@@ -36,7 +50,7 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
     match ast {
         Sym(sym) => Ok(env
             .get(sym)
-            .ok_or(ErrString(format!("'{}' not found", sym)))?
+            .ok_or(MalErr::String(format!("'{}' not found", sym)))?
             .clone()),
         List(v, _) => {
             let mut lst: MalArgs = vec![];
@@ -53,11 +67,18 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
             Ok(vector!(lst))
         }
         Hash(hm, _) => {
-            let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+            let mut new_hm = hm.empty();
             for (k, v) in hm.iter() {
-                new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+                new_hm.insert(k.clone(), eval(v.clone(), env.clone())?)?;
+            }
+            Ok(Hash(new_hm, Rc::new(Nil)))
+        }
+        Set(s, _) => {
+            let mut new_set = MalSet::default();
+            for v in s.iter() {
+                new_set.insert(eval(v.clone(), env.clone())?);
             }
-            Ok(Hash(Rc::new(new_hm), Rc::new(Nil)))
+            Ok(Set(new_set, Rc::new(Nil)))
         }
         _ => Ok(ast.clone()),
     }
@@ -71,6 +92,7 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
             }
             match eval_ast(&ast, &env)? {
                 List(ref el, _) => {
+                    let el: MalArgs = el.iter().cloned().collect();
                     let ref f = el[0].clone();
                     f.apply(el[1..].to_vec())
                 }
@@ -92,11 +114,8 @@ fn rep(str: &str, env: &Env) -> Result<String, MalErr> {
     Ok(print(&exp))
 }
 
-fn int_op(op: fn(i64, i64) -> i64, a: MalArgs) -> MalRet {
-    match (a[0].clone(), a[1].clone()) {
-        (Int(a0), Int(a1)) => Ok(Int(op(a0, a1))),
-        _ => error("invalid int_op args"),
-    }
+fn num_op(op: fn(&MalVal, &MalVal) -> MalRet, a: MalArgs) -> MalRet {
+    op(&a[0], &a[1])
 }
 
 fn main() {
@@ -107,10 +126,22 @@ fn main() {
     }
 
     let mut repl_env = Env::default();
-    repl_env.insert("+".to_string(), func(|a: MalArgs| int_op(|i, j| i + j, a)));
-    repl_env.insert("-".to_string(), func(|a: MalArgs| int_op(|i, j| i - j, a)));
-    repl_env.insert("*".to_string(), func(|a: MalArgs| int_op(|i, j| i * j, a)));
-    repl_env.insert("/".to_string(), func(|a: MalArgs| int_op(|i, j| i / j, a)));
+    repl_env.insert(
+        Symbol::new("+"),
+        func("+", Exactly(2), |a: MalArgs| num_op(number::add, a)),
+    );
+    repl_env.insert(
+        Symbol::new("-"),
+        func("-", Exactly(2), |a: MalArgs| num_op(number::sub, a)),
+    );
+    repl_env.insert(
+        Symbol::new("*"),
+        func("*", Exactly(2), |a: MalArgs| num_op(number::mul, a)),
+    );
+    repl_env.insert(
+        Symbol::new("/"),
+        func("/", Exactly(2), |a: MalArgs| num_op(number::div, a)),
+    );
 
     loop {
         let readline = rl.readline("user> ");
@@ -1,12 +1,13 @@
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
-#[macro_use]
 extern crate lazy_static;
 extern crate fnv;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
@@ -16,12 +17,27 @@ use rustyline::Editor;
 #[macro_use]
 #[allow(dead_code)]
 mod types;
-use crate::types::MalVal::{Hash, Int, List, Nil, Sym, Vector};
-use crate::types::{error, format_error, func, MalArgs, MalErr, MalRet, MalVal};
+use crate::types::Arity::Exactly;
+use crate::types::MalVal::{Hash, List, Nil, Set, Sym, Vector};
+use crate::types::{
+    check_special_form, error, format_error, func, Arity, MalArgs, MalErr, MalRet, MalSet, MalVal,
+};
 mod env;
+#[allow(dead_code)]
+mod lazy;
+#[allow(dead_code)]
+mod list;
+#[allow(dead_code)]
+mod map;
+#[allow(dead_code)]
+mod number;
 mod printer;
+#[allow(dead_code)]
+mod re;
 mod reader;
+mod symbol;
 use crate::env::{env_get, env_new, env_set, env_sets, Env};
+use crate::symbol::Symbol;
 
 // read
 fn read(str: &str) -> MalRet {
@@ -47,33 +63,43 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
             Ok(vector!(lst))
         }
         Hash(hm, _) => {
-            let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+            let mut new_hm = hm.empty();
             for (k, v) in hm.iter() {
-                new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+                new_hm.insert(k.clone(), eval(v.clone(), env.clone())?)?;
             }
-            Ok(Hash(Rc::new(new_hm), Rc::new(Nil)))
+            Ok(Hash(new_hm, Rc::new(Nil)))
+        }
+        Set(s, _) => {
+            let mut new_set = MalSet::default();
+            for v in s.iter() {
+                new_set.insert(eval(v.clone(), env.clone())?);
+            }
+            Ok(Set(new_set, Rc::new(Nil)))
         }
         _ => Ok(ast.clone()),
     }
 }
 
+// The number of args each special form takes.
+const SPECIAL_FORMS: &[(Symbol, Arity)] = &[(symbol::DEF, Exactly(2)), (symbol::LET, Exactly(2))];
+
 fn eval(ast: MalVal, env: Env) -> MalRet {
     match ast.clone() {
         List(l, _) => {
             if l.len() == 0 {
                 return Ok(ast);
             }
+            check_special_form(&l, SPECIAL_FORMS)?;
+            let l: MalArgs = l.iter().cloned().collect();
             let a0 = &l[0];
             match a0 {
-                Sym(ref a0sym) if a0sym == "def!" => {
-                    env_set(&env, l[1].clone(), eval(l[2].clone(), env.clone())?)
-                }
-                Sym(ref a0sym) if a0sym == "let*" => {
+                Sym(symbol::DEF) => env_set(&env, l[1].clone(), eval(l[2].clone(), env.clone())?),
+                Sym(symbol::LET) => {
                     let let_env = env_new(Some(env.clone()));
                     let (a1, a2) = (l[1].clone(), l[2].clone());
-                    match a1 {
-                        List(ref binds, _) | Vector(ref binds, _) => {
-                            for (b, e) in binds.iter().tuples() {
+                    match a1.seq_iter() {
+                        Some(binds) => {
+                            for (b, e) in binds.tuples() {
                                 match b {
                                     Sym(_) => {
                                         let _ = env_set(
@@ -96,6 +122,7 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                 }
                 _ => match eval_ast(&ast, &env)? {
                     List(ref el, _) => {
+                        let el: MalArgs = el.iter().cloned().collect();
                         let ref f = el[0].clone();
                         f.apply(el[1..].to_vec())
                     }
@@ -118,11 +145,8 @@ fn rep(str: &str, env: &Env) -> Result<String, MalErr> {
     Ok(print(&exp))
 }
 
-fn int_op(op: fn(i64, i64) -> i64, a: MalArgs) -> MalRet {
-    match (a[0].clone(), a[1].clone()) {
-        (Int(a0), Int(a1)) => Ok(Int(op(a0, a1))),
-        _ => error("invalid int_op args"),
-    }
+fn num_op(op: fn(&MalVal, &MalVal) -> MalRet, a: MalArgs) -> MalRet {
+    op(&a[0], &a[1])
 }
 
 fn main() {
@@ -133,10 +157,26 @@ fn main() {
     }
 
     let repl_env = env_new(None);
-    env_sets(&repl_env, "+", func(|a: MalArgs| int_op(|i, j| i + j, a)));
-    env_sets(&repl_env, "-", func(|a: MalArgs| int_op(|i, j| i - j, a)));
-    env_sets(&repl_env, "*", func(|a: MalArgs| int_op(|i, j| i * j, a)));
-    env_sets(&repl_env, "/", func(|a: MalArgs| int_op(|i, j| i / j, a)));
+    env_sets(
+        &repl_env,
+        "+",
+        func("+", Exactly(2), |a: MalArgs| num_op(number::add, a)),
+    );
+    env_sets(
+        &repl_env,
+        "-",
+        func("-", Exactly(2), |a: MalArgs| num_op(number::sub, a)),
+    );
+    env_sets(
+        &repl_env,
+        "*",
+        func("*", Exactly(2), |a: MalArgs| num_op(number::mul, a)),
+    );
+    env_sets(
+        &repl_env,
+        "/",
+        func("/", Exactly(2), |a: MalArgs| num_op(number::div, a)),
+    );
 
     loop {
         let readline = rl.readline("user> ");
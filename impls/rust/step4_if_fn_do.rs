@@ -1,12 +1,14 @@
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
 extern crate lazy_static;
 extern crate fnv;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
@@ -15,12 +17,23 @@ use rustyline::Editor;
 
 #[macro_use]
 mod types;
-use crate::types::MalVal::{Bool, Hash, List, MalFunc, Nil, Sym, Vector};
-use crate::types::{error, format_error, MalArgs, MalErr, MalRet, MalVal};
+use crate::types::Arity::{AtLeast, Between, Exactly};
+use crate::types::MalVal::{Bool, Hash, List, MalFunc, Nil, Set, Sym, Vector};
+use crate::types::{
+    check_special_form, error, format_error, Arity, MalArgs, MalErr, MalRet, MalSet, MalVal,
+};
 mod env;
+mod lazy;
+mod list;
+mod map;
+mod number;
 mod printer;
+mod re;
 mod reader;
+mod string;
+mod symbol;
 use crate::env::{env_get, env_new, env_set, env_sets, Env};
+use crate::symbol::Symbol;
 #[macro_use]
 mod core;
 
@@ -48,33 +61,49 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
             Ok(vector!(lst))
         }
         Hash(hm, _) => {
-            let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+            let mut new_hm = hm.empty();
             for (k, v) in hm.iter() {
-                new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+                new_hm.insert(k.clone(), eval(v.clone(), env.clone())?)?;
             }
-            Ok(Hash(Rc::new(new_hm), Rc::new(Nil)))
+            Ok(Hash(new_hm, Rc::new(Nil)))
+        }
+        Set(s, _) => {
+            let mut new_set = MalSet::default();
+            for v in s.iter() {
+                new_set.insert(eval(v.clone(), env.clone())?);
+            }
+            Ok(Set(new_set, Rc::new(Nil)))
         }
         _ => Ok(ast.clone()),
     }
 }
 
+// The number of args each special form takes.
+const SPECIAL_FORMS: &[(Symbol, Arity)] = &[
+    (symbol::DEF, Exactly(2)),
+    (symbol::LET, Exactly(2)),
+    (symbol::DO, AtLeast(0)),
+    (symbol::IF, Between(2, 3)),
+    (symbol::FN, Exactly(2)),
+];
+
 fn eval(ast: MalVal, env: Env) -> MalRet {
     match ast.clone() {
         List(l, _) => {
             if l.len() == 0 {
                 return Ok(ast);
             }
+            check_special_form(&l, SPECIAL_FORMS)?;
+            let l: MalArgs = l.iter().cloned().collect();
             let a0 = &l[0];
             match a0 {
-                Sym(ref a0sym) if a0sym == "def!" => {
-                    env_set(&env, l[1].clone(), eval(l[2].clone(), env.clone())?)
-                }
-                Sym(ref a0sym) if a0sym == "let*" => {
+                Sym(symbol::DEF) => env_set(&env, l[1].clone(), eval(l[2].clone(), env.clone())?),
+                Sym(symbol::LET) => {
                     let let_env = env_new(Some(env.clone()));
                     let (a1, a2) = (l[1].clone(), l[2].clone());
-                    match a1 {
-                        List(ref binds, _) | Vector(ref binds, _) => {
-                            for (b, e) in binds.iter().tuples() {
+                    match a1.seq_iter() {
+                        Some(binds) => {
+                            for (b, e) in binds.tuples() {
                                 match b {
                                     Sym(_) => {
                                         let _ = env_set(
@@ -95,11 +124,11 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                     };
                     eval(a2, let_env)
                 }
-                Sym(ref a0sym) if a0sym == "do" => match eval_ast(&list!(l[1..].to_vec()), &env)? {
+                Sym(symbol::DO) => match eval_ast(&list!(l[1..].to_vec()), &env)? {
                     List(el, _) => Ok(el.last().unwrap_or(&Nil).clone()),
                     _ => error("invalid do form"),
                 },
-                Sym(ref a0sym) if a0sym == "if" => {
+                Sym(symbol::IF) => {
                     let cond = eval(l[1].clone(), env.clone())?;
                     match cond {
                         Bool(false) | Nil if l.len() >= 4 => eval(l[3].clone(), env.clone()),
@@ -108,8 +137,9 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                         _ => Ok(Nil),
                     }
                 }
-                Sym(ref a0sym) if a0sym == "fn*" => {
+                Sym(symbol::FN) => {
                     let (a1, a2) = (l[1].clone(), l[2].clone());
+                    Arity::of_params(&a1)?;
                     Ok(MalFunc {
                         eval: eval,
                         ast: Rc::new(a2),
@@ -121,6 +151,7 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                 }
                 _ => match eval_ast(&ast, &env)? {
                     List(ref el, _) => {
+                        let el: MalArgs = el.iter().cloned().collect();
                         let ref f = el[0].clone();
                         f.apply(el[1..].to_vec())
                     }
@@ -133,14 +164,16 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
 }
 
 // print
-fn print(ast: &MalVal) -> String {
-    ast.pr_str(true)
+fn print(ast: &MalVal) -> Result<String, MalErr> {
+    // realizing lazy seqs can fail, so that's done before printing
+    ast.realize()?;
+    Ok(ast.pr_str(true))
 }
 
 fn rep(str: &str, env: &Env) -> Result<String, MalErr> {
     let ast = read(str)?;
     let exp = eval(ast, env.clone())?;
-    Ok(print(&exp))
+    print(&exp)
 }
 
 fn main() {
@@ -1,12 +1,14 @@
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 #[macro_use]
 extern crate lazy_static;
 extern crate fnv;
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_rational;
+extern crate num_traits;
 extern crate regex;
 
 extern crate rustyline;
@@ -15,12 +17,23 @@ use rustyline::Editor;
 
 #[macro_use]
 mod types;
-use crate::types::MalVal::{Bool, Func, Hash, List, MalFunc, Nil, Str, Sym, Vector};
-use crate::types::{error, format_error, MalArgs, MalErr, MalRet, MalVal};
+use crate::types::Arity::{AtLeast, Between, Exactly};
+use crate::types::MalVal::{Bool, Func, Hash, Keyword, List, MalFunc, Nil, Set, Str, Sym, Vector};
+use crate::types::{
+    check_special_form, error, format_error, Arity, MalArgs, MalErr, MalRet, MalSet, MalVal,
+};
 mod env;
+mod lazy;
+mod list;
+mod map;
+mod number;
 mod printer;
+mod re;
 mod reader;
+mod string;
+mod symbol;
 use crate::env::{env_bind, env_find, env_get, env_new, env_set, env_sets, Env};
+use crate::symbol::Symbol;
 #[macro_use]
 mod core;
 
@@ -36,15 +49,15 @@ fn qq_iter(elts: &MalArgs) -> MalVal {
     for elt in elts.iter().rev() {
         if let List(v, _) = elt {
             if v.len() == 2 {
-                if let Sym(ref s) = v[0] {
-                    if s == "splice-unquote" {
-                        acc = list![Sym("concat".to_string()), v[1].clone(), acc];
+                if let Some(Sym(ref s)) = v.first() {
+                    if *s == symbol::SPLICE_UNQUOTE {
+                        acc = list![Sym(symbol::CONCAT), v.last().unwrap().clone(), acc];
                         continue;
                     }
                 }
             }
         }
-        acc = list![Sym("cons".to_string()), quasiquote(&elt), acc];
+        acc = list![Sym(symbol::CONS), quasiquote(&elt), acc];
     }
     return acc;
 }
@@ -53,26 +66,31 @@ fn quasiquote(ast: &MalVal) -> MalVal {
     match ast {
         List(v, _) => {
             if v.len() == 2 {
-                if let Sym(ref s) = v[0] {
-                    if s == "unquote" {
-                        return v[1].clone();
+                if let Some(Sym(ref s)) = v.first() {
+                    if *s == symbol::UNQUOTE {
+                        return v.last().unwrap().clone();
                     }
                 }
             }
-            return qq_iter(&v);
+            return qq_iter(&v.iter().cloned().collect());
         },
-        Vector(v, _) => return list![Sym("vec".to_string()), qq_iter(&v)],
-        Hash(_, _) | Sym(_)=> return list![Sym("quote".to_string()), ast.clone()],
+        Vector(v, _) => {
+            let v: MalArgs = v.iter().cloned().collect();
+            return list![Sym(symbol::VEC), qq_iter(&v)];
+        }
+        Hash(_, _) | Set(_, _) | Sym(_)=> return list![Sym(symbol::QUOTE), ast.clone()],
         _ => ast.clone(),
     }
 }
 
 fn is_macro_call(ast: &MalVal, env: &Env) -> Option<(MalVal, MalArgs)> {
     match ast {
-        List(v, _) => match v[0] {
-            Sym(ref s) => match env_find(env, s) {
-                Some(e) => match env_get(&e, &v[0]) {
-                    Ok(f @ MalFunc { is_macro: true, .. }) => Some((f, v[1..].to_vec())),
+        List(v, _) => match v.first() {
+            Some(a0 @ Sym(s)) => match env_find(env, *s) {
+                Some(e) => match env_get(&e, a0) {
+                    Ok(f @ MalFunc { is_macro: true, .. }) => {
+                        Some((f, v.iter().skip(1).cloned().collect()))
+                    }
                     _ => None,
                 },
                 _ => None,
@@ -115,16 +133,38 @@ fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
             Ok(vector!(lst))
         }
         Hash(hm, _) => {
-            let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+            let mut new_hm = hm.empty();
             for (k, v) in hm.iter() {
-                new_hm.insert(k.to_string(), eval(v.clone(), env.clone())?);
+                new_hm.insert(k.clone(), eval(v.clone(), env.clone())?)?;
             }
-            Ok(Hash(Rc::new(new_hm), Rc::new(Nil)))
+            Ok(Hash(new_hm, Rc::new(Nil)))
+        }
+        Set(s, _) => {
+            let mut new_set = MalSet::default();
+            for v in s.iter() {
+                new_set.insert(eval(v.clone(), env.clone())?);
+            }
+            Ok(Set(new_set, Rc::new(Nil)))
         }
         _ => Ok(ast.clone()),
     }
 }
 
+// The number of args each special form takes.
+const SPECIAL_FORMS: &[(Symbol, Arity)] = &[
+    (symbol::DEF, Exactly(2)),
+    (symbol::LET, Exactly(2)),
+    (symbol::QUOTE, Exactly(1)),
+    (symbol::QUASIQUOTEEXPAND, Exactly(1)),
+    (symbol::QUASIQUOTE, Exactly(1)),
+    (symbol::DEFMACRO, Exactly(2)),
+    (symbol::MACROEXPAND, Exactly(1)),
+    (symbol::DO, AtLeast(0)),
+    (symbol::IF, Between(2, 3)),
+    (symbol::FN, Exactly(2)),
+    (symbol::EVAL, Exactly(1)),
+];
+
 fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
     let ret: MalRet;
 
@@ -146,17 +186,19 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                 if l.len() == 0 {
                     return Ok(ast);
                 }
+                check_special_form(&l, SPECIAL_FORMS)?;
+                let l: Vec<&MalVal> = l.iter().collect();
                 let a0 = &l[0];
                 match a0 {
-                    Sym(ref a0sym) if a0sym == "def!" => {
+                    Sym(symbol::DEF) => {
                         env_set(&env, l[1].clone(), eval(l[2].clone(), env.clone())?)
                     }
-                    Sym(ref a0sym) if a0sym == "let*" => {
+                    Sym(symbol::LET) => {
                         env = env_new(Some(env.clone()));
                         let (a1, a2) = (l[1].clone(), l[2].clone());
-                        match a1 {
-                            List(ref binds, _) | Vector(ref binds, _) => {
-                                for (b, e) in binds.iter().tuples() {
+                        match a1.seq_iter() {
+                            Some(binds) => {
+                                for (b, e) in binds.tuples() {
                                     match b {
                                         Sym(_) => {
                                             let _ = env_set(
@@ -178,13 +220,13 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                         ast = a2;
                         continue 'tco;
                     }
-                    Sym(ref a0sym) if a0sym == "quote" => Ok(l[1].clone()),
-                    Sym(ref a0sym) if a0sym == "quasiquoteexpand" => Ok(quasiquote(&l[1])),
-                    Sym(ref a0sym) if a0sym == "quasiquote" => {
+                    Sym(symbol::QUOTE) => Ok(l[1].clone()),
+                    Sym(symbol::QUASIQUOTEEXPAND) => Ok(quasiquote(&l[1])),
+                    Sym(symbol::QUASIQUOTE) => {
                         ast = quasiquote(&l[1]);
                         continue 'tco;
                     }
-                    Sym(ref a0sym) if a0sym == "defmacro!" => {
+                    Sym(symbol::DEFMACRO) => {
                         let (a1, a2) = (l[1].clone(), l[2].clone());
                         let r = eval(a2, env.clone())?;
                         match r {
@@ -209,22 +251,19 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                             _ => error("set_macro on non-function"),
                         }
                     }
-                    Sym(ref a0sym) if a0sym == "macroexpand" => {
-                        match macroexpand(l[1].clone(), &env) {
-                            (_, Ok(new_ast)) => Ok(new_ast),
-                            (_, e) => return e,
-                        }
-                    }
-                    Sym(ref a0sym) if a0sym == "do" => {
-                        match eval_ast(&list!(l[1..l.len() - 1].to_vec()), &env)? {
-                            List(_, _) => {
-                                ast = l.last().unwrap_or(&Nil).clone();
-                                continue 'tco;
-                            }
-                            _ => error("invalid do form"),
+                    Sym(symbol::MACROEXPAND) => match macroexpand(l[1].clone(), &env) {
+                        (_, Ok(new_ast)) => Ok(new_ast),
+                        (_, e) => return e,
+                    },
+                    Sym(symbol::DO) => {
+                        let body = l.get(1..l.len() - 1).unwrap_or(&[]);
+                        for form in body {
+                            eval((*form).clone(), env.clone())?;
                         }
+                        ast = l[l.len() - 1].clone();
+                        continue 'tco;
                     }
-                    Sym(ref a0sym) if a0sym == "if" => {
+                    Sym(symbol::IF) => {
                         let cond = eval(l[1].clone(), env.clone())?;
                         match cond {
                             Bool(false) | Nil if l.len() >= 4 => {
@@ -239,8 +278,9 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                             _ => Ok(Nil),
                         }
                     }
-                    Sym(ref a0sym) if a0sym == "fn*" => {
+                    Sym(symbol::FN) => {
                         let (a1, a2) = (l[1].clone(), l[2].clone());
+                        Arity::of_params(&a1)?;
                         Ok(MalFunc {
                             eval: eval,
                             ast: Rc::new(a2),
@@ -250,36 +290,36 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
                             meta: Rc::new(Nil),
                         })
                     }
-                    Sym(ref a0sym) if a0sym == "eval" => {
+                    Sym(symbol::EVAL) => {
                         ast = eval(l[1].clone(), env.clone())?;
                         while let Some(ref e) = env.clone().outer {
                             env = e.clone();
                         }
                         continue 'tco;
                     }
-                    _ => match eval_ast(&ast, &env)? {
-                        List(ref el, _) => {
-                            let ref f = el[0].clone();
-                            let args = el[1..].to_vec();
-                            match f {
-                                Func(_, _) => f.apply(args),
-                                MalFunc {
-                                    ast: mast,
-                                    env: menv,
-                                    params,
-                                    ..
-                                } => {
-                                    let a = &**mast;
-                                    let p = &**params;
-                                    env = env_bind(Some(menv.clone()), p.clone(), args)?;
-                                    ast = a.clone();
-                                    continue 'tco;
-                                }
-                                _ => error("attempt to call non-function"),
+                    _ => {
+                        let ref f = eval(l[0].clone(), env.clone())?;
+                        let mut args = Vec::with_capacity(l.len() - 1);
+                        for a in &l[1..] {
+                            args.push(eval((*a).clone(), env.clone())?);
+                        }
+                        match f {
+                            Func(_, _) | Keyword(_) => f.apply(args),
+                            MalFunc {
+                                ast: mast,
+                                env: menv,
+                                params,
+                                ..
+                            } => {
+                                let a = &**mast;
+                                let p = &**params;
+                                env = env_bind(Some(menv.clone()), p.clone(), args)?;
+                                ast = a.clone();
+                                continue 'tco;
                             }
+                            _ => error("attempt to call non-function"),
                         }
-                        _ => error("expected a list"),
-                    },
+                    }
                 }
             }
             _ => eval_ast(&ast, &env),
@@ -292,14 +332,16 @@ fn eval(mut ast: MalVal, mut env: Env) -> MalRet {
 }
 
 // print
-fn print(ast: &MalVal) -> String {
-    ast.pr_str(true)
+fn print(ast: &MalVal) -> Result<String, MalErr> {
+    // realizing lazy seqs can fail, so that's done before printing
+    ast.realize()?;
+    Ok(ast.pr_str(true))
 }
 
 fn rep(str: &str, env: &Env) -> Result<String, MalErr> {
     let ast = read(str)?;
     let exp = eval(ast, env.clone())?;
-    Ok(print(&exp))
+    print(&exp)
 }
 
 fn main() {
@@ -317,12 +359,12 @@ fn main() {
     for (k, v) in core::ns() {
         env_sets(&repl_env, k, v);
     }
-    env_sets(&repl_env, "*ARGV*", list!(args.map(Str).collect()));
+    env_sets(&repl_env, "*ARGV*", list!(args.map(Str)));
 
     // core.mal: defined using the language itself
     let _ = rep("(def! not (fn* (a) (if a false true)))", &repl_env);
     let _ = rep(
-        "(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \"\nnil)\")))))",
+        "(def! load-file (fn* (f) (do (dorun (map (fn* (form) (eval form)) (form-seq f))) nil)))",
         &repl_env,
     );
     let _ = rep("(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))", &repl_env);
@@ -0,0 +1,408 @@
+// The string and char builtins. A `Str` is indexed by char, never by byte:
+// indexes go through `offset`, which can't split a multi-byte character.
+
+use std::iter;
+use std::rc::Rc;
+
+use crate::core::coll_items;
+use crate::number;
+use crate::types::Arity::{AtLeast, Between, Exactly};
+use crate::types::MalVal::{BigInt, Bool, Char, Float, Int, Nil, Ratio, Str, Vector};
+use crate::types::{error, func, Arity, BuiltinFn, MalArgs, MalErr, MalRet, MalVal};
+
+fn string<'a>(name: &str, v: &'a MalVal) -> Result<&'a str, MalErr> {
+    match v {
+        Str(s) => Ok(s),
+        _ => Err(MalErr::String(format!("{}: expecting string args", name))),
+    }
+}
+
+// A character index, which mustn't be negative.
+fn index(name: &str, v: &MalVal) -> Result<usize, MalErr> {
+    match v {
+        Int(i) if *i >= 0 => Ok(*i as usize),
+        _ => Err(MalErr::String(format!(
+            "{}: indexes must be non-negative integers",
+            name
+        ))),
+    }
+}
+
+// Where the `idx`th char of `s` starts, or `s.len()` for one past the last
+// char, or None beyond that.
+fn offset(s: &str, idx: usize) -> Option<usize> {
+    s.char_indices()
+        .map(|(offset, _)| offset)
+        .chain(iter::once(s.len()))
+        .nth(idx)
+}
+
+fn char_index(s: &str, offset: usize) -> MalVal {
+    Int(s[..offset].chars().count() as i64)
+}
+
+// `(subs s start)` or `(subs s start end)`.
+fn subs(a: MalArgs) -> MalRet {
+    let s = string("subs", &a[0])?;
+    let start = offset(s, index("subs", &a[1])?);
+    let end = match a.get(2) {
+        Some(end) => offset(s, index("subs", end)?),
+        None => Some(s.len()),
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => Ok(Str(s[start..end].to_string())),
+        _ => error("subs: index out of range"),
+    }
+}
+
+// `(split s sep)` or `(split s sep limit)`, giving a vector of at most
+// `limit` parts. An empty `sep` splits `s` into its characters.
+fn split(a: MalArgs) -> MalRet {
+    let s = string("split", &a[0])?;
+    let sep = string("split", &a[1])?;
+    let limit = match a.get(2) {
+        Some(Int(n)) if *n > 0 => *n as usize,
+        Some(_) => return error("split: limit must be a positive integer"),
+        None => usize::MAX,
+    };
+    let parts: Vec<&str> = if sep.is_empty() {
+        let mut parts: Vec<&str> = vec![];
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            if parts.len() + 1 == limit {
+                break;
+            }
+            parts.push(&rest[..c.len_utf8()]);
+            rest = &rest[c.len_utf8()..];
+        }
+        if !rest.is_empty() {
+            parts.push(rest);
+        }
+        parts
+    } else {
+        s.splitn(limit, sep).collect()
+    };
+    Ok(vector!(parts.into_iter().map(|p| Str(p.to_string()))))
+}
+
+// `(join coll)` or `(join sep coll)`, joining the items as `str` would.
+fn join(a: MalArgs) -> MalRet {
+    let (sep, coll) = match a.len() {
+        1 => ("", &a[0]),
+        _ => (string("join", &a[0])?, &a[1]),
+    };
+    let mut res = String::new();
+    for (i, item) in coll_items("join", coll)?.enumerate() {
+        let item = item?;
+        item.realize()?;
+        if i > 0 {
+            res.push_str(sep);
+        }
+        res.push_str(&item.pr_str(false));
+    }
+    Ok(Str(res))
+}
+
+fn replace(a: MalArgs) -> MalRet {
+    let s = string("replace", &a[0])?;
+    let from = string("replace", &a[1])?;
+    let to = string("replace", &a[2])?;
+    if from.is_empty() {
+        return error("replace: can't replace an empty string");
+    }
+    Ok(Str(s.replace(from, to)))
+}
+
+// `(index-of s value)` or `(index-of s value from)`: the index of the first
+// occurrence of `value` at or after `from`, or nil if there's none.
+fn index_of(a: MalArgs) -> MalRet {
+    let s = string("index-of", &a[0])?;
+    let value = string("index-of", &a[1])?;
+    let from = match a.get(2) {
+        Some(from) => offset(s, index("index-of", from)?).unwrap_or(s.len()),
+        None => 0,
+    };
+    match s[from..].find(value) {
+        Some(found) => Ok(char_index(s, from + found)),
+        None => Ok(Nil),
+    }
+}
+
+fn char_at(a: MalArgs) -> MalRet {
+    let s = string("char-at", &a[0])?;
+    match s.chars().nth(index("char-at", &a[1])?) {
+        Some(c) => Ok(Char(c)),
+        None => error("char-at: index out of range"),
+    }
+}
+
+// `(char code)` is the char with a code point.
+fn to_char(a: MalArgs) -> MalRet {
+    match &a[0] {
+        Char(_) => Ok(a[0].clone()),
+        Int(code) if *code >= 0 && *code <= u32::MAX as i64 => {
+            match std::char::from_u32(*code as u32) {
+                Some(c) => Ok(Char(c)),
+                None => error("char: invalid code point"),
+            }
+        }
+        Int(_) => error("char: invalid code point"),
+        _ => error("char: expecting a char or an integer"),
+    }
+}
+
+// `(int x)` is the code point of a char, or a number truncated to an
+// integer.
+fn to_int(a: MalArgs) -> MalRet {
+    match &a[0] {
+        Char(c) => Ok(Int(*c as i64)),
+        Int(_) => Ok(a[0].clone()),
+        Float(f) if (i64::MIN as f64..i64::MAX as f64).contains(f) => Ok(Int(f.trunc() as i64)),
+        Ratio(r) => match number::from_bigint(r.to_integer()) {
+            Int(i) => Ok(Int(i)),
+            _ => error("int: out of range"),
+        },
+        Float(_) | BigInt(_) => error("int: out of range"),
+        _ => error("int: expecting a char or a number"),
+    }
+}
+
+// `(str->int s)` or `(str->int s radix)`, or nil if `s` isn't an integer.
+fn str_to_int(a: MalArgs) -> MalRet {
+    let s = string("str->int", &a[0])?;
+    let radix = match a.get(1) {
+        Some(Int(r)) if (2..=36).contains(r) => *r as u32,
+        Some(_) => return error("str->int: radix must be between 2 and 36"),
+        None => 10,
+    };
+    let (negative, digits) = match s.chars().next() {
+        Some('-') => (true, &s[1..]),
+        Some('+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    match number::parse_int(digits, radix) {
+        Some(i) if negative => Ok(number::from_bigint(-i)),
+        Some(i) => Ok(number::from_bigint(i)),
+        None => Ok(Nil),
+    }
+}
+
+// One `%[flags][width][.precision]conv` directive of a `format` string.
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    width: usize,
+    precision: Option<usize>,
+    conv: char,
+}
+
+fn parse_spec(chars: &mut iter::Peekable<std::str::Chars>) -> Result<Spec, MalErr> {
+    let mut spec = Spec {
+        left: false,
+        zero: false,
+        plus: false,
+        width: 0,
+        precision: None,
+        conv: '%',
+    };
+    while let Some(&c) = chars.peek() {
+        match c {
+            '-' => spec.left = true,
+            '0' => spec.zero = true,
+            '+' => spec.plus = true,
+            _ => break,
+        }
+        chars.next();
+    }
+    spec.width = digits(chars)?;
+    if chars.peek() == Some(&'.') {
+        chars.next();
+        spec.precision = Some(digits(chars)?);
+    }
+    match chars.next() {
+        Some(c) => spec.conv = c,
+        None => {
+            return Err(MalErr::String(
+                "format: incomplete conversion at end of string".to_string(),
+            ))
+        }
+    }
+    Ok(spec)
+}
+
+// The widest a conversion can be padded to, or the most digits it can have
+// after the point: as many as `format!` takes for a precision.
+const MAX_WIDTH: usize = u16::MAX as usize;
+
+fn digits(chars: &mut iter::Peekable<std::str::Chars>) -> Result<usize, MalErr> {
+    let mut n: usize = 0;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        n = match n.checked_mul(10).and_then(|n| n.checked_add(d as usize)) {
+            Some(n) if n <= MAX_WIDTH => n,
+            _ => {
+                return Err(MalErr::String(format!(
+                    "format: width or precision over {}",
+                    MAX_WIDTH
+                )))
+            }
+        };
+        chars.next();
+    }
+    Ok(n)
+}
+
+// An integer arg as a BigInt, so it can be printed in any radix.
+fn integer(spec: &Spec, arg: &MalVal) -> Result<num_bigint::BigInt, MalErr> {
+    match arg {
+        Int(i) => Ok((*i).into()),
+        BigInt(i) => Ok((**i).clone()),
+        _ => Err(MalErr::String(format!(
+            "format: %{} expects an integer, got {}",
+            spec.conv,
+            arg.pr_str(true)
+        ))),
+    }
+}
+
+fn float(spec: &Spec, arg: &MalVal) -> Result<f64, MalErr> {
+    if number::is_number(arg) {
+        Ok(number::to_f64(arg))
+    } else {
+        Err(MalErr::String(format!(
+            "format: %{} expects a number, got {}",
+            spec.conv,
+            arg.pr_str(true)
+        )))
+    }
+}
+
+// `%e` as C prints it, `1.234568e+04` where Rust's `{:e}` would give
+// `1.234568e4`.
+fn exponent(f: f64, precision: usize) -> String {
+    let s = format!("{:.*e}", precision, f);
+    match s.find('e') {
+        Some(idx) => {
+            let exp: i32 = s[idx + 1..].parse().unwrap_or(0);
+            let sign = if exp < 0 { '-' } else { '+' };
+            format!("{}e{}{:02}", &s[..idx], sign, exp.abs())
+        }
+        None => s,
+    }
+}
+
+fn pad(spec: &Spec, body: String, numeric: bool) -> String {
+    let body = if numeric && spec.plus && !body.starts_with('-') {
+        format!("+{}", body)
+    } else {
+        body
+    };
+    let len = body.chars().count();
+    if len >= spec.width {
+        return body;
+    }
+    let fill = spec.width - len;
+    if spec.left {
+        body + &" ".repeat(fill)
+    } else if spec.zero && numeric {
+        // the zeros go after the sign
+        let sign = if body.starts_with('-') || body.starts_with('+') {
+            1
+        } else {
+            0
+        };
+        format!("{}{}{}", &body[..sign], "0".repeat(fill), &body[sign..])
+    } else {
+        " ".repeat(fill) + &body
+    }
+}
+
+// printf-style formatting. The flags are `-` to left-align, `0` to pad
+// numbers with zeros and `+` to always sign them, and the conversions are
+// `%s` (as by `str`), `%d`, `%x`, `%X` and `%o` for integers, `%f` and `%e`
+// for numbers, and `%%` for a percent sign.
+fn format(a: MalArgs) -> MalRet {
+    let fmt = string("format", &a[0])?;
+    let mut args = a[1..].iter();
+    let mut res = String::new();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+        let spec = parse_spec(&mut chars)?;
+        if spec.conv == '%' {
+            res.push('%');
+            continue;
+        }
+        let arg = match args.next() {
+            Some(arg) => arg,
+            None => return error("format: not enough args for the format string"),
+        };
+        let (body, numeric) = match spec.conv {
+            's' => {
+                arg.realize()?;
+                let s = arg.pr_str(false);
+                match spec.precision {
+                    Some(p) => (s.chars().take(p).collect(), false),
+                    None => (s, false),
+                }
+            }
+            'd' => (integer(&spec, arg)?.to_string(), true),
+            'x' => (integer(&spec, arg)?.to_str_radix(16), true),
+            'X' => (integer(&spec, arg)?.to_str_radix(16).to_uppercase(), true),
+            'o' => (integer(&spec, arg)?.to_str_radix(8), true),
+            'f' => {
+                let f = float(&spec, arg)?;
+                (format!("{:.*}", spec.precision.unwrap_or(6), f), true)
+            }
+            'e' => {
+                let f = float(&spec, arg)?;
+                (exponent(f, spec.precision.unwrap_or(6)), true)
+            }
+            conv => return error(&format!("format: unknown conversion %{}", conv)),
+        };
+        res.push_str(&pad(&spec, body, numeric));
+    }
+    Ok(Str(res))
+}
+
+pub fn ns() -> Vec<(&'static str, MalVal)> {
+    let builtins: Vec<(&'static str, Arity, BuiltinFn)> = vec![
+        ("subs", Between(2, 3), subs),
+        ("split", Between(2, 3), split),
+        ("join", Between(1, 2), join),
+        ("replace", Exactly(3), replace),
+        ("upper-case", Exactly(1), |a| {
+            Ok(Str(string("upper-case", &a[0])?.to_uppercase()))
+        }),
+        ("lower-case", Exactly(1), |a| {
+            Ok(Str(string("lower-case", &a[0])?.to_lowercase()))
+        }),
+        ("trim", Exactly(1), |a| {
+            Ok(Str(string("trim", &a[0])?.trim().to_string()))
+        }),
+        ("starts-with?", Exactly(2), |a| {
+            let s = string("starts-with?", &a[0])?;
+            Ok(Bool(s.starts_with(string("starts-with?", &a[1])?)))
+        }),
+        ("ends-with?", Exactly(2), |a| {
+            let s = string("ends-with?", &a[0])?;
+            Ok(Bool(s.ends_with(string("ends-with?", &a[1])?)))
+        }),
+        ("index-of", Between(2, 3), index_of),
+        ("char-at", Exactly(2), char_at),
+        ("char", Exactly(1), to_char),
+        ("int", Exactly(1), to_int),
+        ("format", AtLeast(1), format),
+        ("str->int", Between(1, 2), str_to_int),
+        ("parse-number", Exactly(1), |a| {
+            Ok(number::parse(string("parse-number", &a[0])?).unwrap_or(Nil))
+        }),
+    ];
+    builtins
+        .into_iter()
+        .map(|(name, arity, f)| (name, func(name, arity, f)))
+        .collect()
+}
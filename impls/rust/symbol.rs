@@ -0,0 +1,110 @@
+// Symbols and keywords are interned: each name is stored once, and a `Symbol`
+// is its index, so comparing, hashing or looking one up in an env is done on
+// an integer rather than a string. Names are never freed, so a program that
+// makes an unbounded number of distinct symbols at runtime grows the table.
+
+use fnv::FnvHashMap;
+use std::cell::RefCell;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// The symbols that the evaluator and reader look for are interned up front
+// with fixed ids, so special forms can be matched as `Sym(symbol::DEF)`. The
+// ids are numbered by an enum whose variants share the constants' names, which
+// clippy takes for acronyms.
+macro_rules! predefined {
+    ($($name:ident = $text:expr,)*) => {
+        #[allow(non_camel_case_types, dead_code, clippy::upper_case_acronyms)]
+        enum Predefined {
+            $($name,)*
+        }
+
+        $(
+            #[allow(dead_code)]
+            pub const $name: Symbol = Symbol(Predefined::$name as u32);
+        )*
+
+        const PREDEFINED: &[&str] = &[$($text,)*];
+    };
+}
+
+predefined! {
+    AMP = "&",
+    DEF = "def!",
+    LET = "let*",
+    QUOTE = "quote",
+    QUASIQUOTEEXPAND = "quasiquoteexpand",
+    QUASIQUOTE = "quasiquote",
+    UNQUOTE = "unquote",
+    SPLICE_UNQUOTE = "splice-unquote",
+    DEFMACRO = "defmacro!",
+    MACROEXPAND = "macroexpand",
+    TRY = "try*",
+    DO = "do",
+    IF = "if",
+    FN = "fn*",
+    EVAL = "eval",
+    LAZY_SEQ = "lazy-seq",
+    CONCAT = "concat",
+    CONS = "cons",
+    VEC = "vec",
+    WITH_META = "with-meta",
+    DEREF = "deref",
+}
+
+struct Interner {
+    ids: FnvHashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+impl Interner {
+    fn new() -> Interner {
+        let mut interner = Interner {
+            ids: FnvHashMap::default(),
+            names: vec![],
+        };
+        for name in PREDEFINED {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&sym) = self.ids.get(name) {
+            return sym;
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name);
+        self.ids.insert(name, sym);
+        sym
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+impl Symbol {
+    pub fn new(name: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize])
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
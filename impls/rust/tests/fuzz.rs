@@ -0,0 +1,220 @@
+// Feeds randomly generated input to the interpreter and checks that none of
+// it makes the process panic, crash or hang: bad input, such as calling a
+// builtin or a special form with the wrong number of args, should always be
+// a mal error.
+//
+// The generator is seeded, so failures are reproducible. MAL_FUZZ_SEED and
+// MAL_FUZZ_BATCHES can be set to explore further. Inputs that once crashed the
+// interpreter, but that the generator is unlikely to hit again, are run on
+// every pass as KNOWN_ERRORS.
+
+use std::env;
+use std::io::{Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The infinite seqs from `range`, `iterate`, `repeat` and `cycle` are left out,
+// as printing one never finishes.
+const SYMBOLS: &str = "\
+    = throw nil? true? false? symbol symbol? string? char? keyword keyword? name \
+    namespace number? fn? macro? pr-str str read-string slurp line-seq < <= > >= + - * / \
+    max min quot rem mod inc dec abs bit-and bit-or bit-xor bit-not bit-shift-left \
+    bit-shift-right sequential? list list? vector vector? hash-map sorted-map \
+    sorted-map-by compare map? set set? assoc dissoc get contains? keys vals vec cons \
+    concat empty? nth first rest count apply map filter take drop remove keep mapcat \
+    partition reduce reduce-kv reduced reduced? transduce into group-by frequencies sort \
+    sort-by doall dorun conj disj union intersection difference subs split join replace \
+    upper-case lower-case trim starts-with? ends-with? index-of char-at char int format \
+    str->int parse-number re-pattern re-find re-matches re-seq re-replace seq meta \
+    with-meta atom atom? deref reset! swap! not cond def! let* do if fn* lazy-seq eval \
+    quote quasiquote quasiquoteexpand unquote splice-unquote defmacro! macroexpand try* \
+    catch* & a b";
+
+const ATOMS: &str = r#"
+    nil true false 0 1 -1 9223372036854775807 -9223372036854775808 99999999999999999999
+    3/4 -1.5 ##NaN ##Inf 0x10 "" "a" "\n" #"a|\d" \a \newline :a :b :a/b"#;
+
+// Each of these should be a mal error, rather than a panic or an abort.
+const KNOWN_ERRORS: &[&str] = &[
+    "(/ 99999999999999999999 0)",
+    "(/ 3/4 0)",
+    "(quot 99999999999999999999 0)",
+    "(quot 3/4 0)",
+    "(rem 99999999999999999999 0)",
+    "(rem 3/4 0)",
+    "(mod 99999999999999999999 0)",
+    "(mod 3/4 0)",
+    "(bit-shift-left 1 100000000000)",
+    "(bit-shift-left 1 99999999999999999999)",
+    "(bit-shift-left 99999999999999999999 100000000000)",
+    "(bit-shift-left 1 -1)",
+    "(bit-shift-right 1 -99999999999999999999)",
+    "(format \"%.65536f\" 1.5)",
+    "(format \"%.65536e\" 1.5)",
+    "(format \"%99999999999999999999d\" 1)",
+    "(format \"%.99999999999999999999s\" 1)",
+];
+
+const NOISE: &[u8] = b"()[]{}\"\\;'`~@^&:# 0a";
+
+// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+
+    // Picks one of the whitespace-separated `words`.
+    fn pick_word(&mut self, words: &'static str) -> &'static str {
+        let words = words.split_whitespace().collect::<Vec<_>>();
+        self.pick(&words)
+    }
+}
+
+fn form(rng: &mut Rng, depth: usize, out: &mut String) {
+    let (open, close) = match rng.below(12) {
+        _ if depth == 0 => ("", ""),
+        0..=4 => ("(", ")"),
+        5 => ("[", "]"),
+        6 => ("{", "}"),
+        8 => ("#{", "}"),
+        7 => {
+            out.push_str(rng.pick(&["'", "`", "~", "~@", "@"]));
+            return form(rng, depth - 1, out);
+        }
+        _ => ("", ""),
+    };
+    if open.is_empty() {
+        let atom = if rng.below(2) == 0 {
+            rng.pick_word(SYMBOLS)
+        } else {
+            rng.pick_word(ATOMS)
+        };
+        out.push_str(atom);
+        return;
+    }
+    out.push_str(open);
+    if open == "(" && rng.below(4) != 0 {
+        out.push_str(rng.pick_word(SYMBOLS));
+        out.push(' ');
+    }
+    for _ in 0..rng.below(5) {
+        form(rng, depth - 1, out);
+        out.push(' ');
+    }
+    out.push_str(close);
+}
+
+fn line(rng: &mut Rng) -> String {
+    let mut line = String::new();
+    form(rng, 4, &mut line);
+    // sometimes corrupt it, to exercise the reader
+    if rng.below(8) == 0 && !line.is_empty() {
+        let mut bytes = line.into_bytes();
+        for _ in 0..1 + rng.below(3) {
+            let idx = rng.below(bytes.len());
+            bytes[idx] = NOISE[rng.below(NOISE.len())];
+        }
+        line = String::from_utf8(bytes).unwrap();
+    }
+    line
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+// Runs `input` through the REPL, returning its output, or None if it didn't
+// finish in time.
+fn run(input: String) -> Option<Output> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stepA_mal"))
+        .current_dir(env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let stdout = drain(child.stdout.take().unwrap());
+    let stderr = drain(child.stderr.take().unwrap());
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Some(Output {
+        status: child.wait().unwrap(),
+        stdout: stdout.join().unwrap(),
+        stderr: stderr.join().unwrap(),
+    })
+}
+
+// Reads everything from `pipe` on another thread, so the child never blocks
+// on a full pipe.
+fn drain<R: Read + Send + 'static>(mut pipe: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut out = Vec::new();
+        pipe.read_to_end(&mut out).unwrap();
+        out
+    })
+}
+
+#[test]
+fn no_input_panics() {
+    let mut rng = Rng(env_or("MAL_FUZZ_SEED", 0x5eed) | 1);
+    for _ in 0..env_or("MAL_FUZZ_BATCHES", 20) {
+        let input = (0..100).map(|_| line(&mut rng) + "\n").collect::<String>();
+        let output = match run(input.clone()) {
+            Some(output) => output,
+            None => panic!("the interpreter hung on this input:\n{}", input),
+        };
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // a panic exits with 101, and a stack overflow or an abort is killed
+        // by a signal, which leaves no exit code
+        let crashed = matches!(output.status.code(), Some(101) | None);
+        assert!(
+            !crashed && !stderr.contains("panicked"),
+            "the interpreter crashed ({}):\n{}\non this input:\n{}",
+            output.status,
+            stderr,
+            input
+        );
+    }
+}
+
+#[test]
+fn known_errors_are_mal_errors() {
+    let input = KNOWN_ERRORS.iter().map(|case| format!("{}\n", case));
+    let output = run(input.collect()).expect("the interpreter hung");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "the interpreter crashed:\n{}",
+        stderr
+    );
+    let errors = stdout.matches("Error: ").count();
+    assert_eq!(errors, KNOWN_ERRORS.len(), "the output was:\n{}", stdout);
+}
@@ -1,25 +1,51 @@
+use std::any::Any;
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash as StdHash, Hasher};
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
+use fnv::{FnvBuildHasher, FnvHasher};
 use itertools::Itertools;
+use num_bigint;
+use num_rational::BigRational;
+use regex;
 
 use crate::env::{env_bind, Env};
-use crate::types::MalErr::{ErrMalVal, ErrString};
-use crate::types::MalVal::{Atom, Bool, Func, Hash, Int, List, MalFunc, Nil, Str, Sym, Vector};
+use crate::lazy::Lazy;
+use crate::list;
+use crate::map::MalMap;
+use crate::number;
+use crate::symbol;
+use crate::symbol::Symbol;
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Char, Compiled, Float, Func, Hash, Int, Keyword, LazySeq, List, MalFunc,
+    Nil, Ratio, Reduced, Regex, Set, Str, Sym, Vector,
+};
 
 #[derive(Debug, Clone)]
 pub enum MalVal {
     Nil,
     Bool(bool),
     Int(i64),
-    //Float(f64),
+    Float(f64),
+    BigInt(Rc<num_bigint::BigInt>),
+    Ratio(Rc<BigRational>),
     Str(String),
-    Sym(String),
-    List(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Vector(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Hash(Rc<FnvHashMap<String, MalVal>>, Rc<MalVal>),
-    Func(fn(MalArgs) -> MalRet, Rc<MalVal>),
+    Char(char),
+    Sym(Symbol),
+    // The name of a keyword, without the colon.
+    Keyword(Symbol),
+    Regex(Rc<regex::Regex>),
+    List(MalList, Rc<MalVal>),
+    Vector(MalVector, Rc<MalVal>),
+    Hash(MalHash, Rc<MalVal>),
+    Set(MalSet, Rc<MalVal>),
+    LazySeq(Rc<Lazy>),
+    Func(Builtin, Rc<MalVal>),
+    // A `fn*` that's interpreted each time it's called. stepA compiles
+    // functions instead.
+    #[allow(dead_code)]
     MalFunc {
         eval: fn(ast: MalVal, env: Env) -> MalRet,
         ast: Rc<MalVal>,
@@ -28,50 +54,220 @@ pub enum MalVal {
         is_macro: bool,
         meta: Rc<MalVal>,
     },
+    // A `fn*` that's been compiled before it's called; see `Callable`. Only
+    // stepA makes these.
+    #[allow(dead_code)]
+    Compiled {
+        f: Rc<dyn Callable>,
+        is_macro: bool,
+        meta: Rc<MalVal>,
+    },
     Atom(Rc<RefCell<MalVal>>),
+    // A value wrapped by `reduced`, which ends a `reduce` early.
+    Reduced(Rc<MalVal>),
+}
+
+// The collections are persistent, so they're cheap to clone, and updating
+// one shares structure with the original rather than copying it. Lists are
+// cons lists, so `cons` and `rest` are O(1) but indexing is O(n); vectors are
+// RRB trees, and sets are HAMTs, so lookups and updates are O(log n). Maps
+// are HAMTs or B-trees too; see map.rs.
+pub type MalList = list::List<MalVal>;
+pub type MalVector = im_rc::Vector<MalVal>;
+pub type MalHash = MalMap;
+pub type MalSet = im_rc::HashSet<MalVal, FnvBuildHasher>;
+
+// A function implemented in Rust. `f` can index `args` freely, since it's
+// only called once the number of args has been checked against `arity`.
+// `bound` is passed to `f` ahead of the args it's called with, which lets a
+// builtin return a function that closes over values, like a transducer.
+#[derive(Debug, Clone)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub f: BuiltinFn,
+    pub bound: Rc<MalArgs>,
 }
 
-#[derive(Debug)]
+// A function compiled by stepA's compiler, which is opaque to everything
+// else. See compile.rs.
+pub trait Callable: fmt::Debug {
+    fn apply(&self, args: MalArgs) -> MalRet;
+
+    // The `fn*` form it was compiled from, which it's printed as.
+    fn source(&self) -> MalVal;
+
+    #[allow(dead_code)]
+    fn as_any(&self) -> &dyn Any;
+}
+
+// How many args a function or special form takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+#[derive(Debug, Clone)]
 pub enum MalErr {
-    ErrString(String),
-    ErrMalVal(MalVal),
+    String(String),
+    MalVal(MalVal),
+    // An error raised inside mal functions, along with the call stack at the
+    // point it was raised, innermost call first. Only stepA traces errors.
+    #[allow(dead_code)]
+    Traced(Box<MalErr>, Rc<Vec<TraceFrame>>),
+}
+
+// A call on the mal call stack. Forms aren't read with their positions, so a
+// frame only knows which function was called.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct TraceFrame {
+    // The name the function was defined with, or else the symbol it was
+    // called through.
+    pub name: Option<&'static str>,
+    // How many calls were made from this frame in tail position, each
+    // replacing it.
+    pub elided: usize,
 }
 
 pub type MalArgs = Vec<MalVal>;
 pub type MalRet = Result<MalVal, MalErr>;
 
+// What a `Builtin` runs.
+pub type BuiltinFn = fn(MalArgs) -> MalRet;
+
 // type utility macros
 
 macro_rules! list {
   ($seq:expr) => {{
-    List(Rc::new($seq),Rc::new(Nil))
+    List($seq.into_iter().collect(),Rc::new(Nil))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    List(Rc::new(v),Rc::new(Nil))
+    List(v.into_iter().collect(),Rc::new(Nil))
   }}
 }
 
 macro_rules! vector {
   ($seq:expr) => {{
-    Vector(Rc::new($seq),Rc::new(Nil))
+    Vector($seq.into_iter().collect(),Rc::new(Nil))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    Vector(Rc::new(v),Rc::new(Nil))
+    Vector(v.into_iter().collect(),Rc::new(Nil))
   }}
 }
 
+// The chars that are written by name, like `\newline`, rather than as
+// themselves.
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("newline", '\n'),
+    ("space", ' '),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("backspace", '\u{8}'),
+    ("formfeed", '\u{c}'),
+];
+
 // type utility functions
 
 pub fn error(s: &str) -> MalRet {
-    Err(ErrString(s.to_string()))
+    Err(MalErr::String(s.to_string()))
 }
 
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exactly(m) => n == m,
+            Arity::AtLeast(min) => n >= min,
+            Arity::Between(min, max) => min <= n && n <= max,
+        }
+    }
+
+    pub fn check(&self, name: &str, n: usize) -> Result<(), MalErr> {
+        if self.accepts(n) {
+            Ok(())
+        } else {
+            Err(MalErr::String(format!(
+                "wrong number of args ({}) passed to {}",
+                n, name
+            )))
+        }
+    }
+
+    // The arity of a `fn*` with the parameters `params`, which must be
+    // symbols, optionally followed by `&` and a symbol for the rest.
+    pub fn of_params(params: &MalVal) -> Result<Arity, MalErr> {
+        let params: Vec<&MalVal> = match params {
+            List(p, _) => p.iter().collect(),
+            Vector(p, _) => p.iter().collect(),
+            _ => return Err(MalErr::String("fn* params not List/Vector".to_string())),
+        };
+        for (i, p) in params.iter().enumerate() {
+            match p {
+                Sym(symbol::AMP) => {
+                    return match params[i + 1..] {
+                        [Sym(rest)] if *rest != symbol::AMP => Ok(Arity::AtLeast(i)),
+                        _ => Err(MalErr::String(
+                            "fn* params: expected one symbol after &".to_string(),
+                        )),
+                    };
+                }
+                Sym(_) => {}
+                _ => return Err(MalErr::String("fn* params: non-Sym param".to_string())),
+            }
+        }
+        Ok(Arity::Exactly(params.len()))
+    }
+}
+
+// Checks the args of `form` if it's one of `special_forms`.
+pub fn check_special_form(form: &MalList, special_forms: &[(Symbol, Arity)]) -> Result<(), MalErr> {
+    if let Some(Sym(name)) = form.first() {
+        for (special, arity) in special_forms {
+            if special == name {
+                return arity.check(name.as_str(), form.len() - 1);
+            }
+        }
+    }
+    Ok(())
+}
+
+// The error's message, followed by its trace with a frame per line.
 pub fn format_error(e: MalErr) -> String {
     match e {
-        ErrString(s) => s.clone(),
-        ErrMalVal(mv) => mv.pr_str(true),
+        MalErr::String(s) => s.clone(),
+        MalErr::MalVal(mv) => mv.pr_str(true),
+        MalErr::Traced(e, trace) => trace.iter().fold(format_error(*e), |res, frame| {
+            format!("{}\n  {}", res, frame)
+        }),
+    }
+}
+
+impl MalErr {
+    // The value that a `catch*` binds for the error, and its trace, which is
+    // empty if it wasn't raised inside a function. Steps before 9 don't
+    // catch errors.
+    #[allow(dead_code)]
+    pub fn caught(self) -> (MalVal, Vec<TraceFrame>) {
+        match self {
+            MalErr::String(s) => (Str(s), vec![]),
+            MalErr::MalVal(mv) => (mv, vec![]),
+            MalErr::Traced(e, trace) => (e.caught().0, trace.to_vec()),
+        }
+    }
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {}", self.name.unwrap_or("<anonymous>"))?;
+        match self.elided {
+            0 => Ok(()),
+            1 => write!(f, " [1 tail call elided]"),
+            elided => write!(f, " [{} tail calls elided]", elided),
+        }
     }
 }
 
@@ -79,18 +275,40 @@ pub fn atom(mv: &MalVal) -> MalVal {
     Atom(Rc::new(RefCell::new(mv.clone())))
 }
 
+pub fn symbol(name: &str) -> MalVal {
+    Sym(Symbol::new(name))
+}
+
+// The keyword `:name`.
+pub fn keyword(name: &str) -> MalVal {
+    Keyword(Symbol::new(name))
+}
+
+// Splits the name of a symbol or keyword into its namespace, if it has one,
+// and its name, so `a/b` is in `a`. A `/` on its own is a name.
+pub fn split_ns(name: &str) -> (Option<&str>, &str) {
+    match name.find('/') {
+        Some(idx) if idx > 0 && idx < name.len() - 1 => (Some(&name[..idx]), &name[idx + 1..]),
+        _ => (None, name),
+    }
+}
+
 impl MalVal {
     pub fn keyword(&self) -> MalRet {
         match self {
-            Str(s) if s.starts_with("\u{29e}") => Ok(Str(s.to_string())),
-            Str(s) => Ok(Str(format!("\u{29e}{}", s))),
+            Keyword(_) => Ok(self.clone()),
+            Str(s) => Ok(keyword(s)),
             _ => error("invalid type for keyword"),
         }
     }
 
     pub fn empty_q(&self) -> MalRet {
         match self {
-            List(l, _) | Vector(l, _) => Ok(Bool(l.len() == 0)),
+            List(l, _) => Ok(Bool(l.is_empty())),
+            Vector(v, _) => Ok(Bool(v.is_empty())),
+            Hash(hm, _) => Ok(Bool(hm.is_empty())),
+            Set(s, _) => Ok(Bool(s.is_empty())),
+            LazySeq(lazy) => Ok(Bool(lazy.realize()?.is_none())),
             Nil => Ok(Bool(true)),
             _ => error("invalid type for empty?"),
         }
@@ -98,7 +316,15 @@ impl MalVal {
 
     pub fn count(&self) -> MalRet {
         match self {
-            List(l, _) | Vector(l, _) => Ok(Int(l.len() as i64)),
+            List(l, _) => Ok(Int(l.len() as i64)),
+            Vector(v, _) => Ok(Int(v.len() as i64)),
+            Hash(hm, _) => Ok(Int(hm.len() as i64)),
+            Set(s, _) => Ok(Int(s.len() as i64)),
+            // strings are counted in chars, as they're indexed
+            Str(s) => Ok(Int(s.chars().count() as i64)),
+            LazySeq(_) => Ok(Int(self
+                .items()
+                .try_fold(0, |n, item| item.map(|_| n + 1))?)),
             Nil => Ok(Int(0)),
             _ => error("invalid type for count"),
         }
@@ -106,7 +332,16 @@ impl MalVal {
 
     pub fn apply(&self, args: MalArgs) -> MalRet {
         match *self {
-            Func(f, _) => f(args),
+            Func(ref b, _) => {
+                b.arity.check(b.name, args.len())?;
+                if b.bound.is_empty() {
+                    (b.f)(args)
+                } else {
+                    let mut all = (*b.bound).clone();
+                    all.extend(args);
+                    (b.f)(all)
+                }
+            }
             MalFunc {
                 eval,
                 ref ast,
@@ -119,20 +354,97 @@ impl MalVal {
                 let fn_env = env_bind(Some(env.clone()), p.clone(), args)?;
                 Ok(eval(a.clone(), fn_env)?)
             }
+            Compiled { ref f, .. } => f.apply(args),
+            // `(:k m)` looks `:k` up in `m`, and `(:k m default)` gives
+            // `default` if it isn't there
+            Keyword(_) => {
+                Arity::Between(1, 2).check(&self.pr_str(true), args.len())?;
+                let found = match &args[0] {
+                    Hash(hm, _) => hm.get(self)?.cloned(),
+                    Set(s, _) if s.contains(self) => Some(self.clone()),
+                    _ => None,
+                };
+                Ok(found.unwrap_or_else(|| args.get(1).cloned().unwrap_or(Nil)))
+            }
             _ => error("attempt to call non-function"),
         }
     }
 
-    pub fn keyword_q(&self) -> bool {
+    // The items of a list or vector.
+    pub fn seq_iter(&self) -> Option<Box<dyn Iterator<Item = &MalVal> + '_>> {
         match self {
-            Str(s) if s.starts_with("\u{29e}") => true,
-            _ => false,
+            List(l, _) => Some(Box::new(l.iter())),
+            Vector(v, _) => Some(Box::new(v.iter())),
+            _ => None,
+        }
+    }
+
+    // Splits a seq into its first item and the rest, or None if it's empty,
+    // realizing a lazy seq as far as its first item. The rest of a vector is
+    // still a vector, so it's only for walking the seq.
+    pub fn uncons(&self) -> Result<Option<(MalVal, MalVal)>, MalErr> {
+        match self {
+            List(l, _) => Ok(l
+                .first()
+                .map(|first| (first.clone(), List(l.drop_first().unwrap(), Rc::new(Nil))))),
+            Vector(v, _) => Ok(v
+                .front()
+                .map(|first| (first.clone(), Vector(v.skip(1), Rc::new(Nil))))),
+            Set(s, _) => list!(s.iter().cloned()).uncons(),
+            LazySeq(lazy) => lazy.realize(),
+            Nil => Ok(None),
+            _ => Err(MalErr::String(format!(
+                "can't make a seq of {}",
+                self.pr_str(true)
+            ))),
+        }
+    }
+
+    // The items of a seq, realizing a lazy seq as they're reached.
+    pub fn items(&self) -> Items {
+        self.clone().into_items()
+    }
+
+    // Like `items`, but as the head of a lazy seq isn't kept, the items that
+    // have been passed can be freed.
+    pub fn into_items(self) -> Items {
+        Items {
+            rest: match self {
+                Set(s, _) => list!(s.iter().cloned()),
+                _ => self,
+            },
+        }
+    }
+
+    // Realizes every lazy seq within this value, as printing it can't fail.
+    pub fn realize(&self) -> Result<(), MalErr> {
+        match self {
+            List(l, _) => l.iter().try_for_each(MalVal::realize),
+            Vector(v, _) => v.iter().try_for_each(MalVal::realize),
+            Hash(hm, _) => hm
+                .iter()
+                .try_for_each(|(k, v)| k.realize().and(v.realize())),
+            Set(s, _) => s.iter().try_for_each(MalVal::realize),
+            LazySeq(_) => self.items().try_for_each(|item| item?.realize()),
+            Atom(a) => a.borrow().realize(),
+            Reduced(v) => v.realize(),
+            _ => Ok(()),
+        }
+    }
+
+    // A list or vector as a list, sharing its structure if it's a list.
+    pub fn to_list(&self) -> Option<MalList> {
+        match self {
+            List(l, _) => Some(l.clone()),
+            Vector(v, _) => Some(v.iter().cloned().collect()),
+            _ => None,
         }
     }
 
     pub fn deref(&self) -> MalRet {
         match self {
             Atom(a) => Ok(a.borrow().clone()),
+            Reduced(v) => Ok((**v).clone()),
             _ => error("attempt to deref a non-Atom"),
         }
     }
@@ -162,9 +474,9 @@ impl MalVal {
 
     pub fn get_meta(&self) -> MalRet {
         match self {
-            List(_, meta) | Vector(_, meta) | Hash(_, meta) => Ok((&**meta).clone()),
+            List(_, meta) | Vector(_, meta) | Hash(_, meta) | Set(_, meta) => Ok((&**meta).clone()),
             Func(_, meta) => Ok((&**meta).clone()),
-            MalFunc { meta, .. } => Ok((&**meta).clone()),
+            MalFunc { meta, .. } | Compiled { meta, .. } => Ok((&**meta).clone()),
             _ => error("meta not supported by type"),
         }
     }
@@ -174,8 +486,10 @@ impl MalVal {
             List(_, ref mut meta)
             | Vector(_, ref mut meta)
             | Hash(_, ref mut meta)
+            | Set(_, ref mut meta)
             | Func(_, ref mut meta)
-            | MalFunc { ref mut meta, .. } => {
+            | MalFunc { ref mut meta, .. }
+            | Compiled { ref mut meta, .. } => {
                 *meta = Rc::new((&*new_meta).clone());
             }
             _ => return error("with-meta not supported by type"),
@@ -190,51 +504,201 @@ impl PartialEq for MalVal {
             (Nil, Nil) => true,
             (Bool(ref a), Bool(ref b)) => a == b,
             (Int(ref a), Int(ref b)) => a == b,
+            (Float(ref a), Float(ref b)) => a == b,
+            (BigInt(ref a), BigInt(ref b)) => a == b,
+            (Ratio(ref a), Ratio(ref b)) => a == b,
             (Str(ref a), Str(ref b)) => a == b,
-            (Sym(ref a), Sym(ref b)) => a == b,
-            (List(ref a, _), List(ref b, _))
-            | (Vector(ref a, _), Vector(ref b, _))
-            | (List(ref a, _), Vector(ref b, _))
-            | (Vector(ref a, _), List(ref b, _)) => a == b,
+            (Char(a), Char(b)) => a == b,
+            (Sym(a), Sym(b)) | (Keyword(a), Keyword(b)) => a == b,
+            (Regex(ref a), Regex(ref b)) => a.as_str() == b.as_str(),
+            (List(ref a, _), List(ref b, _)) => a == b,
+            (Vector(ref a, _), Vector(ref b, _)) => a == b,
+            (List(ref a, _), Vector(ref b, _)) | (Vector(ref b, _), List(ref a, _)) => {
+                a.len() == b.len() && a.iter().eq(b.iter())
+            }
             (Hash(ref a, _), Hash(ref b, _)) => a == b,
+            (Set(ref a, _), Set(ref b, _)) => a == b,
+            (LazySeq(ref a), LazySeq(ref b)) if Rc::ptr_eq(a, b) => true,
+            (LazySeq(_), List(_, _))
+            | (LazySeq(_), Vector(_, _))
+            | (LazySeq(_), LazySeq(_))
+            | (List(_, _), LazySeq(_))
+            | (Vector(_, _), LazySeq(_)) => items_eq(self.items(), other.items()),
+            (Reduced(ref a), Reduced(ref b)) => a == b,
             (MalFunc { .. }, MalFunc { .. }) => false,
             _ => false,
         }
     }
 }
 
-pub fn func(f: fn(MalArgs) -> MalRet) -> MalVal {
-    Func(f, Rc::new(Nil))
+fn items_eq(mut a: Items, mut b: Items) -> bool {
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return true,
+            (Some(Ok(a)), Some(Ok(b))) if a == b => {}
+            _ => return false,
+        }
+    }
+}
+
+// The natural order of values, as used by `compare` and `sorted-map`: nil
+// sorts first, numbers compare by value, and lists and vectors compare by
+// length, then item by item. Other values only compare with their own kind.
+pub fn compare(a: &MalVal, b: &MalVal) -> Result<Ordering, MalErr> {
+    let ord = match (a, b) {
+        (Nil, Nil) => Ordering::Equal,
+        (Nil, _) => Ordering::Less,
+        (_, Nil) => Ordering::Greater,
+        (Bool(a), Bool(b)) => a.cmp(b),
+        // NaN compares equal to everything
+        _ if number::is_number(a) && number::is_number(b) => {
+            number::compare(a, b)?.unwrap_or(Ordering::Equal)
+        }
+        (Str(a), Str(b)) => a.cmp(b),
+        (Char(a), Char(b)) => a.cmp(b),
+        (Sym(a), Sym(b)) | (Keyword(a), Keyword(b)) => a.as_str().cmp(b.as_str()),
+        _ => match (a.seq_iter(), b.seq_iter()) {
+            (Some(a_items), Some(b_items)) => {
+                let (a_items, b_items): (Vec<_>, Vec<_>) = (a_items.collect(), b_items.collect());
+                match a_items.len().cmp(&b_items.len()) {
+                    Ordering::Equal => {
+                        for (a, b) in a_items.into_iter().zip(b_items) {
+                            match compare(a, b)? {
+                                Ordering::Equal => {}
+                                ord => return Ok(ord),
+                            }
+                        }
+                        Ordering::Equal
+                    }
+                    ord => ord,
+                }
+            }
+            _ => {
+                return Err(MalErr::String(format!(
+                    "can't compare {} with {}",
+                    a.pr_str(true),
+                    b.pr_str(true)
+                )))
+            }
+        },
+    };
+    Ok(ord)
+}
+
+// Strictly, NaN breaks `Eq`, as it isn't equal to itself. As in Clojure, a
+// set can end up holding several NaNs.
+impl Eq for MalVal {}
+
+// Consistent with `==`, so lists and vectors with equal items hash the same,
+// and maps and sets hash the same whatever order they iterate in.
+impl StdHash for MalVal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Nil => {}
+            Bool(b) => b.hash(state),
+            Int(i) => i.hash(state),
+            // -0.0 == 0.0
+            Float(f) => (if *f == 0.0 { 0.0 } else { *f }).to_bits().hash(state),
+            BigInt(i) => i.hash(state),
+            Ratio(r) => r.hash(state),
+            Str(s) => s.hash(state),
+            Sym(s) | Keyword(s) => s.hash(state),
+            Char(c) => c.hash(state),
+            Regex(re) => re.as_str().hash(state),
+            List(l, _) => hash_ordered(l.len(), l.iter(), state),
+            Vector(v, _) => hash_ordered(v.len(), v.iter(), state),
+            Hash(hm, _) => hash_unordered(hm.iter(), state),
+            Set(s, _) => hash_unordered(s.iter(), state),
+            LazySeq(_) => {
+                let items: Vec<MalVal> = self.items().filter_map(Result::ok).collect();
+                hash_ordered(items.len(), items.iter(), state)
+            }
+            Reduced(v) => v.hash(state),
+            // these are only ever equal to themselves
+            Func(_, _) | MalFunc { .. } | Compiled { .. } | Atom(_) => {}
+        }
+    }
+}
+
+fn hash_ordered<'a, H: Hasher>(len: usize, items: impl Iterator<Item = &'a MalVal>, state: &mut H) {
+    len.hash(state);
+    for item in items {
+        item.hash(state);
+    }
+}
+
+fn hash_unordered<T: StdHash, H: Hasher>(items: impl Iterator<Item = T>, state: &mut H) {
+    let mut sum: u64 = 0;
+    for item in items {
+        let mut hasher = FnvHasher::default();
+        item.hash(&mut hasher);
+        sum = sum.wrapping_add(hasher.finish());
+    }
+    sum.hash(state);
+}
+
+// See `MalVal::items`. An error ends the iteration.
+pub struct Items {
+    rest: MalVal,
+}
+
+impl Iterator for Items {
+    type Item = Result<MalVal, MalErr>;
+
+    fn next(&mut self) -> Option<Result<MalVal, MalErr>> {
+        match self.rest.uncons() {
+            Ok(Some((first, rest))) => {
+                self.rest = rest;
+                Some(Ok(first))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.rest = Nil;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+pub fn func(name: &'static str, arity: Arity, f: BuiltinFn) -> MalVal {
+    partial(name, arity, f, vec![])
+}
+
+// A builtin that's passed `bound`, followed by the args it's called with.
+pub fn partial(name: &'static str, arity: Arity, f: BuiltinFn, bound: MalArgs) -> MalVal {
+    let bound = Rc::new(bound);
+    Func(
+        Builtin {
+            name,
+            arity,
+            f,
+            bound,
+        },
+        Rc::new(Nil),
+    )
 }
 
-pub fn _assoc(mut hm: FnvHashMap<String, MalVal>, kvs: MalArgs) -> MalRet {
+pub fn _assoc(mut hm: MalHash, kvs: MalArgs) -> MalRet {
     if kvs.len() % 2 != 0 {
         return error("odd number of elements");
     }
     for (k, v) in kvs.iter().tuples() {
-        match k {
-            Str(s) => {
-                hm.insert(s.to_string(), v.clone());
-            }
-            _ => return error("key is not string"),
-        }
+        hm.insert(k.clone(), v.clone())?;
     }
-    Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+    Ok(Hash(hm, Rc::new(Nil)))
 }
 
-pub fn _dissoc(mut hm: FnvHashMap<String, MalVal>, ks: MalArgs) -> MalRet {
+pub fn _dissoc(mut hm: MalHash, ks: MalArgs) -> MalRet {
     for k in ks.iter() {
-        match k {
-            Str(ref s) => {
-                hm.remove(s);
-            }
-            _ => return error("key is not string"),
-        }
+        hm.remove(k)?;
     }
-    Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+    Ok(Hash(hm, Rc::new(Nil)))
 }
 
 pub fn hash_map(kvs: MalArgs) -> MalRet {
-    let hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
-    _assoc(hm, kvs)
+    _assoc(MalHash::default(), kvs)
+}
+
+pub fn hash_set(items: impl IntoIterator<Item = MalVal>) -> MalVal {
+    Set(items.into_iter().collect(), Rc::new(Nil))
 }